@@ -0,0 +1,403 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use colored::Colorize;
+
+use crate::{
+    diff::{apply_hunks, hunks, is_binary, split_lines, DiffAlgorithm, DiffLine},
+    error::{Error, Result},
+    ignore::IgnoreRules,
+    index::{
+        mode_from_metadata, Index, IndexEntry, EXTENDED_INTENT_TO_ADD, MODE_BLOB, MODE_EXECUTABLE,
+        MODE_GITLINK,
+    },
+    pathspec::Pathspec,
+    repository::Repository,
+    structures::ObjectType,
+    utils::path_from_bytes,
+    worktree::{cwd_prefix, entry_state, read_worktree_file, untracked_files, WorktreeState},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Stage every change in the work tree, including new and deleted files.
+    pub all: bool,
+    /// Only stage changes to files that are already tracked.
+    pub update: bool,
+    /// Pick which hunks of tracked files to stage.
+    pub patch: bool,
+    /// Record new files as "will be added" without staging their contents.
+    pub intent_to_add: bool,
+    /// Add files even if they are ignored.
+    pub force: bool,
+}
+
+/// Stages the paths matched by `args`. As in git 2.x, naming a path also stages its removal.
+pub fn add(repo: &Repository, args: &[String], options: &AddOptions) -> Result<()> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+    let pathspec = Pathspec::new(&work_tree, &cwd_prefix(repo)?, args)?;
+    let mut index = Index::read(repo)?;
+
+    if options.patch {
+        add_patch(repo, &mut index, &pathspec)?;
+        return index.write(repo);
+    }
+
+    if pathspec.is_empty() && !options.all && !options.update {
+        return Err(Error::InvalidArgument(
+            "Nothing specified, nothing added. Maybe you wanted to say `pgit add .`?".to_owned(),
+        ));
+    }
+
+    let mut matched = vec![false; pathspec.items.len()];
+    let mut mark_matched = |path: &[u8]| {
+        for (item, matched) in pathspec.items.iter().zip(matched.iter_mut()) {
+            if item.matches(path) {
+                *matched = true;
+            }
+        }
+    };
+
+    let mut to_stage = Vec::<Vec<u8>>::new();
+    let mut to_remove = Vec::<Vec<u8>>::new();
+
+    let mut tracked: Vec<&IndexEntry> = index.entries.iter().collect();
+    tracked.dedup_by(|a, b| a.path == b.path);
+
+    for entry in tracked {
+        if !pathspec.matches(&entry.path) {
+            continue;
+        }
+        mark_matched(&entry.path);
+
+        // Conflicted paths are resolved by staging whatever is in the work tree.
+        let state = if entry.stage() == 0 {
+            entry_state(repo, entry)?
+        } else if work_tree
+            .join(path_from_bytes(&entry.path))
+            .symlink_metadata()
+            .is_ok()
+        {
+            WorktreeState::Modified
+        } else {
+            WorktreeState::Deleted
+        };
+
+        match state {
+            WorktreeState::Modified => to_stage.push(entry.path.clone()),
+            WorktreeState::Deleted => to_remove.push(entry.path.clone()),
+            WorktreeState::Unchanged => {}
+        }
+    }
+
+    let mut new_files = Vec::<Vec<u8>>::new();
+
+    if !options.update {
+        let mut ignore = IgnoreRules::new(repo)?;
+        let candidates = untracked_files(repo, &index, Some(&mut ignore))?;
+
+        for path in candidates {
+            if pathspec.matches(&path) {
+                mark_matched(&path);
+                new_files.push(path);
+            }
+        }
+
+        // Paths named explicitly but skipped by the walk are ignored ones.
+        let mut ignored = Vec::<String>::new();
+
+        for (item, matched) in pathspec.items.iter().zip(matched.iter_mut()) {
+            if *matched || item.glob || item.path.is_empty() {
+                continue;
+            }
+
+            let full_path = work_tree.join(path_from_bytes(&item.path));
+            let Ok(metadata) = full_path.symlink_metadata() else {
+                continue;
+            };
+
+            if !options.force {
+                ignored.push(item.original.clone());
+                *matched = true;
+                continue;
+            }
+
+            *matched = true;
+
+            if metadata.is_dir() {
+                // Forcing a directory adds everything below it, ignored or not.
+                for path in untracked_files(repo, &index, None)? {
+                    if item.matches(&path) {
+                        new_files.push(path);
+                    }
+                }
+            } else {
+                new_files.push(item.path.clone());
+            }
+        }
+
+        if !ignored.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "The following paths are ignored by one of your .gitignore files:\n{}\nUse -f if you really want to add them.",
+                ignored.join("\n")
+            )));
+        }
+    }
+
+    if let Some(position) = matched.iter().position(|matched| !matched) {
+        return Err(Error::InvalidArgument(format!(
+            "pathspec '{}' did not match any files",
+            pathspec.items[position].original
+        )));
+    }
+
+    for path in to_remove {
+        index.remove(&path);
+    }
+
+    for path in to_stage {
+        stage_path(repo, &mut index, &path, false)?;
+    }
+
+    for path in new_files {
+        stage_path(repo, &mut index, &path, options.intent_to_add)?;
+    }
+
+    index.write(repo)
+}
+
+/// The mode to stage a file with. Without `core.filemode` the executable bit on disk cannot be
+/// trusted, so a tracked file keeps its mode.
+fn staged_mode(repo: &Repository, metadata: &fs::Metadata, existing: Option<u32>) -> u32 {
+    let mode = mode_from_metadata(metadata);
+
+    if matches!(mode, MODE_BLOB | MODE_EXECUTABLE)
+        && !repo.config().get_bool("core.filemode").unwrap_or(true)
+    {
+        return existing
+            .filter(|mode| matches!(*mode, MODE_BLOB | MODE_EXECUTABLE))
+            .unwrap_or(MODE_BLOB);
+    }
+
+    mode
+}
+
+/// Hashes the work tree file at `path` into the object database and stages it at stage 0.
+pub fn stage_path(
+    repo: &Repository,
+    index: &mut Index,
+    path: &[u8],
+    intent_to_add: bool,
+) -> Result<()> {
+    let work_tree = repo.require_work_tree()?;
+
+    // Nested repositories are staged as a gitlink to their checked out commit.
+    if let Some(dir) = path.strip_suffix(b"/") {
+        let dir_path = work_tree.join(path_from_bytes(dir));
+        let nested = Repository::open(&dir_path)?;
+        let head = nested.head()?.ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "`{}` does not have a commit checked out",
+                String::from_utf8_lossy(dir)
+            ))
+        })?;
+
+        let metadata = dir_path.symlink_metadata()?;
+        let mut entry = IndexEntry::from_metadata(dir.to_vec(), head, &metadata);
+        entry.mode = MODE_GITLINK;
+        entry.size = 0;
+        index.add(entry);
+
+        return Ok(());
+    }
+
+    let full_path = work_tree.join(path_from_bytes(path));
+    let metadata = full_path.symlink_metadata()?;
+    let existing = index.entry(path, 0).map(|entry| entry.mode);
+
+    if intent_to_add {
+        let mut entry = IndexEntry::from_metadata(
+            path.to_vec(),
+            repo.write_object(&ObjectType::Blob, &[])?,
+            &metadata,
+        );
+        entry.mode = staged_mode(repo, &metadata, existing);
+        entry.size = 0;
+        entry.ctime = (0, 0);
+        entry.mtime = (0, 0);
+        entry.extended_flags |= EXTENDED_INTENT_TO_ADD;
+        index.add(entry);
+
+        return Ok(());
+    }
+
+    let contents = read_worktree_file(&full_path, &metadata)?;
+    let hash = repo.write_object(&ObjectType::Blob, &contents)?;
+
+    let mut entry = IndexEntry::from_metadata(path.to_vec(), hash, &metadata);
+    entry.mode = staged_mode(repo, &metadata, existing);
+    index.add(entry);
+
+    Ok(())
+}
+
+/// What the user chose for the remaining hunks of the current file.
+enum Choice {
+    Ask,
+    All,
+    None,
+}
+
+/// Interactively stages individual hunks of tracked, modified files.
+fn add_patch(repo: &Repository, index: &mut Index, pathspec: &Pathspec) -> Result<()> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+    let algorithm = DiffAlgorithm::configured(repo)?;
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    let candidates: Vec<IndexEntry> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() == 0 && pathspec.matches(&entry.path))
+        .cloned()
+        .collect();
+
+    'files: for entry in candidates {
+        if entry_state(repo, &entry)? != WorktreeState::Modified {
+            continue;
+        }
+
+        let full_path = work_tree.join(path_from_bytes(&entry.path));
+        let metadata = full_path.symlink_metadata()?;
+        let old = repo.read_blob(&entry.hash)?;
+        let new = read_worktree_file(&full_path, &metadata)?;
+
+        if is_binary(&old) || is_binary(&new) {
+            println!("Skipping binary file {}", entry.path_str());
+            continue;
+        }
+
+        let old_lines = split_lines(&old);
+        let new_lines = split_lines(&new);
+        let file_hunks = hunks(&old_lines, &new_lines, 3, algorithm);
+
+        if file_hunks.is_empty() {
+            continue;
+        }
+
+        let path = entry.path_str();
+        println!("{}", format!("diff --git a/{path} b/{path}").bold());
+        println!("{}", format!("--- a/{path}").bold());
+        println!("{}", format!("+++ b/{path}").bold());
+
+        let mut selected = vec![false; file_hunks.len()];
+        let mut choice = Choice::Ask;
+        let mut quit = false;
+
+        for (i, hunk) in file_hunks.iter().enumerate() {
+            match choice {
+                Choice::All => {
+                    selected[i] = true;
+                    continue;
+                }
+                Choice::None => continue,
+                Choice::Ask => {}
+            }
+
+            println!("{}", hunk.header().cyan());
+            for line in &hunk.lines {
+                print_hunk_line(line);
+            }
+
+            loop {
+                print!(
+                    "{}",
+                    format!(
+                        "({}/{}) Stage this hunk [y,n,q,a,d,?]? ",
+                        i + 1,
+                        file_hunks.len()
+                    )
+                    .blue()
+                    .bold()
+                );
+                io::stdout().flush()?;
+
+                let mut answer = String::new();
+                if input.read_line(&mut answer)? == 0 {
+                    quit = true;
+                    break;
+                }
+
+                match answer.trim() {
+                    "y" => selected[i] = true,
+                    "n" => {}
+                    "q" => quit = true,
+                    "a" => {
+                        selected[i] = true;
+                        choice = Choice::All;
+                    }
+                    "d" => choice = Choice::None,
+                    _ => {
+                        println!(
+                            "{}",
+                            "y - stage this hunk\nn - do not stage this hunk\nq - quit; do not stage this hunk or any of the remaining ones\na - stage this hunk and all later hunks in the file\nd - do not stage this hunk or any of the later hunks in the file\n? - print help"
+                                .red()
+                                .bold()
+                        );
+                        continue;
+                    }
+                }
+
+                break;
+            }
+
+            if quit {
+                break;
+            }
+        }
+
+        if selected.iter().any(|selected| *selected) {
+            let contents = apply_hunks(&old_lines, &file_hunks, &selected);
+            let hash = repo.write_object(&ObjectType::Blob, &contents)?;
+
+            // The staged blob no longer matches the file, so leave the stat data stale.
+            let mut staged = entry.clone();
+            staged.hash = hash;
+            staged.size = contents.len() as u32;
+            staged.ctime = (0, 0);
+            staged.mtime = (0, 0);
+            index.add(staged);
+        }
+
+        if quit {
+            break 'files;
+        }
+    }
+
+    Ok(())
+}
+
+fn print_hunk_line(line: &DiffLine) {
+    let (sign, text) = match line {
+        DiffLine::Context(text) => (' ', text),
+        DiffLine::Removed(text) => ('-', text),
+        DiffLine::Added(text) => ('+', text),
+    };
+
+    let text = String::from_utf8_lossy(text);
+    let mut rendered = format!("{sign}{}", text.trim_end_matches('\n'));
+
+    rendered = match line {
+        DiffLine::Context(_) => rendered,
+        DiffLine::Removed(_) => rendered.red().to_string(),
+        DiffLine::Added(_) => rendered.green().to_string(),
+    };
+
+    println!("{rendered}");
+
+    if !text.ends_with('\n') {
+        println!("\\ No newline at end of file");
+    }
+}
@@ -0,0 +1,607 @@
+use colored::Colorize;
+
+use crate::{
+    config::{parse_bool, rename_config_section, set_config_value, unset_config_value},
+    error::{Error, Result},
+    log::subject,
+    refs::{
+        check_ref_format, check_writable_name, delete_ref, dwim_ref, list_refs, peel, read_reflog,
+        read_symbolic_ref, rename_ref, resolve_ref, set_symbolic_ref, shorten_ref, update_ref,
+    },
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, resolve_commit},
+    revwalk::{ahead_behind, is_ancestor},
+    status::upstream_ref,
+    utils::fnmatch,
+};
+
+/// Which branches `pgit branch` lists and how much it says about each.
+#[derive(Debug, Clone, Default)]
+pub struct BranchListOptions {
+    /// List remote-tracking branches instead of local ones.
+    pub remotes: bool,
+    /// List local and remote-tracking branches.
+    pub all: bool,
+    /// 1 adds each branch's commit and how it compares with its upstream; 2 also names the
+    /// upstream.
+    pub verbose: u8,
+    /// Only branches whose tip is reachable from this commit.
+    pub merged: Option<String>,
+    /// Only branches whose tip is not reachable from this commit.
+    pub no_merged: Option<String>,
+    /// Shell patterns the branch names must match; empty for all branches.
+    pub patterns: Vec<String>,
+}
+
+/// How a new branch picks its upstream.
+#[derive(Debug, Clone, Default)]
+pub struct CreateBranchOptions {
+    /// Move an existing branch to the new start point.
+    pub force: bool,
+    /// `Some(true)` always tracks the start point and `Some(false)` never does. By default a
+    /// branch started from a remote-tracking branch tracks it, as `branch.autoSetupMerge` says.
+    pub track: Option<bool>,
+}
+
+/// One line of the branch list.
+struct Listed {
+    name: String,
+    /// The commit, or `None` for a symbolic ref such as `origin/HEAD`.
+    hash: Option<String>,
+    /// The short name of the branch a symbolic ref points at.
+    points_to: Option<String>,
+    /// The local branch name, to look up its upstream.
+    branch: Option<String>,
+    current: bool,
+    remote: bool,
+}
+
+/// The branch `HEAD` is on, even if it has no commits yet, or `None` when `HEAD` is detached.
+pub fn current_branch(repo: &Repository) -> Result<Option<String>> {
+    Ok(read_symbolic_ref(repo, "HEAD")?
+        .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_owned)))
+}
+
+/// Describes a detached `HEAD` by the last thing checked out, like git: `(HEAD detached at
+/// v1.0)` while `HEAD` is still there, `(HEAD detached from v1.0)` once commits were made on
+/// top of it.
+pub fn detached_description(repo: &Repository, head: &str) -> Result<String> {
+    let abbrev = default_abbrev(repo);
+
+    for entry in read_reflog(repo, "HEAD")? {
+        let Some((_, target)) = entry
+            .message
+            .strip_prefix("checkout: moving from ")
+            .and_then(|moved| moved.split_once(" to "))
+        else {
+            continue;
+        };
+
+        let mut name = abbreviate(repo, &entry.new, abbrev)?;
+
+        // `HEAD` only means something at the time of the checkout, so it is shown as a commit.
+        if target != "HEAD" {
+            if let Some((full_name, hash)) = dwim_ref(repo, target)? {
+                if hash == entry.new || peel(repo, &hash)? == entry.new {
+                    name = full_name
+                        .strip_prefix("refs/tags/")
+                        .or_else(|| full_name.strip_prefix("refs/remotes/"))
+                        .unwrap_or(&full_name)
+                        .to_owned();
+                }
+            }
+        }
+
+        let at = if entry.new == head { "at" } else { "from" };
+        return Ok(format!("(HEAD detached {at} {name})"));
+    }
+
+    Ok(format!(
+        "(HEAD detached at {})",
+        abbreviate(repo, head, abbrev)?
+    ))
+}
+
+/// The lines of `pgit branch`: the current branch first marked with `*` in green, then the
+/// other local branches, then remote-tracking branches in red.
+pub fn list_branches(repo: &Repository, options: &BranchListOptions) -> Result<Vec<String>> {
+    let head = repo.head()?;
+    let current = current_branch(repo)?;
+    let abbrev = default_abbrev(repo);
+    let mut listed = Vec::new();
+
+    let show_local = options.all || !options.remotes;
+    let show_remote = options.all || options.remotes;
+
+    if let (true, None, Some(head)) = (show_local && options.patterns.is_empty(), &current, &head) {
+        listed.push(Listed {
+            name: detached_description(repo, head)?,
+            hash: Some(head.clone()),
+            points_to: None,
+            branch: None,
+            current: true,
+            remote: false,
+        });
+    }
+
+    for (prefix, shown) in [("refs/heads/", show_local), ("refs/remotes/", show_remote)] {
+        if !shown {
+            continue;
+        }
+
+        for entry in list_refs(repo, prefix)? {
+            let short = &entry.name[prefix.len()..];
+
+            if !options.patterns.is_empty()
+                && !options
+                    .patterns
+                    .iter()
+                    .any(|pattern| fnmatch(pattern.as_bytes(), short.as_bytes()))
+            {
+                continue;
+            }
+
+            let remote = prefix == "refs/remotes/";
+            let points_to =
+                read_symbolic_ref(repo, &entry.name)?.map(|target| shorten_ref(&target).to_owned());
+
+            listed.push(Listed {
+                name: match (remote, options.all) {
+                    (true, true) => format!("remotes/{short}"),
+                    _ => short.to_owned(),
+                },
+                hash: points_to.is_none().then(|| entry.hash.clone()),
+                points_to,
+                branch: (!remote).then(|| short.to_owned()),
+                current: !remote && current.as_deref() == Some(short),
+                remote,
+            });
+        }
+    }
+
+    for (spec, keep_merged) in [(&options.merged, true), (&options.no_merged, false)] {
+        let Some(spec) = spec else {
+            continue;
+        };
+        let target = resolve_commit(repo, spec)?;
+        let mut kept = Vec::new();
+
+        for item in listed {
+            let merged = match &item.hash {
+                Some(hash) => is_ancestor(repo, &peel(repo, hash)?, &target)?,
+                None => false,
+            };
+
+            if item.hash.is_some() && merged == keep_merged {
+                kept.push(item);
+            }
+        }
+
+        listed = kept;
+    }
+
+    let width = listed
+        .iter()
+        .map(|item| item.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut lines = Vec::new();
+
+    for item in &listed {
+        let marker = if item.current { "* " } else { "  " };
+        let padded = if options.verbose > 0 && item.points_to.is_none() {
+            format!("{:width$}", item.name)
+        } else {
+            item.name.clone()
+        };
+        let name = if item.current {
+            padded.green().to_string()
+        } else if item.remote {
+            padded.red().to_string()
+        } else {
+            padded
+        };
+
+        let mut line = format!("{marker}{name}");
+
+        if let Some(target) = &item.points_to {
+            line += &format!(" -> {target}");
+        } else if let (true, Some(hash)) = (options.verbose > 0, &item.hash) {
+            let object = repo.read_object(hash)?;
+            let message = object
+                .meta
+                .and_then(|meta| meta.message)
+                .unwrap_or_default();
+            let tracking = match &item.branch {
+                Some(branch) => tracking_info(repo, branch, hash, options.verbose > 1)?,
+                None => String::new(),
+            };
+
+            line += &format!(
+                " {} {tracking}{}",
+                abbreviate(repo, hash, abbrev)?,
+                subject(&message)
+            );
+        }
+
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+/// The `[origin/main: ahead 1, behind 2] ` part of `pgit branch -v`. Only `verbose_upstream`
+/// names the upstream; without it an up to date branch shows nothing.
+fn tracking_info(
+    repo: &Repository,
+    branch: &str,
+    hash: &str,
+    verbose_upstream: bool,
+) -> Result<String> {
+    let Some((full_name, name)) = upstream_ref(repo, branch) else {
+        return Ok(String::new());
+    };
+
+    let state = match repo.resolve_ref(&full_name)? {
+        None => "gone".to_owned(),
+        Some(upstream) => match ahead_behind(repo, hash, &upstream)? {
+            (0, 0) => String::new(),
+            (ahead, 0) => format!("ahead {ahead}"),
+            (0, behind) => format!("behind {behind}"),
+            (ahead, behind) => format!("ahead {ahead}, behind {behind}"),
+        },
+    };
+
+    Ok(match (verbose_upstream, state.is_empty()) {
+        (true, true) => format!("[{}] ", name.blue()),
+        (true, false) => format!("[{}: {state}] ", name.blue()),
+        (false, true) => String::new(),
+        (false, false) => format!("[{state}] "),
+    })
+}
+
+/// Refuses names git would not accept for a branch.
+pub fn check_branch_name(name: &str) -> Result<()> {
+    if name == "HEAD" || name.starts_with('-') || !check_ref_format(&format!("refs/heads/{name}")) {
+        return Err(Error::InvalidArgument(format!(
+            "`{name}` is not a valid branch name"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Creates the branch `name` at `start` (a revision, `HEAD` by default) and sets up its upstream.
+/// Returns the note about the upstream, if one was set.
+pub fn create_branch(
+    repo: &Repository,
+    name: &str,
+    start: Option<&str>,
+    options: &CreateBranchOptions,
+) -> Result<Option<String>> {
+    check_branch_name(name)?;
+
+    let full_name = format!("refs/heads/{name}");
+    let exists = resolve_ref(repo, &full_name)?.is_some();
+
+    if exists && !options.force {
+        return Err(Error::InvalidArgument(format!(
+            "A branch named `{name}` already exists"
+        )));
+    }
+
+    if exists && current_branch(repo)?.as_deref() == Some(name) {
+        return Err(Error::InvalidArgument(format!(
+            "Cannot force update the current branch `{name}`"
+        )));
+    }
+
+    let start = start.unwrap_or("HEAD");
+    let commit = resolve_commit(repo, start).map_err(|err| match err {
+        Error::UnknownRevision(_) => {
+            Error::InvalidArgument(format!("`{start}` is not a valid branch starting point"))
+        }
+        err => err,
+    })?;
+    let message = if exists {
+        format!("branch: Reset to {start}")
+    } else {
+        format!("branch: Created from {start}")
+    };
+
+    update_ref(repo, &full_name, &commit, None, &message, false)?;
+
+    Ok(setup_tracking(repo, name, start, options.track)?
+        .map(|(_, upstream)| format!("branch '{name}' set up to track '{upstream}'.")))
+}
+
+/// Records in the config that `branch` tracks the branch `start` names, if `track` (or, when it
+/// is `None`, `branch.autoSetupMerge`) asks for it. Remote-tracking branches are tracked through
+/// their remote, local branches through `.`. Returns the upstream's full and short name.
+pub fn setup_tracking(
+    repo: &Repository,
+    branch: &str,
+    start: &str,
+    track: Option<bool>,
+) -> Result<Option<(String, String)>> {
+    let auto = repo
+        .config()
+        .get("branch.autosetupmerge")
+        .unwrap_or_else(|| "true".to_owned());
+    let always = auto.eq_ignore_ascii_case("always");
+
+    if track == Some(false) || (track.is_none() && !always && !parse_bool(&auto)) {
+        return Ok(None);
+    }
+
+    let full_name = match dwim_ref(repo, start)? {
+        Some((full_name, _)) => full_name,
+        None => String::new(),
+    };
+
+    let upstream = if let Some(rest) = full_name.strip_prefix("refs/remotes/") {
+        let remotes: Vec<&str> = repo
+            .config()
+            .entries
+            .iter()
+            .filter(|entry| entry.section == "remote")
+            .filter_map(|entry| entry.subsection.as_deref())
+            .collect();
+
+        remotes
+            .into_iter()
+            .filter(|remote| rest.starts_with(&format!("{remote}/")))
+            .max_by_key(|remote| remote.len())
+            .map(|remote| {
+                (
+                    remote.to_owned(),
+                    format!("refs/heads/{}", &rest[remote.len() + 1..]),
+                )
+            })
+    } else if full_name.starts_with("refs/heads/") && (track == Some(true) || always) {
+        Some((".".to_owned(), full_name.clone()))
+    } else {
+        None
+    };
+
+    let Some((remote, merge)) = upstream else {
+        return match track {
+            Some(true) => Err(Error::InvalidArgument(format!(
+                "Cannot set up tracking information: the starting point `{start}` is not a branch"
+            ))),
+            _ => Ok(None),
+        };
+    };
+
+    let config = repo.config_path();
+    set_config_value(&config, &format!("branch.{branch}.remote"), &remote, false)?;
+    set_config_value(&config, &format!("branch.{branch}.merge"), &merge, false)?;
+
+    let merge_branch = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+
+    Ok(Some(if remote == "." {
+        (merge.clone(), merge_branch.to_owned())
+    } else {
+        (
+            format!("refs/remotes/{remote}/{merge_branch}"),
+            format!("{remote}/{merge_branch}"),
+        )
+    }))
+}
+
+/// Deletes the branch `name`, or with `remote` the remote-tracking branch, and returns the note
+/// saying so. Unless `force` is set, a local branch must be merged into its upstream, or into
+/// `HEAD` when it has none, so that no commits are lost.
+pub fn delete_branch(repo: &Repository, name: &str, force: bool, remote: bool) -> Result<String> {
+    let full_name = if remote {
+        format!("refs/remotes/{name}")
+    } else {
+        format!("refs/heads/{name}")
+    };
+    let kind = if remote {
+        "remote-tracking branch"
+    } else {
+        "branch"
+    };
+
+    let Some(hash) = resolve_ref(repo, &full_name)? else {
+        return Err(Error::Failed(format!("{kind} `{name}` not found")));
+    };
+
+    if !remote && current_branch(repo)?.as_deref() == Some(name) {
+        return Err(Error::InvalidArgument(format!(
+            "Cannot delete branch `{name}` checked out at `{}`",
+            repo.work_tree().unwrap_or(repo.git_dir()).display()
+        )));
+    }
+
+    if !remote && !force {
+        let upstream = match upstream_ref(repo, name) {
+            Some((full_name, _)) => repo.resolve_ref(&full_name)?,
+            None => None,
+        };
+        let merged = match upstream.or(repo.head()?) {
+            Some(reference) => is_ancestor(repo, &hash, &reference)?,
+            None => false,
+        };
+
+        if !merged {
+            return Err(Error::InvalidArgument(format!(
+                "The branch `{name}` is not fully merged. If you are sure you want to delete it, run `pgit branch -D {name}`"
+            )));
+        }
+    }
+
+    delete_ref(repo, &full_name, Some(&hash), false)?;
+
+    if !remote {
+        rename_config_section(&repo.config_path(), &format!("branch.{name}"), None)?;
+    }
+
+    Ok(format!(
+        "Deleted {kind} {name} (was {}).",
+        abbreviate(repo, &hash, default_abbrev(repo))?
+    ))
+}
+
+/// Renames the branch `old` (the current branch by default) to `new`, along with its reflog and
+/// config. `force` allows replacing an existing branch `new`.
+pub fn rename_branch(repo: &Repository, old: Option<&str>, new: &str, force: bool) -> Result<()> {
+    let current = current_branch(repo)?;
+    let old = match (old, &current) {
+        (Some(old), _) => old.to_owned(),
+        (None, Some(current)) => current.clone(),
+        (None, None) => {
+            return Err(Error::InvalidArgument(
+                "Cannot rename the current branch while not on any".to_owned(),
+            ))
+        }
+    };
+
+    check_branch_name(new)?;
+
+    let (old_ref, new_ref) = (format!("refs/heads/{old}"), format!("refs/heads/{new}"));
+    let old_hash = resolve_ref(repo, &old_ref)?;
+    let is_current = current.as_deref() == Some(old.as_str());
+
+    if old_hash.is_none() && !is_current {
+        return Err(Error::InvalidArgument(format!("No branch named `{old}`")));
+    }
+
+    if old == new {
+        return Ok(());
+    }
+
+    let config = repo.config_path();
+
+    // Checked before anything is deleted, so a clash such as `refs/heads/new/x` loses nothing.
+    check_writable_name(
+        repo,
+        &new_ref,
+        old_hash.is_some().then_some(old_ref.as_str()),
+    )?;
+
+    if resolve_ref(repo, &new_ref)?.is_some() {
+        if !force {
+            return Err(Error::InvalidArgument(format!(
+                "A branch named `{new}` already exists"
+            )));
+        }
+
+        if current.as_deref() == Some(new) {
+            return Err(Error::InvalidArgument(format!(
+                "Cannot force update the current branch `{new}`"
+            )));
+        }
+
+        delete_ref(repo, &new_ref, None, false)?;
+        rename_config_section(&config, &format!("branch.{new}"), None)?;
+    }
+
+    match old_hash {
+        Some(_) => rename_ref(
+            repo,
+            &old_ref,
+            &new_ref,
+            &format!("Branch: renamed {old_ref} to {new_ref}"),
+        )?,
+        // A branch without commits only exists as what `HEAD` points at.
+        None => set_symbolic_ref(repo, "HEAD", &new_ref, None)?,
+    }
+
+    rename_config_section(
+        &config,
+        &format!("branch.{old}"),
+        Some(&format!("branch.{new}")),
+    )?;
+
+    Ok(())
+}
+
+/// Makes `branch` (the current branch by default) track `upstream` and returns the note saying so.
+pub fn set_upstream(repo: &Repository, branch: Option<&str>, upstream: &str) -> Result<String> {
+    let branch = existing_branch(repo, branch, "set the upstream of")?;
+
+    match dwim_ref(repo, upstream)? {
+        Some((full_name, _))
+            if full_name.starts_with("refs/heads/") || full_name.starts_with("refs/remotes/") => {}
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "The requested upstream branch `{upstream}` does not exist"
+            )))
+        }
+    }
+
+    let (_, name) = setup_tracking(repo, &branch, upstream, Some(true))?
+        .expect("explicit tracking is always set up or fails");
+
+    Ok(format!("branch '{branch}' set up to track '{name}'."))
+}
+
+/// Forgets the upstream of `branch` (the current branch by default).
+pub fn unset_upstream(repo: &Repository, branch: Option<&str>) -> Result<()> {
+    let branch = existing_branch(repo, branch, "unset the upstream of")?;
+    let config = repo.config_path();
+
+    let removed = unset_config_value(&config, &format!("branch.{branch}.remote"), true)?
+        + unset_config_value(&config, &format!("branch.{branch}.merge"), true)?;
+
+    if removed == 0 {
+        return Err(Error::InvalidArgument(format!(
+            "Branch `{branch}` has no upstream information"
+        )));
+    }
+
+    Ok(())
+}
+
+/// `branch`, or the current branch, after checking that it exists.
+fn existing_branch(repo: &Repository, branch: Option<&str>, action: &str) -> Result<String> {
+    let branch = match branch {
+        Some(branch) => branch.to_owned(),
+        None => current_branch(repo)?.ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "Cannot {action} HEAD: it does not point to any branch"
+            ))
+        })?,
+    };
+
+    if resolve_ref(repo, &format!("refs/heads/{branch}"))?.is_none() {
+        return Err(Error::InvalidArgument(format!(
+            "Branch `{branch}` does not exist"
+        )));
+    }
+
+    Ok(branch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestRepo;
+
+    #[test]
+    fn failed_renames_keep_the_branch_and_its_reflog() {
+        let test = TestRepo::new("branch-rename-clash");
+        let commit = test.commit(&[("a", "a\n")], &[], "first");
+        test.set_ref("refs/heads/main", &commit, "commit (initial): first");
+        test.set_ref("refs/heads/x", &commit, "branch: Created from main");
+        test.set_ref("refs/heads/y/z", &commit, "branch: Created from main");
+
+        for force in [false, true] {
+            assert!(rename_branch(&test.repo, Some("x"), "y", force).is_err());
+            assert!(rename_branch(&test.repo, Some("x"), "y/z/w", force).is_err());
+
+            assert_eq!(
+                resolve_ref(&test.repo, "refs/heads/x").unwrap().as_deref(),
+                Some(commit.as_str())
+            );
+            assert_eq!(
+                read_reflog(&test.repo, "refs/heads/x").unwrap().len(),
+                1,
+                "force: {force}"
+            );
+            assert!(resolve_ref(&test.repo, "refs/heads/y/z").unwrap().is_some());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::structures::Object;
+use crate::{error::Result, repository::Repository, revision::resolve_revision};
 
 #[derive(PartialEq)]
 pub enum GetFileContentsReturnType {
@@ -8,17 +8,21 @@ pub enum GetFileContentsReturnType {
     Type,
 }
 
-pub fn get_file_contents(object_hash: String, return_type: GetFileContentsReturnType) -> String {
-    let object = Object::new(object_hash);
+pub fn get_file_contents(
+    repo: &Repository,
+    object_hash: String,
+    return_type: GetFileContentsReturnType,
+) -> Result<String> {
+    let object = repo.read_object(&resolve_revision(repo, &object_hash)?)?;
 
     if return_type == GetFileContentsReturnType::Contents {
-        object.print_contents();
-        String::new()
+        object.print_contents(repo)?;
+        Ok(String::new())
     } else if return_type == GetFileContentsReturnType::Size {
-        object.size.to_string()
+        Ok(object.size.to_string())
     } else if return_type == GetFileContentsReturnType::Type {
-        object.obj_type.to_string()
+        Ok(object.obj_type.to_string())
     } else {
-        String::new()
+        Ok(String::new())
     }
 }
@@ -0,0 +1,646 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    ignore::IgnoreRules,
+    index::{Index, IndexEntry, MODE_GITLINK},
+    pathspec::Pathspec,
+    repository::Repository,
+    tree::{flatten_tree, TreeFile},
+    utils::{path_from_bytes, path_to_bytes},
+    worktree::{checkout_file, cwd_prefix, entry_state, remove_worktree_file, WorktreeState},
+};
+
+/// The command moving the index and work tree, which decides how a refusal is worded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeUpdate {
+    Checkout,
+    Merge,
+    Reset,
+}
+
+impl TreeUpdate {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Checkout => "checkout",
+            Self::Merge => "merge",
+            Self::Reset => "reset",
+        }
+    }
+
+    /// What the user should deal with their changes before doing.
+    fn before(&self) -> &'static str {
+        match self {
+            Self::Checkout => "you switch branches",
+            Self::Merge => "you merge",
+            Self::Reset => "you reset",
+        }
+    }
+}
+
+/// Moves the index and work tree from the tree `old` to the tree `new` (`None` being the empty
+/// tree of an unborn branch), the way git switches branches.
+///
+/// Paths that are the same in both trees are left alone, so local changes to them are carried
+/// over. The others are only touched when the index and work tree still match `old`; a path
+/// whose staged or unstaged changes would be lost, or an untracked file that is in the way,
+/// stops the switch before anything is written. With `force`, the index and work tree are made
+/// to match `new` no matter what, and conflicts are thrown away.
+pub fn switch_trees(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+    force: bool,
+    update: TreeUpdate,
+) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let old_files = tree_files(repo, old)?;
+    let new_files = tree_files(repo, new)?;
+
+    let changes = if force {
+        forced_changes(repo, &index, &new_files)?
+    } else {
+        careful_changes(repo, &index, &old_files, &new_files, update)?
+    };
+
+    apply_changes(repo, &mut index, &changes)?;
+
+    index.write(repo)
+}
+
+/// Makes the index match `tree` the way `git reset --merge` does: every path whose index entry
+/// differs from `tree`, conflicted ones included, is checked out from it, while changes that
+/// were never staged are kept. A path with both staged and unstaged changes stops the reset
+/// before anything is written.
+pub fn reset_merge(repo: &Repository, tree: Option<&str>) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let files = tree_files(repo, tree)?;
+    let paths: BTreeSet<&Vec<u8>> = files
+        .keys()
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .collect();
+    let mut changes = Vec::new();
+
+    for path in paths {
+        let file = files.get(path);
+        let conflicted = index.entries_for(path).iter().any(|e| e.stage() != 0);
+
+        match index.entry(path, 0) {
+            _ if conflicted => {}
+            entry if staged_as(entry, file) => continue,
+            Some(entry) if entry_state(repo, entry)? == WorktreeState::Modified => {
+                return Err(Error::InvalidArgument(format!(
+                    "Entry '{}' not uptodate. Cannot merge.",
+                    entry.path_str()
+                )));
+            }
+            _ => {}
+        }
+
+        changes.push((path.clone(), file.cloned()));
+    }
+
+    apply_changes(repo, &mut index, &changes)?;
+
+    index.write(repo)
+}
+
+/// Makes the index and work tree match `tree` for the paths `pathspec` selects, the way
+/// `git reset --hard` does: staged and unstaged changes are thrown away, conflicts included, and
+/// tracked files the tree does not have are deleted. Untracked files are left alone.
+pub fn reset_hard(repo: &Repository, tree: Option<&str>, pathspec: &Pathspec) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let files = tree_files(repo, tree)?;
+    let paths: BTreeSet<&Vec<u8>> = files
+        .keys()
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .filter(|path| pathspec.matches(path))
+        .collect();
+    let mut changes = Vec::new();
+
+    for path in paths {
+        let file = files.get(path);
+        let conflicted = index.entries_for(path).iter().any(|e| e.stage() != 0);
+
+        match file {
+            Some(file) if !conflicted && up_to_date(repo, &index, path, file)? => continue,
+            _ => changes.push((path.clone(), file.cloned())),
+        }
+    }
+
+    apply_changes(repo, &mut index, &changes)?;
+
+    index.write(repo)
+}
+
+/// Makes the index match `tree` for the paths `pathspec` selects, the way `git reset --mixed`
+/// does, without touching the work tree. Entries that already match keep their stat data;
+/// conflicted paths are resolved to the tree's version, and paths the tree does not have are
+/// unstaged.
+pub fn reset_index(repo: &Repository, tree: Option<&str>, pathspec: &Pathspec) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let files = tree_files(repo, tree)?;
+    let paths: BTreeSet<Vec<u8>> = files
+        .keys()
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .filter(|path| pathspec.matches(path))
+        .cloned()
+        .collect();
+
+    for path in paths {
+        let file = files.get(&path);
+        let conflicted = index.entries_for(&path).iter().any(|e| e.stage() != 0);
+
+        if !conflicted && staged_as(index.entry(&path, 0), file) {
+            continue;
+        }
+
+        match file {
+            Some(file) => index.add(IndexEntry::new(path, file.mode, file.hash.clone(), 0)),
+            None => {
+                index.remove(&path);
+            }
+        }
+    }
+
+    index.write(repo)
+}
+
+/// Writes `changes` to the work tree only, `None` meaning the file goes away. Index entries that
+/// match what was written get the new file's stat data, so they do not show as modified.
+pub fn checkout_worktree(repo: &Repository, changes: &[(Vec<u8>, Option<TreeFile>)]) -> Result<()> {
+    let mut index = Index::read(repo)?;
+
+    for (path, _) in changes.iter().filter(|(_, file)| file.is_none()) {
+        remove_worktree_file(repo, path)?;
+    }
+
+    for (path, file) in changes {
+        let Some(file) = file else {
+            continue;
+        };
+
+        let metadata = checkout_file(repo, path, file.mode, &file.hash)?;
+        if let Some(entry) = index
+            .entries
+            .iter_mut()
+            .find(|entry| entry.path == *path && entry.stage() == 0)
+            .filter(|entry| entry.mode == file.mode && entry.hash == file.hash)
+        {
+            entry.update_stat(&metadata);
+        }
+    }
+
+    index.write(repo)
+}
+
+/// Refuses to go on while the index has changes staged against `tree`, which an operation that
+/// rebuilds the index from a merge result would lose.
+pub fn require_clean_index(
+    repo: &Repository,
+    tree: Option<&str>,
+    update: TreeUpdate,
+) -> Result<()> {
+    let index = Index::read(repo)?;
+    let files = tree_files(repo, tree)?;
+    let paths: BTreeSet<&Vec<u8>> = files
+        .keys()
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .collect();
+    let staged: Vec<Vec<u8>> = paths
+        .into_iter()
+        .filter(|path| !staged_as(index.entry(path, 0), files.get(*path)))
+        .cloned()
+        .collect();
+
+    if staged.is_empty() {
+        Ok(())
+    } else {
+        Err(local_changes_error(&staged, update))
+    }
+}
+
+/// Writes `changes` to the work tree and the index, `None` meaning the path goes away.
+fn apply_changes(
+    repo: &Repository,
+    index: &mut Index,
+    changes: &[(Vec<u8>, Option<TreeFile>)],
+) -> Result<()> {
+    // Removals go first so that a file can take the place of a directory that just emptied.
+    for (path, _) in changes.iter().filter(|(_, file)| file.is_none()) {
+        remove_worktree_file(repo, path)?;
+        index.remove(path);
+    }
+
+    for (path, file) in changes {
+        if let Some(file) = file {
+            stage_checked_out(repo, index, path, file)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the files of `pathspec` (relative to the current directory) from `source`, a tree, or
+/// from the index when there is none. Files from a tree are staged too; paths the tree does not
+/// have are left as they are. Returns the number of paths that matched.
+pub fn checkout_paths(repo: &Repository, source: Option<&str>, args: &[String]) -> Result<usize> {
+    let work_tree = repo.require_work_tree()?;
+    let pathspec = Pathspec::new(work_tree, &cwd_prefix(repo)?, args)?;
+    let mut index = Index::read(repo)?;
+
+    let files: BTreeMap<Vec<u8>, TreeFile> = match source {
+        Some(tree) => flatten_tree(repo, tree)?
+            .into_iter()
+            .filter(|(path, _)| pathspec.matches(path))
+            .collect(),
+        None => {
+            let mut files = BTreeMap::new();
+            let mut unmerged = Vec::new();
+
+            for entry in index
+                .entries
+                .iter()
+                .filter(|entry| pathspec.matches(&entry.path) && !entry.intent_to_add())
+            {
+                if entry.stage() != 0 {
+                    unmerged.push(format!("path '{}' is unmerged", entry.path_str()));
+                    continue;
+                }
+
+                files.insert(
+                    entry.path.clone(),
+                    TreeFile {
+                        mode: entry.mode,
+                        hash: entry.hash.clone(),
+                    },
+                );
+            }
+
+            unmerged.dedup();
+            if !unmerged.is_empty() {
+                return Err(Error::Failed(unmerged.join("\n")));
+            }
+
+            files
+        }
+    };
+
+    for item in &pathspec.items {
+        if !files.keys().any(|path| item.matches(path)) {
+            return Err(Error::Failed(format!(
+                "pathspec '{}' did not match any file(s) known to git",
+                item.original
+            )));
+        }
+    }
+
+    for (path, file) in &files {
+        if up_to_date(repo, &index, path, file)? {
+            continue;
+        }
+
+        stage_checked_out(repo, &mut index, path, file)?;
+    }
+
+    index.write(repo)?;
+
+    Ok(files.len())
+}
+
+pub(crate) fn tree_files(
+    repo: &Repository,
+    tree: Option<&str>,
+) -> Result<BTreeMap<Vec<u8>, TreeFile>> {
+    match tree {
+        Some(tree) => flatten_tree(repo, tree),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Whether the stage 0 entry of `path` and the file in the work tree are both `file` already.
+fn up_to_date(repo: &Repository, index: &Index, path: &[u8], file: &TreeFile) -> Result<bool> {
+    match index.entry(path, 0) {
+        Some(entry) if staged_as(Some(entry), Some(file)) => {
+            Ok(entry_state(repo, entry)? == WorktreeState::Unchanged)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Writes `file` to the work tree and stages it with the new file's stat data.
+fn stage_checked_out(
+    repo: &Repository,
+    index: &mut Index,
+    path: &[u8],
+    file: &TreeFile,
+) -> Result<()> {
+    let metadata = checkout_file(repo, path, file.mode, &file.hash)?;
+    let mut entry = IndexEntry::from_metadata(path.to_vec(), file.hash.clone(), &metadata);
+
+    // The tree's mode wins over what the file system could represent.
+    entry.mode = file.mode;
+    index.add(entry);
+
+    Ok(())
+}
+
+fn staged_as(entry: Option<&IndexEntry>, file: Option<&TreeFile>) -> bool {
+    match (entry, file) {
+        (None, None) => true,
+        (Some(entry), Some(file)) => {
+            entry.mode == file.mode && entry.hash == file.hash && !entry.intent_to_add()
+        }
+        _ => false,
+    }
+}
+
+/// What `--force` does: every path ends up as in `new`, and files only the index knows about
+/// are deleted.
+fn forced_changes(
+    repo: &Repository,
+    index: &Index,
+    new: &BTreeMap<Vec<u8>, TreeFile>,
+) -> Result<Vec<(Vec<u8>, Option<TreeFile>)>> {
+    let paths: BTreeSet<&Vec<u8>> = new
+        .keys()
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .collect();
+    let mut changes = Vec::new();
+
+    for path in paths {
+        match new.get(path) {
+            Some(file) if up_to_date(repo, index, path, file)? => {}
+            file => changes.push((path.clone(), file.cloned())),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// The paths that differ between `old` and `new`, with what each should become, after making
+/// sure that updating them loses nothing.
+fn careful_changes(
+    repo: &Repository,
+    index: &Index,
+    old: &BTreeMap<Vec<u8>, TreeFile>,
+    new: &BTreeMap<Vec<u8>, TreeFile>,
+    update: TreeUpdate,
+) -> Result<Vec<(Vec<u8>, Option<TreeFile>)>> {
+    if index.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "You need to resolve your current index first: finish or abort the merge, or fix the conflicts and stage them with `pgit add <file>`".to_owned(),
+        ));
+    }
+
+    let paths: BTreeSet<&Vec<u8>> = old
+        .keys()
+        .chain(new.keys())
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .collect();
+    let mut ignore = IgnoreRules::new(repo)?;
+    let mut changes = Vec::new();
+    let mut local_changes = Vec::new();
+    let mut untracked = Vec::new();
+
+    for path in paths {
+        let (old_file, new_file) = (old.get(path), new.get(path));
+        let entry = index.entry(path, 0);
+
+        if old_file == new_file || staged_as(entry, new_file) {
+            continue;
+        }
+
+        if !staged_as(entry, old_file) {
+            local_changes.push(path.clone());
+            continue;
+        }
+
+        match entry {
+            Some(entry) => {
+                if entry_state(repo, entry)? == WorktreeState::Modified {
+                    local_changes.push(path.clone());
+                    continue;
+                }
+            }
+            None => untracked.extend(untracked_obstacles(
+                repo,
+                index,
+                old,
+                &mut ignore,
+                path,
+                new_file.map(|file| file.mode),
+            )?),
+        }
+
+        changes.push((path.clone(), new_file.cloned()));
+    }
+
+    if !local_changes.is_empty() {
+        return Err(local_changes_error(&local_changes, update));
+    }
+
+    if !untracked.is_empty() {
+        return Err(untracked_files_error(&untracked, update));
+    }
+
+    Ok(changes)
+}
+
+/// The untracked, unignored files that writing a new file at `path` would destroy: a file where
+/// one of its directories goes, a file at the path itself, or the files inside a directory there.
+/// Files under that directory count too if they are staged but not part of `old`, since nothing
+/// else will have checked them.
+fn untracked_obstacles(
+    repo: &Repository,
+    index: &Index,
+    old: &BTreeMap<Vec<u8>, TreeFile>,
+    ignore: &mut IgnoreRules,
+    path: &[u8],
+    mode: Option<u32>,
+) -> Result<Vec<Vec<u8>>> {
+    let work_tree = repo.require_work_tree()?;
+    let mut obstacles = Vec::new();
+
+    for (slash, _) in path.iter().enumerate().filter(|(_, b)| **b == b'/') {
+        let dir = &path[..slash];
+        let metadata = fs::symlink_metadata(work_tree.join(path_from_bytes(dir)));
+
+        if metadata.is_ok_and(|metadata| !metadata.is_dir())
+            && index.entries_for(dir).is_empty()
+            && !ignore.is_ignored(dir, false)
+        {
+            obstacles.push(dir.to_vec());
+        }
+    }
+
+    let full_path = work_tree.join(path_from_bytes(path));
+
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() && mode != Some(MODE_GITLINK) => {
+            untracked_inside(&full_path, path, index, old, ignore, &mut obstacles)?;
+        }
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) if !ignore.is_ignored(path, false) => obstacles.push(path.to_vec()),
+        _ => {}
+    }
+
+    Ok(obstacles)
+}
+
+fn untracked_inside(
+    dir: &Path,
+    prefix: &[u8],
+    index: &Index,
+    old: &BTreeMap<Vec<u8>, TreeFile>,
+    ignore: &mut IgnoreRules,
+    found: &mut Vec<Vec<u8>>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let mut path = prefix.to_vec();
+        path.push(b'/');
+        path.extend_from_slice(&path_to_bytes(entry.file_name()));
+
+        if entry.file_type()?.is_dir() {
+            if !ignore.is_ignored(&path, true) {
+                untracked_inside(&entry.path(), &path, index, old, ignore, found)?;
+            }
+        } else if index.entries_for(&path).is_empty() {
+            if !ignore.is_ignored(&path, false) {
+                found.push(path);
+            }
+        } else if !old.contains_key(&path) {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// The refusal to overwrite the local changes to `paths`. Like git, only a refused checkout is a
+/// plain error; the other operations treat it as fatal.
+pub fn local_changes_error(paths: &[Vec<u8>], update: TreeUpdate) -> Error {
+    let message = format!(
+        "Your local changes to the following files would be overwritten by {}:\n{}\nCommit your changes or stash them before {}.",
+        update.name(),
+        list(paths),
+        update.before()
+    );
+
+    match update {
+        TreeUpdate::Checkout => Error::Failed(message),
+        _ => Error::InvalidArgument(message),
+    }
+}
+
+/// The refusal to overwrite untracked files. Like [`local_changes_error`], `checkout` and
+/// `switch` exit with 1 for it, as git does.
+fn untracked_files_error(paths: &[Vec<u8>], update: TreeUpdate) -> Error {
+    let message = format!(
+        "The following untracked working tree files would be overwritten by {}:\n{}\nMove or remove them before {}.",
+        update.name(),
+        list(paths),
+        update.before()
+    );
+
+    match update {
+        TreeUpdate::Checkout => Error::Failed(message),
+        _ => Error::InvalidArgument(message),
+    }
+}
+
+fn list(paths: &[Vec<u8>]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("    {}", String::from_utf8_lossy(path)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{index::MODE_BLOB, structures::ObjectType, test_support::TestRepo};
+
+    /// Writes a tree with a single entry named `name`, bypassing every check on the way.
+    fn raw_tree(test: &TestRepo, mode: &str, name: &str, hash: &str) -> String {
+        let mut body = format!("{mode} {name}\0").into_bytes();
+        body.extend(hex::decode(hash).unwrap());
+
+        test.repo.write_object(&ObjectType::Tree, &body).unwrap()
+    }
+
+    #[test]
+    fn trees_cannot_escape_the_work_tree() {
+        let test = TestRepo::new("checkout-escape");
+        let work_tree = test.repo.require_work_tree().unwrap().to_path_buf();
+        let victim = format!("pgit-escaped-{}", std::process::id());
+        let inner = test.tree(&[(&victim, "pwned\n")]);
+
+        for tree in [
+            raw_tree(&test, "40000", "..", &inner),
+            raw_tree(&test, "40000", ".", &inner),
+            raw_tree(&test, "40000", ".git", &inner),
+            raw_tree(&test, "40000", ".GIT. .", &inner),
+            raw_tree(&test, "40000", "", &inner),
+            raw_tree(&test, "100644", "/etc", &test.blob("pwned\n")),
+        ] {
+            let result = switch_trees(&test.repo, None, Some(&tree), false, TreeUpdate::Checkout);
+
+            assert!(
+                matches!(&result, Err(Error::CorruptObject { reason, .. }) if reason.starts_with("invalid path")),
+                "{result:?}"
+            );
+        }
+
+        assert!(!work_tree.parent().unwrap().join(&victim).exists());
+        assert!(!work_tree.join(".git").join(&victim).exists());
+        assert!(Index::read(&test.repo).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn index_paths_are_checked_before_writing() {
+        let test = TestRepo::new("checkout-index-escape");
+        let blob = test.blob("pwned\n");
+
+        for path in ["../escaped", ".git/hooks/pre-commit", "a//b", "/abs"] {
+            assert!(checkout_file(&test.repo, path.as_bytes(), MODE_BLOB, &blob).is_err());
+        }
+
+        let mut index = Index::default();
+        index.add(IndexEntry::new(b"../escaped".to_vec(), MODE_BLOB, blob, 0));
+        index.write(&test.repo).unwrap();
+
+        assert!(matches!(
+            Index::read(&test.repo),
+            Err(Error::CorruptIndex(reason)) if reason == "invalid path `../escaped`"
+        ));
+    }
+
+    #[test]
+    fn overwriting_untracked_files_fails_with_exit_code_1() {
+        let test = TestRepo::new("checkout-untracked");
+        let work_tree = test.repo.require_work_tree().unwrap().to_path_buf();
+        let tree = test.tree(&[("f", "tracked\n")]);
+
+        std::fs::write(work_tree.join("f"), "untracked\n").unwrap();
+
+        let err =
+            switch_trees(&test.repo, None, Some(&tree), false, TreeUpdate::Checkout).unwrap_err();
+
+        assert!(
+            matches!(&err, Error::Failed(message) if message.contains("untracked working tree files")),
+            "{err:?}"
+        );
+        assert_eq!(err.exit_code(), 1);
+        assert_eq!(
+            std::fs::read_to_string(work_tree.join("f")).unwrap(),
+            "untracked\n"
+        );
+    }
+}
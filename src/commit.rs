@@ -0,0 +1,398 @@
+use std::{
+    env,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    error::{Error, Result},
+    ident::{identity, now, parse_date, parse_identity, Role},
+    index::Index,
+    log::subject,
+    refs::{ref_target, update_ref},
+    repository::Repository,
+    structures::{ObjectType, User},
+    tree::{commit_tree, write_tree},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct CommitOptions {
+    /// Each `-m` paragraph, joined by blank lines.
+    pub messages: Vec<String>,
+    /// Read the message from this file, or from stdin for `-`.
+    pub file: Option<PathBuf>,
+    /// Force (`Some(true)`) or skip (`Some(false)`) the editor. By default it is opened only
+    /// when no message was given.
+    pub edit: Option<bool>,
+    /// Replace the tip of the current branch instead of adding a commit on top of it.
+    pub amend: bool,
+    /// Record a commit even if its tree is the same as its parent's.
+    pub allow_empty: bool,
+    /// Override the author with a `Name <email>` identity.
+    pub author: Option<String>,
+    /// Override the author date.
+    pub date: Option<String>,
+    /// The reflog message, instead of one made from the kind of commit and its subject.
+    pub reflog: Option<String>,
+}
+
+/// What `commit` recorded, displayed the way git summarises a new commit.
+#[derive(Debug, Clone)]
+pub struct CommitSummary {
+    pub hash: String,
+    /// The branch that was advanced, or `None` on a detached `HEAD`.
+    pub branch: Option<String>,
+    pub root: bool,
+    pub subject: String,
+}
+
+impl Display for CommitSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}{} {}] {}",
+            self.branch.as_deref().unwrap_or("detached HEAD"),
+            if self.root { " (root-commit)" } else { "" },
+            &self.hash[..7],
+            self.subject
+        )
+    }
+}
+
+/// Writes the index as a tree, records it in a new commit and moves the ref `HEAD` points to.
+pub fn commit(repo: &Repository, options: &CommitOptions) -> Result<CommitSummary> {
+    let mut index = Index::read(repo)?;
+
+    if index.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "Committing is not possible because you have unmerged files. Fix them up in the work tree, then use `pgit add` to mark them as resolved".to_owned(),
+        ));
+    }
+
+    let head = repo.head()?;
+    let merge_head = read_merge_heads(repo)?;
+    let amended = match (&head, options.amend) {
+        (Some(head), true) => Some(repo.read_object(head)?.meta.unwrap_or_default()),
+        (None, true) => {
+            return Err(Error::InvalidArgument(
+                "You have nothing to amend".to_owned(),
+            ))
+        }
+        (_, false) => None,
+    };
+
+    if amended.is_some() && !merge_head.is_empty() {
+        return Err(Error::InvalidArgument(
+            "You are in the middle of a merge -- cannot amend".to_owned(),
+        ));
+    }
+
+    let mut parents = match &amended {
+        Some(meta) => meta.parents.clone(),
+        None => head.iter().cloned().collect(),
+    };
+    parents.extend(merge_head.iter().cloned());
+
+    let tree = write_tree(repo, &mut index)?;
+    // Persist the cache tree so the next commit can reuse the unchanged directories.
+    index.write(repo)?;
+
+    if !options.allow_empty && amended.is_none() && merge_head.is_empty() {
+        let parent_tree = match parents.first() {
+            Some(parent) => commit_tree(repo, parent)?,
+            None => repo.hash_object(&ObjectType::Tree, &[])?,
+        };
+
+        if parent_tree == tree {
+            return Err(Error::Failed(
+                "nothing to commit (use `pgit add` to stage changes, or --allow-empty to record an empty commit)".to_owned(),
+            ));
+        }
+    }
+
+    // A commit concluding a conflicted cherry-pick keeps the picked commit's author, like git.
+    let picked = match (&amended, read_cherry_pick_head(repo)?) {
+        (None, Some(picked)) => repo.read_object(&picked)?.meta.unwrap_or_default().author,
+        _ => None,
+    };
+    let author = commit_author(
+        repo,
+        options,
+        amended
+            .as_ref()
+            .and_then(|meta| meta.author.clone())
+            .or(picked.clone()),
+    )?;
+    let committer = identity(repo, Role::Committer)?;
+    let message = commit_message(
+        repo,
+        options,
+        amended.as_ref().and_then(|meta| meta.message.clone()),
+    )?;
+
+    let mut body = format!("tree {tree}\n");
+    for parent in &parents {
+        body += &format!("parent {parent}\n");
+    }
+    body += &format!("author {author}\ncommitter {committer}\n\n{message}");
+
+    let hash = repo.write_object(&ObjectType::Commit, body.as_bytes())?;
+
+    let kind = if amended.is_some() {
+        "commit (amend)"
+    } else if !merge_head.is_empty() {
+        "commit (merge)"
+    } else if picked.is_some() {
+        "commit (cherry-pick)"
+    } else if parents.is_empty() {
+        "commit (initial)"
+    } else {
+        "commit"
+    };
+    let reflog = match &options.reflog {
+        Some(reflog) => reflog.clone(),
+        // Like git, the reflog only keeps the first line; `append_reflog` cuts the rest.
+        None => format!("{kind}: {message}"),
+    };
+    update_ref(
+        repo,
+        "HEAD",
+        &hash,
+        head.as_deref().or(Some("")),
+        &reflog,
+        true,
+    )?;
+
+    remove_merge_state(repo)?;
+
+    let target = ref_target(repo, "HEAD")?;
+
+    Ok(CommitSummary {
+        hash,
+        branch: target.strip_prefix("refs/heads/").map(str::to_owned),
+        root: parents.is_empty(),
+        subject: subject(&message),
+    })
+}
+
+/// Forgets about a merge in progress, a squashed merge, or a cherry-pick or revert waiting to be
+/// committed.
+pub fn remove_merge_state(repo: &Repository) -> Result<()> {
+    for file in [
+        "MERGE_HEAD",
+        "MERGE_MSG",
+        "MERGE_MODE",
+        "SQUASH_MSG",
+        "AUTO_MERGE",
+        "CHERRY_PICK_HEAD",
+        "REVERT_HEAD",
+    ] {
+        match fs::remove_file(repo.git_dir().join(file)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The commits named in `MERGE_HEAD`, which become the extra parents of a merge commit.
+fn read_merge_heads(repo: &Repository) -> Result<Vec<String>> {
+    match fs::read_to_string(repo.git_dir().join("MERGE_HEAD")) {
+        Ok(contents) => Ok(contents.split_whitespace().map(str::to_owned).collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The commit a cherry-pick stopped at, left in `CHERRY_PICK_HEAD` until its result is committed.
+fn read_cherry_pick_head(repo: &Repository) -> Result<Option<String>> {
+    match fs::read_to_string(repo.git_dir().join("CHERRY_PICK_HEAD")) {
+        Ok(contents) => Ok(Some(contents.trim().to_owned())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The author: `--author`, else the amended or cherry-picked commit's author, else the
+/// configured identity.
+/// `--date` replaces the time of whichever is used.
+fn commit_author(
+    repo: &Repository,
+    options: &CommitOptions,
+    original: Option<User>,
+) -> Result<User> {
+    let mut author = match (&options.author, original) {
+        (Some(text), _) => {
+            let (time, zone) = match env::var("GIT_AUTHOR_DATE") {
+                Ok(date) => parse_date(&date)?,
+                Err(_) => now(),
+            };
+            parse_identity(text, time, zone)?
+        }
+        (None, Some(original)) => original,
+        (None, None) => identity(repo, Role::Author)?,
+    };
+
+    if let Some(date) = &options.date {
+        (author.time, author.zone) = parse_date(date)?;
+    }
+
+    Ok(author)
+}
+
+/// Collects the message from `-m`, `-F`, the amended commit or what a merge prepared, runs the
+/// editor when asked to, and cleans the result up. An empty message aborts the commit.
+fn commit_message(
+    repo: &Repository,
+    options: &CommitOptions,
+    amended: Option<String>,
+) -> Result<String> {
+    let given = if !options.messages.is_empty() {
+        Some(options.messages.join("\n\n"))
+    } else if let Some(file) = &options.file {
+        let mut text = String::new();
+
+        if file.as_os_str() == "-" {
+            io::stdin().read_to_string(&mut text)?;
+        } else {
+            text = fs::read_to_string(file)?;
+        }
+
+        Some(text)
+    } else {
+        None
+    };
+
+    let edit = options.edit.unwrap_or(given.is_none());
+    let initial = match given {
+        Some(message) => message,
+        None => match amended {
+            Some(message) => message,
+            None => prepared_message(repo)?,
+        },
+    };
+
+    let edit_path = repo.git_dir().join("COMMIT_EDITMSG");
+
+    let message = if edit {
+        let mut text = initial.clone();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text += &editor_template(repo)?;
+
+        fs::write(&edit_path, text)?;
+        launch_editor(repo, &edit_path)?;
+        cleanup_message(&fs::read_to_string(&edit_path)?, true)
+    } else {
+        fs::write(&edit_path, &initial)?;
+        cleanup_message(&initial, false)
+    };
+
+    if message.is_empty() {
+        return Err(Error::InvalidArgument(
+            "Aborting commit due to empty commit message".to_owned(),
+        ));
+    }
+
+    Ok(message)
+}
+
+/// The message a merge left for its commit: `MERGE_MSG`, or `SQUASH_MSG` after a squash.
+fn prepared_message(repo: &Repository) -> Result<String> {
+    for file in ["MERGE_MSG", "SQUASH_MSG"] {
+        match fs::read_to_string(repo.git_dir().join(file)) {
+            Ok(message) => return Ok(message),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(String::new())
+}
+
+fn editor_template(repo: &Repository) -> Result<String> {
+    let target = ref_target(repo, "HEAD")?;
+    let location = match target.strip_prefix("refs/heads/") {
+        Some(branch) => format!("On branch {branch}"),
+        None => "HEAD detached".to_owned(),
+    };
+
+    Ok(format!(
+        "\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n#\n# {location}\n"
+    ))
+}
+
+/// Opens `path` in the user's editor, as configured for commit messages.
+fn launch_editor(repo: &Repository, path: &Path) -> Result<()> {
+    run_editor(&configured_editor(repo), path)
+}
+
+/// The user's editor: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR` and finally `vi`.
+pub fn configured_editor(repo: &Repository) -> String {
+    env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| repo.config().get("core.editor"))
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned())
+}
+
+/// Opens `path` in `editor` and waits for it. Like git, the editor `:` leaves the file untouched.
+pub fn run_editor(editor: &str, path: &Path) -> Result<()> {
+    if editor == ":" {
+        return Ok(());
+    }
+
+    // The editor may carry arguments, so let the shell split it as git does.
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(editor)
+        .arg(path)
+        .status()?;
+
+    if !status.success() {
+        return Err(Error::InvalidArgument(format!(
+            "there was a problem with the editor '{editor}'"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Normalises a commit message the way `git commit --cleanup` does: trailing whitespace is
+/// removed, runs of blank lines collapse into one, leading and trailing blank lines are dropped,
+/// and with `strip_comments` lines starting with `#` are removed.
+pub fn cleanup_message(text: &str, strip_comments: bool) -> String {
+    let mut message = String::new();
+    let mut pending_blank = false;
+
+    for line in text.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            pending_blank = !message.is_empty();
+            continue;
+        }
+
+        if pending_blank {
+            message.push('\n');
+            pending_blank = false;
+        }
+
+        message += line;
+        message.push('\n');
+    }
+
+    message
+}
@@ -1,120 +1,1258 @@
-use crate::utils::{get_git_dir_path, merge_hashmaps};
+use crate::{
+    error::{Error, Result},
+    refs::lock_path,
+    repository::common_dir,
+    utils::{path_to_bytes, wildmatch},
+};
 use home::home_dir;
-use ini::configparser::ini::Ini;
-use std::{collections::HashMap, env, path::Path};
+use std::{
+    env,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug)]
+/// Included files may include others, but not without bound.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConfigLocation {
     Local,
     Global,
     System,
+    /// A file named explicitly, e.g. with `pgit config --file`.
+    Command,
+}
+
+impl Display for ConfigLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigLocation::Local => write!(f, "local"),
+            ConfigLocation::Global => write!(f, "global"),
+            ConfigLocation::System => write!(f, "system"),
+            ConfigLocation::Command => write!(f, "command"),
+        }
+    }
+}
+
+/// A single `key = value` line, remembered along with the file it came from.
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    /// Lowercased section name.
+    pub section: String,
+    /// Subsection names are case-sensitive, so they are kept as written.
+    pub subsection: Option<String>,
+    /// Lowercased variable name.
+    pub key: String,
+    /// `None` for a bare `key` line, which git treats as boolean true.
+    pub value: Option<String>,
+    pub location: ConfigLocation,
+    pub origin: PathBuf,
+}
+
+impl ConfigEntry {
+    /// The canonical `section.subsection.key` name of this entry.
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+
+    fn matches(&self, key: &ConfigKey) -> bool {
+        self.section == key.section && self.subsection == key.subsection && self.key == key.key
+    }
+}
+
+/// A `section[.subsection].key` name split into its parts. The section and key are
+/// case-insensitive; everything between the first and last dot is the subsection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigKey {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+}
+
+impl ConfigKey {
+    pub fn parse(name: &str) -> Option<Self> {
+        let (section, rest) = name.split_once('.')?;
+        let (subsection, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => (Some(subsection.to_owned()), key),
+            None => (None, rest),
+        };
+
+        if section.is_empty() || key.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            section: section.to_ascii_lowercase(),
+            subsection,
+            key: key.to_ascii_lowercase(),
+        })
+    }
+}
+
+/// Every entry of the system, global and local configuration, in the order git reads them, so
+/// later entries override earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// The last value set for `key`. A bare `key` line yields an empty string.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_entry(key)
+            .map(|entry| entry.value.clone().unwrap_or_default())
+    }
+
+    pub fn get_entry(&self, key: &str) -> Option<&ConfigEntry> {
+        let key = ConfigKey::parse(key)?;
+
+        self.entries.iter().rev().find(|entry| entry.matches(&key))
+    }
+
+    /// Every value set for a multi-valued `key`, such as `remote.origin.fetch`, in order.
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        self.get_all_entries(key)
+            .into_iter()
+            .map(|entry| entry.value.clone().unwrap_or_default())
+            .collect()
+    }
+
+    /// Like [`Config::get_all`], but keeps where each value came from.
+    pub fn get_all_entries(&self, key: &str) -> Vec<&ConfigEntry> {
+        let key = match ConfigKey::parse(key) {
+            Some(key) => key,
+            None => return Vec::new(),
+        };
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.matches(&key))
+            .collect()
+    }
+
+    /// Reads `key` as a git boolean, treating a bare `key` line as true.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get_entry(key).map(|entry| match &entry.value {
+            Some(value) => parse_bool(value),
+            None => true,
+        })
+    }
+}
+
+/// Reads a single config file (and whatever it includes) on its own.
+pub fn read_config_file(
+    path: &Path,
+    location: ConfigLocation,
+    git_dir: Option<&Path>,
+) -> Result<Config> {
+    let mut config = Config::default();
+
+    if path.exists() {
+        parse_config_file(path, location, git_dir, 0, &mut config.entries)?;
+    }
+
+    Ok(config)
+}
+
+/// Reads and merges every config file that applies. The repository's own `config` is only read
+/// when `git_dir` is given, so this also works before a repository exists.
+pub fn get_merged_config(git_dir: Option<&Path>) -> Result<Config> {
+    let mut config = Config::default();
+
+    for (path, location) in config_file_paths(git_dir) {
+        if path.exists() {
+            parse_config_file(&path, location, git_dir, 0, &mut config.entries)?;
+        }
+    }
+
+    Ok(config)
+}
+
+/// The config files git reads, lowest precedence first.
+pub fn config_file_paths(git_dir: Option<&Path>) -> Vec<(PathBuf, ConfigLocation)> {
+    let mut paths = Vec::new();
+
+    if let Some(path) = system_config_path() {
+        paths.push((path, ConfigLocation::System));
+    }
+
+    if let Some(home) = home_dir() {
+        let xdg_config = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".config"));
+
+        paths.push((
+            xdg_config.join("git").join("config"),
+            ConfigLocation::Global,
+        ));
+        paths.push((home.join(".gitconfig"), ConfigLocation::Global));
+    }
+
+    // A linked worktree shares the repository's config.
+    if let Some(git_dir) = git_dir {
+        paths.push((common_dir(git_dir).join("config"), ConfigLocation::Local));
+    }
+
+    paths
+}
+
+pub fn system_config_path() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var("ProgramFiles").ok().map(|program_files| {
+            PathBuf::from(program_files)
+                .join("Git")
+                .join("etc")
+                .join("gitconfig")
+        })
+    } else {
+        Some(Path::new("/etc").join("gitconfig"))
+    }
 }
 
-pub fn get_merged_config() -> (
-    HashMap<String, HashMap<String, Option<String>>>,
-    HashMap<String, ConfigLocation>,
-) {
-    let base_git_dir = get_git_dir_path();
-    let local_config_path = base_git_dir.join("config");
-    let global_config_path = home_dir()
-        .unwrap()
-        .join(".config")
+/// The user's global config file: `~/.gitconfig`, unless only the XDG one exists.
+pub fn global_config_path() -> Option<PathBuf> {
+    let home = home_dir()?;
+    let xdg_config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".config"))
         .join("git")
         .join("config");
-    let global_config_path_2 = home_dir().unwrap().join(".gitconfig");
+    let gitconfig = home.join(".gitconfig");
+
+    if !gitconfig.exists() && xdg_config.exists() {
+        Some(xdg_config)
+    } else {
+        Some(gitconfig)
+    }
+}
+
+fn parse_config_file(
+    path: &Path,
+    location: ConfigLocation,
+    git_dir: Option<&Path>,
+    depth: usize,
+    entries: &mut Vec<ConfigEntry>,
+) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(Error::ConfigParse {
+            path: path.to_path_buf(),
+            reason: String::from("includes are nested too deeply"),
+        });
+    }
+
+    let text = fs::read_to_string(path)?;
+    let (parsed, _) = parse_config(&text).map_err(|reason| Error::ConfigParse {
+        path: path.to_path_buf(),
+        reason,
+    })?;
+
+    for parsed_entry in parsed {
+        let entry = ConfigEntry {
+            section: parsed_entry.section,
+            subsection: parsed_entry.subsection,
+            key: parsed_entry.key,
+            value: parsed_entry.value,
+            location,
+            origin: path.to_path_buf(),
+        };
+
+        let include = entry.key == "path"
+            && match (entry.section.as_str(), &entry.subsection) {
+                ("include", None) => true,
+                ("includeif", Some(condition)) => include_condition_holds(condition, path, git_dir),
+                _ => false,
+            };
+        let include_path = if include {
+            entry
+                .value
+                .as_deref()
+                .map(|value| resolve_include(value, path))
+        } else {
+            None
+        };
+
+        entries.push(entry);
+
+        // Missing include files are silently ignored, as git does.
+        if let Some(include_path) = include_path.filter(|include_path| include_path.exists()) {
+            parse_config_file(&include_path, location, git_dir, depth + 1, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Include paths are relative to the file that includes them.
+fn resolve_include(value: &str, including_file: &Path) -> PathBuf {
+    let path = expand_home(value);
 
-    let system_config_path = if cfg!(windows) {
-        Path::new(env::var("ProgramFiles").unwrap().as_str())
-            .join("Git")
-            .join("etc")
-            .join("gitconfig")
+    if path.is_absolute() {
+        path
     } else {
-        Path::new("/etc").join("gitconfig")
+        including_file.parent().unwrap_or(Path::new(".")).join(path)
+    }
+}
+
+fn include_condition_holds(condition: &str, including_file: &Path, git_dir: Option<&Path>) -> bool {
+    if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        return gitdir_matches(pattern, including_file, git_dir, false);
+    }
+
+    if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        return gitdir_matches(pattern, including_file, git_dir, true);
+    }
+
+    if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let branch = git_dir
+            .and_then(|git_dir| fs::read_to_string(git_dir.join("HEAD")).ok())
+            .and_then(|head| {
+                head.trim()
+                    .strip_prefix("ref: refs/heads/")
+                    .map(|branch| branch.to_owned())
+            });
+
+        let mut pattern = pattern.to_owned();
+        if pattern.ends_with('/') {
+            pattern += "**";
+        }
+
+        return branch.is_some_and(|branch| wildmatch(&pattern, branch.as_bytes(), false));
+    }
+
+    false
+}
+
+fn gitdir_matches(
+    pattern: &str,
+    including_file: &Path,
+    git_dir: Option<&Path>,
+    case_insensitive: bool,
+) -> bool {
+    let git_dir = match git_dir {
+        Some(git_dir) => git_dir.canonicalize().unwrap_or(git_dir.to_path_buf()),
+        None => return false,
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+        including_file
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(rest)
+            .to_string_lossy()
+            .to_string()
+    } else {
+        expand_home(pattern).to_string_lossy().to_string()
     };
 
-    let mut config: HashMap<String, HashMap<String, Option<String>>> = HashMap::new();
-    let mut locs: HashMap<String, ConfigLocation> = HashMap::new();
+    if !Path::new(&pattern).is_absolute() {
+        pattern = format!("**/{pattern}");
+    }
+
+    if pattern.ends_with('/') {
+        pattern += "**";
+    }
+
+    wildmatch(&pattern, &path_to_bytes(&git_dir), case_insensitive)
+}
+
+/// A variable line as it appears in a file. Spans are char indices, so files can be edited
+/// without disturbing the surrounding text.
+struct ParsedEntry {
+    section: String,
+    subsection: Option<String>,
+    key: String,
+    value: Option<String>,
+    /// Index of the first character of the variable name.
+    start: usize,
+    /// Index of the newline ending the (last continued) line, or the end of the file.
+    end: usize,
+}
+
+struct ParsedSection {
+    section: String,
+    subsection: Option<String>,
+    /// Index of the opening `[` of the header.
+    header_start: usize,
+    /// Index just past the closing `]` of the header.
+    header_end: usize,
+}
+
+/// Parses git-config syntax into its variables and section headers, in file order.
+fn parse_config(text: &str) -> std::result::Result<(Vec<ParsedEntry>, Vec<ParsedSection>), String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut entries = Vec::<ParsedEntry>::new();
+    let mut sections = Vec::<ParsedSection>::new();
+    let mut section: Option<(String, Option<String>)> = None;
+    let mut cursor = 0;
+    let mut line = 1;
+
+    loop {
+        while cursor < chars.len() && chars[cursor].is_whitespace() {
+            if chars[cursor] == '\n' {
+                line += 1;
+            }
+            cursor += 1;
+        }
+
+        if cursor >= chars.len() {
+            break;
+        }
+
+        match chars[cursor] {
+            '#' | ';' => {
+                while cursor < chars.len() && chars[cursor] != '\n' {
+                    cursor += 1;
+                }
+            }
+            '[' => {
+                let header_start = cursor;
+                cursor += 1;
+                let (name, subsection) = parse_section_header(&chars, &mut cursor, line)?;
+
+                sections.push(ParsedSection {
+                    section: name.clone(),
+                    subsection: subsection.clone(),
+                    header_start,
+                    header_end: cursor,
+                });
+                section = Some((name, subsection));
+            }
+            c if c.is_ascii_alphabetic() => {
+                let start = cursor;
+                while cursor < chars.len()
+                    && (chars[cursor].is_ascii_alphanumeric() || chars[cursor] == '-')
+                {
+                    cursor += 1;
+                }
+                let key: String = chars[start..cursor].iter().collect();
+
+                while cursor < chars.len() && (chars[cursor] == ' ' || chars[cursor] == '\t') {
+                    cursor += 1;
+                }
 
-    if system_config_path.exists() {
-        let mut system_config = Ini::new();
-        match system_config.load(system_config_path.to_str().unwrap()) {
-            Ok(sconfig) => {
-                for (k, v) in &sconfig {
-                    for (k_i, _) in v {
-                        locs.insert(k.to_string() + "." + k_i.as_str(), ConfigLocation::System);
+                let value = match chars.get(cursor) {
+                    Some('=') => {
+                        cursor += 1;
+                        Some(parse_value(&chars, &mut cursor, &mut line)?)
                     }
+                    None | Some('\n') | Some('\r') | Some('#') | Some(';') => None,
+                    Some(c) => return Err(format!("line {line}: unexpected `{c}` after `{key}`")),
+                };
+
+                let (section_name, subsection) = section
+                    .clone()
+                    .ok_or_else(|| format!("line {line}: `{key}` is not inside a section"))?;
+
+                while cursor < chars.len() && chars[cursor] != '\n' {
+                    cursor += 1;
                 }
-                config = merge_hashmaps(config, sconfig);
+
+                entries.push(ParsedEntry {
+                    section: section_name,
+                    subsection,
+                    key: key.to_ascii_lowercase(),
+                    value,
+                    start,
+                    end: cursor,
+                });
             }
-            _ => {}
+            c => return Err(format!("line {line}: unexpected `{c}`")),
         }
     }
 
-    if global_config_path.exists() {
-        let mut global_config = Ini::new();
-        match global_config.load(global_config_path.to_str().unwrap()) {
-            Ok(gconfig) => {
-                for (k, v) in &gconfig {
-                    for (k_i, _) in v {
-                        locs.insert(k.to_string() + "." + k_i.as_str(), ConfigLocation::Global);
+    Ok((entries, sections))
+}
+
+/// Parses `name]`, `name "subsection"]` or the deprecated `name.subsection]` after a `[`.
+fn parse_section_header(
+    chars: &[char],
+    cursor: &mut usize,
+    line: usize,
+) -> std::result::Result<(String, Option<String>), String> {
+    let start = *cursor;
+    while *cursor < chars.len()
+        && (chars[*cursor].is_ascii_alphanumeric()
+            || chars[*cursor] == '-'
+            || chars[*cursor] == '.')
+    {
+        *cursor += 1;
+    }
+    let name: String = chars[start..*cursor].iter().collect();
+
+    if name.is_empty() {
+        return Err(format!("line {line}: empty section name"));
+    }
+
+    match chars.get(*cursor) {
+        Some(']') => {
+            *cursor += 1;
+
+            Ok(match name.split_once('.') {
+                Some((section, subsection)) => (
+                    section.to_ascii_lowercase(),
+                    Some(subsection.to_ascii_lowercase()),
+                ),
+                None => (name.to_ascii_lowercase(), None),
+            })
+        }
+        Some(' ') | Some('\t') => {
+            while *cursor < chars.len() && (chars[*cursor] == ' ' || chars[*cursor] == '\t') {
+                *cursor += 1;
+            }
+
+            if chars.get(*cursor) != Some(&'"') {
+                return Err(format!("line {line}: expected a quoted subsection"));
+            }
+            *cursor += 1;
+
+            let mut subsection = String::new();
+            loop {
+                match chars.get(*cursor) {
+                    Some('"') => break,
+                    Some('\\') => {
+                        *cursor += 1;
+                        match chars.get(*cursor) {
+                            Some('\n') | None => {
+                                return Err(format!("line {line}: unterminated subsection"))
+                            }
+                            Some(c) => subsection.push(*c),
+                        }
                     }
+                    Some('\n') | None => {
+                        return Err(format!("line {line}: unterminated subsection"))
+                    }
+                    Some(c) => subsection.push(*c),
                 }
-                config = merge_hashmaps(config, gconfig);
+                *cursor += 1;
+            }
+            *cursor += 1;
+
+            if chars.get(*cursor) != Some(&']') {
+                return Err(format!("line {line}: expected `]` after subsection"));
             }
-            _ => {}
+            *cursor += 1;
+
+            Ok((name.to_ascii_lowercase(), Some(subsection)))
         }
-    } else if global_config_path_2.exists() {
-        let mut global_config = Ini::new();
-        match global_config.load(global_config_path_2.to_str().unwrap()) {
-            Ok(gconfig) => {
-                for (k, v) in &gconfig {
-                    for (k_i, _) in v {
-                        locs.insert(k.to_string() + "." + k_i.as_str(), ConfigLocation::Global);
+        _ => Err(format!("line {line}: invalid section header")),
+    }
+}
+
+/// Parses a value after `=`: surrounding whitespace is dropped, quotes are removed but keep
+/// their contents verbatim, escapes are decoded and a trailing backslash continues the line.
+fn parse_value(
+    chars: &[char],
+    cursor: &mut usize,
+    line: &mut usize,
+) -> std::result::Result<String, String> {
+    let mut value = String::new();
+    let mut pending_space = String::new();
+    let mut quoted = false;
+
+    while *cursor < chars.len() && (chars[*cursor] == ' ' || chars[*cursor] == '\t') {
+        *cursor += 1;
+    }
+
+    while *cursor < chars.len() {
+        let c = chars[*cursor];
+
+        match c {
+            '\n' if quoted => return Err(format!("line {line}: unterminated quoted value")),
+            '\n' => break,
+            '\r' if chars.get(*cursor + 1) == Some(&'\n') && !quoted => break,
+            '#' | ';' if !quoted => {
+                while *cursor < chars.len() && chars[*cursor] != '\n' {
+                    *cursor += 1;
+                }
+                break;
+            }
+            '\\' => {
+                *cursor += 1;
+                let escaped = match chars.get(*cursor) {
+                    Some('\n') => {
+                        *line += 1;
+                        None
                     }
+                    Some('\r') if chars.get(*cursor + 1) == Some(&'\n') => {
+                        *cursor += 1;
+                        *line += 1;
+                        None
+                    }
+                    Some('n') => Some('\n'),
+                    Some('t') => Some('\t'),
+                    Some('b') => Some('\u{8}'),
+                    Some('"') => Some('"'),
+                    Some('\\') => Some('\\'),
+                    _ => return Err(format!("line {line}: invalid escape in value")),
+                };
+
+                if let Some(escaped) = escaped {
+                    value += &pending_space;
+                    pending_space.clear();
+                    value.push(escaped);
                 }
-                config = merge_hashmaps(config, gconfig);
             }
-            _ => {}
+            '"' => {
+                value += &pending_space;
+                pending_space.clear();
+                quoted = !quoted;
+            }
+            ' ' | '\t' if !quoted => pending_space.push(c),
+            _ => {
+                value += &pending_space;
+                pending_space.clear();
+                value.push(c);
+            }
         }
+
+        *cursor += 1;
     }
 
-    if local_config_path.exists() {
-        let mut local_config = Ini::new();
-        match local_config.load(local_config_path.to_str().unwrap()) {
-            Ok(lconfig) => {
-                for (k, v) in &lconfig {
-                    for (k_i, _) in v {
-                        locs.insert(k.to_string() + "." + k_i.as_str(), ConfigLocation::Local);
-                    }
+    if quoted {
+        return Err(format!("line {line}: unterminated quoted value"));
+    }
+
+    Ok(value)
+}
+
+/// Whether a git boolean value (`true`/`yes`/`on`/`1`, case-insensitively) is set.
+pub fn parse_bool(value: &str) -> bool {
+    parse_bool_strict(value).unwrap_or(false)
+}
+
+/// Parses a git boolean, rejecting anything that is neither a true nor a false spelling.
+pub fn parse_bool_strict(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a git integer, which may carry a `k`, `m` or `g` suffix.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Expands a leading `~/` the way git does for `--type=path` values.
+pub fn parse_path(value: &str) -> PathBuf {
+    expand_home(value)
+}
+
+/// Converts a git color such as `bold red #ffffff` into the ANSI escape sequence git would emit.
+pub fn parse_color(value: &str) -> Option<String> {
+    let mut codes = Vec::<String>::new();
+    let mut colors_seen = 0;
+
+    for word in value.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+
+        let attribute = match word.as_str() {
+            "bold" => Some(1),
+            "dim" => Some(2),
+            "italic" => Some(3),
+            "ul" | "underline" => Some(4),
+            "blink" => Some(5),
+            "reverse" => Some(7),
+            "strike" => Some(9),
+            "nobold" | "no-bold" => Some(22),
+            "nodim" | "no-dim" => Some(22),
+            "noitalic" | "no-italic" => Some(23),
+            "noul" | "no-ul" => Some(24),
+            "noblink" | "no-blink" => Some(25),
+            "noreverse" | "no-reverse" => Some(27),
+            "nostrike" | "no-strike" => Some(29),
+            _ => None,
+        };
+
+        if let Some(attribute) = attribute {
+            codes.push(attribute.to_string());
+            continue;
+        }
+
+        if word == "reset" {
+            codes.push(String::new());
+            continue;
+        }
+
+        // The first color is the foreground, the second the background.
+        let base = if colors_seen == 0 { 30 } else { 40 };
+        colors_seen += 1;
+
+        if colors_seen > 2 {
+            return None;
+        }
+
+        let names = [
+            "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+        ];
+
+        let code = if word == "normal" {
+            continue;
+        } else if word == "default" {
+            (base + 9).to_string()
+        } else if let Some(i) = names.iter().position(|name| *name == word) {
+            (base + i).to_string()
+        } else if let Some(i) = word
+            .strip_prefix("bright")
+            .and_then(|name| names.iter().position(|n| *n == name))
+        {
+            (base + 60 + i).to_string()
+        } else if let Some(hex) = word.strip_prefix('#').filter(|hex| hex.len() == 6) {
+            let rgb = u32::from_str_radix(hex, 16).ok()?;
+            format!(
+                "{};2;{};{};{}",
+                base + 8,
+                (rgb >> 16) & 0xff,
+                (rgb >> 8) & 0xff,
+                rgb & 0xff
+            )
+        } else if let Ok(n) = word.parse::<u8>() {
+            format!("{};5;{}", base + 8, n)
+        } else {
+            return None;
+        };
+
+        codes.push(code);
+    }
+
+    Some(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// Quotes and escapes `value` so that it reads back unchanged.
+fn format_value(value: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped += "\\\\",
+            '"' => escaped += "\\\"",
+            '\n' => escaped += "\\n",
+            '\t' => escaped += "\\t",
+            c => escaped.push(c),
+        }
+    }
+
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+
+    if needs_quotes {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+fn format_section_header(section: &str, subsection: &Option<String>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]", section),
+    }
+}
+
+/// Splits a user-supplied key, keeping the variable name's case for writing it out.
+fn split_key_for_write(name: &str) -> Result<(ConfigKey, String)> {
+    let key = ConfigKey::parse(name).ok_or_else(|| {
+        Error::InvalidArgument(format!("key `{name}` does not contain a section"))
+    })?;
+    let written_key = name.rsplit('.').next().unwrap_or_default().to_owned();
+
+    let valid_section = key
+        .section
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_key = written_key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && written_key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+
+    if !valid_section || !valid_key {
+        return Err(Error::InvalidArgument(format!("invalid key `{name}`")));
+    }
+
+    Ok((key, written_key))
+}
+
+fn parse_for_edit(path: &Path) -> Result<(Vec<char>, Vec<ParsedEntry>, Vec<ParsedSection>)> {
+    let text = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+
+    let (entries, sections) = parse_config(&text).map_err(|reason| Error::ConfigParse {
+        path: path.to_path_buf(),
+        reason,
+    })?;
+
+    Ok((text.chars().collect(), entries, sections))
+}
+
+/// Replaces the file through a `.lock` file so a crash never leaves it half written. The lock is
+/// created exclusively, so a file another process is writing is refused rather than clobbered,
+/// and only a lock this call created is removed when it fails.
+fn write_config_file(path: &Path, chars: &[char]) -> Result<()> {
+    let lock_path = lock_path(path);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut lock = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+    {
+        Ok(lock) => lock,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(Error::InvalidArgument(format!(
+                "could not lock config file {}: File exists",
+                path.display()
+            )))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let written = lock
+        .write_all(chars.iter().collect::<String>().as_bytes())
+        .and_then(|()| fs::rename(&lock_path, path));
+
+    if let Err(err) = written {
+        let _ = fs::remove_file(&lock_path);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+/// Sets `name` to `value` in the config file at `path`, leaving every other line untouched. An
+/// existing single value is replaced in place; `add` appends another value instead.
+pub fn set_config_value(path: &Path, name: &str, value: &str, add: bool) -> Result<()> {
+    let (key, written_key) = split_key_for_write(name)?;
+    let (mut chars, entries, sections) = parse_for_edit(path)?;
+    let line = format!("{} = {}", written_key, format_value(value));
+
+    let matching: Vec<&ParsedEntry> = entries
+        .iter()
+        .filter(|entry| {
+            entry.section == key.section
+                && entry.subsection == key.subsection
+                && entry.key == key.key
+        })
+        .collect();
+
+    if !add && matching.len() > 1 {
+        return Err(Error::InvalidArgument(format!(
+            "`{name}` has multiple values; use `--add` or unset it with `--all` first"
+        )));
+    }
+
+    if let (false, Some(existing)) = (add, matching.first()) {
+        chars.splice(existing.start..existing.end, line.chars());
+        return write_config_file(path, &chars);
+    }
+
+    let section = sections
+        .iter()
+        .rev()
+        .find(|section| section.section == key.section && section.subsection == key.subsection);
+
+    match section {
+        Some(section) => {
+            // Insert after the last variable of that section, or right after its header.
+            let last_in_section = entries
+                .iter()
+                .filter(|entry| entry.start > section.header_end)
+                .take_while(|entry| {
+                    !sections.iter().any(|other| {
+                        other.header_end > section.header_end && other.header_end < entry.start
+                    })
+                })
+                .last();
+
+            let mut at = match last_in_section {
+                Some(entry) => entry.end,
+                None => section.header_end,
+            };
+            while at < chars.len() && chars[at] != '\n' {
+                at += 1;
+            }
+
+            let insertion = if at < chars.len() {
+                at += 1;
+                format!("\t{}\n", line)
+            } else {
+                format!("\n\t{}\n", line)
+            };
+
+            chars.splice(at..at, insertion.chars());
+        }
+        None => {
+            if !chars.is_empty() && chars.last() != Some(&'\n') {
+                chars.push('\n');
+            }
+
+            let block = format!(
+                "{}\n\t{}\n",
+                format_section_header(&key.section, &key.subsection),
+                line
+            );
+            chars.extend(block.chars());
+        }
+    }
+
+    write_config_file(path, &chars)
+}
+
+/// Removes `name` from the config file at `path`, returning how many lines were removed. With
+/// several values present, `all` must be set.
+pub fn unset_config_value(path: &Path, name: &str, all: bool) -> Result<usize> {
+    let key = ConfigKey::parse(name).ok_or_else(|| {
+        Error::InvalidArgument(format!("key `{name}` does not contain a section"))
+    })?;
+    let (mut chars, entries, _) = parse_for_edit(path)?;
+
+    let matching: Vec<&ParsedEntry> = entries
+        .iter()
+        .filter(|entry| {
+            entry.section == key.section
+                && entry.subsection == key.subsection
+                && entry.key == key.key
+        })
+        .collect();
+
+    if matching.len() > 1 && !all {
+        return Err(Error::InvalidArgument(format!(
+            "`{name}` has multiple values; use `--all` to remove them all"
+        )));
+    }
+
+    // Remove from the back so earlier spans stay valid.
+    for entry in matching.iter().rev() {
+        let mut start = entry.start;
+        while start > 0 && (chars[start - 1] == ' ' || chars[start - 1] == '\t') {
+            start -= 1;
+        }
+
+        let owns_line = start == 0 || chars[start - 1] == '\n';
+        let (start, end) = if owns_line {
+            (start, (entry.end + 1).min(chars.len()))
+        } else {
+            (entry.start, entry.end)
+        };
+
+        chars.drain(start..end);
+    }
+
+    if !matching.is_empty() {
+        write_config_file(path, &chars)?;
+    }
+
+    Ok(matching.len())
+}
+
+/// Renames every `[section "subsection"]` block named `old` (e.g. `branch.topic`) in the config
+/// file at `path` to `new`, or removes those blocks with all their variables when `new` is
+/// `None`. Returns whether any block was found.
+pub fn rename_config_section(path: &Path, old: &str, new: Option<&str>) -> Result<bool> {
+    let split = |name: &str| match name.split_once('.') {
+        Some((section, subsection)) => (section.to_ascii_lowercase(), Some(subsection.to_owned())),
+        None => (name.to_ascii_lowercase(), None),
+    };
+    let (section, subsection) = split(old);
+    let (mut chars, _, sections) = parse_for_edit(path)?;
+    let mut found = false;
+
+    // Edit from the back so earlier spans stay valid.
+    for (i, parsed) in sections.iter().enumerate().rev() {
+        if parsed.section != section || parsed.subsection != subsection {
+            continue;
+        }
+        found = true;
+
+        match new {
+            Some(new) => {
+                let (section, subsection) = split(new);
+                let header = format_section_header(&section, &subsection);
+                chars.splice(parsed.header_start..parsed.header_end, header.chars());
+            }
+            None => {
+                // The block runs up to the line holding the next header.
+                let mut end = sections
+                    .get(i + 1)
+                    .map_or(chars.len(), |next| next.header_start);
+                while end > parsed.header_end && chars[end - 1] != '\n' {
+                    end -= 1;
+                }
+                if end == parsed.header_end {
+                    end = sections
+                        .get(i + 1)
+                        .map_or(chars.len(), |next| next.header_start);
+                }
+
+                let mut start = parsed.header_start;
+                while start > 0 && (chars[start - 1] == ' ' || chars[start - 1] == '\t') {
+                    start -= 1;
                 }
-                config = merge_hashmaps(config, lconfig);
+
+                chars.drain(start..end);
             }
-            _ => {}
         }
     }
 
-    return (config, locs);
+    if found {
+        write_config_file(path, &chars)?;
+    }
+
+    Ok(found)
 }
 
-pub fn get_config_value(mut key: String) -> Option<String> {
-    key += ".";
-    let config = get_merged_config().0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        let (parsed, _) = parse_config(text).unwrap();
+
+        Config {
+            entries: parsed
+                .into_iter()
+                .map(|entry| ConfigEntry {
+                    section: entry.section,
+                    subsection: entry.subsection,
+                    key: entry.key,
+                    value: entry.value,
+                    location: ConfigLocation::Local,
+                    origin: PathBuf::from("config"),
+                })
+                .collect(),
+        }
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pgit-config-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn quotes_keep_their_contents_verbatim() {
+        let config = parse(
+            "[s]\n\ta = \"  padded ; not a comment \"\n\tb = one \"two  three\"\n\tc = \"\"\n",
+        );
+
+        assert_eq!(config.get("s.a").unwrap(), "  padded ; not a comment ");
+        assert_eq!(config.get("s.b").unwrap(), "one two  three");
+        assert_eq!(config.get("s.c").unwrap(), "");
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        let config = parse("[s]\n\ta = tab\\there\\nnew \\\"q\\\" back\\\\slash\n");
+
+        assert_eq!(
+            config.get("s.a").unwrap(),
+            "tab\there\nnew \"q\" back\\slash"
+        );
+        assert!(parse_config("[s]\n\ta = \\x\n").is_err());
+    }
+
+    #[test]
+    fn whitespace_is_trimmed_but_kept_inside_values() {
+        let config = parse("[s]\n\ta =    spaced   out   \n\tb=tight\n");
 
-    let config_vec: Vec<&str> = key.split(".").collect();
+        assert_eq!(config.get("s.a").unwrap(), "spaced   out");
+        assert_eq!(config.get("s.b").unwrap(), "tight");
+    }
 
-    let section = config
-        .get(config_vec[0])
-        .unwrap_or(&HashMap::new())
-        .to_owned();
-    let value = section
-        .get(config_vec[1])
-        .unwrap_or(&Some(String::from("")))
-        .to_owned()
-        .unwrap_or(String::from(""));
+    #[test]
+    fn backslash_newline_continues_the_value() {
+        let config = parse("[s]\n\ta = first \\\n  second\n\tb = after\n");
 
-    if value.is_empty() {
-        return None;
+        // Like git, the whitespace on both sides of the continuation is kept.
+        assert_eq!(config.get("s.a").unwrap(), "first   second");
+        assert_eq!(config.get("s.b").unwrap(), "after");
+
+        let crlf = parse("[s]\r\n\ta = one\\\r\ntwo\r\n");
+        assert_eq!(crlf.get("s.a").unwrap(), "onetwo");
     }
 
-    return Some(value);
+    #[test]
+    fn comments_are_skipped() {
+        let config = parse(
+            "# leading comment\n; another\n[s] # after a header\n\ta = value # trailing\n\tb = value ; trailing\n\t# c = commented out\n",
+        );
+
+        assert_eq!(config.get("s.a").unwrap(), "value");
+        assert_eq!(config.get("s.b").unwrap(), "value");
+        assert_eq!(config.get("s.c"), None);
+    }
+
+    #[test]
+    fn bare_keys_are_true() {
+        let config = parse("[core]\n\tbare\n\tfilemode = false\n");
+
+        assert_eq!(config.get_entry("core.bare").unwrap().value, None);
+        assert_eq!(config.get_bool("core.bare"), Some(true));
+        assert_eq!(config.get_bool("core.filemode"), Some(false));
+        assert_eq!(config.get("core.bare").unwrap(), "");
+    }
+
+    #[test]
+    fn sections_and_keys_ignore_case_but_subsections_do_not() {
+        let config = parse("[Remote \"Origin\"]\n\tURL = x\n[Branch.Main]\n\tMerge = y\n");
+
+        assert_eq!(config.get("remote.Origin.url").unwrap(), "x");
+        assert_eq!(config.get("REMOTE.Origin.URL").unwrap(), "x");
+        assert_eq!(config.get("remote.origin.url"), None);
+
+        // The deprecated dotted form lowercases the subsection too.
+        assert_eq!(config.get("branch.main.merge").unwrap(), "y");
+        assert_eq!(config.entries[1].name(), "branch.main.merge");
+    }
+
+    #[test]
+    fn quoted_subsections_unescape() {
+        let config = parse("[s \"a \\\"quoted\\\" \\\\ name\"]\n\tk = v\n");
+
+        assert_eq!(
+            config.entries[0].subsection.as_deref(),
+            Some("a \"quoted\" \\ name")
+        );
+        assert!(parse_config("[s \"open]\n").is_err());
+    }
+
+    #[test]
+    fn subsections_may_contain_dots() {
+        let config = parse("[url \"https://example.com/a.git\"]\n\tinsteadOf = ex:\n");
+
+        assert_eq!(
+            config
+                .get("url.https://example.com/a.git.insteadof")
+                .unwrap(),
+            "ex:"
+        );
+    }
+
+    #[test]
+    fn later_values_win_and_all_are_kept() {
+        let config =
+            parse("[remote \"o\"]\n\tfetch = a\n\tfetch = b\n[remote \"o\"]\n\tfetch = c\n");
+
+        assert_eq!(config.get("remote.o.fetch").unwrap(), "c");
+        assert_eq!(config.get_all("remote.o.fetch"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(parse_config("key = outside\n").is_err());
+        assert!(parse_config("[s]\n\ta = \"unterminated\n").is_err());
+        assert!(parse_config("[]\n").is_err());
+        assert!(parse_config("[s]\n\t= value\n").is_err());
+        assert!(parse_config("[s]\n\ta b\n").is_err());
+    }
+
+    #[test]
+    fn includes_are_read_in_place() {
+        let dir = scratch_dir("include");
+        fs::write(dir.join("extra"), "[s]\n\tb = included\n\ta = override\n").unwrap();
+        fs::write(
+            dir.join("config"),
+            "[s]\n\ta = main\n[include]\n\tpath = extra\n\tpath = missing\n[s]\n\tc = last\n",
+        )
+        .unwrap();
+
+        let config = read_config_file(&dir.join("config"), ConfigLocation::Local, None).unwrap();
+
+        assert_eq!(config.get("s.a").unwrap(), "override");
+        assert_eq!(config.get("s.b").unwrap(), "included");
+        assert_eq!(config.get_entry("s.b").unwrap().origin, dir.join("extra"));
+        assert_eq!(config.get("s.c").unwrap(), "last");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_an_error() {
+        let dir = scratch_dir("cycle");
+        fs::write(dir.join("config"), "[include]\n\tpath = config\n").unwrap();
+
+        assert!(read_config_file(&dir.join("config"), ConfigLocation::Local, None).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn values_convert_like_git() {
+        assert!(parse_bool("Yes"));
+        assert!(!parse_bool("off"));
+        assert_eq!(parse_bool_strict("maybe"), None);
+        assert_eq!(parse_int("2k"), Some(2048));
+        assert_eq!(parse_int("1M"), Some(1024 * 1024));
+        assert_eq!(parse_int("x"), None);
+    }
+
+    #[test]
+    fn written_values_read_back_unchanged() {
+        for value in [
+            "plain",
+            " padded ",
+            "a # b",
+            "quote \" and \\ back",
+            "tab\tline\n",
+        ] {
+            let config = parse(&format!("[s]\n\ta = {}\n", format_value(value)));
+
+            assert_eq!(config.get("s.a").unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn writing_refuses_a_held_lock() {
+        let dir = scratch_dir("lock");
+        let path = dir.join("config");
+        let lock = dir.join("config.lock");
+        fs::write(&path, "[s]\n\ta = 1\n").unwrap();
+        fs::write(&lock, "held").unwrap();
+
+        let result = set_config_value(&path, "s.a", "2", false);
+        assert!(matches!(
+            result,
+            Err(Error::InvalidArgument(message)) if message.starts_with("could not lock config file")
+        ));
+        assert_eq!(fs::read_to_string(&lock).unwrap(), "held");
+
+        fs::remove_file(&lock).unwrap();
+        set_config_value(&path, "s.a", "2", false).unwrap();
+        assert!(!lock.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[s]\n\ta = 2\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
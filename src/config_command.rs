@@ -0,0 +1,227 @@
+use std::{env, path::PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{
+    config::{
+        get_merged_config, global_config_path, parse_bool_strict, parse_color, parse_int,
+        parse_path, read_config_file, set_config_value, system_config_path, unset_config_value,
+        Config, ConfigEntry, ConfigLocation,
+    },
+    error::{Error, Result},
+    repository::Repository,
+};
+
+/// How `--type` asks values to be interpreted before they are printed or stored.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ConfigType {
+    Bool,
+    Int,
+    Path,
+    Color,
+}
+
+/// Which config files a `pgit config` invocation reads from or writes to.
+#[derive(Debug, Clone)]
+pub enum ConfigScope {
+    /// Every file git reads, merged. Writes go to the repository's own config.
+    Merged,
+    Local,
+    Global,
+    System,
+    File(PathBuf),
+}
+
+/// Controls how looked up values are rendered.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOutput {
+    pub show_origin: bool,
+    pub show_scope: bool,
+    pub value_type: Option<ConfigType>,
+}
+
+/// The single file a scope refers to, which is also the file that gets edited.
+fn scope_path(repo: Option<&Repository>, scope: &ConfigScope) -> Result<(PathBuf, ConfigLocation)> {
+    match scope {
+        ConfigScope::Merged | ConfigScope::Local => match repo {
+            Some(repo) => Ok((repo.config_path(), ConfigLocation::Local)),
+            None => Err(Error::NotARepository(env::current_dir()?)),
+        },
+        ConfigScope::Global => global_config_path()
+            .map(|path| (path, ConfigLocation::Global))
+            .ok_or_else(|| Error::InvalidArgument("could not find the home directory".to_owned())),
+        ConfigScope::System => system_config_path()
+            .map(|path| (path, ConfigLocation::System))
+            .ok_or_else(|| {
+                Error::InvalidArgument("could not find the system config file".to_owned())
+            }),
+        ConfigScope::File(path) => Ok((path.clone(), ConfigLocation::Command)),
+    }
+}
+
+fn read_scope(repo: Option<&Repository>, scope: &ConfigScope) -> Result<Config> {
+    let git_dir = repo.map(|repo| repo.git_dir());
+
+    match scope {
+        ConfigScope::Merged => match repo {
+            Some(repo) => Ok(repo.config().clone()),
+            None => get_merged_config(None),
+        },
+        _ => {
+            let (path, location) = scope_path(repo, scope)?;
+            read_config_file(&path, location, git_dir)
+        }
+    }
+}
+
+/// Interprets a raw value according to `--type`, the way `git config --type` canonicalizes it.
+fn convert_value(
+    name: &str,
+    value: Option<&str>,
+    value_type: &Option<ConfigType>,
+) -> std::result::Result<String, String> {
+    let Some(value_type) = value_type else {
+        return Ok(value.unwrap_or_default().to_owned());
+    };
+
+    match value_type {
+        // A key with no `=` at all is git's shorthand for true.
+        ConfigType::Bool => match value.map(parse_bool_strict) {
+            None => Ok("true".to_owned()),
+            Some(Some(value)) => Ok(value.to_string()),
+            Some(None) => Err(format!(
+                "bad boolean value `{}` for `{name}`",
+                value.unwrap_or_default()
+            )),
+        },
+        ConfigType::Int => parse_int(value.unwrap_or_default())
+            .map(|value| value.to_string())
+            .ok_or_else(|| {
+                format!(
+                    "bad numeric value `{}` for `{name}`",
+                    value.unwrap_or_default()
+                )
+            }),
+        ConfigType::Path => Ok(parse_path(value.unwrap_or_default()).display().to_string()),
+        ConfigType::Color => parse_color(value.unwrap_or_default()).ok_or_else(|| {
+            format!(
+                "invalid color value `{}` for `{name}`",
+                value.unwrap_or_default()
+            )
+        }),
+    }
+}
+
+fn format_entry(entry: &ConfigEntry, output: &ConfigOutput, with_name: bool) -> Result<String> {
+    let name = entry.name();
+    let value =
+        convert_value(&name, entry.value.as_deref(), &output.value_type).map_err(|reason| {
+            Error::ConfigParse {
+                path: entry.origin.clone(),
+                reason,
+            }
+        })?;
+
+    let mut line = String::new();
+
+    if output.show_scope {
+        line += &format!("{}\t", entry.location);
+    }
+
+    if output.show_origin {
+        line += &format!("file:{}\t", entry.origin.display());
+    }
+
+    if with_name {
+        // `list` prints valueless keys on their own, with no `=`.
+        match (&entry.value, &output.value_type) {
+            (None, None) => line += &name,
+            _ => line += &format!("{name}={value}"),
+        }
+    } else {
+        line += &value;
+    }
+
+    Ok(line)
+}
+
+/// Looks up the last value of `name`, or `None` if it is not set in `scope`.
+pub fn config_get(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    output: &ConfigOutput,
+) -> Result<Option<String>> {
+    let config = read_scope(repo, scope)?;
+
+    config
+        .get_entry(name)
+        .map(|entry| format_entry(entry, output, false))
+        .transpose()
+}
+
+/// Looks up every value of a multi-valued `name`, in the order git reads them.
+pub fn config_get_all(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    output: &ConfigOutput,
+) -> Result<Vec<String>> {
+    let config = read_scope(repo, scope)?;
+
+    config
+        .get_all_entries(name)
+        .into_iter()
+        .map(|entry| format_entry(entry, output, false))
+        .collect()
+}
+
+/// Lists every variable in `scope` as `name=value`.
+pub fn config_list(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    output: &ConfigOutput,
+) -> Result<Vec<String>> {
+    let config = read_scope(repo, scope)?;
+
+    config
+        .entries
+        .iter()
+        .map(|entry| format_entry(entry, output, true))
+        .collect()
+}
+
+/// Sets `name` in the file `scope` refers to, or adds another value with `add`. Values are
+/// canonicalized through `--type` first, so `--type=bool` turns `yes` into `true`.
+pub fn config_set(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    value: &str,
+    add: bool,
+    value_type: &Option<ConfigType>,
+) -> Result<()> {
+    let (path, _) = scope_path(repo, scope)?;
+    let value = match value_type {
+        // Colors are stored as written; only reading turns them into escape codes.
+        Some(ConfigType::Color) | Some(ConfigType::Path) => {
+            convert_value(name, Some(value), value_type).map_err(Error::InvalidArgument)?;
+            value.to_owned()
+        }
+        _ => convert_value(name, Some(value), value_type).map_err(Error::InvalidArgument)?,
+    };
+
+    set_config_value(&path, name, &value, add)
+}
+
+/// Removes `name` from the file `scope` refers to, returning how many values were removed.
+pub fn config_unset(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    all: bool,
+) -> Result<usize> {
+    let (path, _) = scope_path(repo, scope)?;
+
+    unset_config_value(&path, name, all)
+}
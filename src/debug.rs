@@ -1,12 +1,14 @@
 use clap::ArgMatches;
 
-use crate::structures::Object;
+use crate::{error::Result, repository::Repository, revision::resolve_revision};
 
-pub fn debug(args: ArgMatches) {
+pub fn debug(repo: &Repository, args: ArgMatches) -> Result<()> {
     let arg = args.get_one::<String>("arg").unwrap().to_owned();
     println!("{:#?}", arg);
 
-    let object = Object::new(arg);
+    let object = repo.read_object(&resolve_revision(repo, &arg)?)?;
 
-    println!("{:#?}", object.get_contents());
+    println!("{:#?}", object.get_contents(repo)?);
+
+    Ok(())
 }
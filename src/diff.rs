@@ -0,0 +1,1830 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, Result},
+    repository::Repository,
+};
+
+/// One step of a line diff, referring to line indexes in the old and new text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// A line of a hunk. Lines keep their trailing newline, if they have one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine<'a> {
+    Context(&'a [u8]),
+    Removed(&'a [u8]),
+    Added(&'a [u8]),
+}
+
+/// A run of changes with surrounding context, as shown between `@@` headers.
+#[derive(Debug, Clone)]
+pub struct Hunk<'a> {
+    /// 1-based first old line, or the line before the hunk when `old_len` is 0.
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine<'a>>,
+}
+
+impl Hunk<'_> {
+    /// The `@@ -a,b +c,d @@` line, using git's shorthand for single-line ranges.
+    pub fn header(&self) -> String {
+        let range = |start: usize, len: usize| {
+            if len == 1 {
+                start.to_string()
+            } else {
+                format!("{start},{len}")
+            }
+        };
+
+        format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len),
+            range(self.new_start, self.new_len)
+        )
+    }
+
+    /// Index of the first old line the hunk covers.
+    fn old_offset(&self) -> usize {
+        if self.old_len == 0 {
+            self.old_start
+        } else {
+            self.old_start - 1
+        }
+    }
+}
+
+/// Splits `data` into lines, each keeping its `\n`. A final line without one is kept as is.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// Whether git would treat `data` as binary: it has a NUL in its first 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|b| *b == 0)
+}
+
+/// The line matching algorithm behind a diff, as chosen with `--diff-algorithm`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiffAlgorithm {
+    /// Myers' algorithm, with xdiff's shortcuts for expensive inputs.
+    #[default]
+    Myers,
+    /// Myers' algorithm without the shortcuts, so the edit script is always a shortest one.
+    Minimal,
+    /// git's histogram diff, which anchors the diff on the rarest lines both sides share.
+    Histogram,
+}
+
+impl DiffAlgorithm {
+    /// Parses a `--diff-algorithm` or `diff.algorithm` value.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "myers" | "default" => Ok(Self::Myers),
+            "minimal" => Ok(Self::Minimal),
+            "histogram" => Ok(Self::Histogram),
+            _ => Err(Error::InvalidArgument(format!(
+                "unsupported diff algorithm `{name}`: use myers, minimal or histogram"
+            ))),
+        }
+    }
+
+    /// The algorithm configured with `diff.algorithm`, or Myers.
+    pub fn configured(repo: &Repository) -> Result<Self> {
+        repo.config()
+            .get("diff.algorithm")
+            .map_or(Ok(Self::default()), |name| Self::parse(&name))
+    }
+}
+
+/// Computes the edit script from `old` to `new` the way git does: the lines are matched with
+/// `algorithm`, then runs of changes are slid to where they read best (see [`compact`]).
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: DiffAlgorithm) -> Vec<Edit> {
+    edit_script(old, new, algorithm, true)
+}
+
+/// The edit script between two lists of words for `--word-diff`, which git always computes with
+/// Myers and without the indent heuristic.
+pub fn diff_words(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    edit_script(old, new, DiffAlgorithm::Myers, false)
+}
+
+fn edit_script<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    algorithm: DiffAlgorithm,
+    indent_heuristic: bool,
+) -> Vec<Edit> {
+    // Lines are compared by equivalence class, so each line is hashed only once.
+    let mut classes = HashMap::<&[u8], usize>::new();
+    let mut classify = |lines: &[&'a [u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = classes.len();
+                *classes.entry(line).or_insert(next)
+            })
+            .collect()
+    };
+
+    let mut file1 = DiffFile::new(old, classify(old));
+    let mut file2 = DiffFile::new(new, classify(new));
+    let (n1, n2) = (old.len(), new.len());
+
+    match algorithm {
+        DiffAlgorithm::Myers | DiffAlgorithm::Minimal => myers(
+            &file1.classes,
+            &file2.classes,
+            &mut file1.changed[1..=n1],
+            &mut file2.changed[1..=n2],
+            algorithm == DiffAlgorithm::Minimal,
+        ),
+        // Unlike Myers, xdiff runs histogram on the whole files, common ends included.
+        DiffAlgorithm::Histogram => Histogram {
+            classes1: &file1.classes,
+            classes2: &file2.classes,
+            changed1: &mut file1.changed[1..=n1],
+            changed2: &mut file2.changed[1..=n2],
+        }
+        .diff(1, n1, 1, n2),
+    }
+
+    compact(&mut file1, &mut file2, indent_heuristic);
+    compact(&mut file2, &mut file1, indent_heuristic);
+
+    let mut edits = Vec::with_capacity(n1.max(n2));
+    let (mut i, mut j) = (0, 0);
+
+    while i < n1 || j < n2 {
+        if i < n1 && j < n2 && !file1.changed[i + 1] && !file2.changed[j + 1] {
+            edits.push(Edit::Equal(i, j));
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let before = (i, j);
+
+        while i < n1 && file1.changed[i + 1] {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        }
+        while j < n2 && file2.changed[j + 1] {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        }
+
+        // The unchanged lines of both sides always pair up; this only guards against looping.
+        if (i, j) == before {
+            break;
+        }
+    }
+
+    edits
+}
+
+/// One side of a diff the way xdiff sees it.
+struct DiffFile<'a> {
+    lines: &'a [&'a [u8]],
+    classes: Vec<usize>,
+    /// Whether each line is changed, offset by one: there is an unchanged sentinel on either end
+    /// so that runs of changes can be scanned without bounds checks.
+    changed: Vec<bool>,
+}
+
+impl<'a> DiffFile<'a> {
+    fn new(lines: &'a [&'a [u8]], classes: Vec<usize>) -> Self {
+        Self {
+            changed: vec![false; lines.len() + 2],
+            lines,
+            classes,
+        }
+    }
+
+    fn len(&self) -> isize {
+        self.lines.len() as isize
+    }
+
+    fn is_changed(&self, line: isize) -> bool {
+        self.changed[(line + 1) as usize]
+    }
+
+    fn set_changed(&mut self, line: isize, changed: bool) {
+        self.changed[(line + 1) as usize] = changed;
+    }
+
+    fn same(&self, a: isize, b: isize) -> bool {
+        self.classes[a as usize] == self.classes[b as usize]
+    }
+}
+
+/// xdiff's integer square root approximation.
+fn bogosqrt(mut n: usize) -> usize {
+    let mut root = 1;
+
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+
+    root
+}
+
+const SNAKE_CNT: isize = 20;
+const HEUR_MIN_COST: isize = 256;
+const MAX_COST_MIN: isize = 256;
+const K_HEUR: isize = 4;
+const MAX_EQLIMIT: usize = 1024;
+const SIMSCAN_WINDOW: usize = 100;
+const KPDIS_RUN: usize = 4;
+
+/// xdiff's Myers diff of two runs of lines given by class, marking the changed lines in
+/// `changed1` and `changed2`. As in git, the common ends are trimmed and lines that cannot match
+/// anything are set aside before the search.
+fn myers(
+    classes1: &[usize],
+    classes2: &[usize],
+    changed1: &mut [bool],
+    changed2: &mut [bool],
+    minimal: bool,
+) {
+    let (n1, n2) = (classes1.len(), classes2.len());
+    let mut counts = HashMap::<usize, (usize, usize)>::new();
+
+    for class in classes1 {
+        counts.entry(*class).or_default().0 += 1;
+    }
+    for class in classes2 {
+        counts.entry(*class).or_default().1 += 1;
+    }
+
+    let limit = n1.min(n2);
+    let prefix = (0..limit)
+        .take_while(|&i| classes1[i] == classes2[i])
+        .count();
+    let suffix = (0..limit - prefix)
+        .take_while(|&i| classes1[n1 - 1 - i] == classes2[n2 - 1 - i])
+        .count();
+
+    let (rindex1, ha1) = discard_unmatched(
+        classes1,
+        prefix..n1 - suffix,
+        |class| counts[&class].1,
+        minimal,
+        changed1,
+    );
+    let (rindex2, ha2) = discard_unmatched(
+        classes2,
+        prefix..n2 - suffix,
+        |class| counts[&class].0,
+        minimal,
+        changed2,
+    );
+
+    let diagonals = ha1.len() + ha2.len() + 3;
+    let mut search = MyersSearch {
+        ha1: &ha1,
+        ha2: &ha2,
+        rindex1: &rindex1,
+        rindex2: &rindex2,
+        changed1,
+        changed2,
+        forward: vec![0; diagonals],
+        backward: vec![0; diagonals],
+        offset: ha2.len() as isize + 1,
+        max_cost: (bogosqrt(diagonals) as isize).max(MAX_COST_MIN),
+    };
+
+    search.compare(0, ha1.len() as isize, 0, ha2.len() as isize, minimal);
+}
+
+/// Sets aside the lines in `range` that have no match on the other side, and those with very
+/// many matches that sit among unmatched lines, marking them changed. Returns the index and
+/// class of each line that is kept for the search.
+fn discard_unmatched(
+    classes: &[usize],
+    range: std::ops::Range<usize>,
+    other_count: impl Fn(usize) -> usize,
+    minimal: bool,
+    changed: &mut [bool],
+) -> (Vec<usize>, Vec<usize>) {
+    let limit = bogosqrt(classes.len()).min(MAX_EQLIMIT);
+
+    // 0: no match, 1: keep, 2: so many matches that it may be discarded.
+    let discard: Vec<u8> = range
+        .clone()
+        .map(|i| match other_count(classes[i]) {
+            0 => 0,
+            count if count >= limit && !minimal => 2,
+            _ => 1,
+        })
+        .collect();
+
+    let mut rindex = Vec::new();
+    let mut ha = Vec::new();
+
+    for (k, i) in range.enumerate() {
+        if discard[k] == 1 || (discard[k] == 2 && !discard_multimatch(&discard, k)) {
+            rindex.push(i);
+            ha.push(classes[i]);
+        } else {
+            changed[i] = true;
+        }
+    }
+
+    (rindex, ha)
+}
+
+/// Whether the frequent line `i` is surrounded by mostly unmatched lines, in which case keeping it
+/// would only produce spurious matches (xdiff's `xdl_clean_mmatch`).
+fn discard_multimatch(discard: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(SIMSCAN_WINDOW);
+    let end = (i + SIMSCAN_WINDOW).min(discard.len() - 1);
+
+    let (mut unmatched_before, mut multi_before) = (0, 1);
+    for class in discard[start..i].iter().rev() {
+        match class {
+            0 => unmatched_before += 1,
+            2 => multi_before += 1,
+            _ => break,
+        }
+    }
+
+    if unmatched_before == 0 {
+        return false;
+    }
+
+    let (mut unmatched_after, mut multi_after) = (0, 1);
+    for class in &discard[i + 1..=end] {
+        match class {
+            0 => unmatched_after += 1,
+            2 => multi_after += 1,
+            _ => break,
+        }
+    }
+
+    if unmatched_after == 0 {
+        return false;
+    }
+
+    let unmatched = unmatched_before + unmatched_after;
+    let multi = multi_before + multi_after;
+
+    multi * KPDIS_RUN < multi + unmatched
+}
+
+/// Where [`MyersSearch::split`] divides a box, and whether each half still needs a minimal diff.
+struct Split {
+    i1: isize,
+    i2: isize,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+/// The state of xdiff's divide-and-conquer Myers search over the lines kept by
+/// [`discard_unmatched`]. `forward` and `backward` hold the furthest reaching paths per diagonal.
+struct MyersSearch<'a> {
+    ha1: &'a [usize],
+    ha2: &'a [usize],
+    rindex1: &'a [usize],
+    rindex2: &'a [usize],
+    changed1: &'a mut [bool],
+    changed2: &'a mut [bool],
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+impl MyersSearch<'_> {
+    fn h1(&self, i: isize) -> usize {
+        self.ha1[i as usize]
+    }
+
+    fn h2(&self, i: isize) -> usize {
+        self.ha2[i as usize]
+    }
+
+    fn kf(&self, d: isize) -> isize {
+        self.forward[(d + self.offset) as usize]
+    }
+
+    fn kb(&self, d: isize) -> isize {
+        self.backward[(d + self.offset) as usize]
+    }
+
+    fn set_kf(&mut self, d: isize, value: isize) {
+        self.forward[(d + self.offset) as usize] = value;
+    }
+
+    fn set_kb(&mut self, d: isize, value: isize) {
+        self.backward[(d + self.offset) as usize] = value;
+    }
+
+    fn compare(
+        &mut self,
+        mut off1: isize,
+        mut lim1: isize,
+        mut off2: isize,
+        mut lim2: isize,
+        need_min: bool,
+    ) {
+        while off1 < lim1 && off2 < lim2 && self.h1(off1) == self.h2(off2) {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.h1(lim1 - 1) == self.h2(lim2 - 1) {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                self.changed2[self.rindex2[i as usize]] = true;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                self.changed1[self.rindex1[i as usize]] = true;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+
+            self.compare(off1, split.i1, off2, split.i2, split.min_lo);
+            self.compare(split.i1, lim1, split.i2, lim2, split.min_hi);
+        }
+    }
+
+    /// Finds the middle snake of the box, or, when that gets too expensive and a minimal
+    /// result is not required, a good enough split point (xdiff's `xdl_split`).
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        need_min: bool,
+    ) -> Split {
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+
+        self.set_kf(fmid, off1);
+        self.set_kb(bmid, lim1);
+
+        let mut ec = 1;
+
+        loop {
+            let mut got_snake = false;
+
+            // Extend the diagonal domain by one, or shrink it where it hits the box.
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_kf(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_kf(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.kf(d - 1) >= self.kf(d + 1) {
+                    self.kf(d - 1) + 1
+                } else {
+                    self.kf(d + 1)
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+
+                while i1 < lim1 && i2 < lim2 && self.h1(i1) == self.h2(i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+
+                self.set_kf(d, i1);
+
+                if odd && bmin <= d && d <= bmax && self.kb(d) <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_kb(bmin - 1, isize::MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_kb(bmax + 1, isize::MAX);
+            } else {
+                bmax -= 1;
+            }
+
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.kb(d - 1) < self.kb(d + 1) {
+                    self.kb(d - 1)
+                } else {
+                    self.kb(d + 1) - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+
+                while i1 > off1 && i2 > off2 && self.h1(i1 - 1) == self.h2(i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+
+                self.set_kb(d, i1);
+
+                if !odd && fmin <= d && d <= fmax && i1 <= self.kf(d) {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+
+                d -= 2;
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // Past a certain cost, settle for a diagonal that has reached far along a long snake.
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = 0;
+                let mut found = (0, 0);
+
+                let mut d = fmax;
+                while d >= fmin {
+                    let dd = (d - fmid).abs();
+                    let i1 = self.kf(d);
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 + SNAKE_CNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_CNT <= i2
+                        && i2 < lim2
+                    {
+                        let mut k = 1;
+                        while self.h1(i1 - k) == self.h2(i2 - k) {
+                            if k == SNAKE_CNT {
+                                best = v;
+                                found = (i1, i2);
+                                break;
+                            }
+                            k += 1;
+                        }
+                    }
+
+                    d -= 2;
+                }
+
+                if best > 0 {
+                    return Split {
+                        i1: found.0,
+                        i2: found.1,
+                        min_lo: true,
+                        min_hi: false,
+                    };
+                }
+
+                let mut d = bmax;
+                while d >= bmin {
+                    let dd = (d - bmid).abs();
+                    let i1 = self.kb(d);
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_CNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_CNT
+                    {
+                        let mut k = 0;
+                        while self.h1(i1 + k) == self.h2(i2 + k) {
+                            if k == SNAKE_CNT - 1 {
+                                best = v;
+                                found = (i1, i2);
+                                break;
+                            }
+                            k += 1;
+                        }
+                    }
+
+                    d -= 2;
+                }
+
+                if best > 0 {
+                    return Split {
+                        i1: found.0,
+                        i2: found.1,
+                        min_lo: false,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            // Enough is much too much: split at the furthest point either search reached.
+            if ec >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.kf(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = self.kb(d).max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_lo: true,
+                        min_hi: false,
+                    }
+                } else {
+                    Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_lo: false,
+                        min_hi: true,
+                    }
+                };
+            }
+
+            ec += 1;
+        }
+    }
+}
+
+/// How many times a line may occur before histogram diff gives up on a region and falls back to
+/// Myers.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// A run of matching lines, by 1-based line number on each side. All zero when nothing matched.
+#[derive(Debug, Clone, Copy, Default)]
+struct Region {
+    begin1: usize,
+    end1: usize,
+    begin2: usize,
+    end2: usize,
+}
+
+/// A distinct line of the old side within the current region: its first occurrence and count.
+struct Record {
+    ptr: usize,
+    cnt: usize,
+}
+
+/// A port of git's histogram diff (`xhistogram.c`). Line numbers are 1-based, as in git.
+struct Histogram<'a> {
+    classes1: &'a [usize],
+    classes2: &'a [usize],
+    changed1: &'a mut [bool],
+    changed2: &'a mut [bool],
+}
+
+impl Histogram<'_> {
+    fn c1(&self, line: usize) -> usize {
+        self.classes1[line - 1]
+    }
+
+    fn c2(&self, line: usize) -> usize {
+        self.classes2[line - 1]
+    }
+
+    fn diff(&mut self, mut line1: usize, mut count1: usize, mut line2: usize, mut count2: usize) {
+        loop {
+            if count1 == 0 {
+                self.changed2[line2 - 1..line2 - 1 + count2].fill(true);
+                return;
+            }
+            if count2 == 0 {
+                self.changed1[line1 - 1..line1 - 1 + count1].fill(true);
+                return;
+            }
+
+            let Some(lcs) = self.find_lcs(line1, count1, line2, count2) else {
+                let (range1, range2) =
+                    (line1 - 1..line1 - 1 + count1, line2 - 1..line2 - 1 + count2);
+
+                myers(
+                    &self.classes1[range1.clone()],
+                    &self.classes2[range2.clone()],
+                    &mut self.changed1[range1],
+                    &mut self.changed2[range2],
+                    false,
+                );
+                return;
+            };
+
+            if lcs.begin1 == 0 && lcs.begin2 == 0 {
+                self.changed1[line1 - 1..line1 - 1 + count1].fill(true);
+                self.changed2[line2 - 1..line2 - 1 + count2].fill(true);
+                return;
+            }
+
+            self.diff(line1, lcs.begin1 - line1, line2, lcs.begin2 - line2);
+
+            count1 = line1 + count1 - 1 - lcs.end1;
+            line1 = lcs.end1 + 1;
+            count2 = line2 + count2 - 1 - lcs.end2;
+            line2 = lcs.end2 + 1;
+        }
+    }
+
+    /// The longest run of common lines built around the rarest line both sides share, or `None`
+    /// when every shared line is too frequent and the caller should fall back to Myers.
+    fn find_lcs(&self, line1: usize, count1: usize, line2: usize, count2: usize) -> Option<Region> {
+        let (end1, end2) = (line1 + count1 - 1, line2 + count2 - 1);
+        let mut records = HashMap::<usize, Record>::new();
+        // The next occurrence of the same line, by line number; 0 ends the chain.
+        let mut next_ptrs = vec![0; count1];
+
+        for ptr in (line1..=end1).rev() {
+            match records.get_mut(&self.c1(ptr)) {
+                Some(record) => {
+                    next_ptrs[ptr - line1] = record.ptr;
+                    record.ptr = ptr;
+                    record.cnt += 1;
+                }
+                None => {
+                    records.insert(self.c1(ptr), Record { ptr, cnt: 1 });
+                }
+            }
+        }
+
+        let count_of = |line: usize| records[&self.c1(line)].cnt;
+        let mut lcs = Region::default();
+        let mut min_count = MAX_CHAIN_LENGTH + 1;
+        let mut has_common = false;
+        let mut b_ptr = line2;
+
+        while b_ptr <= end2 {
+            let mut b_next = b_ptr + 1;
+
+            if let Some(record) = records.get(&self.c2(b_ptr)) {
+                has_common = true;
+
+                if record.cnt <= min_count {
+                    let mut a_ptr = record.ptr;
+
+                    'occurrences: loop {
+                        let mut np = next_ptrs[a_ptr - line1];
+                        let (mut as_, mut bs) = (a_ptr, b_ptr);
+                        let (mut ae, mut be) = (a_ptr, b_ptr);
+                        let mut rc = record.cnt;
+
+                        while line1 < as_ && line2 < bs && self.c1(as_ - 1) == self.c2(bs - 1) {
+                            as_ -= 1;
+                            bs -= 1;
+                            if 1 < rc {
+                                rc = rc.min(count_of(as_));
+                            }
+                        }
+                        while ae < end1 && be < end2 && self.c1(ae + 1) == self.c2(be + 1) {
+                            ae += 1;
+                            be += 1;
+                            if 1 < rc {
+                                rc = rc.min(count_of(ae));
+                            }
+                        }
+
+                        if b_next <= be {
+                            b_next = be + 1;
+                        }
+                        if lcs.end1 - lcs.begin1 < ae - as_ || rc < min_count {
+                            lcs = Region {
+                                begin1: as_,
+                                end1: ae,
+                                begin2: bs,
+                                end2: be,
+                            };
+                            min_count = rc;
+                        }
+
+                        if np == 0 {
+                            break;
+                        }
+                        while np <= ae {
+                            np = next_ptrs[np - line1];
+                            if np == 0 {
+                                break 'occurrences;
+                            }
+                        }
+
+                        a_ptr = np;
+                    }
+                }
+            }
+
+            b_ptr = b_next;
+        }
+
+        if has_common && MAX_CHAIN_LENGTH < min_count {
+            None
+        } else {
+            Some(lcs)
+        }
+    }
+}
+
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+const INDENT_HEURISTIC_MAX_SLIDING: isize = 100;
+
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+
+/// A run of changed lines, `start..end`. Between two changes it is the empty run where the
+/// other side's change lines up.
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    start: isize,
+    end: isize,
+}
+
+impl Group {
+    fn first(file: &DiffFile) -> Self {
+        let mut end = 0;
+        while file.is_changed(end) {
+            end += 1;
+        }
+
+        Self { start: 0, end }
+    }
+
+    fn next(&mut self, file: &DiffFile) -> bool {
+        if self.end == file.len() {
+            return false;
+        }
+
+        self.start = self.end + 1;
+        self.end = self.start;
+        while file.is_changed(self.end) {
+            self.end += 1;
+        }
+
+        true
+    }
+
+    fn previous(&mut self, file: &DiffFile) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+
+        self.end = self.start - 1;
+        self.start = self.end;
+        while file.is_changed(self.start - 1) {
+            self.start -= 1;
+        }
+
+        true
+    }
+
+    fn slide_down(&mut self, file: &mut DiffFile) -> bool {
+        if self.end < file.len() && file.same(self.start, self.end) {
+            file.set_changed(self.start, false);
+            file.set_changed(self.end, true);
+            self.start += 1;
+            self.end += 1;
+
+            while file.is_changed(self.end) {
+                self.end += 1;
+            }
+
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, file: &mut DiffFile) -> bool {
+        if self.start > 0 && file.same(self.start - 1, self.end - 1) {
+            self.start -= 1;
+            self.end -= 1;
+            file.set_changed(self.start, true);
+            file.set_changed(self.end, false);
+
+            while file.is_changed(self.start - 1) {
+                self.start -= 1;
+            }
+
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Slides each run of changes in `file` to the position git would show it at (xdiff's
+/// `xdl_change_compact`): runs that can be merged are merged, a run that can line up with a
+/// change on the `other` side does, and otherwise the indent heuristic picks the position whose
+/// boundaries fall best between blocks of code.
+fn compact(file: &mut DiffFile, other: &mut DiffFile, indent_heuristic: bool) {
+    let mut g = Group::first(file);
+    let mut go = Group::first(other);
+
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = -1;
+
+                while g.slide_up(file) {
+                    go.previous(other);
+                }
+
+                earliest_end = g.end;
+
+                if go.end > go.start {
+                    end_matching_other = g.end;
+                }
+
+                while g.slide_down(file) {
+                    go.next(other);
+
+                    if go.end > go.start {
+                        end_matching_other = g.end;
+                    }
+                }
+
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // The run cannot move.
+            } else if end_matching_other != -1 {
+                while go.end == go.start {
+                    g.slide_up(file);
+                    go.previous(other);
+                }
+            } else if indent_heuristic {
+                let size = g.end - g.start;
+                let mut shift = earliest_end
+                    .max(g.end - size - 1)
+                    .max(g.end - INDENT_HEURISTIC_MAX_SLIDING);
+                let mut best: Option<(isize, SplitScore)> = None;
+
+                while shift <= g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&SplitMeasurement::new(file, shift));
+                    score.add(&SplitMeasurement::new(file, shift - size));
+
+                    if best.is_none_or(|(_, best)| score.cmp(&best) <= 0) {
+                        best = Some((shift, score));
+                    }
+
+                    shift += 1;
+                }
+
+                if let Some((best_shift, _)) = best {
+                    while g.end > best_shift {
+                        g.slide_up(file);
+                        go.previous(other);
+                    }
+                }
+            }
+        }
+
+        if !g.next(file) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+/// The indentation of `line` with tabs to multiples of 8, or -1 for a blank line.
+fn indent_of(line: &[u8]) -> i32 {
+    let mut indent = 0;
+
+    for b in line {
+        if !matches!(b, b' ' | b'\t' | b'\n' | b'\r') {
+            return indent;
+        }
+
+        match b {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            _ => {}
+        }
+
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+
+    -1
+}
+
+/// What the lines around a split point (the boundary before line `split`) look like.
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: i32,
+    pre_blank: i32,
+    pre_indent: i32,
+    post_blank: i32,
+    post_indent: i32,
+}
+
+impl SplitMeasurement {
+    fn new(file: &DiffFile, split: isize) -> Self {
+        let (end_of_file, indent) = if split >= file.len() {
+            (true, -1)
+        } else {
+            (false, indent_of(file.lines[split as usize]))
+        };
+
+        let (mut pre_blank, mut pre_indent) = (0, -1);
+        for i in (0..split.max(0)).rev() {
+            pre_indent = indent_of(file.lines[i as usize]);
+            if pre_indent != -1 {
+                break;
+            }
+            pre_blank += 1;
+            if pre_blank == MAX_BLANKS {
+                pre_indent = 0;
+                break;
+            }
+        }
+
+        let (mut post_blank, mut post_indent) = (0, -1);
+        for i in split + 1..file.len() {
+            post_indent = indent_of(file.lines[i as usize]);
+            if post_indent != -1 {
+                break;
+            }
+            post_blank += 1;
+            if post_blank == MAX_BLANKS {
+                post_indent = 0;
+                break;
+            }
+        }
+
+        Self {
+            end_of_file,
+            indent,
+            pre_blank,
+            pre_indent,
+            post_blank,
+            post_indent,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            // No adjustment.
+        } else if indent > m.pre_indent {
+            self.penalty += if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += if any_blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            };
+        } else {
+            self.penalty += if any_blanks {
+                RELATIVE_DEDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_DEDENT_PENALTY
+            };
+        }
+    }
+
+    /// Negative when `self` is the better split.
+    fn cmp(&self, other: &Self) -> i32 {
+        let indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// Groups the differences between `old` and `new` into hunks with `context` unchanged lines
+/// around each change. Changes whose contexts overlap share a hunk.
+pub fn hunks<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    context: usize,
+    algorithm: DiffAlgorithm,
+) -> Vec<Hunk<'a>> {
+    let edits = diff_lines(old, new, algorithm);
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+
+    // The old and new line numbers reached before each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in &edits {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Equal(..) => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Edit::Delete(_) => old_pos += 1,
+            Edit::Insert(_) => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let mut hunks = Vec::new();
+    let mut c = 0;
+
+    while c < changes.len() {
+        let begin = changes[c].saturating_sub(context);
+        let mut last = changes[c];
+
+        while c + 1 < changes.len() && changes[c + 1] - last <= 2 * context + 1 {
+            c += 1;
+            last = changes[c];
+        }
+        c += 1;
+
+        let end = (last + context + 1).min(edits.len());
+
+        let lines: Vec<DiffLine> = edits[begin..end]
+            .iter()
+            .map(|edit| match *edit {
+                Edit::Equal(x, _) => DiffLine::Context(old[x]),
+                Edit::Delete(x) => DiffLine::Removed(old[x]),
+                Edit::Insert(y) => DiffLine::Added(new[y]),
+            })
+            .collect();
+
+        let (old_first, new_first) = positions[begin];
+        let (old_last, new_last) = positions[end];
+        let old_len = old_last - old_first;
+        let new_len = new_last - new_first;
+
+        hunks.push(Hunk {
+            old_start: if old_len == 0 {
+                old_first
+            } else {
+                old_first + 1
+            },
+            old_len,
+            new_start: if new_len == 0 {
+                new_first
+            } else {
+                new_first + 1
+            },
+            new_len,
+            lines,
+        });
+    }
+
+    hunks
+}
+
+/// Rebuilds a file from `old` applying only the hunks marked in `selected`, as `add -p` does.
+pub fn apply_hunks(old: &[&[u8]], hunks: &[Hunk], selected: &[bool]) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut old_pos = 0;
+
+    for (hunk, selected) in hunks.iter().zip(selected) {
+        let offset = hunk.old_offset();
+
+        for line in &old[old_pos..offset] {
+            result.extend_from_slice(line);
+        }
+
+        for line in &hunk.lines {
+            match (line, selected) {
+                (DiffLine::Context(line), _)
+                | (DiffLine::Added(line), true)
+                | (DiffLine::Removed(line), false) => result.extend_from_slice(line),
+                _ => {}
+            }
+        }
+
+        old_pos = offset + hunk.old_len;
+    }
+
+    for line in &old[old_pos..] {
+        result.extend_from_slice(line);
+    }
+
+    result
+}
+
+/// The text git shows after a hunk's `@@` header: the last line before the hunk that starts with
+/// a letter, `_` or `$` (git's default function name rule), cut to 80 bytes without trailing
+/// whitespace.
+pub fn function_line<'a>(old: &[&'a [u8]], hunk: &Hunk) -> Option<&'a [u8]> {
+    let line = old[..hunk.old_offset()]
+        .iter()
+        .rev()
+        .find(|line| matches!(line.first(), Some(b) if b.is_ascii_alphabetic() || matches!(b, b'_' | b'$')))?;
+    let mut line = &line[..line.len().min(80)];
+
+    while let Some((last, rest)) = line.split_last() {
+        if !matches!(last, b' ' | b'\t' | b'\n' | b'\r') {
+            break;
+        }
+        line = rest;
+    }
+
+    Some(line)
+}
+
+/// The scale of git's similarity scores: a score is the fraction of this many.
+pub const MAX_SCORE: u32 = 60_000;
+
+/// The minimum similarity for a rename, git's default of 50%.
+pub const RENAME_THRESHOLD: u32 = MAX_SCORE / 2;
+
+/// How much of `old` survives in `new`, from 0 to 100, the way git measures it for rename
+/// detection: both are cut into chunks at each newline or every 64 bytes, and the bytes of the
+/// chunks they share are counted against the larger of the two.
+pub fn similarity(old: &[u8], new: &[u8]) -> u32 {
+    let larger = old.len().max(new.len());
+
+    if larger == 0 {
+        return 100;
+    }
+
+    (copied_bytes(&span_hashes(old), &span_hashes(new)) * 100 / larger) as u32
+}
+
+/// The number of bytes in each kind of chunk of `data`, keyed by git's chunk hash. As in git, a
+/// CR before a LF is not counted in text files, and chunks whose hashes collide are merged.
+fn span_hashes(data: &[u8]) -> HashMap<u32, usize> {
+    const HASHBASE: u32 = 107_927;
+
+    let text = !is_binary(data);
+    let mut spans = HashMap::<u32, usize>::new();
+    let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0usize);
+
+    for (i, &c) in data.iter().enumerate() {
+        if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+
+        let old1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old1 >> 25);
+        accum1 = accum1.wrapping_add(c as u32);
+        n += 1;
+
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+
+        *spans
+            .entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE)
+            .or_default() += n;
+        (accum1, accum2, n) = (0, 0, 0);
+    }
+
+    if n > 0 {
+        *spans
+            .entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE)
+            .or_default() += n;
+    }
+
+    spans
+}
+
+/// The bytes of `src` that reappear in `dst`.
+fn copied_bytes(src: &HashMap<u32, usize>, dst: &HashMap<u32, usize>) -> usize {
+    src.iter()
+        .filter_map(|(hash, count)| Some((*count).min(*dst.get(hash)?)))
+        .sum()
+}
+
+/// A file that rename and copy detection may pair up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenameFile {
+    pub path: Vec<u8>,
+    pub mode: u32,
+    pub hash: String,
+}
+
+/// A target paired with the source it was renamed or copied from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rename {
+    pub from: Vec<u8>,
+    pub to: Vec<u8>,
+    /// The similarity, from 0 to 100.
+    pub score: u32,
+    /// Whether the source lives on, either because it still exists or because a later target
+    /// is its rename, making this pair a copy.
+    pub copy: bool,
+}
+
+/// Above this many source/destination pairs only exact renames are looked for, like git's
+/// `diff.renameLimit`.
+const RENAME_LIMIT: usize = 1000 * 1000;
+
+/// How many of the best sources are remembered for each target during inexact matching.
+const CANDIDATES_PER_TARGET: usize = 4;
+
+/// Pairs `added` files with the `deleted` ones they were renamed from. With `copies`, `kept`
+/// files (ones that still exist) may be the source of copies too, and a source may be used more
+/// than once. Identical blobs are matched first, preferring unused sources with the same file
+/// name; the rest are paired best first by [`similarity`] when it reaches `threshold` (out of
+/// [`MAX_SCORE`]), loading blobs with `read`. Renames come back in the order of `added`.
+pub fn find_renames(
+    deleted: &[RenameFile],
+    kept: &[RenameFile],
+    added: &[RenameFile],
+    threshold: u32,
+    copies: bool,
+    mut read: impl FnMut(&str) -> Result<Vec<u8>>,
+) -> Result<Vec<Rename>> {
+    let file_name = |path: &[u8]| path.rsplit(|b| *b == b'/').next().unwrap_or(path).to_vec();
+    let is_regular = |mode: u32| mode & 0o170000 == 0o100000;
+
+    let sources: Vec<&RenameFile> = deleted
+        .iter()
+        .chain(kept.iter().filter(|_| copies))
+        .collect();
+    // Like git, a source that still exists counts as already used once.
+    let mut used: Vec<usize> = (0..sources.len())
+        .map(|i| (i >= deleted.len()) as usize)
+        .collect();
+    let mut matches: Vec<Option<(usize, u32)>> = vec![None; added.len()];
+
+    for (target, to) in added.iter().enumerate() {
+        let mut best = None;
+        let mut best_score = -1;
+
+        for (source, from) in sources.iter().enumerate() {
+            if from.hash != to.hash
+                || ((!is_regular(from.mode) || !is_regular(to.mode)) && from.mode != to.mode)
+                || (used[source] > 0 && !copies)
+            {
+                continue;
+            }
+
+            let score =
+                (used[source] == 0) as i32 + (file_name(&from.path) == file_name(&to.path)) as i32;
+
+            if score > best_score {
+                best = Some(source);
+                best_score = score;
+
+                if score == 2 {
+                    break;
+                }
+            }
+        }
+
+        if let Some(source) = best {
+            used[source] += 1;
+            matches[target] = Some((source, MAX_SCORE));
+        }
+    }
+
+    let targets: Vec<usize> = (0..added.len())
+        .filter(|i| matches[*i].is_none() && is_regular(added[*i].mode))
+        .collect();
+    let candidates: Vec<usize> = (0..sources.len())
+        .filter(|i| is_regular(sources[*i].mode) && (copies || used[*i] == 0))
+        .collect();
+
+    if !targets.is_empty()
+        && !candidates.is_empty()
+        && targets.len() * candidates.len() <= RENAME_LIMIT
+    {
+        let mut spans = HashMap::<String, (usize, HashMap<u32, usize>)>::new();
+        for hash in candidates
+            .iter()
+            .map(|i| &sources[*i].hash)
+            .chain(targets.iter().map(|i| &added[*i].hash))
+        {
+            if !spans.contains_key(hash) {
+                let data = read(hash)?;
+                spans.insert(hash.clone(), (data.len(), span_hashes(&data)));
+            }
+        }
+
+        // `(score, same file name, target, source)` for the best few sources of each target.
+        let mut pairs = Vec::<(u32, bool, usize, usize)>::new();
+        for &target in &targets {
+            let (dst_size, dst_spans) = &spans[&added[target].hash];
+            let mut best = Vec::new();
+
+            for &source in &candidates {
+                let (src_size, src_spans) = &spans[&sources[source].hash];
+                let larger = (*src_size).max(*dst_size);
+                let delta = larger - (*src_size).min(*dst_size);
+
+                // Files whose sizes differ this much cannot be similar enough.
+                if larger == 0
+                    || larger as u64 * u64::from(MAX_SCORE - threshold)
+                        < delta as u64 * u64::from(MAX_SCORE)
+                {
+                    continue;
+                }
+
+                let copied = copied_bytes(src_spans, dst_spans) as u64;
+                let score = (copied * u64::from(MAX_SCORE) / larger as u64) as u32;
+
+                if score >= threshold {
+                    let same_name =
+                        file_name(&sources[source].path) == file_name(&added[target].path);
+                    best.push((score, same_name, target, source));
+                }
+            }
+
+            best.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+            best.truncate(CANDIDATES_PER_TARGET);
+            pairs.extend(best);
+        }
+
+        pairs.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(b.1.cmp(&a.1))
+                .then(a.2.cmp(&b.2))
+                .then(a.3.cmp(&b.3))
+        });
+
+        // Renames are settled first; only then may used sources be copied.
+        for allow_used in [false, true] {
+            if allow_used && !copies {
+                break;
+            }
+
+            for &(score, _, target, source) in &pairs {
+                if matches[target].is_some() || (!allow_used && used[source] > 0) {
+                    continue;
+                }
+
+                used[source] += 1;
+                matches[target] = Some((source, score));
+            }
+        }
+    }
+
+    // A source used several times is copied to all but its last target, which is its rename.
+    let mut renames = Vec::new();
+    for (target, found) in matches.into_iter().enumerate() {
+        let Some((source, score)) = found else {
+            continue;
+        };
+
+        used[source] -= 1;
+        renames.push(Rename {
+            from: sources[source].path.clone(),
+            to: added[target].path.clone(),
+            score: score * 100 / MAX_SCORE,
+            copy: used[source] > 0,
+        });
+    }
+
+    Ok(renames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [DiffAlgorithm; 3] = [
+        DiffAlgorithm::Myers,
+        DiffAlgorithm::Minimal,
+        DiffAlgorithm::Histogram,
+    ];
+
+    /// Checks that `edits` turn `old` into `new`: every line of each side is visited once and
+    /// in order, and lines are only kept when they are equal. Returns how many were kept.
+    fn check_script(old: &[&[u8]], new: &[&[u8]], edits: &[Edit]) -> usize {
+        let (mut i, mut j, mut kept) = (0, 0, 0);
+
+        for edit in edits {
+            match *edit {
+                Edit::Equal(a, b) => {
+                    assert_eq!((a, b), (i, j), "{edits:?}");
+                    assert_eq!(old[a], new[b]);
+                    (i, j, kept) = (i + 1, j + 1, kept + 1);
+                }
+                Edit::Delete(a) => {
+                    assert_eq!(a, i, "{edits:?}");
+                    i += 1;
+                }
+                Edit::Insert(b) => {
+                    assert_eq!(b, j, "{edits:?}");
+                    j += 1;
+                }
+            }
+        }
+
+        assert_eq!((i, j), (old.len(), new.len()), "{edits:?}");
+        kept
+    }
+
+    #[test]
+    fn every_algorithm_handles_empty_files() {
+        let lines = split_lines(b"a\nb\n");
+
+        for algorithm in ALGORITHMS {
+            assert!(diff_lines(&[], &[], algorithm).is_empty());
+            assert_eq!(
+                diff_lines(&[], &lines, algorithm),
+                [Edit::Insert(0), Edit::Insert(1)]
+            );
+            assert_eq!(
+                diff_lines(&lines, &[], algorithm),
+                [Edit::Delete(0), Edit::Delete(1)]
+            );
+        }
+    }
+
+    #[test]
+    fn every_algorithm_sees_a_missing_trailing_newline() {
+        let old = split_lines(b"a\nb\nc");
+        let new = split_lines(b"a\nb\nc\n");
+
+        for algorithm in ALGORITHMS {
+            let edits = diff_lines(&old, &new, algorithm);
+
+            assert_eq!(check_script(&old, &new, &edits), 2, "{algorithm:?}");
+            assert!(edits.contains(&Edit::Delete(2)) && edits.contains(&Edit::Insert(2)));
+        }
+    }
+
+    #[test]
+    fn every_algorithm_handles_files_with_nothing_in_common() {
+        let old = split_lines(b"a\nb\nc\n");
+        let new = split_lines(b"d\ne\n");
+
+        for algorithm in ALGORITHMS {
+            let edits = diff_lines(&old, &new, algorithm);
+
+            assert_eq!(check_script(&old, &new, &edits), 0, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn every_algorithm_keeps_what_is_unchanged() {
+        let old = split_lines(b"1\n2\n3\n4\n5\n6\n7\n8\n");
+        let new = split_lines(b"1\n2\nx\n4\n5\n6\n8\ny\n");
+
+        for algorithm in ALGORITHMS {
+            let edits = diff_lines(&old, &new, algorithm);
+
+            assert_eq!(check_script(&old, &new, &edits), 6, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn binary_files_have_a_nul_in_their_first_8000_bytes() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary("text with ünïcödé\r\n".as_bytes()));
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+
+        let mut data = vec![b'a'; 8000];
+        data.push(0);
+        assert!(!is_binary(&data));
+
+        data[7999] = 0;
+        assert!(is_binary(&data));
+    }
+
+    /// `count` lines of seven bytes each, numbered from `from`.
+    fn numbered(from: usize, count: usize) -> String {
+        (from..from + count)
+            .map(|i| format!("line{i:02}\n"))
+            .collect()
+    }
+
+    fn file(path: &str, hash: &str) -> RenameFile {
+        RenameFile {
+            path: path.as_bytes().to_vec(),
+            mode: 0o100644,
+            hash: hash.to_owned(),
+        }
+    }
+
+    /// Runs rename detection over blobs named by their contents.
+    fn renames(
+        deleted: &[RenameFile],
+        kept: &[RenameFile],
+        added: &[RenameFile],
+        threshold: u32,
+        copies: bool,
+    ) -> Vec<(String, String, u32, bool)> {
+        find_renames(deleted, kept, added, threshold, copies, |hash| {
+            Ok(hash.as_bytes().to_vec())
+        })
+        .unwrap()
+        .into_iter()
+        .map(|rename| {
+            (
+                String::from_utf8(rename.from).unwrap(),
+                String::from_utf8(rename.to).unwrap(),
+                rename.score,
+                rename.copy,
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn renames_need_the_threshold_similarity() {
+        let old = numbered(0, 10);
+        // Half of the file survives, exactly the default threshold.
+        let half = numbered(0, 5) + &numbered(50, 5);
+        // Only four lines in ten survive.
+        let less = numbered(0, 4) + &numbered(50, 6);
+
+        assert_eq!(similarity(old.as_bytes(), half.as_bytes()), 50);
+        assert_eq!(
+            renames(
+                &[file("a", &old)],
+                &[],
+                &[file("b", &half)],
+                RENAME_THRESHOLD,
+                false
+            ),
+            [("a".to_owned(), "b".to_owned(), 50, false)]
+        );
+        assert!(renames(
+            &[file("a", &old)],
+            &[],
+            &[file("b", &half)],
+            RENAME_THRESHOLD + 1,
+            false
+        )
+        .is_empty());
+
+        assert!(renames(
+            &[file("a", &old)],
+            &[],
+            &[file("b", &less)],
+            RENAME_THRESHOLD,
+            false
+        )
+        .is_empty());
+        assert_eq!(
+            renames(
+                &[file("a", &old)],
+                &[],
+                &[file("b", &less)],
+                MAX_SCORE * 2 / 5,
+                false
+            ),
+            [("a".to_owned(), "b".to_owned(), 40, false)]
+        );
+
+        // An identical file is a rename whatever the threshold.
+        assert_eq!(
+            renames(
+                &[file("a", &old)],
+                &[],
+                &[file("b", &old)],
+                MAX_SCORE,
+                false
+            ),
+            [("a".to_owned(), "b".to_owned(), 100, false)]
+        );
+    }
+
+    #[test]
+    fn copies_come_from_kept_files_above_the_threshold() {
+        let kept = numbered(0, 10);
+        let similar = numbered(0, 6) + &numbered(50, 4);
+        let different = numbered(0, 3) + &numbered(50, 7);
+        let added = [
+            file("copy", &kept),
+            file("similar", &similar),
+            file("other", &different),
+        ];
+
+        assert!(renames(&[], &[file("k", &kept)], &added, RENAME_THRESHOLD, false).is_empty());
+        assert_eq!(
+            renames(&[], &[file("k", &kept)], &added, RENAME_THRESHOLD, true),
+            [
+                ("k".to_owned(), "copy".to_owned(), 100, true),
+                ("k".to_owned(), "similar".to_owned(), 60, true),
+            ]
+        );
+
+        // A deleted source copied more than once is renamed to its last target.
+        assert_eq!(
+            renames(
+                &[file("k", &kept)],
+                &[],
+                &added[..2],
+                RENAME_THRESHOLD,
+                true
+            ),
+            [
+                ("k".to_owned(), "copy".to_owned(), 100, true),
+                ("k".to_owned(), "similar".to_owned(), 60, false),
+            ]
+        );
+    }
+}
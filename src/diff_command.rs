@@ -0,0 +1,2029 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env, fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
+
+use colored::control::SHOULD_COLORIZE;
+
+use crate::{
+    diff::{
+        diff_lines, diff_words, find_renames, function_line, hunks, is_binary, split_lines,
+        DiffAlgorithm, DiffLine, Edit, RenameFile, MAX_SCORE,
+    },
+    error::{Error, Result},
+    index::{mode_from_metadata, Index, IndexEntry, MODE_GITLINK},
+    pathspec::Pathspec,
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, parse_revision_arg, resolve_revision, RevisionItem},
+    status::{file_type, tree_file},
+    structures::ObjectType,
+    tree::{commit_tree, find_tree_entry, flatten_tree, TreeFile},
+    utils::{path_from_bytes, quote_path},
+    worktree::{cwd_prefix, entry_state, read_worktree_file, WorktreeState},
+};
+
+/// How `--word-diff` marks the changed words.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordDiff {
+    /// `[-removed-]{+added+}`.
+    Plain,
+    /// Changed words only in colour, which this mode turns on.
+    Color,
+    /// One word run per line, prefixed with `+`, `-` or a space, for scripts.
+    Porcelain,
+}
+
+impl WordDiff {
+    /// Parses a `--word-diff` mode; `none` turns word diffs off.
+    pub fn parse(mode: &str) -> Result<Option<Self>> {
+        match mode {
+            "plain" => Ok(Some(Self::Plain)),
+            "color" => Ok(Some(Self::Color)),
+            "porcelain" => Ok(Some(Self::Porcelain)),
+            "none" => Ok(None),
+            _ => Err(Error::Usage(format!("bad --word-diff argument: {mode}"))),
+        }
+    }
+}
+
+/// How `--color-moved` colours lines that were moved rather than added or removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorMoved {
+    #[default]
+    No,
+    /// Every moved line, however short the block.
+    Plain,
+    /// Blocks of moved lines with at least 20 alphanumeric characters.
+    Blocks,
+    /// Like `Blocks`, alternating colours between adjacent blocks.
+    Zebra,
+    /// Like `Zebra`, dimming the uninteresting middle of each block.
+    DimmedZebra,
+}
+
+impl ColorMoved {
+    /// Parses a `--color-moved` or `diff.colorMoved` value.
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode {
+            "no" | "false" => Ok(Self::No),
+            "plain" => Ok(Self::Plain),
+            "blocks" => Ok(Self::Blocks),
+            "default" | "zebra" | "true" => Ok(Self::Zebra),
+            "dimmed-zebra" | "dimmed_zebra" => Ok(Self::DimmedZebra),
+            _ => Err(Error::Usage(format!("bad --color-moved argument: {mode}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Compare a commit with the index rather than with the work tree.
+    pub cached: bool,
+    pub algorithm: DiffAlgorithm,
+    /// Unchanged lines shown around each change.
+    pub context: usize,
+    /// Show the patch itself; off when only `--stat` or `--name-status` was asked for.
+    pub patch: bool,
+    pub stat: bool,
+    /// The width `--stat` fits into; the terminal's when unset.
+    pub stat_width: Option<usize>,
+    /// List created, deleted and renamed files and mode changes after the stat (`--summary`).
+    pub summary: bool,
+    /// Only list the changed paths and how they changed.
+    pub name_status: bool,
+    /// The minimum similarity (out of [`MAX_SCORE`]) for renames, or `None` to not look for any.
+    pub renames: Option<u32>,
+    /// Also look for copies of modified files.
+    pub copies: bool,
+    /// Look for copies of unmodified files too, which is expensive.
+    pub find_copies_harder: bool,
+    pub word_diff: Option<WordDiff>,
+    pub color_moved: ColorMoved,
+}
+
+/// Parses a `-M`/`-C` similarity like git: digits are a fraction (`5` is 50%, `05` is 5%)
+/// unless followed by `%`. Returns the score out of [`MAX_SCORE`].
+pub fn parse_rename_score(text: &str) -> Result<u32> {
+    let (mut num, mut scale) = (0u64, 1u64);
+    let mut dot = false;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                rest = &rest[1..];
+                break;
+            }
+            '0'..='9' => {
+                if scale < 100_000 {
+                    scale *= 10;
+                    num = num * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+
+    if !rest.is_empty() {
+        return Err(Error::Usage(format!("invalid similarity score `{text}`")));
+    }
+
+    Ok(if num >= scale {
+        MAX_SCORE
+    } else {
+        (u64::from(MAX_SCORE) * num / scale) as u32
+    })
+}
+
+/// One side of a file pair.
+#[derive(Debug, Clone, PartialEq)]
+struct DiffSide {
+    path: Vec<u8>,
+    mode: u32,
+    hash: String,
+}
+
+/// A changed path, as git's `diff_filepair`: `A`dded, `D`eleted, `M`odified, `T`ype changed,
+/// `R`enamed, `C`opied or `U`nmerged (which has neither side).
+#[derive(Debug, Clone)]
+struct FilePair {
+    old: Option<DiffSide>,
+    new: Option<DiffSide>,
+    status: char,
+    /// The similarity of renames and copies, from 0 to 100.
+    score: u32,
+    /// The path of an unmerged pair.
+    path: Vec<u8>,
+}
+
+impl FilePair {
+    fn old_path(&self) -> &[u8] {
+        self.old.as_ref().map_or(&self.path, |side| &side.path)
+    }
+
+    fn new_path(&self) -> &[u8] {
+        self.new.as_ref().map_or(self.old_path(), |side| &side.path)
+    }
+}
+
+/// The two sets of files being compared, keyed by path.
+#[derive(Debug, Default)]
+struct Comparison {
+    old: BTreeMap<Vec<u8>, TreeFile>,
+    new: BTreeMap<Vec<u8>, TreeFile>,
+    /// Paths with conflicts in the index, reported as unmerged ahead of any pair of their own.
+    unmerged: BTreeSet<Vec<u8>>,
+}
+
+/// Reads the contents of either side, including work tree files hashed along the way.
+pub(crate) struct Contents<'r> {
+    repo: &'r Repository,
+    worktree: HashMap<String, Vec<u8>>,
+}
+
+impl<'r> Contents<'r> {
+    pub(crate) fn new(repo: &'r Repository) -> Self {
+        Self {
+            repo,
+            worktree: HashMap::new(),
+        }
+    }
+
+    fn read(&self, side: &DiffSide) -> Result<Vec<u8>> {
+        // Like git, a submodule is shown as the commit it is at.
+        if side.mode == MODE_GITLINK {
+            return Ok(format!("Subproject commit {}\n", side.hash).into_bytes());
+        }
+
+        match self.worktree.get(&side.hash) {
+            Some(data) => Ok(data.clone()),
+            None => self.repo.read_blob(&side.hash),
+        }
+    }
+
+    /// The work tree file at `path` as it would be staged, remembering its contents. Without
+    /// `core.filemode`, the executable bit is taken from `staged_mode`.
+    pub(crate) fn worktree_file(
+        &mut self,
+        path: &[u8],
+        staged_mode: Option<u32>,
+    ) -> Result<Option<TreeFile>> {
+        let full_path = self.repo.require_work_tree()?.join(path_from_bytes(path));
+
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if !metadata.is_dir() => metadata,
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let data = read_worktree_file(&full_path, &metadata)?;
+        let hash = self.repo.hash_object(&ObjectType::Blob, &data)?;
+        let mut mode = mode_from_metadata(&metadata);
+
+        let filemode = self.repo.config().get_bool("core.filemode").unwrap_or(true);
+        if let Some(staged_mode) = staged_mode {
+            if !filemode && file_type(mode) == file_type(staged_mode) {
+                mode = staged_mode;
+            }
+        }
+
+        self.worktree.insert(hash.clone(), data);
+
+        Ok(Some(TreeFile { mode, hash }))
+    }
+}
+
+/// Runs `pgit diff`: compares the work tree with the index, or with `--cached` the index with a
+/// commit (`HEAD` by default), or a commit with the work tree, two commits (also as `A..B`, or
+/// `A...B` from their merge base), or two blobs. Arguments that are not revisions but name
+/// existing files are taken as paths. Returns the output, coloured if colours are on.
+pub fn diff(
+    repo: &Repository,
+    revisions: &[String],
+    paths: &[String],
+    options: &DiffOptions,
+) -> Result<Vec<u8>> {
+    let mut paths = paths.to_vec();
+    let mut items = Vec::new();
+
+    for (i, arg) in revisions.iter().enumerate() {
+        match parse_revision_arg(repo, arg) {
+            Ok(parsed) => items.extend(parsed),
+            Err(Error::UnknownRevision(_)) if Path::new(arg).exists() => {
+                paths.extend(revisions[i..].iter().cloned());
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let pathspec = match repo.work_tree() {
+        Some(work_tree) => Pathspec::new(work_tree, &cwd_prefix(repo)?, &paths)?,
+        None => Pathspec::new(Path::new(""), &[], &paths)?,
+    };
+
+    let mut contents = Contents::new(repo);
+
+    let pairs = match blob_pair(repo, &items)? {
+        Some(pair) => vec![pair],
+        None => {
+            let Comparison { old, new, unmerged } = compare(repo, &items, options, &mut contents)?;
+            let mut pairs = pair_files(old, new, &pathspec, options);
+
+            if let Some(threshold) = options.renames {
+                detect_renames(&mut pairs, threshold, options, &contents)?;
+            }
+
+            let mut unmerged = unmerged
+                .into_iter()
+                .filter(|path| pathspec.matches(path))
+                .map(|path| FilePair {
+                    old: None,
+                    new: None,
+                    status: 'U',
+                    score: 0,
+                    path,
+                })
+                .peekable();
+            let mut list = Vec::new();
+
+            for (path, pair) in pairs {
+                while let Some(conflict) = unmerged.next_if(|conflict| conflict.path <= path) {
+                    list.push(conflict);
+                }
+                list.push(pair);
+            }
+
+            list.extend(unmerged);
+            list
+        }
+    };
+
+    let mut printer = Printer {
+        repo,
+        options,
+        colors: Palette::new(
+            SHOULD_COLORIZE.should_colorize() || options.word_diff == Some(WordDiff::Color),
+        ),
+        abbrev: default_abbrev(repo),
+        contents,
+        symbols: Vec::new(),
+    };
+
+    printer.print(&pairs)
+}
+
+/// `<blob> <blob>`: the two objects are compared directly. Blobs given as `<rev>:<path>` or
+/// `:[<stage>:]<path>` are named by their path, others as they were written.
+fn blob_pair(repo: &Repository, items: &[RevisionItem]) -> Result<Option<FilePair>> {
+    let [old, new] = items else {
+        return Ok(None);
+    };
+
+    if old.negated || new.negated {
+        return Ok(None);
+    }
+
+    let is_blob = |item: &RevisionItem| -> Result<bool> {
+        Ok(repo.read_object(&item.hash)?.obj_type == ObjectType::Blob)
+    };
+
+    match (is_blob(old)?, is_blob(new)?) {
+        (true, true) => {}
+        (false, false) => return Ok(None),
+        _ => {
+            return Err(Error::InvalidArgument(
+                "cannot compare a blob with a tree".to_owned(),
+            ))
+        }
+    }
+
+    let side = |item: &RevisionItem| -> Result<DiffSide> {
+        let spec = item.spec.as_deref().unwrap_or(&item.hash);
+
+        Ok(match blob_location(repo, spec)? {
+            Some((path, mode)) => DiffSide {
+                path,
+                mode,
+                hash: item.hash.clone(),
+            },
+            None => DiffSide {
+                path: spec.as_bytes().to_vec(),
+                mode: 0o100644,
+                hash: item.hash.clone(),
+            },
+        })
+    };
+
+    Ok(Some(FilePair {
+        old: Some(side(old)?),
+        new: Some(side(new)?),
+        status: 'M',
+        score: 0,
+        path: Vec::new(),
+    }))
+}
+
+/// The path and mode of a blob named as `<rev>:<path>` or `:[<stage>:]<path>`.
+fn blob_location(repo: &Repository, spec: &str) -> Result<Option<(Vec<u8>, u32)>> {
+    let Some((rev, path)) = spec.split_once(':') else {
+        return Ok(None);
+    };
+
+    if rev.is_empty() {
+        let (stage, path) = match path.split_once(':') {
+            Some((stage @ ("0" | "1" | "2" | "3"), path)) => (stage.parse().unwrap_or(0), path),
+            _ => (0, path),
+        };
+        let index = Index::read(repo)?;
+
+        return Ok(index
+            .entry(path.as_bytes(), stage)
+            .map(|entry| (entry.path.clone(), entry.mode)));
+    }
+
+    let tree = commit_tree(repo, &resolve_revision(repo, rev)?)?;
+
+    Ok(find_tree_entry(repo, &tree, path.as_bytes())?
+        .map(|file| (path.as_bytes().to_vec(), file.mode)))
+}
+
+/// Works out which two sets of files `items` (the parsed revision arguments) ask to compare.
+fn compare(
+    repo: &Repository,
+    items: &[RevisionItem],
+    options: &DiffOptions,
+    contents: &mut Contents,
+) -> Result<Comparison> {
+    let tree_files = |hash: &str| flatten_tree(repo, &commit_tree(repo, hash)?);
+
+    let positive: Vec<&RevisionItem> = items.iter().filter(|item| !item.negated).collect();
+    let negative: Vec<&RevisionItem> = items.iter().filter(|item| item.negated).collect();
+
+    match (positive.as_slice(), negative.first()) {
+        ([], None) if options.cached => {
+            let old = match repo.head()? {
+                Some(head) => tree_files(&head)?,
+                None => BTreeMap::new(),
+            };
+            tree_to_index(repo, old)
+        }
+        ([], None) => index_to_worktree(repo, contents),
+        ([commit], None) if options.cached => tree_to_index(repo, tree_files(&commit.hash)?),
+        ([commit], None) => tree_to_worktree(repo, tree_files(&commit.hash)?, contents),
+        // `A..B` and `^A B`.
+        ([new], Some(old)) => Ok(Comparison {
+            old: tree_files(&old.hash)?,
+            new: tree_files(&new.hash)?,
+            unmerged: BTreeSet::new(),
+        }),
+        // `A...B` is B against the merge base, which comes first among the exclusions.
+        ([new, _], Some(base)) => Ok(Comparison {
+            old: tree_files(&base.hash)?,
+            new: tree_files(&new.hash)?,
+            unmerged: BTreeSet::new(),
+        }),
+        ([_, _], None) if items.len() == 2 => Ok(Comparison {
+            old: tree_files(&items[0].hash)?,
+            new: tree_files(&items[1].hash)?,
+            unmerged: BTreeSet::new(),
+        }),
+        ([_, _], None) => Err(Error::InvalidArgument(
+            "the revisions have no merge base".to_owned(),
+        )),
+        _ => Err(Error::InvalidArgument(
+            "combined diffs of more than two revisions are not supported".to_owned(),
+        )),
+    }
+}
+
+/// `--cached`: `old` (a commit's files) against the index. Intent-to-add entries are not
+/// staged yet, so they do not show.
+fn tree_to_index(repo: &Repository, old: BTreeMap<Vec<u8>, TreeFile>) -> Result<Comparison> {
+    let index = Index::read(repo)?;
+    let mut comparison = Comparison {
+        old,
+        ..Comparison::default()
+    };
+
+    for entry in &index.entries {
+        if entry.stage() != 0 {
+            comparison.old.remove(&entry.path);
+            comparison.unmerged.insert(entry.path.clone());
+        } else if !entry.intent_to_add() {
+            comparison.new.insert(entry.path.clone(), tree_file(entry));
+        }
+    }
+
+    Ok(comparison)
+}
+
+/// The default: the index against the work tree. Only tracked files take part; intent-to-add
+/// entries show as new files. Like git, conflicted files are compared from our side (stage 2).
+fn index_to_worktree(repo: &Repository, contents: &mut Contents) -> Result<Comparison> {
+    let index = Index::read(repo)?;
+    let mut comparison = Comparison::default();
+
+    for entry in &index.entries {
+        if entry.stage() != 0 {
+            comparison.unmerged.insert(entry.path.clone());
+
+            if entry.stage() == 2 {
+                comparison.old.insert(entry.path.clone(), tree_file(entry));
+                if let Some(file) = contents.worktree_file(&entry.path, Some(entry.mode))? {
+                    comparison.new.insert(entry.path.clone(), file);
+                }
+            }
+            continue;
+        }
+
+        if !entry.intent_to_add() {
+            comparison.old.insert(entry.path.clone(), tree_file(entry));
+        }
+
+        if let Some(file) = worktree_version(repo, entry, contents)? {
+            comparison.new.insert(entry.path.clone(), file);
+        }
+    }
+
+    Ok(comparison)
+}
+
+/// `<commit>`: a commit's files against the work tree versions of the tracked files, including
+/// conflicted ones.
+fn tree_to_worktree(
+    repo: &Repository,
+    old: BTreeMap<Vec<u8>, TreeFile>,
+    contents: &mut Contents,
+) -> Result<Comparison> {
+    let index = Index::read(repo)?;
+    let mut comparison = Comparison {
+        old,
+        ..Comparison::default()
+    };
+
+    for entry in &index.entries {
+        let file = if entry.stage() == 0 {
+            worktree_version(repo, entry, contents)?
+        } else if !comparison.new.contains_key(&entry.path) {
+            contents.worktree_file(&entry.path, Some(entry.mode))?
+        } else {
+            continue;
+        };
+
+        if let Some(file) = file {
+            comparison.new.insert(entry.path.clone(), file);
+        }
+    }
+
+    Ok(comparison)
+}
+
+/// The work tree version of a staged file, hashed only when its stat data says it changed.
+pub(crate) fn worktree_version(
+    repo: &Repository,
+    entry: &IndexEntry,
+    contents: &mut Contents,
+) -> Result<Option<TreeFile>> {
+    if entry.intent_to_add() {
+        return contents.worktree_file(&entry.path, None);
+    }
+
+    match entry_state(repo, entry)? {
+        WorktreeState::Deleted => Ok(None),
+        // Submodule checkouts are not looked into.
+        WorktreeState::Unchanged => Ok(Some(tree_file(entry))),
+        WorktreeState::Modified if entry.mode == MODE_GITLINK => Ok(Some(tree_file(entry))),
+        WorktreeState::Modified => contents.worktree_file(&entry.path, Some(entry.mode)),
+    }
+}
+
+/// Pairs up the files of both sides that `pathspec` selects, keyed by path. Unchanged files are
+/// kept only when `--find-copies-harder` may use them as copy sources.
+fn pair_files(
+    mut old: BTreeMap<Vec<u8>, TreeFile>,
+    mut new: BTreeMap<Vec<u8>, TreeFile>,
+    pathspec: &Pathspec,
+    options: &DiffOptions,
+) -> BTreeMap<Vec<u8>, FilePair> {
+    let mut pairs = BTreeMap::new();
+
+    let paths: BTreeSet<Vec<u8>> = old.keys().chain(new.keys()).cloned().collect();
+
+    for path in paths {
+        if !pathspec.matches(&path) {
+            continue;
+        }
+
+        let side = |file: Option<TreeFile>| {
+            file.map(|file| DiffSide {
+                path: path.clone(),
+                mode: file.mode,
+                hash: file.hash,
+            })
+        };
+        let (old, new) = (side(old.remove(&path)), side(new.remove(&path)));
+
+        let status = match (&old, &new) {
+            (Some(old), Some(new)) if old == new => {
+                if options.find_copies_harder {
+                    ' '
+                } else {
+                    continue;
+                }
+            }
+            (Some(old), Some(new)) if file_type(old.mode) != file_type(new.mode) => 'T',
+            (Some(_), Some(_)) => 'M',
+            (Some(_), None) => 'D',
+            (None, _) => 'A',
+        };
+
+        pairs.insert(
+            path.clone(),
+            FilePair {
+                old,
+                new,
+                status,
+                score: 0,
+                path,
+            },
+        );
+    }
+
+    pairs
+}
+
+/// Turns deletions and additions into renames and, if asked, additions into copies. A renamed
+/// pair takes the place of the addition; the deletion goes away.
+fn detect_renames(
+    pairs: &mut BTreeMap<Vec<u8>, FilePair>,
+    threshold: u32,
+    options: &DiffOptions,
+    contents: &Contents,
+) -> Result<()> {
+    let rename_file = |side: &DiffSide| RenameFile {
+        path: side.path.clone(),
+        mode: side.mode,
+        hash: side.hash.clone(),
+    };
+    let sides = |status: &[char], new: bool| -> Vec<RenameFile> {
+        pairs
+            .values()
+            .filter(|pair| status.contains(&pair.status))
+            .filter_map(|pair| if new { &pair.new } else { &pair.old }.as_ref())
+            .map(rename_file)
+            .collect()
+    };
+
+    let deleted = sides(&['D'], false);
+    let added = sides(&['A'], true);
+    let copies = options.copies || options.find_copies_harder;
+    let kept = if copies {
+        sides(&['M', ' '], false)
+    } else {
+        Vec::new()
+    };
+
+    if added.is_empty() || (deleted.is_empty() && kept.is_empty()) {
+        pairs.retain(|_, pair| pair.status != ' ');
+        return Ok(());
+    }
+
+    let renames = find_renames(&deleted, &kept, &added, threshold, copies, |hash| {
+        contents.read(&DiffSide {
+            path: Vec::new(),
+            mode: 0,
+            hash: hash.to_owned(),
+        })
+    })?;
+
+    let mut moved_away = BTreeSet::new();
+
+    for rename in renames {
+        let Some(old) = pairs.get(&rename.from).and_then(|pair| pair.old.clone()) else {
+            continue;
+        };
+
+        if let Some(pair) = pairs.get_mut(&rename.to) {
+            pair.old = Some(old);
+            pair.status = if rename.copy { 'C' } else { 'R' };
+            pair.score = rename.score;
+        }
+
+        moved_away.insert(rename.from);
+    }
+
+    pairs.retain(|path, pair| {
+        pair.status != ' ' && !(pair.status == 'D' && moved_away.contains(path))
+    });
+
+    Ok(())
+}
+
+/// The escape sequences for each part of the output; all empty without colours.
+struct Palette {
+    meta: &'static str,
+    frag: &'static str,
+    old: &'static str,
+    new: &'static str,
+    context: &'static str,
+    func: &'static str,
+    whitespace: &'static str,
+    old_moved: &'static str,
+    old_moved_alt: &'static str,
+    old_moved_dim: &'static str,
+    old_moved_alt_dim: &'static str,
+    new_moved: &'static str,
+    new_moved_alt: &'static str,
+    new_moved_dim: &'static str,
+    new_moved_alt_dim: &'static str,
+    reset: &'static str,
+}
+
+impl Palette {
+    /// git's default diff colours.
+    fn new(enabled: bool) -> Self {
+        let color = |code: &'static str| if enabled { code } else { "" };
+
+        Self {
+            meta: color("\x1b[1m"),
+            frag: color("\x1b[36m"),
+            old: color("\x1b[31m"),
+            new: color("\x1b[32m"),
+            context: "",
+            func: "",
+            whitespace: color("\x1b[41m"),
+            old_moved: color("\x1b[1;35m"),
+            old_moved_alt: color("\x1b[1;34m"),
+            old_moved_dim: color("\x1b[2m"),
+            old_moved_alt_dim: color("\x1b[2;3m"),
+            new_moved: color("\x1b[1;36m"),
+            new_moved_alt: color("\x1b[1;33m"),
+            new_moved_dim: color("\x1b[2m"),
+            new_moved_alt_dim: color("\x1b[2;3m"),
+            reset: color("\x1b[m"),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        !self.reset.is_empty()
+    }
+}
+
+/// A line of patch output, kept until the whole diff is known so moved lines can be found.
+#[derive(Debug, Clone, PartialEq)]
+enum Symbol {
+    /// Output that is already formatted, such as the `diff --git` header block.
+    Raw(Vec<u8>),
+    Fragment {
+        header: String,
+        function: Vec<u8>,
+    },
+    /// Lines of the file, without their newline.
+    Context(Vec<u8>),
+    Removed(Vec<u8>),
+    Added(Vec<u8>),
+    /// `\ No newline at end of file` after the previous line.
+    Incomplete,
+}
+
+const MOVED: u8 = 1;
+const MOVED_ALT: u8 = 2;
+const MOVED_UNINTERESTING: u8 = 4;
+
+/// Marks a blank line added at the end of the file, which git shows as a whitespace error.
+const BLANK_AT_EOF: u8 = 8;
+
+/// Moved blocks need at least this many alphanumeric characters to count, except in `plain` mode.
+const MOVED_MIN_ALNUM: usize = 20;
+
+#[derive(Debug, Clone)]
+struct Emitted {
+    symbol: Symbol,
+    /// `MOVED*` bits from `--color-moved`, and `BLANK_AT_EOF`.
+    flags: u8,
+}
+
+struct Printer<'r> {
+    repo: &'r Repository,
+    options: &'r DiffOptions,
+    colors: Palette,
+    abbrev: usize,
+    contents: Contents<'r>,
+    symbols: Vec<Emitted>,
+}
+
+/// The `--word-diff` texts collected from a run of removed and added lines.
+#[derive(Default)]
+struct WordBuffers {
+    minus: Vec<u8>,
+    plus: Vec<u8>,
+}
+
+impl Printer<'_> {
+    fn print(&mut self, pairs: &[FilePair]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+
+        if pairs.is_empty() {
+            return Ok(out);
+        }
+
+        // Like git, `--name-status` leaves out the stat and the patch.
+        if self.options.name_status {
+            for pair in pairs {
+                out.extend(self.name_status_line(pair).into_bytes());
+            }
+            return Ok(out);
+        }
+
+        if self.options.stat {
+            self.stat(pairs, &mut out)?;
+        }
+
+        if self.options.summary {
+            for pair in pairs {
+                out.extend(summary_line(pair).into_bytes());
+            }
+        }
+
+        if self.options.patch {
+            if self.options.stat || self.options.summary {
+                out.push(b'\n');
+            }
+
+            for pair in pairs {
+                self.patch(pair)?;
+            }
+
+            if self.colors.enabled()
+                && self.options.word_diff.is_none()
+                && self.options.color_moved != ColorMoved::No
+            {
+                self.mark_moved_lines();
+
+                if self.options.color_moved == ColorMoved::DimmedZebra {
+                    self.dim_moved_lines();
+                }
+            }
+
+            for emitted in &self.symbols {
+                self.render(emitted, &mut out);
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn name_status_line(&self, pair: &FilePair) -> String {
+        let quote = |path: &[u8]| quote_path(path, false);
+
+        match pair.status {
+            'R' | 'C' => format!(
+                "{}{:03}\t{}\t{}\n",
+                pair.status,
+                pair.score,
+                quote(pair.old_path()),
+                quote(pair.new_path())
+            ),
+            status => format!("{status}\t{}\n", quote(pair.new_path())),
+        }
+    }
+
+    fn emit(&mut self, symbol: Symbol) {
+        self.symbols.push(Emitted { symbol, flags: 0 });
+    }
+
+    fn meta_line(&self, text: &str) -> String {
+        format!("{}{text}{}\n", self.colors.meta, self.colors.reset)
+    }
+
+    fn abbreviated(&self, side: Option<&DiffSide>) -> Result<String> {
+        match side {
+            Some(side) => abbreviate(self.repo, &side.hash, self.abbrev),
+            None => Ok("0".repeat(self.abbrev)),
+        }
+    }
+
+    /// The patch for one pair. A change between a file and a symlink (or submodule) is shown as
+    /// a deletion followed by a creation, as git does.
+    fn patch(&mut self, pair: &FilePair) -> Result<()> {
+        if pair.status == 'U' {
+            let line = [b"* Unmerged path ", pair.path.as_slice(), b"\n"].concat();
+            self.emit(Symbol::Raw(line));
+            return Ok(());
+        }
+
+        match (&pair.old, &pair.new) {
+            (Some(old), Some(new)) if file_type(old.mode) != file_type(new.mode) => {
+                self.file_patch(pair, Some(old), None)?;
+                self.file_patch(pair, None, Some(new))
+            }
+            (old, new) => self.file_patch(pair, old.as_ref(), new.as_ref()),
+        }
+    }
+
+    fn file_patch(
+        &mut self,
+        pair: &FilePair,
+        old: Option<&DiffSide>,
+        new: Option<&DiffSide>,
+    ) -> Result<()> {
+        let quote =
+            |prefix: &str, path: &[u8]| quote_path(&[prefix.as_bytes(), path].concat(), false);
+        let a = quote("a/", pair.old_path());
+        let b = quote("b/", pair.new_path());
+        let old_label = if old.is_some() {
+            a.clone()
+        } else {
+            "/dev/null".to_owned()
+        };
+        let new_label = if new.is_some() {
+            b.clone()
+        } else {
+            "/dev/null".to_owned()
+        };
+
+        // What git calls the extended header: similarity, rename or copy lines and `index`.
+        let mut metainfo = String::new();
+        let mut must_show_header = false;
+
+        if matches!(pair.status, 'R' | 'C') {
+            let (from, to) = if pair.status == 'R' {
+                ("rename from", "rename to")
+            } else {
+                ("copy from", "copy to")
+            };
+
+            metainfo += &self.meta_line(&format!("similarity index {}%", pair.score));
+            metainfo += &self.meta_line(&format!("{from} {}", quote_path(pair.old_path(), false)));
+            metainfo += &self.meta_line(&format!("{to} {}", quote_path(pair.new_path(), false)));
+            must_show_header = true;
+        }
+
+        if old.map(|side| &side.hash) != new.map(|side| &side.hash) {
+            let mut index = format!(
+                "index {}..{}",
+                self.abbreviated(old)?,
+                self.abbreviated(new)?
+            );
+
+            if let (Some(old), Some(new)) = (old, new) {
+                if old.mode == new.mode {
+                    index += &format!(" {:06o}", old.mode);
+                }
+            }
+
+            metainfo += &self.meta_line(&index);
+        }
+
+        let mut header = self.meta_line(&format!("diff --git {a} {b}"));
+
+        match (old, new) {
+            (None, Some(new)) => {
+                header += &self.meta_line(&format!("new file mode {:06o}", new.mode));
+                must_show_header = true;
+            }
+            (Some(old), None) => {
+                header += &self.meta_line(&format!("deleted file mode {:06o}", old.mode));
+                must_show_header = true;
+            }
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                header += &self.meta_line(&format!("old mode {:06o}", old.mode));
+                header += &self.meta_line(&format!("new mode {:06o}", new.mode));
+                must_show_header = true;
+            }
+            _ => {}
+        }
+
+        header += &metainfo;
+
+        let old_data = old
+            .map(|side| self.contents.read(side))
+            .transpose()?
+            .unwrap_or_default();
+        let new_data = new
+            .map(|side| self.contents.read(side))
+            .transpose()?
+            .unwrap_or_default();
+
+        if is_binary(&old_data) || is_binary(&new_data) {
+            if must_show_header || old_data != new_data {
+                self.emit(Symbol::Raw(header.into_bytes()));
+            }
+            if old_data != new_data {
+                self.emit(Symbol::Raw(
+                    format!("Binary files {old_label} and {new_label} differ\n").into_bytes(),
+                ));
+            }
+            return Ok(());
+        }
+
+        if must_show_header {
+            self.emit(Symbol::Raw(std::mem::take(&mut header).into_bytes()));
+        }
+
+        let old_lines = split_lines(&old_data);
+        let new_lines = split_lines(&new_data);
+        let file_hunks = hunks(
+            &old_lines,
+            &new_lines,
+            self.options.context,
+            self.options.algorithm,
+        );
+
+        if file_hunks.is_empty() {
+            return Ok(());
+        }
+
+        let label = |prefix: &str, label: &str| {
+            let tab = if label.contains(' ') { "\t" } else { "" };
+            format!(
+                "{}{prefix} {label}{}{tab}\n",
+                self.colors.meta, self.colors.reset
+            )
+        };
+
+        header += &label("---", &old_label);
+        header += &label("+++", &new_label);
+        self.emit(Symbol::Raw(header.into_bytes()));
+
+        let mut words = WordBuffers::default();
+        let blank_at_eof = blank_at_eof(&old_data, &new_data);
+
+        for hunk in &file_hunks {
+            self.flush_words(&mut words);
+            self.emit(Symbol::Fragment {
+                header: hunk.header(),
+                function: function_line(&old_lines, hunk).unwrap_or_default().to_vec(),
+            });
+
+            // Counted the way git does, from the numbers in the header.
+            let (mut old_line, mut new_line) = (hunk.old_start, hunk.new_start);
+
+            for line in &hunk.lines {
+                let (DiffLine::Context(text) | DiffLine::Removed(text) | DiffLine::Added(text)) =
+                    line;
+                let content = text.strip_suffix(b"\n").unwrap_or(text).to_vec();
+                let incomplete = !text.ends_with(b"\n");
+
+                if self.options.word_diff.is_some() {
+                    match line {
+                        DiffLine::Removed(_) => words.minus.extend(content.iter().chain(b"\n")),
+                        DiffLine::Added(_) => words.plus.extend(content.iter().chain(b"\n")),
+                        DiffLine::Context(_) => {
+                            self.flush_words(&mut words);
+                            self.emit_word_context(&content);
+                        }
+                    }
+                    continue;
+                }
+
+                match line {
+                    DiffLine::Context(_) => {
+                        old_line += 1;
+                        new_line += 1;
+                        self.emit(Symbol::Context(content));
+                    }
+                    DiffLine::Removed(_) => {
+                        old_line += 1;
+                        self.emit(Symbol::Removed(content));
+                    }
+                    DiffLine::Added(_) => {
+                        new_line += 1;
+                        let blank = blank_at_eof.is_some_and(|(old_start, new_start)| {
+                            old_start <= old_line
+                                && new_start <= new_line
+                                && content.iter().all(|b| is_space(*b))
+                        });
+                        self.symbols.push(Emitted {
+                            symbol: Symbol::Added(content),
+                            flags: if blank { BLANK_AT_EOF } else { 0 },
+                        });
+                    }
+                }
+
+                if incomplete {
+                    self.emit(Symbol::Incomplete);
+                }
+            }
+        }
+
+        self.flush_words(&mut words);
+
+        Ok(())
+    }
+
+    /// A context line in `--word-diff` output, without its leading space except in porcelain.
+    fn emit_word_context(&mut self, content: &[u8]) {
+        let mut out = Vec::new();
+
+        if self.options.word_diff == Some(WordDiff::Porcelain) {
+            let line = [b" ", content, b"\n"].concat();
+            emit_line(&mut out, self.colors.context, self.colors.reset, &line);
+            out.extend_from_slice(b"~\n");
+        } else {
+            let line = [content, b"\n"].concat();
+            emit_line(&mut out, self.colors.context, self.colors.reset, &line);
+        }
+
+        self.emit(Symbol::Raw(out));
+    }
+
+    /// Shows the words that changed between the collected removed and added lines (git's
+    /// `diff_words_show`): text common to both comes from the added side, removed words are
+    /// marked as old and added ones as new.
+    fn flush_words(&mut self, words: &mut WordBuffers) {
+        let Some(mode) = self.options.word_diff else {
+            return;
+        };
+
+        if words.minus.is_empty() && words.plus.is_empty() {
+            return;
+        }
+
+        let style = WordStyle::new(mode, &self.colors);
+        let mut out = Vec::new();
+        let (minus, plus) = (
+            std::mem::take(&mut words.minus),
+            std::mem::take(&mut words.plus),
+        );
+
+        if plus.is_empty() {
+            style.write(&mut out, &style.old, &minus);
+            self.emit(Symbol::Raw(out));
+            return;
+        }
+
+        let minus_words = split_words(&minus);
+        let plus_words = split_words(&plus);
+        let minus_texts: Vec<&[u8]> = minus_words.iter().map(|(b, e)| &minus[*b..*e]).collect();
+        let plus_texts: Vec<&[u8]> = plus_words.iter().map(|(b, e)| &plus[*b..*e]).collect();
+
+        // The span of `count` words from `first`, or the empty span after the word before it.
+        let span = |words: &[(usize, usize)], first: usize, count: usize| {
+            if count > 0 {
+                (words[first].0, words[first + count - 1].1)
+            } else if first == 0 {
+                (0, 0)
+            } else {
+                (words[first - 1].1, words[first - 1].1)
+            }
+        };
+
+        let mut current_plus = 0;
+        let edits = diff_words(&minus_texts, &plus_texts);
+        let (mut i, mut j, mut k) = (0, 0, 0);
+
+        while k < edits.len() {
+            if let Edit::Equal(..) = edits[k] {
+                i += 1;
+                j += 1;
+                k += 1;
+                continue;
+            }
+
+            let (first_minus, first_plus) = (i, j);
+            while let Some(edit) = edits.get(k).filter(|edit| !matches!(edit, Edit::Equal(..))) {
+                match edit {
+                    Edit::Delete(_) => i += 1,
+                    _ => j += 1,
+                }
+                k += 1;
+            }
+
+            let (minus_begin, minus_end) = span(&minus_words, first_minus, i - first_minus);
+            let (plus_begin, plus_end) = span(&plus_words, first_plus, j - first_plus);
+
+            if current_plus != plus_begin {
+                style.write(&mut out, &style.ctx, &plus[current_plus..plus_begin]);
+            }
+            if minus_begin != minus_end {
+                style.write(&mut out, &style.old, &minus[minus_begin..minus_end]);
+            }
+            if plus_begin != plus_end {
+                style.write(&mut out, &style.new, &plus[plus_begin..plus_end]);
+            }
+
+            current_plus = plus_end;
+        }
+
+        if current_plus != plus.len() {
+            style.write(&mut out, &style.ctx, &plus[current_plus..]);
+        }
+
+        self.emit(Symbol::Raw(out));
+    }
+
+    /// Finds blocks of removed lines that were added elsewhere and the other way around
+    /// (git's `mark_color_as_moved`). Blocks are tracked as the set of earlier occurrences they
+    /// could still be a copy of; adjacent blocks alternate colours.
+    fn mark_moved_lines(&mut self) {
+        let mode = self.options.color_moved;
+        let count = self.symbols.len();
+
+        // Each line's content class, and per class the removed and added lines.
+        let mut classes = HashMap::<&[u8], usize>::new();
+        let mut line_class = vec![usize::MAX; count];
+        let mut removed = Vec::<Vec<usize>>::new();
+        let mut added = Vec::<Vec<usize>>::new();
+        // The next line of the same kind, if it directly follows.
+        let mut next_line = vec![None; count];
+        let mut previous: Option<usize> = None;
+
+        for (n, emitted) in self.symbols.iter().enumerate() {
+            let (line, is_added) = match &emitted.symbol {
+                Symbol::Removed(line) => (line, false),
+                Symbol::Added(line) => (line, true),
+                _ => {
+                    previous = None;
+                    continue;
+                }
+            };
+
+            let next = classes.len();
+            let class = *classes.entry(line).or_insert(next);
+            if class == removed.len() {
+                removed.push(Vec::new());
+                added.push(Vec::new());
+            }
+            line_class[n] = class;
+
+            if let Some(prev) = previous {
+                if is_added == matches!(self.symbols[prev].symbol, Symbol::Added(_)) {
+                    next_line[prev] = Some(n);
+                }
+            }
+            previous = Some(n);
+
+            if is_added {
+                added[class].push(n);
+            } else {
+                removed[class].push(n);
+            }
+        }
+
+        let mut pmb: Vec<usize> = Vec::new();
+        let mut flipped = false;
+        let mut block_length = 0;
+        // The kind of line the current block is made of.
+        let mut moved_symbol: Option<bool> = None;
+        let mut n = 0;
+
+        while n < count {
+            let kind = match self.symbols[n].symbol {
+                Symbol::Added(_) => Some(true),
+                Symbol::Removed(_) => Some(false),
+                _ => None,
+            };
+            let mut matches: &[usize] = match kind {
+                Some(true) => &removed[line_class[n]],
+                Some(false) => &added[line_class[n]],
+                None => {
+                    flipped = false;
+                    &[]
+                }
+            };
+
+            if !pmb.is_empty() && (matches.is_empty() || kind != moved_symbol) {
+                if !self.adjust_last_block(n, block_length) && block_length > 1 {
+                    // Rewind in case another match starts at the second line of the block.
+                    matches = &[];
+                    n -= block_length;
+                }
+                pmb.clear();
+                block_length = 0;
+                flipped = false;
+            }
+
+            if matches.is_empty() {
+                moved_symbol = None;
+                n += 1;
+                continue;
+            }
+
+            if mode == ColorMoved::Plain {
+                self.symbols[n].flags |= MOVED;
+                n += 1;
+                continue;
+            }
+
+            // Advance each potential block to its next line, dropping those that stop matching.
+            pmb.retain_mut(|entry| match next_line[*entry] {
+                Some(next) if line_class[next] == line_class[n] => {
+                    *entry = next;
+                    true
+                }
+                _ => false,
+            });
+
+            if pmb.is_empty() {
+                let contiguous = self.adjust_last_block(n, block_length);
+
+                if !contiguous && block_length > 1 {
+                    n -= block_length;
+                } else {
+                    pmb.extend_from_slice(matches);
+                }
+
+                flipped = contiguous && !pmb.is_empty() && moved_symbol == kind && !flipped;
+                moved_symbol = if pmb.is_empty() { None } else { kind };
+                block_length = 0;
+            }
+
+            if !pmb.is_empty() {
+                block_length += 1;
+                self.symbols[n].flags |= MOVED;
+                if flipped && mode != ColorMoved::Blocks {
+                    self.symbols[n].flags |= MOVED_ALT;
+                }
+            }
+
+            n += 1;
+        }
+
+        self.adjust_last_block(n, block_length);
+    }
+
+    /// Unmarks the block of `length` lines before `n` if it is too short to be interesting,
+    /// returning whether it stays marked.
+    fn adjust_last_block(&mut self, n: usize, length: usize) -> bool {
+        if self.options.color_moved == ColorMoved::Plain {
+            return length > 0;
+        }
+
+        let mut alnum = 0;
+        for i in 1..=length {
+            if let Symbol::Removed(line) | Symbol::Added(line) = &self.symbols[n - i].symbol {
+                alnum += line.iter().filter(|b| b.is_ascii_alphanumeric()).count();
+                if alnum >= MOVED_MIN_ALNUM {
+                    return true;
+                }
+            }
+        }
+
+        for i in 1..=length {
+            self.symbols[n - i].flags &= !(MOVED | MOVED_ALT);
+        }
+
+        false
+    }
+
+    /// For `dimmed-zebra`: only the first and last line of each moved block keep their colour.
+    fn dim_moved_lines(&mut self) {
+        let is_line =
+            |emitted: &Emitted| matches!(emitted.symbol, Symbol::Removed(_) | Symbol::Added(_));
+        let zebra = |flags: u8| flags & (MOVED | MOVED_ALT);
+
+        for n in 0..self.symbols.len() {
+            let flags = self.symbols[n].flags;
+
+            if !is_line(&self.symbols[n]) || flags & MOVED == 0 {
+                continue;
+            }
+
+            let prev = n
+                .checked_sub(1)
+                .map(|i| &self.symbols[i])
+                .filter(|emitted| is_line(emitted))
+                .map(|emitted| emitted.flags);
+            let next = self
+                .symbols
+                .get(n + 1)
+                .filter(|emitted| is_line(emitted))
+                .map(|emitted| emitted.flags);
+
+            let inside = prev.is_some_and(|prev| zebra(prev) == zebra(flags))
+                && next.is_some_and(|next| zebra(next) == zebra(flags));
+            let boundary = |other: Option<u8>| {
+                other.is_some_and(|other| {
+                    other & MOVED != 0 && other & MOVED_ALT != flags & MOVED_ALT
+                })
+            };
+
+            if inside || !(boundary(prev) || boundary(next)) {
+                self.symbols[n].flags |= MOVED_UNINTERESTING;
+            }
+        }
+    }
+
+    fn render(&self, emitted: &Emitted, out: &mut Vec<u8>) {
+        let colors = &self.colors;
+
+        match &emitted.symbol {
+            Symbol::Raw(text) => out.extend_from_slice(text),
+            Symbol::Fragment { header, function } => {
+                out.extend(format!("{}{header}{}", colors.frag, colors.reset).into_bytes());
+                if !function.is_empty() {
+                    out.extend(
+                        format!("{} {}{}", colors.context, colors.reset, colors.func).bytes(),
+                    );
+                    out.extend_from_slice(function);
+                    out.extend_from_slice(colors.reset.as_bytes());
+                }
+                out.push(b'\n');
+            }
+            Symbol::Context(line) => {
+                emit_line_0(
+                    out,
+                    Some(colors.context),
+                    None,
+                    colors.reset,
+                    Some(b' '),
+                    line,
+                );
+                out.push(b'\n');
+            }
+            Symbol::Removed(line) => {
+                let set = moved_color(
+                    emitted.flags,
+                    colors.old,
+                    [
+                        colors.old_moved,
+                        colors.old_moved_alt,
+                        colors.old_moved_dim,
+                        colors.old_moved_alt_dim,
+                    ],
+                );
+                emit_line_0(out, Some(set), None, colors.reset, Some(b'-'), line);
+                out.push(b'\n');
+            }
+            Symbol::Added(line) => {
+                let set = moved_color(
+                    emitted.flags,
+                    colors.new,
+                    [
+                        colors.new_moved,
+                        colors.new_moved_alt,
+                        colors.new_moved_dim,
+                        colors.new_moved_alt_dim,
+                    ],
+                );
+
+                if colors.enabled() && emitted.flags & BLANK_AT_EOF != 0 {
+                    let ws = colors.whitespace;
+                    emit_line_0(out, Some(ws), None, colors.reset, Some(b'+'), line);
+                } else if colors.enabled() {
+                    emit_line_0(out, Some(set), None, colors.reset, Some(b'+'), b"");
+                    whitespace_check_emit(out, line, set, colors.reset, colors.whitespace);
+                } else {
+                    emit_line_0(out, Some(set), None, colors.reset, Some(b'+'), line);
+                }
+                out.push(b'\n');
+            }
+            Symbol::Incomplete => {
+                emit_line(
+                    out,
+                    colors.context,
+                    colors.reset,
+                    b"\\ No newline at end of file\n",
+                );
+            }
+        }
+    }
+
+    /// `--stat`: a line per file with the number of changed lines and a `+`/`-` graph scaled to
+    /// the width, then the totals (git's `show_stats`).
+    fn stat(&mut self, pairs: &[FilePair], out: &mut Vec<u8>) -> Result<()> {
+        struct FileStat {
+            name: String,
+            added: usize,
+            deleted: usize,
+            binary: bool,
+            unmerged: bool,
+        }
+
+        let mut files = Vec::new();
+
+        for pair in pairs {
+            let name = if matches!(pair.status, 'R' | 'C') {
+                pprint_rename(pair.old_path(), pair.new_path())
+            } else {
+                quote_path(pair.new_path(), false)
+            };
+
+            if pair.status == 'U' {
+                files.push(FileStat {
+                    name,
+                    added: 0,
+                    deleted: 0,
+                    binary: false,
+                    unmerged: true,
+                });
+                continue;
+            }
+
+            let read = |side: &Option<DiffSide>| -> Result<Vec<u8>> {
+                side.as_ref()
+                    .map_or(Ok(Vec::new()), |side| self.contents.read(side))
+            };
+            let same = pair.old.as_ref().map(|side| &side.hash)
+                == pair.new.as_ref().map(|side| &side.hash);
+            let (old, new) = (read(&pair.old)?, read(&pair.new)?);
+            let mut stat = FileStat {
+                name,
+                added: 0,
+                deleted: 0,
+                binary: is_binary(&old) || is_binary(&new),
+                unmerged: false,
+            };
+
+            if stat.binary {
+                if !same {
+                    (stat.added, stat.deleted) = (new.len(), old.len());
+                }
+            } else if !same {
+                for edit in diff_lines(
+                    &split_lines(&old),
+                    &split_lines(&new),
+                    self.options.algorithm,
+                ) {
+                    match edit {
+                        Edit::Insert(_) => stat.added += 1,
+                        Edit::Delete(_) => stat.deleted += 1,
+                        Edit::Equal(..) => {}
+                    }
+                }
+            }
+
+            files.push(stat);
+        }
+
+        let decimal_width = |n: usize| n.to_string().len();
+        let mut max_len = 0;
+        let mut max_change = 0;
+        let mut bin_width = 0;
+        let mut number_width = 0;
+
+        for file in &files {
+            max_len = max_len.max(file.name.chars().count());
+
+            if file.unmerged {
+                bin_width = bin_width.max(8);
+            } else if file.binary {
+                bin_width =
+                    bin_width.max(14 + decimal_width(file.added) + decimal_width(file.deleted));
+                number_width = 3;
+            } else {
+                max_change = max_change.max(file.added + file.deleted);
+            }
+        }
+
+        number_width = number_width.max(decimal_width(max_change));
+
+        let width = self.options.stat_width.unwrap_or_else(terminal_columns);
+        let width = width.max(16 + 6 + number_width);
+
+        let mut graph_width = if max_change + 4 > bin_width {
+            max_change
+        } else {
+            bin_width - 4
+        };
+        let mut name_width = max_len;
+
+        if name_width + number_width + 6 + graph_width > width {
+            let limit = (width * 3 / 8).saturating_sub(number_width + 6);
+            if graph_width > limit {
+                graph_width = limit.max(6);
+            }
+
+            if name_width > width.saturating_sub(number_width + 6 + graph_width) {
+                name_width = width.saturating_sub(number_width + 6 + graph_width);
+            } else {
+                graph_width = width - number_width - 6 - name_width;
+            }
+        }
+
+        let scale_linear = |it: usize| {
+            if it == 0 {
+                0
+            } else {
+                1 + it * (graph_width - 1) / max_change
+            }
+        };
+        let colors = &self.colors;
+        let (mut total_files, mut adds, mut dels) = (0, 0, 0);
+
+        for file in &files {
+            // Cut long names from the left, at a directory boundary if possible.
+            let mut prefix = "";
+            let mut name: &str = &file.name;
+            let mut len = name_width;
+            let name_len = name.chars().count();
+
+            if name_width < name_len {
+                prefix = "...";
+                len = len.saturating_sub(3);
+                let skip = name_len - len;
+                name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+                if let Some(slash) = name.find('/') {
+                    name = &name[slash..];
+                }
+            }
+
+            let padding = len.saturating_sub(name.chars().count());
+            let line_start = format!(" {prefix}{name}{:padding$} | ", "");
+
+            if file.unmerged {
+                out.extend(format!("{line_start}{:>number_width$}\n", "Unmerged").into_bytes());
+                continue;
+            }
+
+            total_files += 1;
+
+            if file.binary {
+                out.extend(format!("{line_start}{:>number_width$}", "Bin").into_bytes());
+                if file.added != 0 || file.deleted != 0 {
+                    out.extend(
+                        format!(
+                            " {}{}{} -> {}{}{} bytes",
+                            colors.old,
+                            file.deleted,
+                            colors.reset,
+                            colors.new,
+                            file.added,
+                            colors.reset
+                        )
+                        .into_bytes(),
+                    );
+                }
+                out.push(b'\n');
+                continue;
+            }
+
+            adds += file.added;
+            dels += file.deleted;
+
+            let (mut add, mut del) = (file.added, file.deleted);
+            if graph_width <= max_change {
+                let mut total = scale_linear(add + del);
+                if total < 2 && add > 0 && del > 0 {
+                    total = 2;
+                }
+                if add < del {
+                    add = scale_linear(add);
+                    del = total - add;
+                } else {
+                    del = scale_linear(del);
+                    add = total - del;
+                }
+            }
+
+            let changes = file.added + file.deleted;
+            out.extend(
+                format!(
+                    "{line_start}{changes:>number_width$}{}",
+                    if changes > 0 { " " } else { "" }
+                )
+                .into_bytes(),
+            );
+            for (count, sign, color) in [(add, '+', colors.new), (del, '-', colors.old)] {
+                if count > 0 {
+                    out.extend(
+                        format!("{color}{}{}", sign.to_string().repeat(count), colors.reset)
+                            .into_bytes(),
+                    );
+                }
+            }
+            out.push(b'\n');
+        }
+
+        out.extend(stat_summary(total_files, adds, dels).into_bytes());
+
+        Ok(())
+    }
+}
+
+/// The colour of a removed or added line given its `--color-moved` flags: `moved` holds the
+/// moved, alternate, dimmed and dimmed alternate colours.
+fn moved_color(flags: u8, plain: &'static str, moved: [&'static str; 4]) -> &'static str {
+    let [moved, alt, dim, alt_dim] = moved;
+
+    if flags & MOVED_ALT != 0 && flags & MOVED_UNINTERESTING != 0 {
+        alt_dim
+    } else if flags & MOVED_ALT != 0 {
+        alt
+    } else if flags & MOVED_UNINTERESTING != 0 {
+        dim
+    } else if flags & MOVED != 0 {
+        moved
+    } else {
+        plain
+    }
+}
+
+/// git's `emit_line_0`, for a line without its newline: the sign and text are wrapped in the
+/// colours, and a reset ends every line that started one.
+fn emit_line_0(
+    out: &mut Vec<u8>,
+    set_sign: Option<&str>,
+    set: Option<&str>,
+    reset: &str,
+    first: Option<u8>,
+    line: &[u8],
+) {
+    let (line, carriage_return) = match line.strip_suffix(b"\r") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+
+    if line.is_empty() && first.is_none() {
+        if carriage_return {
+            out.push(b'\r');
+        }
+        return;
+    }
+
+    let mut needs_reset = false;
+
+    if let Some(set_sign) = set_sign {
+        out.extend_from_slice(set_sign.as_bytes());
+        needs_reset = true;
+    }
+
+    out.extend(first);
+
+    if !line.is_empty() {
+        if let Some(set) = set {
+            if set_sign.is_some_and(|set_sign| set_sign != set) {
+                out.extend_from_slice(reset.as_bytes());
+            }
+            out.extend_from_slice(set.as_bytes());
+        }
+        out.extend_from_slice(line);
+        needs_reset = true;
+    }
+
+    if needs_reset {
+        out.extend_from_slice(reset.as_bytes());
+    }
+    if carriage_return {
+        out.push(b'\r');
+    }
+}
+
+/// git's `emit_line`: `line` in the colour `set`, keeping its newline after the reset.
+fn emit_line(out: &mut Vec<u8>, set: &str, reset: &str, line: &[u8]) {
+    let (line, newline) = match line.strip_suffix(b"\n") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+
+    emit_line_0(out, Some(set), None, reset, None, line);
+
+    if newline {
+        out.push(b'\n');
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Writes an added line's text highlighting whitespace errors with git's default rules:
+/// trailing whitespace, and spaces before a tab in the indent (git's `ws_check_emit`).
+fn whitespace_check_emit(out: &mut Vec<u8>, line: &[u8], set: &str, reset: &str, ws: &str) {
+    let trailing = line
+        .iter()
+        .rposition(|b| !is_space(*b))
+        .map_or(0, |i| i + 1);
+    let mut written = 0;
+
+    for i in 0..trailing {
+        match line[i] {
+            b' ' => continue,
+            b'\t' => {}
+            _ => break,
+        }
+
+        if written < i {
+            out.extend_from_slice(ws.as_bytes());
+            out.extend_from_slice(&line[written..i]);
+            out.extend_from_slice(reset.as_bytes());
+            out.push(line[i]);
+        } else {
+            out.extend_from_slice(&line[written..=i]);
+        }
+        written = i + 1;
+    }
+
+    if trailing > written {
+        out.extend_from_slice(set.as_bytes());
+        out.extend_from_slice(&line[written..trailing]);
+        out.extend_from_slice(reset.as_bytes());
+    }
+    if trailing != line.len() {
+        out.extend_from_slice(ws.as_bytes());
+        out.extend_from_slice(&line[trailing..]);
+        out.extend_from_slice(reset.as_bytes());
+    }
+}
+
+/// Where the blank lines that the new version adds at its end start in each version, counted as
+/// git's `check_blank_at_eof` does; `None` unless `new` ends in more blank lines than `old`.
+fn blank_at_eof(old: &[u8], new: &[u8]) -> Option<(usize, usize)> {
+    let (old_blank, new_blank) = (trailing_blank_lines(old), trailing_blank_lines(new));
+
+    if new_blank <= old_blank {
+        return None;
+    }
+
+    let lines = |data: &[u8]| {
+        let newlines = data.iter().filter(|b| **b == b'\n').count();
+        newlines + usize::from(data.last().is_some_and(|b| *b != b'\n'))
+    };
+
+    Some((lines(old) - old_blank + 1, lines(new) - new_blank + 1))
+}
+
+/// The number of whitespace-only lines at the end of `data`. As in git, the first line of the
+/// file is never counted.
+fn trailing_blank_lines(data: &[u8]) -> usize {
+    let Some(mut end) = data.len().checked_sub(1) else {
+        return 0;
+    };
+
+    if data[end] == b'\n' {
+        let Some(before) = end.checked_sub(1) else {
+            return 0;
+        };
+        end = before;
+    }
+
+    let mut count = 0;
+    while end > 0 {
+        let start = data[..=end]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |eol| eol + 1);
+
+        if !data[start..=end].iter().all(|b| is_space(*b)) {
+            break;
+        }
+
+        count += 1;
+        match start.checked_sub(2) {
+            Some(before) => end = before,
+            None => break,
+        }
+    }
+
+    count
+}
+
+/// The byte ranges of the words of `text`: runs of anything but whitespace.
+fn split_words(text: &[u8]) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        if is_space(text[i]) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < text.len() && !is_space(text[i]) {
+            i += 1;
+        }
+        words.push((start, i));
+    }
+
+    words
+}
+
+/// How one kind of text is marked in `--word-diff` output.
+struct WordElement {
+    color: &'static str,
+    prefix: &'static str,
+    suffix: &'static str,
+}
+
+struct WordStyle {
+    new: WordElement,
+    old: WordElement,
+    ctx: WordElement,
+    newline: &'static str,
+    reset: &'static str,
+}
+
+impl WordStyle {
+    fn new(mode: WordDiff, colors: &Palette) -> Self {
+        let element = |color, prefix, suffix| WordElement {
+            color,
+            prefix,
+            suffix,
+        };
+
+        let (new, old, ctx, newline) = match mode {
+            WordDiff::Porcelain => (("+", "\n"), ("-", "\n"), (" ", "\n"), "~\n"),
+            WordDiff::Plain => (("{+", "+}"), ("[-", "-]"), ("", ""), "\n"),
+            WordDiff::Color => (("", ""), ("", ""), ("", ""), "\n"),
+        };
+
+        Self {
+            new: element(colors.new, new.0, new.1),
+            old: element(colors.old, old.0, old.1),
+            ctx: element(colors.context, ctx.0, ctx.1),
+            newline,
+            reset: colors.reset,
+        }
+    }
+
+    /// Writes `text` as `element`, marking each line of it separately (git's
+    /// `fn_out_diff_words_write_helper`).
+    fn write(&self, out: &mut Vec<u8>, element: &WordElement, mut text: &[u8]) {
+        while !text.is_empty() {
+            let end = text.iter().position(|b| *b == b'\n');
+            let segment = &text[..end.unwrap_or(text.len())];
+
+            if !segment.is_empty() {
+                out.extend_from_slice(element.color.as_bytes());
+                out.extend_from_slice(element.prefix.as_bytes());
+                out.extend_from_slice(segment);
+                out.extend_from_slice(element.suffix.as_bytes());
+                if !element.color.is_empty() {
+                    out.extend_from_slice(self.reset.as_bytes());
+                }
+            }
+
+            let Some(end) = end else {
+                return;
+            };
+
+            out.extend_from_slice(self.newline.as_bytes());
+            text = &text[end + 1..];
+        }
+    }
+}
+
+/// The `--summary` line of a pair, if it has one: files created or deleted, renames and copies,
+/// and mode changes.
+fn summary_line(pair: &FilePair) -> String {
+    let quote = |path: &[u8]| quote_path(path, false);
+
+    match (pair.status, &pair.old, &pair.new) {
+        ('A', _, Some(new)) => format!(" create mode {:06o} {}\n", new.mode, quote(&new.path)),
+        ('D', Some(old), _) => format!(" delete mode {:06o} {}\n", old.mode, quote(&old.path)),
+        (status @ ('R' | 'C'), Some(old), Some(new)) => {
+            let mut line = format!(
+                " {} {} ({}%)\n",
+                if status == 'R' { "rename" } else { "copy" },
+                pprint_rename(&old.path, &new.path),
+                pair.score
+            );
+            if old.mode != new.mode {
+                line += &format!(" mode change {:06o} => {:06o}\n", old.mode, new.mode);
+            }
+            line
+        }
+        ('M', Some(old), Some(new)) if old.mode != new.mode => format!(
+            " mode change {:06o} => {:06o} {}\n",
+            old.mode,
+            new.mode,
+            quote(&new.path)
+        ),
+        _ => String::new(),
+    }
+}
+
+/// A rename's name for `--stat`, with the common leading directories and trailing part
+/// factored out: `dir/{old => new}/file` (git's `pprint_rename`).
+fn pprint_rename(a: &[u8], b: &[u8]) -> String {
+    let quoted_a = quote_path(a, false);
+    let quoted_b = quote_path(b, false);
+
+    if quoted_a.as_bytes() != a || quoted_b.as_bytes() != b {
+        return format!("{quoted_a} => {quoted_b}");
+    }
+
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // Compare from the end, seeing the slash that ends the prefix if there is one.
+    let adjust = (prefix > 0) as usize;
+    let (mut i, mut j) = (a.len() as isize, b.len() as isize);
+    let mut suffix = 0;
+    let at = |text: &[u8], k: isize| text.get(k as usize).copied().unwrap_or(0);
+
+    while i >= (prefix - adjust) as isize && j >= (prefix - adjust) as isize && at(a, i) == at(b, j)
+    {
+        if at(a, i) == b'/' {
+            suffix = a.len() - i as usize;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+
+    if prefix + suffix > 0 {
+        format!(
+            "{}{{{} => {}}}{}",
+            text(&a[..prefix]),
+            text(&a[prefix..prefix + a_mid]),
+            text(&b[prefix..prefix + b_mid]),
+            text(&a[a.len() - suffix..])
+        )
+    } else {
+        format!("{} => {}", text(&a[..a_mid]), text(&b[..b_mid]))
+    }
+}
+
+/// The `N files changed, X insertions(+), Y deletions(-)` line, leaving out zero counts the way
+/// git does.
+fn stat_summary(files: usize, insertions: usize, deletions: usize) -> String {
+    if files == 0 {
+        return " 0 files changed\n".to_owned();
+    }
+
+    let plural =
+        |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+    let mut summary = format!(" {}", plural(files, "file changed", "files changed"));
+
+    if insertions > 0 || deletions == 0 {
+        summary += &format!(", {}", plural(insertions, "insertion(+)", "insertions(+)"));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary += &format!(", {}", plural(deletions, "deletion(-)", "deletions(-)"));
+    }
+
+    summary + "\n"
+}
+
+/// The width to fit `--stat` into: `COLUMNS`, the terminal's width, or 80.
+fn terminal_columns() -> usize {
+    if let Some(columns) = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .filter(|columns| *columns > 0)
+    {
+        return columns;
+    }
+
+    if io::stdout().is_terminal() {
+        let (columns, _) = termimad::terminal_size();
+        if columns > 0 {
+            return columns as usize;
+        }
+    }
+
+    80
+}
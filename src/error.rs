@@ -0,0 +1,120 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    path::PathBuf,
+};
+
+/// Everything that can go wrong inside pgit. Only the CLI decides how these are shown to the user.
+#[derive(Debug)]
+pub enum Error {
+    /// No loose or packed object exists with this name.
+    ObjectNotFound(String),
+    /// The object exists but its contents could not be decoded.
+    CorruptObject {
+        hash: String,
+        reason: String,
+    },
+    /// A `"<type> <size>"` object header, or a commit/tag header, could not be parsed.
+    BadHeader(String),
+    /// A config file exists but is not valid git-config syntax.
+    ConfigParse {
+        path: PathBuf,
+        reason: String,
+    },
+    /// `.git/index` could not be decoded.
+    CorruptIndex(String),
+    /// A ref file or `packed-refs` could not be decoded.
+    CorruptRef(String),
+    /// A revision expression did not name any object.
+    UnknownRevision(String),
+    /// Neither the path nor any of its parents is a git repository.
+    NotARepository(PathBuf),
+    /// The user asked for something pgit cannot do, e.g. a bad revision or a refused operation.
+    InvalidArgument(String),
+    /// The command line itself is malformed, e.g. an option value that does not parse.
+    Usage(String),
+    /// The operation was refused or found nothing to do, which git reports as an error with exit
+    /// code 1 rather than as a fatal one.
+    Failed(String),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn corrupt(hash: &str, reason: &str) -> Self {
+        Error::CorruptObject {
+            hash: hash.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+
+    /// The process exit code the CLI should use for this error, following git's convention of
+    /// 129 for usage errors, 1 for plain errors and 128 for everything fatal.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 129,
+            Error::Failed(_) => 1,
+            _ => 128,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ObjectNotFound(hash) => {
+                write!(f, "Could not find object `{hash}`")
+            }
+            Error::CorruptObject { hash, reason } => {
+                write!(f, "Object `{hash}` is corrupt: {reason}")
+            }
+            Error::BadHeader(reason) => {
+                write!(f, "Git object header is malformed: {reason}")
+            }
+            Error::ConfigParse { path, reason } => {
+                write!(
+                    f,
+                    "Could not parse config file `{}`: {reason}",
+                    path.display()
+                )
+            }
+            Error::CorruptIndex(reason) => {
+                write!(f, "Index file is corrupt: {reason}")
+            }
+            Error::CorruptRef(reason) => {
+                write!(f, "Ref store is corrupt: {reason}")
+            }
+            Error::UnknownRevision(spec) => {
+                write!(
+                    f,
+                    "Ambiguous argument `{spec}`: unknown revision or path not in the working tree"
+                )
+            }
+            Error::NotARepository(path) => {
+                write!(f, "`{}` is not a git repository", path.display())
+            }
+            Error::InvalidArgument(reason) | Error::Usage(reason) | Error::Failed(reason) => {
+                write!(f, "{reason}")
+            }
+            Error::Io(err) => {
+                write!(f, "{err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,651 @@
+use colored::{Color, Colorize};
+
+/// The colours branch lines cycle through, as in git's default `color.graph` palette.
+const COLUMN_COLORS: [(Color, bool); 12] = [
+    (Color::Red, false),
+    (Color::Green, false),
+    (Color::Yellow, false),
+    (Color::Blue, false),
+    (Color::Magenta, false),
+    (Color::Cyan, false),
+    (Color::Red, true),
+    (Color::Green, true),
+    (Color::Yellow, true),
+    (Color::Blue, true),
+    (Color::Magenta, true),
+    (Color::Cyan, true),
+];
+
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+/// The kind of line the graph prints next for the current commit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Padding,
+    Skip,
+    PreCommit,
+    Commit,
+    PostMerge,
+    Collapsing,
+}
+
+/// A branch line: the commit it leads to and the colour it is drawn in.
+#[derive(Debug, Clone)]
+struct Column {
+    commit: String,
+    color: usize,
+}
+
+/// One line of graph output, with its width on screen (colour codes take up no room).
+#[derive(Default)]
+struct Line {
+    text: String,
+    width: usize,
+}
+
+impl Line {
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.width += 1;
+    }
+
+    fn push_column(&mut self, column: &Column, c: char) {
+        let (color, bold) = COLUMN_COLORS[column.color];
+        let painted = c.to_string().color(color);
+
+        self.text += &if bold { painted.bold() } else { painted }.to_string();
+        self.width += 1;
+    }
+}
+
+/// Draws the ASCII history graph next to `log` output, one commit at a time. This follows git's
+/// `graph.c` line for line, so the drawing matches `git log --graph` exactly: commits must be fed
+/// in topological order with only their shown parents, and each call to [`Graph::next_line`]
+/// yields the graph part of the next output line.
+#[derive(Debug)]
+pub struct Graph {
+    commit: String,
+    parents: Vec<String>,
+    /// The width of the graph for the current commit, which every line is padded to.
+    width: usize,
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    commit_index: usize,
+    prev_commit_index: usize,
+    /// Whether the first parent of a merge is to the left (0) or below (1) the merge.
+    merge_layout: i32,
+    edges_added: i32,
+    prev_edges_added: i32,
+    /// The branch lines going into the current commit's row.
+    columns: Vec<Column>,
+    /// The branch lines coming out of it.
+    new_columns: Vec<Column>,
+    /// For each screen position of the current row, the new column the line there ends up in,
+    /// or -1.
+    mapping: Vec<i32>,
+    old_mapping: Vec<i32>,
+    mapping_size: usize,
+    default_color: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self {
+            commit: String::new(),
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+            mapping_size: 0,
+            // The first increment moves to the first colour.
+            default_color: COLUMN_COLORS.len() - 1,
+        }
+    }
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves on to `commit`, drawing edges to `parents`: the parents that will be shown too.
+    pub fn update(&mut self, commit: &str, parents: Vec<String>) {
+        self.commit = commit.to_owned();
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+
+        self.update_columns();
+
+        self.expansion_row = 0;
+
+        // If the previous commit never got to print all its lines, mark the gap.
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    /// Whether every line of the current commit's graph has been printed.
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    /// The graph part of the next line, and whether it is the line with the commit's `*`.
+    pub fn next_line(&mut self) -> (String, bool) {
+        let mut line = Line::default();
+        let mut commit_line = false;
+
+        match self.state {
+            State::Padding => self.output_padding_line(&mut line),
+            State::Skip => self.output_skip_line(&mut line),
+            State::PreCommit => self.output_pre_commit_line(&mut line),
+            State::Commit => {
+                self.output_commit_line(&mut line);
+                commit_line = true;
+            }
+            State::PostMerge => self.output_post_merge_line(&mut line),
+            State::Collapsing => self.output_collapsing_line(&mut line),
+        }
+
+        self.pad(&mut line);
+
+        (line.text, commit_line)
+    }
+
+    /// A line that only continues the branch lines, used between the lines of a commit's text
+    /// and between commits.
+    pub fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+
+        let mut line = Line::default();
+
+        for column in &self.columns {
+            line.push_column(column, '|');
+
+            if column.commit == self.commit && self.parents.len() > 2 {
+                for _ in 0..(self.parents.len() - 2) * 2 {
+                    line.push(' ');
+                }
+            } else {
+                line.push(' ');
+            }
+        }
+
+        self.pad(&mut line);
+        self.prev_state = State::Padding;
+
+        line.text
+    }
+
+    /// The lines up to and including the commit line, the last one without its newline.
+    pub fn show_commit(&mut self) -> String {
+        let mut text = String::new();
+
+        if self.is_commit_finished() {
+            return self.padding_line();
+        }
+
+        loop {
+            let (line, commit_line) = self.next_line();
+            text += &line;
+
+            if commit_line || self.is_commit_finished() {
+                return text;
+            }
+
+            text.push('\n');
+        }
+    }
+
+    /// The lines the current commit still needs once its text is printed, the last one without
+    /// its newline.
+    pub fn show_remainder(&mut self) -> String {
+        let mut text = String::new();
+
+        while !self.is_commit_finished() {
+            text += &self.next_line().0;
+
+            if !self.is_commit_finished() {
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn increment_column_color(&mut self) {
+        self.default_color = (self.default_color + 1) % COLUMN_COLORS.len();
+    }
+
+    fn find_commit_color(&self, commit: &str) -> usize {
+        self.columns
+            .iter()
+            .find(|column| column.commit == commit)
+            .map_or(self.default_color, |column| column.color)
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns
+            .iter()
+            .position(|column| column.commit == commit)
+    }
+
+    fn update_columns(&mut self) {
+        // The new columns of the previous commit are where this commit's row starts.
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+
+        let num_columns = self.columns.len();
+        let max_new_columns = num_columns + self.parents.len();
+
+        self.mapping_size = 2 * max_new_columns;
+        if self.mapping.len() < self.mapping_size {
+            self.mapping.resize(self.mapping_size, -1);
+            self.old_mapping.resize(self.mapping_size, -1);
+        }
+        self.mapping[..self.mapping_size].fill(-1);
+
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let mut seen_this = false;
+        let mut is_commit_in_columns = true;
+
+        // The current commit may not be in any column yet if no child of it was shown.
+        for i in 0..=num_columns {
+            let column_commit = if i == num_columns {
+                if seen_this {
+                    break;
+                }
+                is_commit_in_columns = false;
+                self.commit.clone()
+            } else {
+                self.columns[i].commit.clone()
+            };
+
+            if column_commit == self.commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+
+                for parent in self.parents.clone() {
+                    // Merges and new childless lines get a fresh colour.
+                    if self.parents.len() > 1 || !is_commit_in_columns {
+                        self.increment_column_color();
+                    }
+                    self.insert_into_new_columns(parent, i as i32);
+                }
+
+                // The commit takes up at least two characters, even without parents.
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(column_commit, -1);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    fn insert_into_new_columns(&mut self, commit: String, index: i32) {
+        let i = match self.find_new_column(&commit) {
+            Some(i) => i,
+            None => {
+                let color = self.find_commit_color(&commit);
+                self.new_columns.push(Column { commit, color });
+                self.new_columns.len() - 1
+            }
+        } as i32;
+
+        let mapping_index;
+
+        if self.parents.len() > 1 && index > -1 && self.merge_layout == -1 {
+            // The first parent of a merge: lay the merge out depending on whether that parent
+            // is in a column to the left of it.
+            let dist = index - i;
+            let shift = if dist > 1 { 2 * dist - 3 } else { 1 };
+
+            self.merge_layout = if dist > 0 { 0 } else { 1 };
+            self.edges_added = self.parents.len() as i32 + self.merge_layout - 2;
+
+            mapping_index = self.width as i32 + (self.merge_layout - 1) * shift;
+            self.width += 2 * self.merge_layout as usize;
+        } else if self.edges_added > 0 && self.width >= 2 && i == self.mapping[self.width - 2] {
+            // A merge added columns, but this parent was found in the last existing column,
+            // so the two edges join straight away.
+            mapping_index = self.width as i32 - 2;
+            self.edges_added = -1;
+        } else {
+            mapping_index = self.width as i32;
+            self.width += 2;
+        }
+
+        self.mapping[mapping_index as usize] = i;
+    }
+
+    fn num_expansion_rows(&self) -> usize {
+        self.parents.len().saturating_sub(2) * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && self.expansion_row < self.num_expansion_rows()
+    }
+
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, target)| *target < 0 || *target as usize == i / 2)
+    }
+
+    fn pad(&self, line: &mut Line) {
+        while line.width < self.width {
+            line.push(' ');
+        }
+    }
+
+    fn output_padding_line(&mut self, line: &mut Line) {
+        for column in &self.new_columns {
+            line.push_column(column, '|');
+            line.push(' ');
+        }
+    }
+
+    fn output_skip_line(&mut self, line: &mut Line) {
+        line.text += "...";
+        line.width += 3;
+
+        if self.needs_pre_commit_line() {
+            self.update_state(State::PreCommit);
+        } else {
+            self.update_state(State::Commit);
+        }
+    }
+
+    /// Widens the space around an octopus merge before its commit line, two rows per parent
+    /// beyond the second.
+    fn output_pre_commit_line(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+
+        for (i, column) in self.columns.iter().enumerate() {
+            if column.commit == self.commit {
+                seen_this = true;
+                line.push_column(column, '|');
+                for _ in 0..self.expansion_row {
+                    line.push(' ');
+                }
+            } else if seen_this && self.expansion_row == 0 {
+                // Lines the previous merge drew as `\` keep going that way.
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    line.push_column(column, '\\');
+                } else {
+                    line.push_column(column, '|');
+                }
+            } else if seen_this {
+                line.push_column(column, '\\');
+            } else {
+                line.push_column(column, '|');
+            }
+            line.push(' ');
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    fn output_commit_line(&mut self, line: &mut Line) {
+        let num_columns = self.columns.len();
+        let mut seen_this = false;
+
+        for i in 0..=num_columns {
+            let is_commit = if i == num_columns {
+                if seen_this {
+                    break;
+                }
+                true
+            } else {
+                self.columns[i].commit == self.commit
+            };
+
+            if is_commit {
+                seen_this = true;
+                line.push('*');
+
+                if self.parents.len() > 2 {
+                    self.draw_octopus_merge(line);
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push_column(&self.columns[i], '\\');
+            } else if seen_this && self.edges_added == 1 {
+                // A right-skewed merge has no pre-commit line, so continue a `\` the previous
+                // merge left behind.
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push_column(&self.columns[i], '\\');
+                } else {
+                    line.push_column(&self.columns[i], '|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping[2 * i + 1] == i as i32
+                && self.mapping[2 * i] < i as i32
+            {
+                line.push_column(&self.columns[i], '/');
+            } else {
+                line.push_column(&self.columns[i], '|');
+            }
+            line.push(' ');
+        }
+
+        if self.parents.len() > 1 {
+            self.update_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    fn draw_octopus_merge(&self, line: &mut Line) {
+        let dashed_parents = self.parents.len() as i32 + self.merge_layout - 3;
+
+        for i in 0..dashed_parents.max(0) as usize {
+            let j = self.mapping[(self.commit_index + i + 2) * 2];
+            let column = &self.new_columns[j as usize];
+
+            line.push_column(column, '-');
+            line.push_column(
+                column,
+                if i as i32 == dashed_parents - 1 {
+                    '.'
+                } else {
+                    '-'
+                },
+            );
+        }
+    }
+
+    fn output_post_merge_line(&mut self, line: &mut Line) {
+        let num_columns = self.columns.len();
+        let first_parent = self.parents.first().cloned().unwrap_or_default();
+        let mut parent_column: Option<usize> = None;
+        let mut seen_this = false;
+
+        for i in 0..=num_columns {
+            let column_commit = if i == num_columns {
+                if seen_this {
+                    break;
+                }
+                self.commit.clone()
+            } else {
+                self.columns[i].commit.clone()
+            };
+
+            if column_commit == self.commit {
+                // Draw an edge to the column each parent went to.
+                let mut index = self.merge_layout as usize;
+                seen_this = true;
+
+                for (j, parent) in self.parents.iter().enumerate() {
+                    let Some(parent_index) = self.find_new_column(parent) else {
+                        continue;
+                    };
+
+                    line.push_column(&self.new_columns[parent_index], MERGE_CHARS[index]);
+
+                    if index == 2 {
+                        if self.edges_added > 0 || j < self.parents.len() - 1 {
+                            line.push(' ');
+                        }
+                    } else {
+                        index += 1;
+                    }
+                }
+
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                let c = if self.edges_added > 0 { '\\' } else { '|' };
+                line.push_column(&self.columns[i], c);
+                line.push(' ');
+            } else {
+                line.push_column(&self.columns[i], '|');
+
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    match parent_column {
+                        Some(parent_column) => line.push_column(&self.columns[parent_column], '_'),
+                        None => line.push(' '),
+                    }
+                }
+            }
+
+            if i < num_columns && column_commit == first_parent {
+                parent_column = Some(i);
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    /// Moves branch lines one step left towards the column they belong in, crossing at most one
+    /// other line per row.
+    fn output_collapsing_line(&mut self, line: &mut Line) {
+        let mut used_horizontal = false;
+        let mut horizontal_edge: i32 = -1;
+        let mut horizontal_edge_target: i32 = -1;
+
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        self.mapping[..self.mapping_size].fill(-1);
+
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+
+            // Lines only ever move left, so crossing lines never both move.
+            let target_position = target as usize * 2;
+
+            if target_position == i {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                // Nothing to the left: move one step left.
+                self.mapping[i - 1] = target;
+
+                if horizontal_edge == -1 {
+                    horizontal_edge = i as i32;
+                    horizontal_edge_target = target;
+
+                    let mut j = target_position + 3;
+                    while j + 2 < i {
+                        self.mapping[j] = target;
+                        j += 2;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // The line to the left goes to the same place; merge with it.
+            } else {
+                // Cross over the line to the left, which has space on its own left.
+                self.mapping[i - 2] = target;
+
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = i as i32 - 1;
+
+                    let mut j = target_position + 3;
+                    while j + 2 < i {
+                        self.mapping[j] = target;
+                        j += 2;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping[..self.mapping_size].copy_from_slice(&self.mapping[..self.mapping_size]);
+
+        if self.mapping_size > 0 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+
+            if target < 0 {
+                line.push(' ');
+            } else if target as usize * 2 == i {
+                line.push_column(&self.new_columns[target as usize], '|');
+            } else if target == horizontal_edge_target && i as i32 != horizontal_edge - 1 {
+                // Only the first segment of a horizontal edge carries on to the next row.
+                if i != target as usize * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push_column(&self.new_columns[target as usize], '_');
+            } else {
+                if used_horizontal && (i as i32) < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.push_column(&self.new_columns[target as usize], '/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::{
+    fs,
+    io::{self, BufRead, Read},
+};
+
+use crate::{
+    error::Result,
+    repository::Repository,
+    structures::{write_object, ObjectType},
+};
+
+fn hash_file(repo: &Repository, path: &str, obj_type: &ObjectType, write: bool) -> Result<String> {
+    let contents = fs::read(path)?;
+
+    write_object(repo, obj_type, &contents, write)
+}
+
+pub fn hash_object(
+    repo: &Repository,
+    obj_type: ObjectType,
+    write: bool,
+    stdin: bool,
+    stdin_paths: bool,
+    paths: Vec<String>,
+) -> Result<Vec<String>> {
+    let mut hashes = Vec::<String>::new();
+
+    if stdin {
+        let mut contents = Vec::<u8>::new();
+        io::stdin().read_to_end(&mut contents)?;
+
+        hashes.push(write_object(repo, &obj_type, &contents, write)?);
+    }
+
+    for path in &paths {
+        hashes.push(hash_file(repo, path, &obj_type, write)?);
+    }
+
+    if stdin_paths {
+        for line in io::stdin().lock().lines() {
+            hashes.push(hash_file(repo, &line?, &obj_type, write)?);
+        }
+    }
+
+    Ok(hashes)
+}
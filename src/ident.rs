@@ -0,0 +1,228 @@
+use std::env;
+
+use chrono::{DateTime, Local, NaiveDateTime, Offset, TimeZone};
+
+use crate::{
+    error::{Error, Result},
+    repository::Repository,
+    structures::User,
+};
+
+/// Whose identity is being looked up. Each has its own `GIT_*` environment variables and
+/// config keys, falling back to `user.name` and `user.email`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn config_section(&self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+}
+
+/// The identity and current time to record for `role`, honouring `GIT_AUTHOR_DATE` and
+/// `GIT_COMMITTER_DATE`.
+pub fn identity(repo: &Repository, role: Role) -> Result<User> {
+    let prefix = role.env_prefix();
+    let section = role.config_section();
+    let config = repo.config();
+
+    let lookup = |field: &str| {
+        env::var(format!("{prefix}_{}", field.to_ascii_uppercase()))
+            .ok()
+            .or_else(|| config.get(&format!("{section}.{field}")))
+            .or_else(|| config.get(&format!("user.{field}")))
+            .filter(|value| !value.trim().is_empty())
+    };
+
+    let (Some(name), Some(email)) = (lookup("name"), lookup("email")) else {
+        let role = match role {
+            Role::Author => "Author",
+            Role::Committer => "Committer",
+        };
+
+        return Err(Error::InvalidArgument(format!(
+            "{role} identity unknown. Tell pgit who you are with `pgit config set --global user.name \"Your Name\"` and `pgit config set --global user.email you@example.com`"
+        )));
+    };
+
+    let (time, zone) = match env::var(format!("{prefix}_DATE")) {
+        Ok(date) => parse_date(&date)?,
+        Err(_) => now(),
+    };
+
+    Ok(User {
+        name,
+        email: Some(email),
+        time,
+        zone,
+    })
+}
+
+/// Parses an explicit `Name <email>` identity, as given to `--author`.
+pub fn parse_identity(text: &str, time: u64, zone: String) -> Result<User> {
+    let invalid = || {
+        Error::InvalidArgument(format!(
+            "`{text}` is not an identity of the form `Name <email>`"
+        ))
+    };
+
+    let (name, rest) = text.split_once('<').ok_or_else(invalid)?;
+    let email = rest.strip_suffix('>').ok_or_else(invalid)?;
+
+    Ok(User {
+        name: name.trim().to_owned(),
+        email: Some(email.trim().to_owned()),
+        time,
+        zone,
+    })
+}
+
+/// The current time and local timezone offset.
+pub fn now() -> (u64, String) {
+    let now = Local::now();
+
+    (
+        now.timestamp() as u64,
+        format_zone(now.offset().fix().local_minus_utc()),
+    )
+}
+
+fn format_zone(offset_seconds: i32) -> String {
+    let sign = if offset_seconds < 0 { '-' } else { '+' };
+    let minutes = offset_seconds.abs() / 60;
+
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Parses the date formats git accepts for `--date` and `GIT_*_DATE`: its internal
+/// `<seconds> <zone>` form (optionally prefixed by `@`), RFC 2822 and ISO 8601. Dates without a
+/// zone are taken to be local time.
+pub fn parse_date(text: &str) -> Result<(u64, String)> {
+    let text = text.trim();
+    let invalid = || Error::InvalidArgument(format!("invalid date format: {text}"));
+
+    let raw = text.strip_prefix('@').unwrap_or(text);
+    let mut parts = raw.split_whitespace();
+
+    if let Some(seconds) = parts.next().and_then(|seconds| seconds.parse::<u64>().ok()) {
+        let zone = match parts.next() {
+            Some(zone) if is_zone(zone) => zone.to_owned(),
+            Some(_) => return Err(invalid()),
+            None => "+0000".to_owned(),
+        };
+
+        return Ok((seconds, zone));
+    }
+
+    if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+        return Ok((
+            date.timestamp() as u64,
+            format_zone(date.offset().local_minus_utc()),
+        ));
+    }
+
+    let zoned_formats = [
+        "%Y-%m-%dT%H:%M:%S%z",
+        "%Y-%m-%dT%H:%M:%S%:z",
+        "%Y-%m-%d %H:%M:%S %z",
+        "%Y-%m-%d %H:%M:%S%z",
+        "%Y-%m-%d %H:%M:%S %:z",
+    ];
+
+    for format in zoned_formats {
+        if let Ok(date) = DateTime::parse_from_str(text, format) {
+            return Ok((
+                date.timestamp() as u64,
+                format_zone(date.offset().local_minus_utc()),
+            ));
+        }
+    }
+
+    if let Some(utc) = text.strip_suffix('Z') {
+        if let Ok(date) = NaiveDateTime::parse_from_str(utc, "%Y-%m-%dT%H:%M:%S") {
+            return Ok((date.and_utc().timestamp() as u64, "+0000".to_owned()));
+        }
+    }
+
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            let local = Local
+                .from_local_datetime(&date)
+                .earliest()
+                .ok_or_else(invalid)?;
+
+            return Ok((
+                local.timestamp() as u64,
+                format_zone(local.offset().fix().local_minus_utc()),
+            ));
+        }
+    }
+
+    Err(invalid())
+}
+
+/// Parses the looser dates accepted by `--since`, `--until` and `<ref>@{<date>}`: anything
+/// [`parse_date`] accepts, a bare `YYYY-MM-DD` (local midnight), `now`, `yesterday` and relative
+/// forms like `2 weeks ago` or `3.days.ago`. Returns seconds since the epoch.
+pub fn parse_approxidate(text: &str) -> Result<u64> {
+    if let Ok((time, _)) = parse_date(text) {
+        return Ok(time);
+    }
+
+    let text = text.trim().to_ascii_lowercase().replace('.', " ");
+    let (now, _) = now();
+    let invalid = || Error::InvalidArgument(format!("invalid date format: {text}"));
+
+    match text.as_str() {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now.saturating_sub(86400)),
+        _ => {}
+    }
+
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(&text, "%Y-%m-%d") {
+        let local = Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).ok_or_else(invalid)?)
+            .earliest()
+            .ok_or_else(invalid)?;
+
+        return Ok(local.timestamp() as u64);
+    }
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let [count, unit, "ago"] = words.as_slice() else {
+        return Err(invalid());
+    };
+
+    let count = count.parse::<u64>().map_err(|_| invalid())?;
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    Ok(now.saturating_sub(count * seconds))
+}
+
+fn is_zone(zone: &str) -> bool {
+    zone.len() == 5
+        && matches!(zone.as_bytes()[0], b'+' | b'-')
+        && zone[1..].bytes().all(|b| b.is_ascii_digit())
+}
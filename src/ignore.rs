@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use home::home_dir;
+
+use crate::{
+    config::parse_path,
+    error::Result,
+    repository::Repository,
+    utils::{path_from_bytes, wildmatch},
+};
+
+/// One line of a `.gitignore`-style file.
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns with a `/` other than a trailing one match the whole path relative to `base`;
+    /// the rest only match the last path component.
+    anchored: bool,
+    /// The directory of the file the pattern came from, as an index path prefix ending in `/`.
+    base: Vec<u8>,
+}
+
+impl IgnorePattern {
+    fn parse(line: &str, base: &[u8]) -> Option<Self> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // Trailing spaces are dropped unless escaped with a backslash.
+        let mut line = line.to_owned();
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line.pop();
+        }
+
+        // A leading `\` lets a pattern start with a literal `!` or `#`.
+        let (negated, mut pattern) = if let Some(rest) = line.strip_prefix('!') {
+            (true, rest.to_owned())
+        } else if line.starts_with("\\!") || line.starts_with("\\#") {
+            (false, line[1..].to_owned())
+        } else {
+            (false, line)
+        };
+
+        let dir_only = pattern.ends_with('/');
+        if dir_only {
+            pattern.pop();
+        }
+
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(&pattern).to_owned();
+
+        Some(Self {
+            pattern,
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_vec(),
+        })
+    }
+
+    fn matches(&self, path: &[u8], is_dir: bool, case_insensitive: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let Some(relative) = path.strip_prefix(self.base.as_slice()) else {
+            return false;
+        };
+
+        if self.anchored {
+            wildmatch(&self.pattern, relative, case_insensitive)
+        } else {
+            let name = relative.rsplit(|b| *b == b'/').next().unwrap_or_default();
+            wildmatch(&self.pattern, name, case_insensitive)
+        }
+    }
+}
+
+fn read_patterns(path: &Path, base: &[u8]) -> Vec<IgnorePattern> {
+    fs::read_to_string(path)
+        .map(|text| {
+            text.lines()
+                .filter_map(|line| IgnorePattern::parse(line, base))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Decides which work tree paths are ignored, from `core.excludesFile`, `.git/info/exclude` and
+/// every `.gitignore` between the top of the work tree and the path. `.gitignore` files are read
+/// lazily and cached, so one of these should be reused across a whole walk.
+pub struct IgnoreRules {
+    work_tree: PathBuf,
+    /// Lowest precedence first.
+    global: Vec<IgnorePattern>,
+    per_directory: HashMap<Vec<u8>, Vec<IgnorePattern>>,
+    case_insensitive: bool,
+}
+
+impl IgnoreRules {
+    pub fn new(repo: &Repository) -> Result<Self> {
+        let work_tree = repo.require_work_tree()?.to_path_buf();
+
+        let excludes_file = match repo.config().get("core.excludesfile") {
+            Some(path) => Some(parse_path(&path)),
+            None => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| home_dir().map(|home| home.join(".config")))
+                .map(|config| config.join("git").join("ignore")),
+        };
+
+        let mut global = Vec::new();
+
+        if let Some(excludes_file) = excludes_file {
+            global.extend(read_patterns(&excludes_file, &[]));
+        }
+
+        global.extend(read_patterns(
+            &repo.common_dir().join("info").join("exclude"),
+            &[],
+        ));
+
+        Ok(Self {
+            work_tree,
+            global,
+            per_directory: HashMap::new(),
+            case_insensitive: repo.config().get_bool("core.ignorecase").unwrap_or(false),
+        })
+    }
+
+    fn patterns_in(&mut self, dir: &[u8]) -> &[IgnorePattern] {
+        let work_tree = &self.work_tree;
+
+        self.per_directory.entry(dir.to_vec()).or_insert_with(|| {
+            let dir_path = work_tree.join(path_from_bytes(dir));
+            read_patterns(&dir_path.join(".gitignore"), dir)
+        })
+    }
+
+    /// Whether `path` is ignored. A path inside an ignored directory is always ignored, since git
+    /// never looks inside such directories to find a negation.
+    pub fn is_ignored(&mut self, path: &[u8], is_dir: bool) -> bool {
+        let mut dir_end = 0;
+
+        while let Some(slash) = path[dir_end..].iter().position(|b| *b == b'/') {
+            let parent = &path[..dir_end + slash];
+
+            if self.matches_directly(parent, true) {
+                return true;
+            }
+
+            dir_end += slash + 1;
+        }
+
+        self.matches_directly(path, is_dir)
+    }
+
+    /// Checks `path` against the patterns that apply to it, without considering its parents. The
+    /// last matching pattern wins, and deeper `.gitignore` files take precedence.
+    fn matches_directly(&mut self, path: &[u8], is_dir: bool) -> bool {
+        let case_insensitive = self.case_insensitive;
+        let mut ignored = self
+            .global
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir, case_insensitive))
+            .map(|pattern| !pattern.negated);
+
+        let mut dirs = vec![Vec::new()];
+        for (i, byte) in path.iter().enumerate() {
+            if *byte == b'/' {
+                dirs.push(path[..=i].to_vec());
+            }
+        }
+
+        for dir in dirs {
+            let matched = self
+                .patterns_in(&dir)
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(path, is_dir, case_insensitive))
+                .map(|pattern| !pattern.negated);
+
+            if matched.is_some() {
+                ignored = matched;
+            }
+        }
+
+        ignored.unwrap_or(false)
+    }
+}
@@ -0,0 +1,1067 @@
+use std::{
+    fs::{self, Metadata},
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    refs::RefLock,
+    repository::Repository,
+    utils::{path_to_bytes, HashAlgo},
+};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+const INDEX_HEADER_LEN: usize = 12;
+/// Ten 32-bit stat fields precede the object name of every entry.
+const STAT_LEN: usize = 40;
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+pub const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+pub const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+pub const MODE_BLOB: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_GITLINK: u32 = 0o160000;
+
+/// One staged path. Conflicted paths have up to three entries, one per stage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: String,
+    /// The stage (0 normally, 1–3 during a conflict) and the assume-valid bit.
+    pub flags: u16,
+    /// Skip-worktree and intent-to-add bits, only stored by version 3 and later.
+    pub extended_flags: u16,
+    pub path: Vec<u8>,
+}
+
+impl IndexEntry {
+    /// Builds an entry at `stage` without stat data, for content that is not in the work tree
+    /// as is, such as the sides of a conflict.
+    pub fn new(path: Vec<u8>, mode: u32, hash: String, stage: u8) -> Self {
+        let mut entry = Self {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            flags: 0,
+            extended_flags: 0,
+            path,
+        };
+        entry.set_stage(stage);
+
+        entry
+    }
+
+    /// Builds a stage 0 entry for `path` from the file's current stat data.
+    pub fn from_metadata(path: Vec<u8>, hash: String, metadata: &Metadata) -> Self {
+        let mut entry = Self {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode: mode_from_metadata(metadata),
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            flags: 0,
+            extended_flags: 0,
+            path,
+        };
+        entry.update_stat(metadata);
+
+        entry
+    }
+
+    pub fn stage(&self) -> u8 {
+        ((self.flags & FLAG_STAGE_MASK) >> 12) as u8
+    }
+
+    pub fn set_stage(&mut self, stage: u8) {
+        self.flags = (self.flags & !FLAG_STAGE_MASK) | (((stage as u16) & 0x3) << 12);
+    }
+
+    pub fn assume_valid(&self) -> bool {
+        self.flags & FLAG_ASSUME_VALID != 0
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & EXTENDED_SKIP_WORKTREE != 0
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & EXTENDED_INTENT_TO_ADD != 0
+    }
+
+    pub fn path_str(&self) -> String {
+        String::from_utf8_lossy(&self.path).to_string()
+    }
+
+    /// Refreshes the cached stat data, e.g. after the file was rehashed and found unchanged.
+    pub fn update_stat(&mut self, metadata: &Metadata) {
+        let stat = Stat::from(metadata);
+
+        self.ctime = stat.ctime;
+        self.mtime = stat.mtime;
+        self.dev = stat.dev;
+        self.ino = stat.ino;
+        self.uid = stat.uid;
+        self.gid = stat.gid;
+        self.size = stat.size;
+    }
+
+    /// Whether the cached stat data still describes the file. A mismatch does not necessarily mean
+    /// the contents changed, only that they need rehashing to tell.
+    pub fn stat_matches(&self, metadata: &Metadata) -> bool {
+        let stat = Stat::from(metadata);
+
+        self.mtime == stat.mtime
+            && self.ctime == stat.ctime
+            && self.ino == stat.ino
+            && self.dev == stat.dev
+            && self.uid == stat.uid
+            && self.gid == stat.gid
+            && self.size == stat.size
+            && self.mode == mode_from_metadata(metadata)
+    }
+}
+
+/// The stat fields the index caches, truncated to 32 bits the way git stores them.
+struct Stat {
+    ctime: (u32, u32),
+    mtime: (u32, u32),
+    dev: u32,
+    ino: u32,
+    uid: u32,
+    gid: u32,
+    size: u32,
+}
+
+#[cfg(unix)]
+impl From<&Metadata> for Stat {
+    fn from(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl From<&Metadata> for Stat {
+    fn from(metadata: &Metadata) -> Self {
+        use std::time::UNIX_EPOCH;
+
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| (time.as_secs() as u32, time.subsec_nanos()))
+            .unwrap_or((0, 0));
+
+        Self {
+            ctime: mtime,
+            mtime,
+            dev: 0,
+            ino: 0,
+            uid: 0,
+            gid: 0,
+            size: metadata.len() as u32,
+        }
+    }
+}
+
+/// The index mode git would record for a file with this metadata.
+pub fn mode_from_metadata(metadata: &Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        return MODE_SYMLINK;
+    }
+
+    if metadata.is_dir() {
+        return MODE_GITLINK;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return MODE_EXECUTABLE;
+        }
+    }
+
+    MODE_BLOB
+}
+
+/// Whether `path` may be written into the work tree, as git's `verify_path` decides. Paths must
+/// be relative and made of non-empty components other than `.` and `..`, and none of them may be
+/// `.git` in any case. Like git by default, names that some filesystems treat as `.git` are
+/// refused too: `.git` followed by dots or spaces, and the short name `git~1`.
+pub fn verify_path(path: &[u8]) -> bool {
+    path.split(|b| *b == b'/').all(|component| {
+        let trimmed = component
+            .iter()
+            .rposition(|b| *b != b'.' && *b != b' ')
+            .map_or(&[][..], |end| &component[..=end]);
+
+        !matches!(component, b"" | b"." | b"..")
+            && !trimmed.eq_ignore_ascii_case(b".git")
+            && !component.eq_ignore_ascii_case(b"git~1")
+    })
+}
+
+/// A node of the `TREE` extension: the tree object a directory of the index would be written as,
+/// or an invalidated node (`entry_count` of -1) whose tree has to be recomputed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheTree {
+    /// The directory name relative to its parent; empty for the root.
+    pub name: Vec<u8>,
+    /// Number of index entries covered, or -1 when invalid.
+    pub entry_count: i32,
+    pub hash: Option<String>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    /// Marks every node on the way to `path` invalid, since the tree it names has changed.
+    pub fn invalidate(&mut self, path: &[u8]) {
+        self.entry_count = -1;
+        self.hash = None;
+
+        if let Some(slash) = path.iter().position(|b| *b == b'/') {
+            let (dir, rest) = (&path[..slash], &path[slash + 1..]);
+
+            if let Some(subtree) = self.subtrees.iter_mut().find(|tree| tree.name == dir) {
+                subtree.invalidate(rest);
+            }
+        }
+    }
+
+    /// Looks up the node for the directory `path`, with `""` meaning the root.
+    pub fn find(&self, path: &[u8]) -> Option<&CacheTree> {
+        if path.is_empty() {
+            return Some(self);
+        }
+
+        let (dir, rest) = match path.iter().position(|b| *b == b'/') {
+            Some(slash) => (&path[..slash], &path[slash + 1..]),
+            None => (path, &[][..]),
+        };
+
+        self.subtrees
+            .iter()
+            .find(|tree| tree.name == dir)?
+            .find(rest)
+    }
+}
+
+/// An entry of the `REUC` extension, remembering the stages of a conflict that was resolved so
+/// the conflict can be recreated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveUndo {
+    pub path: Vec<u8>,
+    /// Modes of stages 1–3; zero where the stage did not exist.
+    pub modes: [u32; 3],
+    pub hashes: [Option<String>; 3],
+}
+
+/// The parsed contents of `.git/index`.
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub version: u32,
+    /// Sorted by path, then stage, as git requires.
+    pub entries: Vec<IndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+    pub resolve_undo: Vec<ResolveUndo>,
+    /// The `UNTR` untracked cache, kept as raw bytes and written back unchanged.
+    pub untracked_cache: Option<Vec<u8>>,
+    /// Optional extensions pgit does not understand, preserved in order.
+    pub other_extensions: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            resolve_undo: Vec::new(),
+            untracked_cache: None,
+            other_extensions: Vec::new(),
+        }
+    }
+}
+
+impl Index {
+    /// Reads the repository's index, or an empty one if it has never been written.
+    pub fn read(repo: &Repository) -> Result<Self> {
+        match fs::read(repo.index_path()) {
+            Ok(data) => Self::parse(&data, repo.object_format()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parses index file contents, verifying the trailing checksum.
+    pub fn parse(data: &[u8], algo: &HashAlgo) -> Result<Self> {
+        let raw_len = algo.raw_len();
+
+        if data.len() < INDEX_HEADER_LEN + raw_len || &data[..4] != INDEX_SIGNATURE {
+            return Err(Error::CorruptIndex("missing `DIRC` signature".to_owned()));
+        }
+
+        let (content, checksum) = data.split_at(data.len() - raw_len);
+
+        // An all-zero checksum means the writer skipped it (`index.skipHash`).
+        if checksum.iter().any(|b| *b != 0) && algo.hash(content) != hex::encode(checksum) {
+            return Err(Error::CorruptIndex("checksum does not match".to_owned()));
+        }
+
+        let version = read_u32(content, 4)?;
+
+        if !(2..=4).contains(&version) {
+            return Err(Error::CorruptIndex(format!(
+                "unsupported version {version}"
+            )));
+        }
+
+        let count = read_u32(content, 8)? as usize;
+        let mut index = Self {
+            version,
+            ..Default::default()
+        };
+        let mut cursor = INDEX_HEADER_LEN;
+        let mut previous_path = Vec::<u8>::new();
+
+        for _ in 0..count {
+            let entry = parse_entry(content, &mut cursor, version, raw_len, &previous_path)?;
+
+            if !verify_path(&entry.path) {
+                return Err(Error::CorruptIndex(format!(
+                    "invalid path `{}`",
+                    String::from_utf8_lossy(&entry.path)
+                )));
+            }
+
+            previous_path = entry.path.clone();
+            index.entries.push(entry);
+        }
+
+        while cursor < content.len() {
+            let signature: [u8; 4] = slice(content, cursor, 4)?.try_into().unwrap();
+            let size = read_u32(content, cursor + 4)? as usize;
+            let body = slice(content, cursor + 8, size)?;
+            cursor += 8 + size;
+
+            match &signature {
+                b"TREE" => index.cache_tree = Some(parse_cache_tree(body, algo)?),
+                b"REUC" => index.resolve_undo = parse_resolve_undo(body, algo)?,
+                b"UNTR" => index.untracked_cache = Some(body.to_vec()),
+                // Extensions starting with an uppercase letter are optional and may be ignored.
+                _ if signature[0].is_ascii_uppercase() => {
+                    index.other_extensions.push((signature, body.to_vec()))
+                }
+                _ => {
+                    return Err(Error::CorruptIndex(format!(
+                        "required extension `{}` is not supported",
+                        String::from_utf8_lossy(&signature)
+                    )))
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Writes the index back through `index.lock`, so readers never see a partial file. The lock
+    /// is created exclusively, so an index locked by another process is left alone.
+    pub fn write(&mut self, repo: &Repository) -> Result<()> {
+        let lock = RefLock::acquire_file(repo.index_path())?;
+
+        lock.commit(&self.serialize(repo.object_format()))
+    }
+
+    /// Encodes the index in its current version, moving from version 2 to 3 if any entry needs
+    /// extended flags.
+    pub fn serialize(&mut self, algo: &HashAlgo) -> Vec<u8> {
+        if self.version == 2 && self.entries.iter().any(|entry| entry.extended_flags != 0) {
+            self.version = 3;
+        }
+
+        let mut data = Vec::<u8>::new();
+        data.extend_from_slice(INDEX_SIGNATURE);
+        data.extend_from_slice(&self.version.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        let mut previous_path: &[u8] = &[];
+
+        for entry in &self.entries {
+            serialize_entry(&mut data, entry, self.version, previous_path);
+            previous_path = &entry.path;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut body = Vec::new();
+            serialize_cache_tree(&mut body, cache_tree);
+            push_extension(&mut data, b"TREE", &body);
+        }
+
+        if !self.resolve_undo.is_empty() {
+            let mut body = Vec::new();
+
+            for undo in &self.resolve_undo {
+                body.extend_from_slice(&undo.path);
+                body.push(0);
+
+                for mode in undo.modes {
+                    body.extend_from_slice(format!("{:o}", mode).as_bytes());
+                    body.push(0);
+                }
+
+                for hash in undo.hashes.iter().flatten() {
+                    body.extend(hex::decode(hash).unwrap_or_default());
+                }
+            }
+
+            push_extension(&mut data, b"REUC", &body);
+        }
+
+        if let Some(untracked_cache) = &self.untracked_cache {
+            push_extension(&mut data, b"UNTR", untracked_cache);
+        }
+
+        for (signature, body) in &self.other_extensions {
+            push_extension(&mut data, signature, body);
+        }
+
+        let checksum = hex::decode(algo.hash(&data)).unwrap_or_default();
+        data.extend(checksum);
+
+        data
+    }
+
+    /// The position of `path` at `stage`, or where it would be inserted.
+    fn position(&self, path: &[u8], stage: u8) -> std::result::Result<usize, usize> {
+        self.entries.binary_search_by(|entry| {
+            entry
+                .path
+                .as_slice()
+                .cmp(path)
+                .then(entry.stage().cmp(&stage))
+        })
+    }
+
+    /// The entry for `path` at `stage`.
+    pub fn entry(&self, path: &[u8], stage: u8) -> Option<&IndexEntry> {
+        self.position(path, stage)
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    /// Every stage of `path`, which is more than one only while it is conflicted.
+    pub fn entries_for(&self, path: &[u8]) -> &[IndexEntry] {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.path.as_slice() < path);
+        let end = self
+            .entries
+            .partition_point(|entry| entry.path.as_slice() <= path);
+
+        &self.entries[start..end]
+    }
+
+    /// Stages `entry`, replacing whatever was at its path and stage. Staging at stage 0 resolves
+    /// a conflict, so the higher stages are dropped.
+    pub fn add(&mut self, entry: IndexEntry) {
+        if entry.stage() == 0 {
+            self.record_resolve_undo(&entry.path);
+            self.remove_stages(&entry.path, 1..=3);
+        }
+
+        self.invalidate(&entry.path.clone());
+
+        match self.position(&entry.path, entry.stage()) {
+            Ok(position) => self.entries[position] = entry,
+            Err(position) => self.entries.insert(position, entry),
+        }
+    }
+
+    /// Unstages every stage of `path`, returning whether anything was removed.
+    pub fn remove(&mut self, path: &[u8]) -> bool {
+        let removed = self.remove_stages(path, 0..=3);
+
+        if removed {
+            self.invalidate(path);
+        }
+
+        removed
+    }
+
+    /// Remembers the conflicted stages of `path` in the `REUC` extension before they go away.
+    fn record_resolve_undo(&mut self, path: &[u8]) {
+        let mut undo = ResolveUndo {
+            path: path.to_vec(),
+            modes: [0; 3],
+            hashes: [None, None, None],
+        };
+
+        for entry in self.entries_for(path) {
+            if (1..=3).contains(&entry.stage()) {
+                let stage = entry.stage() as usize - 1;
+                undo.modes[stage] = entry.mode;
+                undo.hashes[stage] = Some(entry.hash.clone());
+            }
+        }
+
+        if undo.modes.iter().all(|mode| *mode == 0) {
+            return;
+        }
+
+        self.resolve_undo.retain(|existing| existing.path != path);
+        let position = self
+            .resolve_undo
+            .partition_point(|existing| existing.path.as_slice() < path);
+        self.resolve_undo.insert(position, undo);
+    }
+
+    fn remove_stages(&mut self, path: &[u8], stages: std::ops::RangeInclusive<u8>) -> bool {
+        let before = self.entries.len();
+
+        self.entries
+            .retain(|entry| entry.path != path || !stages.contains(&entry.stage()));
+
+        self.entries.len() != before
+    }
+
+    /// Drops cached information that no longer holds once `path` changed. The untracked cache
+    /// is thrown away wholesale and git rebuilds it on its next run.
+    fn invalidate(&mut self, path: &[u8]) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+
+        self.untracked_cache = None;
+    }
+
+    /// Whether any path has entries at a stage other than 0.
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage() != 0)
+    }
+}
+
+fn slice(data: &[u8], at: usize, len: usize) -> Result<&[u8]> {
+    data.get(at..at + len)
+        .ok_or_else(|| Error::CorruptIndex("unexpected end of file".to_owned()))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(slice(data, at, 4)?.try_into().unwrap()))
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(slice(data, at, 2)?.try_into().unwrap()))
+}
+
+fn parse_entry(
+    data: &[u8],
+    cursor: &mut usize,
+    version: u32,
+    raw_len: usize,
+    previous_path: &[u8],
+) -> Result<IndexEntry> {
+    let start = *cursor;
+    let field = |i: usize| read_u32(data, start + i * 4);
+
+    let mut entry = IndexEntry {
+        ctime: (field(0)?, field(1)?),
+        mtime: (field(2)?, field(3)?),
+        dev: field(4)?,
+        ino: field(5)?,
+        mode: field(6)?,
+        uid: field(7)?,
+        gid: field(8)?,
+        size: field(9)?,
+        hash: hex::encode(slice(data, start + STAT_LEN, raw_len)?),
+        flags: 0,
+        extended_flags: 0,
+        path: Vec::new(),
+    };
+    *cursor = start + STAT_LEN + raw_len;
+
+    let flags = read_u16(data, *cursor)?;
+    *cursor += 2;
+    entry.flags = flags & (FLAG_ASSUME_VALID | FLAG_STAGE_MASK);
+
+    if flags & FLAG_EXTENDED != 0 {
+        if version < 3 {
+            return Err(Error::CorruptIndex(
+                "extended flags in a version 2 index".to_owned(),
+            ));
+        }
+
+        entry.extended_flags = read_u16(data, *cursor)?;
+        *cursor += 2;
+    }
+
+    if version == 4 {
+        // The path is stored as the number of bytes to drop from the previous path, followed
+        // by the NUL-terminated suffix to append.
+        let strip = read_offset_varint(data, cursor)?;
+        let keep = previous_path
+            .len()
+            .checked_sub(strip)
+            .ok_or_else(|| Error::CorruptIndex("path prefix is too long".to_owned()))?;
+        let nul = data[*cursor..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| Error::CorruptIndex("unterminated path".to_owned()))?;
+
+        entry.path = previous_path[..keep].to_vec();
+        entry.path.extend_from_slice(&data[*cursor..*cursor + nul]);
+        *cursor += nul + 1;
+    } else {
+        let nul = data[*cursor..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| Error::CorruptIndex("unterminated path".to_owned()))?;
+
+        entry.path = data[*cursor..*cursor + nul].to_vec();
+
+        // Entries are NUL padded to a multiple of eight bytes, with at least one NUL.
+        let entry_len = *cursor + nul - start;
+        *cursor = start + (entry_len + 8) / 8 * 8;
+    }
+
+    Ok(entry)
+}
+
+fn serialize_entry(data: &mut Vec<u8>, entry: &IndexEntry, version: u32, previous_path: &[u8]) {
+    let start = data.len();
+
+    for field in [
+        entry.ctime.0,
+        entry.ctime.1,
+        entry.mtime.0,
+        entry.mtime.1,
+        entry.dev,
+        entry.ino,
+        entry.mode,
+        entry.uid,
+        entry.gid,
+        entry.size,
+    ] {
+        data.extend_from_slice(&field.to_be_bytes());
+    }
+
+    data.extend(hex::decode(&entry.hash).unwrap_or_default());
+
+    let mut flags = entry.flags & (FLAG_ASSUME_VALID | FLAG_STAGE_MASK);
+    flags |= entry.path.len().min(FLAG_NAME_MASK as usize) as u16;
+
+    if version >= 3 && entry.extended_flags != 0 {
+        flags |= FLAG_EXTENDED;
+    }
+
+    data.extend_from_slice(&flags.to_be_bytes());
+
+    if flags & FLAG_EXTENDED != 0 {
+        data.extend_from_slice(&entry.extended_flags.to_be_bytes());
+    }
+
+    if version == 4 {
+        let common = previous_path
+            .iter()
+            .zip(&entry.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        write_offset_varint(data, previous_path.len() - common);
+        data.extend_from_slice(&entry.path[common..]);
+        data.push(0);
+    } else {
+        data.extend_from_slice(&entry.path);
+
+        let entry_len = data.len() - start;
+        let padded = (entry_len + 8) / 8 * 8;
+        data.resize(start + padded, 0);
+    }
+}
+
+/// Reads the varint git uses for index v4 path prefixes (and pack offsets), where each
+/// continuation adds one before shifting so that every value has a single encoding.
+fn read_offset_varint(data: &[u8], cursor: &mut usize) -> Result<usize> {
+    let mut byte = *slice(data, *cursor, 1)?.first().unwrap();
+    *cursor += 1;
+    let mut value = (byte & 0x7f) as usize;
+
+    while byte & 0x80 != 0 {
+        byte = *slice(data, *cursor, 1)?.first().unwrap();
+        *cursor += 1;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+
+    Ok(value)
+}
+
+fn write_offset_varint(data: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+
+    while value >> 7 != 0 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+
+    bytes.reverse();
+    data.extend(bytes);
+}
+
+fn push_extension(data: &mut Vec<u8>, signature: &[u8; 4], body: &[u8]) {
+    data.extend_from_slice(signature);
+    data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    data.extend_from_slice(body);
+}
+
+/// Parses the `TREE` extension, a pre-order list of `<name>\0<entry count> <subtree count>\n`
+/// records, each followed by the tree's object name when it is valid.
+fn parse_cache_tree(body: &[u8], algo: &HashAlgo) -> Result<CacheTree> {
+    let mut cursor = 0;
+    let tree = parse_cache_tree_node(body, &mut cursor, algo)?;
+
+    if cursor != body.len() {
+        return Err(Error::CorruptIndex(
+            "trailing data in the TREE extension".to_owned(),
+        ));
+    }
+
+    Ok(tree)
+}
+
+fn parse_cache_tree_node(body: &[u8], cursor: &mut usize, algo: &HashAlgo) -> Result<CacheTree> {
+    let bad = || Error::CorruptIndex("malformed TREE extension".to_owned());
+
+    let nul = body[*cursor..]
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(bad)?;
+    let name = body[*cursor..*cursor + nul].to_vec();
+    *cursor += nul + 1;
+
+    let newline = body[*cursor..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or_else(bad)?;
+    let counts = std::str::from_utf8(&body[*cursor..*cursor + newline]).map_err(|_| bad())?;
+    *cursor += newline + 1;
+
+    let (entry_count, subtree_count) = counts.split_once(' ').ok_or_else(bad)?;
+    let entry_count: i32 = entry_count.parse().map_err(|_| bad())?;
+    let subtree_count: usize = subtree_count.parse().map_err(|_| bad())?;
+
+    let hash = if entry_count >= 0 {
+        let raw = body
+            .get(*cursor..*cursor + algo.raw_len())
+            .ok_or_else(bad)?;
+        *cursor += algo.raw_len();
+        Some(hex::encode(raw))
+    } else {
+        None
+    };
+
+    let mut subtrees = Vec::with_capacity(subtree_count);
+
+    for _ in 0..subtree_count {
+        subtrees.push(parse_cache_tree_node(body, cursor, algo)?);
+    }
+
+    Ok(CacheTree {
+        name,
+        entry_count,
+        hash,
+        subtrees,
+    })
+}
+
+fn serialize_cache_tree(body: &mut Vec<u8>, tree: &CacheTree) {
+    body.extend_from_slice(&tree.name);
+    body.push(0);
+    body.extend_from_slice(format!("{} {}\n", tree.entry_count, tree.subtrees.len()).as_bytes());
+
+    if let (true, Some(hash)) = (tree.entry_count >= 0, &tree.hash) {
+        body.extend(hex::decode(hash).unwrap_or_default());
+    }
+
+    for subtree in &tree.subtrees {
+        serialize_cache_tree(body, subtree);
+    }
+}
+
+/// Parses the `REUC` extension: a path, three octal modes and a name for every non-zero mode.
+fn parse_resolve_undo(body: &[u8], algo: &HashAlgo) -> Result<Vec<ResolveUndo>> {
+    let bad = || Error::CorruptIndex("malformed REUC extension".to_owned());
+    let mut cursor = 0;
+    let mut entries = Vec::new();
+
+    let next_string = |cursor: &mut usize| -> Result<Vec<u8>> {
+        let nul = body[*cursor..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(bad)?;
+        let string = body[*cursor..*cursor + nul].to_vec();
+        *cursor += nul + 1;
+        Ok(string)
+    };
+
+    while cursor < body.len() {
+        let path = next_string(&mut cursor)?;
+        let mut modes = [0u32; 3];
+
+        for mode in modes.iter_mut() {
+            let text = String::from_utf8(next_string(&mut cursor)?).map_err(|_| bad())?;
+            *mode = u32::from_str_radix(&text, 8).map_err(|_| bad())?;
+        }
+
+        let mut hashes = [None, None, None];
+
+        for (i, mode) in modes.iter().enumerate() {
+            if *mode != 0 {
+                let raw = body.get(cursor..cursor + algo.raw_len()).ok_or_else(bad)?;
+                hashes[i] = Some(hex::encode(raw));
+                cursor += algo.raw_len();
+            }
+        }
+
+        entries.push(ResolveUndo {
+            path,
+            modes,
+            hashes,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// The path of `file` relative to the work tree, with `/` separators as the index stores them.
+pub fn index_path_for(work_tree: &Path, file: &Path) -> Option<Vec<u8>> {
+    let relative = file.strip_prefix(work_tree).ok()?;
+    let parts: Vec<Vec<u8>> = relative
+        .components()
+        .map(|component| path_to_bytes(component.as_os_str()))
+        .collect();
+
+    if parts.is_empty() {
+        return None;
+    }
+
+    Some(parts.join(&b'/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_respects_a_held_lock() {
+        let test = crate::test_support::TestRepo::new("index-lock");
+        let lock = test.repo.index_path().with_extension("lock");
+        fs::write(&lock, "held").unwrap();
+
+        let mut index = Index::default();
+        assert!(index.write(&test.repo).is_err());
+        assert_eq!(fs::read_to_string(&lock).unwrap(), "held");
+        assert!(!test.repo.index_path().exists());
+
+        fs::remove_file(&lock).unwrap();
+        index.write(&test.repo).unwrap();
+        assert!(!lock.exists());
+        assert!(Index::read(&test.repo).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn verify_path_refuses_what_git_refuses() {
+        for path in [
+            "a",
+            "a/b/c",
+            ".gitignore",
+            "a/.github/x",
+            "git~2",
+            "..a",
+            "a..",
+            "x/.g it",
+        ] {
+            assert!(verify_path(path.as_bytes()), "{path}");
+        }
+
+        for path in [
+            "",
+            "/",
+            "/a",
+            "a/",
+            "a//b",
+            ".",
+            "a/./b",
+            "..",
+            "a/../b",
+            "../a",
+            ".git",
+            "a/.git",
+            ".git/config",
+            ".GIT",
+            ".Git",
+            ".git.",
+            ".git ",
+            ".git. .",
+            "a/.gIt ./b",
+            "git~1",
+            "GIT~1/config",
+        ] {
+            assert!(!verify_path(path.as_bytes()), "{path}");
+        }
+    }
+
+    /// An index with stat data, several stages, shared path prefixes for version 4, and the
+    /// `TREE` and `REUC` extensions.
+    fn sample_index() -> Index {
+        let hash = |digit: &str| digit.repeat(40);
+        let mut index = Index::default();
+
+        for (i, path) in ["a", "dir/file", "dir/file2", "dir/sub/deep", "z"]
+            .iter()
+            .enumerate()
+        {
+            let mut entry = IndexEntry::new(path.as_bytes().to_vec(), MODE_BLOB, hash("1"), 0);
+            entry.ctime = (1_600_000_000 + i as u32, 7);
+            entry.mtime = (1_600_000_100, 9);
+            entry.dev = 2049;
+            entry.ino = 1000 + i as u32;
+            entry.uid = 1000;
+            entry.gid = 100;
+            entry.size = 12;
+            index.add(entry);
+        }
+        for stage in 1..=3 {
+            index.add(IndexEntry::new(
+                b"conflicted".to_vec(),
+                MODE_EXECUTABLE,
+                hash(&stage.to_string()),
+                stage,
+            ));
+        }
+
+        index.cache_tree = Some(CacheTree {
+            name: Vec::new(),
+            entry_count: -1,
+            hash: None,
+            subtrees: vec![CacheTree {
+                name: b"dir".to_vec(),
+                entry_count: 3,
+                hash: Some(hash("a")),
+                subtrees: vec![CacheTree {
+                    name: b"sub".to_vec(),
+                    entry_count: 1,
+                    hash: Some(hash("b")),
+                    subtrees: Vec::new(),
+                }],
+            }],
+        });
+        index.resolve_undo = vec![ResolveUndo {
+            path: b"resolved".to_vec(),
+            modes: [MODE_BLOB, MODE_BLOB, 0],
+            hashes: [Some(hash("c")), Some(hash("d")), None],
+        }];
+
+        index
+    }
+
+    fn assert_round_trip(mut index: Index, version: u32) {
+        let data = index.serialize(&HashAlgo::Sha1);
+        let mut parsed = Index::parse(&data, &HashAlgo::Sha1).unwrap();
+
+        assert_eq!(parsed.version, version);
+        assert_eq!(parsed.entries, index.entries);
+        assert_eq!(parsed.cache_tree, index.cache_tree);
+        assert_eq!(parsed.resolve_undo, index.resolve_undo);
+        assert_eq!(parsed.serialize(&HashAlgo::Sha1), data);
+    }
+
+    #[test]
+    fn every_version_round_trips() {
+        for version in [2, 4] {
+            let mut index = sample_index();
+            index.version = version;
+            assert_round_trip(index, version);
+        }
+
+        // Extended flags need version 3; a version 2 index is moved up to it.
+        let mut index = sample_index();
+        let skipped = index.entries.iter_mut().find(|entry| entry.path == b"z");
+        skipped.unwrap().extended_flags = EXTENDED_SKIP_WORKTREE;
+        let added = index.entries.iter_mut().find(|entry| entry.path == b"a");
+        added.unwrap().extended_flags = EXTENDED_INTENT_TO_ADD;
+        assert_round_trip(index.clone(), 3);
+
+        // Version 4 keeps them too, with its paths stored as a prefix of the previous one.
+        index.version = 4;
+        assert_round_trip(index, 4);
+    }
+
+    #[test]
+    fn version_4_compresses_paths_against_the_previous_one() {
+        let mut index = sample_index();
+        let v2 = index.serialize(&HashAlgo::Sha1);
+        index.version = 4;
+        let v4 = index.serialize(&HashAlgo::Sha1);
+
+        // After its flags (with the full name length), each path says how many bytes to drop
+        // from the end of the previous one, then what to add: `dir/file` becomes `dir/file2`
+        // by adding `2`, and `dir/sub/deep` by dropping `file2` and adding `sub/deep`.
+        let contains = |bytes: &[u8]| v4.windows(bytes.len()).any(|window| window == bytes);
+        assert!(contains(b"\x00\x09\x002\0"));
+        assert!(contains(b"\x00\x0c\x05sub/deep\0"));
+        assert!(v4.len() < v2.len());
+    }
+
+    #[test]
+    fn checksum_mismatches_are_rejected() {
+        let mut data = sample_index().serialize(&HashAlgo::Sha1);
+        let end = data.len();
+
+        let mut corrupted = data.clone();
+        corrupted[20] ^= 1;
+        assert!(matches!(
+            Index::parse(&corrupted, &HashAlgo::Sha1),
+            Err(Error::CorruptIndex(reason)) if reason == "checksum does not match"
+        ));
+
+        // An all-zero checksum means it was skipped, as with `index.skipHash`.
+        data[end - 20..].fill(0);
+        assert_eq!(
+            Index::parse(&data, &HashAlgo::Sha1).unwrap().entries,
+            sample_index().entries
+        );
+    }
+}
@@ -1,38 +1,29 @@
-use crate::config::get_config_value;
-use crate::utils::get_git_dir_path;
+use crate::config::get_merged_config;
+use crate::error::Result;
 use crate::utils::HashAlgo;
-use std::path::PathBuf;
-use std::{env, fs};
-
-fn already_init() -> bool {
-    let mut inited = false;
-
-    if get_git_dir_path().exists() {
-        inited = true;
-    }
-
-    return inited;
-}
+use std::fs;
+use std::path::{Path, PathBuf};
 
+/// Creates a repository in `directory` (or reports that one already exists there).
 pub fn init_repo(
+    directory: &Path,
     quiet: Option<bool>,
     bare: Option<bool>,
     separate_git_dir: Option<PathBuf>,
     branch_name: Option<&str>,
-    hashing_algo: Option<HashAlgo>
-) {
-    let base_git_dir = if separate_git_dir.is_some() {
-        if !bare.unwrap() {
-            separate_git_dir.unwrap().join(".git")
+    hashing_algo: Option<HashAlgo>,
+) -> Result<()> {
+    let bare = bare.unwrap_or(false);
+    let base_git_dir = if let Some(separate_git_dir) = separate_git_dir {
+        if !bare {
+            separate_git_dir.join(".git")
         } else {
-            separate_git_dir.unwrap()
+            separate_git_dir
         }
+    } else if !bare {
+        directory.join(".git")
     } else {
-        if !bare.unwrap() {
-            get_git_dir_path()
-        } else {
-            env::current_dir().unwrap()
-        }
+        directory.to_path_buf()
     };
     let objects_git_dir = base_git_dir.join("objects");
     let objects_info_git_dir = objects_git_dir.join("info");
@@ -46,50 +37,57 @@ pub fn init_repo(
     let config_git_file = base_git_dir.join("config");
     let description_git_file = base_git_dir.join("description");
 
-    if already_init() {
+    if head_git_file.exists() {
         println!(
             "Reinitialized existing Git repository in {}.",
-            get_git_dir_path().into_os_string().to_string_lossy()
+            base_git_dir.to_string_lossy()
         );
     } else {
-        fs::create_dir_all(base_git_dir).unwrap();
-        fs::create_dir_all(objects_git_dir).unwrap();
-        fs::create_dir_all(objects_info_git_dir).unwrap();
-        fs::create_dir_all(objects_pack_git_dir).unwrap();
-        fs::create_dir_all(refs_git_dir).unwrap();
-        fs::create_dir_all(refs_heads_git_dir).unwrap();
-        fs::create_dir_all(refs_tags_git_dir).unwrap();
-        fs::create_dir_all(hooks_git_dir).unwrap();
-        fs::create_dir_all(info_git_dir).unwrap();
+        fs::create_dir_all(&base_git_dir)?;
+        fs::create_dir_all(objects_git_dir)?;
+        fs::create_dir_all(objects_info_git_dir)?;
+        fs::create_dir_all(objects_pack_git_dir)?;
+        fs::create_dir_all(refs_git_dir)?;
+        fs::create_dir_all(refs_heads_git_dir)?;
+        fs::create_dir_all(refs_tags_git_dir)?;
+        fs::create_dir_all(hooks_git_dir)?;
+        fs::create_dir_all(info_git_dir)?;
 
-        let def_branch = get_config_value(String::from("init.defaultbranch"));
+        let def_branch = get_merged_config(None)?.get("init.defaultbranch");
 
         fs::write(
             head_git_file,
             String::from("ref: refs/heads/")
                 + branch_name.unwrap_or(def_branch.unwrap_or(String::from("master")).as_str())
                 + "\n",
-        )
-        .unwrap();
+        )?;
 
-        let mut config = String::from("");
+        let sha256 = hashing_algo == Some(HashAlgo::Sha256);
 
-        if hashing_algo.is_some() && hashing_algo.unwrap() == HashAlgo::Sha256 {
-            config += "[extensions]\n\tobjectformat = sha256";
+        // Extensions are only honoured by git from repository format version 1 onwards.
+        let mut config = format!(
+            "[core]\n\trepositoryformatversion = {}\n\tfilemode = true\n\tbare = {}\n",
+            if sha256 { 1 } else { 0 },
+            bare
+        );
+
+        if sha256 {
+            config += "[extensions]\n\tobjectformat = sha256\n";
         }
 
-        fs::write(config_git_file, config).unwrap();
+        fs::write(config_git_file, config)?;
         fs::write(
             description_git_file,
             "Unnamed repository; edit this file 'description' to name the repository.",
-        )
-        .unwrap();
+        )?;
 
-        if !quiet.unwrap() {
+        if !quiet.unwrap_or(false) {
             println!(
                 "Initialized empty Git repository in {}.",
-                get_git_dir_path().into_os_string().to_string_lossy()
+                base_git_dir.to_string_lossy()
             );
         }
     }
+
+    Ok(())
 }
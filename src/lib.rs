@@ -0,0 +1,49 @@
+//! pgit as a library. The `pgit` binary is a thin CLI over the [`Repository`] API exposed here.
+
+pub mod add;
+pub mod branch;
+pub mod cat_file;
+pub mod checkout;
+pub mod commit;
+pub mod config;
+pub mod config_command;
+pub mod debug;
+pub mod diff;
+pub mod diff_command;
+pub mod error;
+pub mod graph;
+pub mod hash_object;
+pub mod ident;
+pub mod ignore;
+pub mod index;
+pub mod init;
+pub mod log;
+pub mod ls_files;
+pub mod merge;
+pub mod merge_command;
+pub mod pack;
+pub mod pager;
+pub mod pathspec;
+pub mod rebase;
+pub mod ref_commands;
+pub mod refs;
+pub mod repository;
+pub mod reset;
+pub mod rev_parse;
+pub mod revision;
+pub mod revwalk;
+pub mod rm;
+pub mod sequencer;
+pub mod stash;
+pub mod state;
+pub mod status;
+pub mod structures;
+pub mod switch;
+#[cfg(test)]
+mod test_support;
+pub mod tree;
+pub mod utils;
+pub mod worktree;
+
+pub use error::{Error, Result};
+pub use repository::Repository;
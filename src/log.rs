@@ -0,0 +1,1038 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    io::{self, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, FixedOffset};
+use colored::{control::SHOULD_COLORIZE, Colorize};
+use regex::Regex;
+
+use crate::{
+    error::{Error, Result},
+    graph::Graph,
+    ident::now,
+    pathspec::Pathspec,
+    refs::{list_refs, peel, read_symbolic_ref},
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, parse_revision_arg, peel_to},
+    revwalk::uninteresting,
+    structures::{ObjectType, User},
+    tree::{flatten_tree, TreeFile},
+    worktree::cwd_prefix,
+};
+
+/// How each commit is printed, as chosen with `--pretty`/`--format`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum LogFormat {
+    /// The abbreviated hash and the subject on one line.
+    Oneline,
+    Short,
+    #[default]
+    Medium,
+    Full,
+    Fuller,
+    /// `format:<string>`: placeholders are expanded and commits are separated by newlines.
+    Format(String),
+    /// `tformat:<string>`: like `Format`, but each commit ends with a newline.
+    TFormat(String),
+}
+
+impl LogFormat {
+    /// Parses a `--pretty`/`--format` value. Like git, a bare string with a `%` in it is taken as
+    /// `tformat:`.
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(match text {
+            "oneline" => Self::Oneline,
+            "short" => Self::Short,
+            "medium" => Self::Medium,
+            "full" => Self::Full,
+            "fuller" => Self::Fuller,
+            text => {
+                if let Some(format) = text.strip_prefix("format:") {
+                    Self::Format(format.to_owned())
+                } else if let Some(format) = text.strip_prefix("tformat:") {
+                    Self::TFormat(format.to_owned())
+                } else if text.contains('%') {
+                    Self::TFormat(text.to_owned())
+                } else {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid --pretty format: {text}"
+                    )));
+                }
+            }
+        })
+    }
+
+    /// Whether each commit is followed by a newline, rather than commits being separated by one.
+    fn uses_terminator(&self) -> bool {
+        matches!(self, Self::Oneline | Self::TFormat(_))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Draw the history graph next to the commits.
+    pub graph: bool,
+    /// Start from every ref and `HEAD`, as well as the given revisions.
+    pub all: bool,
+    /// Show at most this many commits.
+    pub max_count: Option<usize>,
+    /// Only show commits whose author matches one of these regexes.
+    pub authors: Vec<String>,
+    /// Only show commits whose message matches one of these regexes.
+    pub greps: Vec<String>,
+    /// Only show commits committed at or after this time, and stop walking past older ones.
+    pub since: Option<u64>,
+    /// Only show commits committed at or before this time.
+    pub until: Option<u64>,
+    /// Only follow the first parent of merges.
+    pub first_parent: bool,
+    /// Show the refs pointing at each commit.
+    pub decorate: bool,
+}
+
+/// The fields of a commit `log` needs, read once per commit.
+#[derive(Debug, Clone)]
+struct CommitInfo {
+    tree: String,
+    parents: Vec<String>,
+    author: User,
+    committer: User,
+    message: String,
+}
+
+/// Walks the history from `revisions` (all of `git log`'s revision syntax, including ranges) and
+/// writes it to `out` as `git log` would, limited to the commits that change `paths` if any are
+/// given. Arguments that are not revisions but name existing files are taken as paths too.
+///
+/// Commits are written as the walk finds them (except with `--graph`, which needs them all to
+/// order them), and the walk stops once `out` is closed.
+pub fn log(
+    repo: &Repository,
+    revisions: &[String],
+    paths: &[String],
+    options: &LogOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut paths = paths.to_vec();
+    let mut items = Vec::new();
+
+    for (i, arg) in revisions.iter().enumerate() {
+        match parse_revision_arg(repo, arg) {
+            Ok(parsed) => items.extend(parsed),
+            Err(Error::UnknownRevision(_)) if Path::new(arg).exists() => {
+                paths.extend(revisions[i..].iter().cloned());
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let mut tips = Vec::new();
+    let mut negatives = Vec::new();
+
+    for item in items {
+        let hash = peel_to(repo, &item.hash, &ObjectType::Commit)?;
+
+        if item.negated {
+            negatives.push(hash);
+        } else {
+            tips.push(hash);
+        }
+    }
+
+    if options.all {
+        tips.extend(repo.head()?);
+
+        for entry in list_refs(repo, "refs/")? {
+            // Refs to trees or blobs have no history to show.
+            if let Ok(hash) = peel_to(repo, &entry.hash, &ObjectType::Commit) {
+                tips.push(hash);
+            }
+        }
+    } else if tips.is_empty() && negatives.is_empty() {
+        match repo.head()? {
+            Some(head) => tips.push(head),
+            None => {
+                let branch = read_symbolic_ref(repo, "HEAD")?.unwrap_or_default();
+
+                return Err(Error::InvalidArgument(format!(
+                    "your current branch '{}' does not have any commits yet",
+                    branch.strip_prefix("refs/heads/").unwrap_or(&branch)
+                )));
+            }
+        }
+    }
+
+    let pathspec = match repo.work_tree() {
+        Some(work_tree) => Pathspec::new(work_tree, &cwd_prefix(repo)?, &paths)?,
+        None => Pathspec::new(Path::new(""), &[], &paths)?,
+    };
+
+    let excluded = if negatives.is_empty() {
+        HashSet::new()
+    } else {
+        uninteresting(repo, &tips, &negatives)?
+    };
+
+    let mut history = History::new(repo, pathspec, options, excluded)?;
+    history.start(&tips)?;
+
+    match history.render(out) {
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// The state of one `log` run: the commits and filtered trees read so far, and what the walk
+/// decided to show.
+struct History<'a> {
+    repo: &'a Repository,
+    pathspec: Pathspec,
+    options: &'a LogOptions,
+    authors: Vec<Regex>,
+    greps: Vec<Regex>,
+    commits: HashMap<String, CommitInfo>,
+    /// Each tree flattened and limited to the pathspec, by tree hash.
+    trees: HashMap<String, BTreeMap<Vec<u8>, TreeFile>>,
+    /// The parents the walk went on to from each commit: all of them, or just the one the
+    /// commit is TREESAME to when history is simplified.
+    followed: HashMap<String, Vec<String>>,
+    /// Commits left out because they do not change the pathspec.
+    treesame: HashSet<String>,
+    /// Where the walk stops: the commits excluded by `^` and `..`.
+    excluded: HashSet<String>,
+    /// The commits waiting to be walked, newest first, with ties in the order they were found.
+    queue: BinaryHeap<(u64, Reverse<usize>, String)>,
+    queued: HashSet<String>,
+    abbreviations: HashMap<String, String>,
+    abbrev_len: usize,
+}
+
+impl<'a> History<'a> {
+    fn new(
+        repo: &'a Repository,
+        pathspec: Pathspec,
+        options: &'a LogOptions,
+        excluded: HashSet<String>,
+    ) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Regex::new(pattern).map_err(|err| {
+                        Error::InvalidArgument(format!("invalid pattern `{pattern}`: {err}"))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            repo,
+            pathspec,
+            options,
+            authors: compile(&options.authors)?,
+            greps: compile(&options.greps)?,
+            commits: HashMap::new(),
+            trees: HashMap::new(),
+            followed: HashMap::new(),
+            treesame: HashSet::new(),
+            excluded,
+            queue: BinaryHeap::new(),
+            queued: HashSet::new(),
+            abbreviations: HashMap::new(),
+            abbrev_len: default_abbrev(repo),
+        })
+    }
+
+    fn commit(&mut self, hash: &str) -> Result<&CommitInfo> {
+        if !self.commits.contains_key(hash) {
+            let object = self.repo.read_object(hash)?;
+
+            if object.obj_type != ObjectType::Commit {
+                return Err(Error::InvalidArgument(format!(
+                    "`{hash}` is a {}, not a commit",
+                    object.obj_type
+                )));
+            }
+
+            let meta = object.meta.unwrap_or_default();
+            let missing = |field| Error::corrupt(hash, &format!("commit has no {field}"));
+
+            let info = CommitInfo {
+                tree: meta.tree.ok_or_else(|| missing("tree"))?,
+                parents: meta.parents,
+                author: meta.author.ok_or_else(|| missing("author"))?,
+                committer: meta.committer.ok_or_else(|| missing("committer"))?,
+                message: meta.message.unwrap_or_default(),
+            };
+
+            self.commits.insert(hash.to_owned(), info);
+        }
+
+        Ok(&self.commits[hash])
+    }
+
+    /// The commit's tree, limited to the pathspec.
+    fn filtered_tree(&mut self, commit: &str) -> Result<&BTreeMap<Vec<u8>, TreeFile>> {
+        let tree = self.commit(commit)?.tree.clone();
+
+        if !self.trees.contains_key(&tree) {
+            let files = flatten_tree(self.repo, &tree)?
+                .into_iter()
+                .filter(|(path, _)| self.pathspec.matches(path))
+                .collect();
+
+            self.trees.insert(tree.clone(), files);
+        }
+
+        Ok(&self.trees[&tree])
+    }
+
+    /// The parents to walk on to from `commit`, and whether the commit is TREESAME: it changes
+    /// nothing in the pathspec compared to the first parent that has the same files, which is
+    /// then the only one followed. Root commits are TREESAME when the pathspec matches nothing
+    /// in them.
+    fn simplify(&mut self, commit: &str) -> Result<(Vec<String>, bool)> {
+        let mut parents = self.commit(commit)?.parents.clone();
+
+        if self.options.first_parent {
+            parents.truncate(1);
+        }
+
+        if self.pathspec.is_empty() {
+            return Ok((parents, false));
+        }
+
+        let files = self.filtered_tree(commit)?.clone();
+
+        if parents.is_empty() {
+            return Ok((parents, files.is_empty()));
+        }
+
+        for parent in &parents {
+            if *self.filtered_tree(parent)? == files {
+                return Ok((vec![parent.clone()], true));
+            }
+        }
+
+        Ok((parents, false))
+    }
+
+    /// Whether the commit passes `--author`, `--grep` and `--until`.
+    fn matches_filters(&mut self, commit: &str) -> Result<bool> {
+        self.commit(commit)?;
+
+        let until = self.options.until;
+        let info = &self.commits[commit];
+
+        let author = format!(
+            "{} <{}>",
+            info.author.name,
+            info.author.email.as_deref().unwrap_or_default()
+        );
+
+        Ok(until.is_none_or(|until| info.committer.time <= until)
+            && (self.authors.is_empty() || self.authors.iter().any(|re| re.is_match(&author)))
+            && (self.greps.is_empty() || self.greps.iter().any(|re| re.is_match(&info.message))))
+    }
+
+    /// Queues the commit for the walk unless it was already queued or is excluded.
+    fn enqueue(&mut self, commit: &str) -> Result<()> {
+        if !self.excluded.contains(commit) && self.queued.insert(commit.to_owned()) {
+            let time = self.commit(commit)?.committer.time;
+            let order = self.queued.len();
+
+            self.queue.push((time, Reverse(order), commit.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Starts the walk back from `tips`.
+    fn start(&mut self, tips: &[String]) -> Result<()> {
+        for tip in tips {
+            self.enqueue(tip)?;
+        }
+
+        Ok(())
+    }
+
+    /// Walks on, newest commit first, up to the next commit to show, or `None` once the walk
+    /// is over.
+    fn next_shown(&mut self) -> Result<Option<String>> {
+        while let Some((time, _, commit)) = self.queue.pop() {
+            // Like git, stop walking down a line of history once it is older than `--since`.
+            if self.options.since.is_some_and(|since| time < since) {
+                continue;
+            }
+
+            let (parents, treesame) = self.simplify(&commit)?;
+
+            for parent in &parents {
+                self.enqueue(parent)?;
+            }
+
+            self.followed.insert(commit.clone(), parents);
+
+            if treesame {
+                self.treesame.insert(commit);
+            } else if self.matches_filters(&commit)? {
+                return Ok(Some(commit));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The parents of a shown commit as the graph draws them: simplified-away commits are
+    /// skipped over, and edges to commits that are not shown are dropped.
+    fn graph_parents(&self, commit: &str, shown: &HashSet<&String>) -> Vec<String> {
+        let mut parents = Vec::new();
+
+        for parent in self.followed.get(commit).into_iter().flatten() {
+            let mut parent = parent;
+
+            while self.treesame.contains(parent) {
+                match self.followed.get(parent).and_then(|next| next.first()) {
+                    Some(next) => parent = next,
+                    None => break,
+                }
+            }
+
+            if shown.contains(parent) && !parents.contains(parent) {
+                parents.push(parent.clone());
+            }
+        }
+
+        parents
+    }
+
+    /// Reorders the shown commits so that no commit comes before any of its children, keeping
+    /// each line of history together the way git does for `--graph`: the commits are taken off
+    /// a stack, and a parent is pushed once all its children are out.
+    fn topo_order(shown: &[String], parents: &HashMap<String, Vec<String>>) -> Vec<String> {
+        let mut indegree: HashMap<&String, usize> =
+            shown.iter().map(|commit| (commit, 1)).collect();
+
+        for commit in shown {
+            for parent in &parents[commit] {
+                if let Some(count) = indegree.get_mut(parent) {
+                    *count += 1;
+                }
+            }
+        }
+
+        let mut stack: Vec<&String> = shown
+            .iter()
+            .filter(|commit| indegree[commit] == 1)
+            .collect();
+        stack.reverse();
+
+        let mut sorted = Vec::new();
+
+        while let Some(commit) = stack.pop() {
+            for parent in &parents[commit] {
+                let count = indegree.get_mut(parent).unwrap();
+                *count -= 1;
+
+                if *count == 1 {
+                    stack.push(parent);
+                }
+            }
+
+            sorted.push(commit.clone());
+        }
+
+        sorted
+    }
+
+    fn abbreviate(&mut self, hash: &str) -> Result<String> {
+        if let Some(short) = self.abbreviations.get(hash) {
+            return Ok(short.clone());
+        }
+
+        let short = abbreviate(self.repo, hash, self.abbrev_len)?;
+        self.abbreviations.insert(hash.to_owned(), short.clone());
+
+        Ok(short)
+    }
+
+    /// Writes the shown commits out with the graph (if any) in the margin, following git's
+    /// `log-tree.c`: entries are separated or terminated by newlines depending on the format,
+    /// and every line after a commit's first gets the next line of the graph.
+    fn render(&mut self, out: &mut impl Write) -> Result<()> {
+        // The graph needs every commit up front to put them in topological order; otherwise
+        // the walk order is the output order, and commits go out as they are found.
+        let mut sorted = None;
+        let mut parents = HashMap::new();
+
+        if self.options.graph {
+            let mut shown = Vec::new();
+            while let Some(commit) = self.next_shown()? {
+                shown.push(commit);
+            }
+
+            let set: HashSet<&String> = shown.iter().collect();
+            parents = shown
+                .iter()
+                .map(|commit| (commit.clone(), self.graph_parents(commit, &set)))
+                .collect();
+
+            sorted = Some(Self::topo_order(&shown, &parents).into_iter());
+        }
+
+        let decorations = if self.options.decorate || self.format_uses_decorations() {
+            decorations(self.repo)?
+        } else {
+            HashMap::new()
+        };
+
+        let terminator = self.options.format.uses_terminator();
+        let mut graph = self.options.graph.then(Graph::new);
+        let mut missing_newline = false;
+        let mut count = 0;
+
+        while self
+            .options
+            .max_count
+            .is_none_or(|max_count| count < max_count)
+        {
+            let commit = match &mut sorted {
+                Some(sorted) => sorted.next(),
+                None => self.next_shown()?,
+            };
+            let Some(commit) = commit else {
+                break;
+            };
+
+            let mut output = String::new();
+
+            if let Some(graph) = &mut graph {
+                graph.update(&commit, parents[&commit].clone());
+            }
+
+            if count > 0 && !terminator {
+                // Keep the graph going through the blank line between commits.
+                if !missing_newline {
+                    if let Some(graph) = &mut graph {
+                        output += &graph.padding_line();
+                    }
+                }
+                output.push('\n');
+            }
+
+            let text = self.format_commit(&commit, &decorations)?;
+            missing_newline = !text.ends_with('\n');
+
+            match &mut graph {
+                Some(graph) => {
+                    output += &graph.show_commit();
+
+                    let mut lines = text.split_inclusive('\n').peekable();
+                    while let Some(line) = lines.next() {
+                        output += line;
+
+                        if line.ends_with('\n') && lines.peek().is_some() {
+                            output += &graph.next_line().0;
+                        }
+                    }
+
+                    if !graph.is_commit_finished() {
+                        if missing_newline {
+                            output.push('\n');
+                        }
+                        output += &graph.show_remainder();
+                        if !missing_newline {
+                            output.push('\n');
+                        }
+                    }
+                }
+                None => output += &text,
+            }
+
+            if terminator {
+                if !missing_newline {
+                    if let Some(graph) = &mut graph {
+                        output += &graph.padding_line();
+                    }
+                }
+                output.push('\n');
+            }
+
+            out.write_all(output.as_bytes())?;
+            count += 1;
+        }
+
+        Ok(())
+    }
+
+    fn format_uses_decorations(&self) -> bool {
+        match &self.options.format {
+            LogFormat::Format(format) | LogFormat::TFormat(format) => {
+                format.contains("%d") || format.contains("%D")
+            }
+            _ => false,
+        }
+    }
+
+    /// The text for one commit: for the built-in formats everything but `oneline` ends with a
+    /// newline.
+    fn format_commit(
+        &mut self,
+        commit: &str,
+        decorations: &HashMap<String, Vec<Decoration>>,
+    ) -> Result<String> {
+        let decoration = match (self.options.decorate, decorations.get(commit)) {
+            (true, Some(refs)) => format!(" {}", format_decorations(refs, true)),
+            _ => String::new(),
+        };
+
+        let format = match &self.options.format {
+            LogFormat::Format(format) | LogFormat::TFormat(format) => {
+                return self.expand_format(commit, format, decorations);
+            }
+            LogFormat::Oneline => {
+                let short = self.abbreviate(commit)?;
+                let subject = subject(&self.commit(commit)?.message);
+
+                return Ok(format!("{}{decoration} {subject}", short.yellow()));
+            }
+            format => format.clone(),
+        };
+
+        let info = self.commit(commit)?.clone();
+        let mut text = format!("{}{decoration}\n", format!("commit {commit}").yellow());
+
+        if info.parents.len() > 1 {
+            let parents = info
+                .parents
+                .iter()
+                .map(|parent| self.abbreviate(parent))
+                .collect::<Result<Vec<_>>>()?;
+
+            text += &format!("Merge: {}\n", parents.join(" "));
+        }
+
+        match format {
+            LogFormat::Short => text += &format!("Author: {}\n", identity(&info.author)),
+            LogFormat::Medium => {
+                text += &format!("Author: {}\n", identity(&info.author));
+                text += &format!("Date:   {}\n", format_date(&info.author, DEFAULT_DATE));
+            }
+            LogFormat::Full => {
+                text += &format!("Author: {}\n", identity(&info.author));
+                text += &format!("Commit: {}\n", identity(&info.committer));
+            }
+            _ => {
+                text += &format!("Author:     {}\n", identity(&info.author));
+                text += &format!("AuthorDate: {}\n", format_date(&info.author, DEFAULT_DATE));
+                text += &format!("Commit:     {}\n", identity(&info.committer));
+                text += &format!(
+                    "CommitDate: {}\n",
+                    format_date(&info.committer, DEFAULT_DATE)
+                );
+            }
+        }
+
+        text.push('\n');
+
+        let lines = message_lines(&info.message);
+        let lines = if format == LogFormat::Short {
+            &lines[..lines
+                .iter()
+                .position(|line| line.trim().is_empty())
+                .unwrap_or(lines.len())]
+        } else {
+            &lines[..]
+        };
+
+        for line in lines {
+            text += &format!("    {line}\n");
+        }
+
+        Ok(text)
+    }
+
+    /// Expands the `%` placeholders of a `--format` string. Unknown placeholders are kept as
+    /// they are, like git does.
+    fn expand_format(
+        &mut self,
+        commit: &str,
+        format: &str,
+        decorations: &HashMap<String, Vec<Decoration>>,
+    ) -> Result<String> {
+        let mut output = String::new();
+        let mut rest = format;
+
+        while let Some(percent) = rest.find('%') {
+            output += &rest[..percent];
+            rest = &rest[percent + 1..];
+
+            match self.placeholder(commit, rest, decorations)? {
+                Some((text, used)) => {
+                    output += &text;
+                    rest = &rest[used..];
+                }
+                None => output.push('%'),
+            }
+        }
+
+        output += rest;
+
+        Ok(output)
+    }
+
+    /// Expands the placeholder at the start of `spec` (just after its `%`), returning the text
+    /// and how many bytes of `spec` it took up.
+    fn placeholder(
+        &mut self,
+        commit: &str,
+        spec: &str,
+        decorations: &HashMap<String, Vec<Decoration>>,
+    ) -> Result<Option<(String, usize)>> {
+        for (name, color) in [
+            ("Cred", "red"),
+            ("Cgreen", "green"),
+            ("Cblue", "blue"),
+            ("Creset", "reset"),
+        ] {
+            if spec.starts_with(name) {
+                return Ok(Some((ansi_color(color).unwrap_or_default(), name.len())));
+            }
+        }
+
+        if let Some(color) = spec.strip_prefix("C(") {
+            let Some(end) = color.find(')') else {
+                return Ok(None);
+            };
+
+            return Ok(ansi_color(&color[..end]).map(|code| (code, end + 3)));
+        }
+
+        if let Some(hex) = spec.strip_prefix('x').and_then(|hex| hex.get(..2)) {
+            return Ok(u8::from_str_radix(hex, 16)
+                .ok()
+                .map(|byte| ((byte as char).to_string(), 3)));
+        }
+
+        let Some(key) = spec.chars().next() else {
+            return Ok(None);
+        };
+
+        let info = self.commit(commit)?.clone();
+
+        let text = match key {
+            'n' => "\n".to_owned(),
+            '%' => "%".to_owned(),
+            'H' => commit.to_owned(),
+            'h' => self.abbreviate(commit)?,
+            'T' => info.tree.clone(),
+            't' => self.abbreviate(&info.tree)?,
+            'P' => info.parents.join(" "),
+            'p' => info
+                .parents
+                .iter()
+                .map(|parent| self.abbreviate(parent))
+                .collect::<Result<Vec<_>>>()?
+                .join(" "),
+            's' => subject(&info.message),
+            'b' => body(&info.message),
+            'B' => {
+                let mut message = info.message.trim_start_matches('\n').to_owned();
+                if !message.is_empty() && !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                message
+            }
+            'd' => match decorations.get(commit) {
+                Some(refs) => format!(" {}", format_decorations(refs, true)),
+                None => String::new(),
+            },
+            'D' => match decorations.get(commit) {
+                Some(refs) => format_decorations(refs, false),
+                None => String::new(),
+            },
+            'a' | 'c' => {
+                let user = if key == 'a' {
+                    &info.author
+                } else {
+                    &info.committer
+                };
+
+                let text = match spec[1..].chars().next() {
+                    Some('n') => user.name.clone(),
+                    Some('e') => user.email.clone().unwrap_or_default(),
+                    Some('d') => format_date(user, DEFAULT_DATE),
+                    Some('r') => relative_date(user.time, now().0),
+                    Some('t') => user.time.to_string(),
+                    Some('i') => format_date(user, "%Y-%m-%d %H:%M:%S %z"),
+                    Some('I') => format_date(user, "%Y-%m-%dT%H:%M:%S%:z"),
+                    Some('s') => format_date(user, "%Y-%m-%d"),
+                    _ => return Ok(None),
+                };
+
+                return Ok(Some((text, 2)));
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some((text, 1)))
+    }
+}
+
+/// git's default date format, e.g. `Thu Mar 7 10:00:00 2024 +0100`.
+pub const DEFAULT_DATE: &str = "%a %b %-d %H:%M:%S %Y %z";
+
+fn identity(user: &User) -> String {
+    format!(
+        "{} <{}>",
+        user.name,
+        user.email.as_deref().unwrap_or_default()
+    )
+}
+
+/// Formats the identity's timestamp in its own timezone.
+pub fn format_date(user: &User, format: &str) -> String {
+    let offset = DateTime::parse_from_str(&format!("0 {}", user.zone), "%s %z")
+        .map(|zoned| *zoned.offset())
+        .unwrap_or(FixedOffset::east_opt(0).unwrap());
+
+    match DateTime::from_timestamp(user.time as i64, 0) {
+        Some(time) => time.with_timezone(&offset).format(format).to_string(),
+        None => format!("{} {}", user.time, user.zone),
+    }
+}
+
+/// Describes how long ago `time` was the way git's `--date=relative` does, rounding to the
+/// nearest unit and switching to coarser units as the distance grows.
+fn relative_date(time: u64, now: u64) -> String {
+    let plural =
+        |count: u64, unit: &str| format!("{count} {unit}{} ago", if count == 1 { "" } else { "s" });
+
+    if time > now {
+        return "in the future".to_owned();
+    }
+
+    let seconds = now - time;
+    if seconds < 90 {
+        return plural(seconds, "second");
+    }
+
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return plural(minutes, "minute");
+    }
+
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return plural(hours, "hour");
+    }
+
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return plural(days, "day");
+    }
+    if days < 70 {
+        return plural((days + 3) / 7, "week");
+    }
+    if days < 365 {
+        return plural((days + 15) / 30, "month");
+    }
+
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        let years = format!("{years} year{}", if years == 1 { "" } else { "s" });
+
+        return match months {
+            0 => format!("{years} ago"),
+            1 => format!("{years}, 1 month ago"),
+            months => format!("{years}, {months} months ago"),
+        };
+    }
+
+    plural((days + 183) / 365, "year")
+}
+
+/// The message's lines, without leading and trailing blank lines.
+fn message_lines(message: &str) -> Vec<&str> {
+    let lines: Vec<&str> = message.split('\n').collect();
+    let start = lines
+        .iter()
+        .position(|line| !line.trim().is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(start, |end| end + 1);
+
+    lines[start..end].to_vec()
+}
+
+/// The first paragraph of the message, joined into one line: the subject as `%s` shows it, and
+/// as every command that names a commit by its message prints it.
+pub fn subject(message: &str) -> String {
+    message_lines(message)
+        .into_iter()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything after the first paragraph of the message.
+fn body(message: &str) -> String {
+    let lines = message_lines(message);
+    let rest: Vec<&str> = lines
+        .iter()
+        .skip_while(|line| !line.trim().is_empty())
+        .skip_while(|line| line.trim().is_empty())
+        .copied()
+        .collect();
+
+    if rest.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", rest.join("\n"))
+    }
+}
+
+/// The escape sequence for a `%C(...)` colour spec such as `red`, `bold blue` or `auto,green`,
+/// or an empty string when output is not coloured. Returns `None` for specs git would reject.
+fn ansi_color(spec: &str) -> Option<String> {
+    let spec = spec.strip_prefix("auto,").unwrap_or(spec);
+    let mut codes = Vec::new();
+    let mut colors = 0;
+
+    for word in spec.split_whitespace() {
+        let color = match word {
+            "normal" => Some(None),
+            "black" => Some(Some(30)),
+            "red" => Some(Some(31)),
+            "green" => Some(Some(32)),
+            "yellow" => Some(Some(33)),
+            "blue" => Some(Some(34)),
+            "magenta" => Some(Some(35)),
+            "cyan" => Some(Some(36)),
+            "white" => Some(Some(37)),
+            _ => None,
+        };
+
+        if let Some(color) = color {
+            // The first colour is the foreground, the second the background.
+            if let Some(code) = color {
+                codes.push((code + 10 * colors).to_string());
+            }
+            colors += 1;
+            continue;
+        }
+
+        let attribute = match word {
+            "reset" | "auto" => continue,
+            "bold" => 1,
+            "dim" => 2,
+            "italic" => 3,
+            "ul" => 4,
+            "blink" => 5,
+            "reverse" => 7,
+            "strike" => 9,
+            _ => return None,
+        };
+
+        codes.push(attribute.to_string());
+    }
+
+    if !SHOULD_COLORIZE.should_colorize() || spec == "auto" {
+        return Some(String::new());
+    }
+
+    Some(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// A ref pointing at a commit, as shown next to it.
+#[derive(Debug, Clone, PartialEq)]
+enum Decoration {
+    /// `HEAD`, and the branch it is on if that points here too.
+    Head(Option<String>),
+    Branch(String),
+    RemoteBranch(String),
+    Tag(String),
+    Other(String),
+}
+
+/// The refs pointing at each commit, in the order git shows them: `HEAD` first, then the rest in
+/// reverse order of their full names. Annotated tags decorate the commit they point at.
+fn decorations(repo: &Repository) -> Result<HashMap<String, Vec<Decoration>>> {
+    let mut decorations = HashMap::<String, Vec<Decoration>>::new();
+    let head_branch = read_symbolic_ref(repo, "HEAD")?;
+    let head = repo.head()?;
+
+    for entry in list_refs(repo, "refs/")?.into_iter().rev() {
+        if Some(&entry.name) == head_branch.as_ref() && Some(&entry.hash) == head.as_ref() {
+            continue;
+        }
+
+        let hash = match entry.peeled {
+            Some(peeled) => peeled,
+            None => peel(repo, &entry.hash)?,
+        };
+
+        let decoration = if let Some(name) = entry.name.strip_prefix("refs/heads/") {
+            Decoration::Branch(name.to_owned())
+        } else if let Some(name) = entry.name.strip_prefix("refs/remotes/") {
+            Decoration::RemoteBranch(name.to_owned())
+        } else if let Some(name) = entry.name.strip_prefix("refs/tags/") {
+            Decoration::Tag(name.to_owned())
+        } else {
+            Decoration::Other(entry.name.clone())
+        };
+
+        decorations.entry(hash).or_default().push(decoration);
+    }
+
+    if let Some(head) = head {
+        let branch = head_branch
+            .as_deref()
+            .and_then(|branch| branch.strip_prefix("refs/heads/"))
+            .map(str::to_owned);
+
+        decorations
+            .entry(head)
+            .or_default()
+            .insert(0, Decoration::Head(branch));
+    }
+
+    Ok(decorations)
+}
+
+/// Formats decorations as `HEAD -> main, tag: v1, origin/main`, wrapped in parentheses for the
+/// commit header and `%d`.
+fn format_decorations(refs: &[Decoration], parenthesized: bool) -> String {
+    let separator = ", ".yellow().to_string();
+    let names = refs
+        .iter()
+        .map(|decoration| match decoration {
+            Decoration::Head(Some(branch)) => format!(
+                "{}{}",
+                "HEAD -> ".cyan().bold(),
+                branch.as_str().green().bold()
+            ),
+            Decoration::Head(None) => "HEAD".cyan().bold().to_string(),
+            Decoration::Branch(name) => name.as_str().green().bold().to_string(),
+            Decoration::RemoteBranch(name) => name.as_str().red().bold().to_string(),
+            Decoration::Tag(name) => format!("tag: {name}").yellow().bold().to_string(),
+            Decoration::Other(name) => name.as_str().magenta().bold().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(&separator);
+
+    if parenthesized {
+        format!("{}{names}{}", "(".yellow(), ")".yellow())
+    } else {
+        names
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    error::Result,
+    index::Index,
+    repository::Repository,
+    utils::quote_path_bytes,
+    worktree::{cwd_prefix, entry_state, untracked_files, WorktreeState},
+};
+
+/// Which groups of paths `ls-files` shows. With none of `modified`, `others` and `deleted` set,
+/// the staged paths are shown.
+#[derive(Debug, Clone, Default)]
+pub struct LsFilesOptions {
+    pub cached: bool,
+    pub stage: bool,
+    pub modified: bool,
+    pub others: bool,
+    pub deleted: bool,
+    /// Leave paths unquoted, for output that ends each line with NUL (`-z`).
+    pub null: bool,
+}
+
+/// Lists paths below the current directory, relative to it, in the same order as git: untracked
+/// files first, then staged entries, then deleted and modified ones. Unless `null` is set, paths
+/// are quoted as `core.quotePath` asks.
+pub fn ls_files(repo: &Repository, options: &LsFilesOptions) -> Result<Vec<Vec<u8>>> {
+    let index = Index::read(repo)?;
+    let prefix = cwd_prefix(repo)?;
+    let quote_non_ascii = repo.config().get_bool("core.quotepath").unwrap_or(true);
+    let mut lines = Vec::<Vec<u8>>::new();
+
+    let cached =
+        options.cached || !(options.stage || options.modified || options.others || options.deleted);
+
+    let relative = |path: &[u8]| -> Option<Vec<u8>> {
+        let path = path.strip_prefix(prefix.as_slice())?;
+
+        Some(if options.null {
+            path.to_vec()
+        } else {
+            quote_path_bytes(path, false, quote_non_ascii)
+        })
+    };
+
+    if options.others {
+        for path in untracked_files(repo, &index, None)? {
+            if let Some(path) = relative(&path) {
+                lines.push(path);
+            }
+        }
+    }
+
+    if cached || options.stage {
+        for entry in &index.entries {
+            let Some(path) = relative(&entry.path) else {
+                continue;
+            };
+
+            if options.stage {
+                let mut line =
+                    format!("{:06o} {} {}\t", entry.mode, entry.hash, entry.stage()).into_bytes();
+                line.extend(path);
+                lines.push(line);
+            } else {
+                lines.push(path);
+            }
+        }
+    }
+
+    if options.deleted || options.modified {
+        for entry in &index.entries {
+            let Some(path) = relative(&entry.path) else {
+                continue;
+            };
+
+            // Like git, every stage of a conflicted path is listed as modified.
+            let state = if entry.stage() == 0 {
+                entry_state(repo, entry)?
+            } else {
+                WorktreeState::Modified
+            };
+
+            // Like git, a deleted file also counts as modified.
+            if options.deleted && state == WorktreeState::Deleted {
+                lines.push(path.clone());
+            }
+
+            if options.modified && state != WorktreeState::Unchanged {
+                lines.push(path);
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::set_config_value,
+        index::{IndexEntry, MODE_BLOB},
+        test_support::TestRepo,
+    };
+
+    #[test]
+    fn paths_are_quoted_as_core_quotepath_asks() {
+        let test = TestRepo::new("ls-files-quoting");
+        let blob = test.blob("x");
+
+        let mut index = Index::default();
+        for path in ["caf\u{e9}", "plain", "t\tab"] {
+            index.add(IndexEntry::new(path.into(), MODE_BLOB, blob.clone(), 0));
+        }
+        index.write(&test.repo).unwrap();
+
+        let list = |repo: &Repository, null| {
+            let options = LsFilesOptions {
+                null,
+                ..Default::default()
+            };
+
+            ls_files(repo, &options).unwrap()
+        };
+
+        assert_eq!(
+            list(&test.repo, false),
+            [&b"\"caf\\303\\251\""[..], b"plain", b"\"t\\tab\""]
+        );
+        assert_eq!(
+            list(&test.repo, true),
+            [&b"caf\xc3\xa9"[..], b"plain", b"t\tab"]
+        );
+
+        set_config_value(
+            &test.repo.git_dir().join("config"),
+            "core.quotepath",
+            "false",
+            false,
+        )
+        .unwrap();
+        let repo = Repository::open(test.repo.work_tree().unwrap()).unwrap();
+
+        assert_eq!(
+            list(&repo, false),
+            [&b"caf\xc3\xa9"[..], b"plain", b"\"t\\tab\""]
+        );
+    }
+}
@@ -1,16 +1,59 @@
-mod cat_file;
-mod config;
-mod debug;
-mod init;
-mod utils;
-mod structures;
-
-use cat_file::{get_file_contents, GetFileContentsReturnType};
-use clap::{builder::EnumValueParser, Arg, ArgAction, Command};
-use debug::debug;
-use init::init_repo;
-use std::{env, path::Path};
-use utils::HashAlgo;
+use clap::{builder::EnumValueParser, error::ContextKind, Arg, ArgAction, ArgMatches, Command};
+use pgit::{
+    add::{add, AddOptions},
+    branch::{
+        create_branch, current_branch, delete_branch, list_branches, rename_branch, set_upstream,
+        unset_upstream, BranchListOptions, CreateBranchOptions,
+    },
+    cat_file::{get_file_contents, GetFileContentsReturnType},
+    commit::{commit, CommitOptions},
+    config::parse_bool,
+    config_command::{
+        config_get, config_get_all, config_list, config_set, config_unset, ConfigOutput,
+        ConfigScope, ConfigType,
+    },
+    debug::debug,
+    diff::{DiffAlgorithm, RENAME_THRESHOLD},
+    diff_command::{diff, parse_rename_score, ColorMoved, DiffOptions, WordDiff},
+    hash_object::hash_object,
+    ident::parse_approxidate,
+    init::init_repo,
+    log::{log, LogFormat, LogOptions},
+    ls_files::{ls_files, LsFilesOptions},
+    merge_command::{merge, merge_abort, merge_continue, FastForward, MergeCommandOptions},
+    pager::{page, Pager},
+    rebase::{rebase, rebase_abort, rebase_continue, rebase_skip, RebaseOptions},
+    ref_commands::{
+        delete_ref_command, show_ref, symbolic_ref_get, update_ref_command, ShowRefOptions,
+    },
+    refs::{delete_symbolic_ref, set_symbolic_ref},
+    reset::{carry_out, plan_reset, plan_restore, ResetMode, RestoreOptions},
+    rev_parse::{repo_query, rev_parse, RepoQuery, RevParseOptions, SymbolicName},
+    revision::default_abbrev,
+    rm::{rm, RmOptions},
+    sequencer::{
+        pick_revisions, sequencer_abort, sequencer_continue, sequencer_skip, SequencerAction,
+        SequencerOptions,
+    },
+    stash::{
+        stash_apply, stash_branch, stash_clear, stash_drop, stash_list, stash_pop, stash_push,
+        stash_show, StashOptions,
+    },
+    status::{status, StatusOptions, UntrackedMode},
+    structures::ObjectType,
+    switch::{checkout, switch, Checkout, SwitchOptions},
+    utils::HashAlgo,
+    worktree::cwd_prefix,
+    Error, Repository, Result,
+};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+use termimad::{crossterm::style::Color::Red, MadSkin};
 
 fn main() {
     let init = Command::new("init")
@@ -74,6 +117,1175 @@ fn main() {
                 .conflicts_with("size")
                 .conflicts_with("type"),
         ]);
+    let hash_object_cmd = Command::new("hash-object")
+        .about("Compute object hash and optionally create an object from a file")
+        .args([
+            Arg::new("files").num_args(0..).value_name("file"),
+            Arg::new("write")
+                .short('w')
+                .help("Write the object into the object database")
+                .action(ArgAction::SetTrue),
+            Arg::new("type")
+                .short('t')
+                .help("Type of object to create")
+                .action(ArgAction::Set)
+                .value_parser(EnumValueParser::<ObjectType>::new())
+                .default_value("blob")
+                .value_name("type"),
+            Arg::new("stdin")
+                .long("stdin")
+                .help("Read the object from standard input")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("stdin-paths"),
+            Arg::new("stdin-paths")
+                .long("stdin-paths")
+                .help("Read file names from standard input, one per line")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("files"),
+        ]);
+    let ls_files_cmd = Command::new("ls-files")
+        .about("Show information about files in the index and the working tree")
+        .args([
+            Arg::new("cached")
+                .long("cached")
+                .short('c')
+                .help("Show staged files (the default)")
+                .action(ArgAction::SetTrue),
+            Arg::new("stage")
+                .long("stage")
+                .short('s')
+                .help("Show the mode, object name and stage of staged files")
+                .action(ArgAction::SetTrue),
+            Arg::new("modified")
+                .long("modified")
+                .short('m')
+                .help("Show files that differ from the index")
+                .action(ArgAction::SetTrue),
+            Arg::new("others")
+                .long("others")
+                .short('o')
+                .help("Show files that are not staged")
+                .action(ArgAction::SetTrue),
+            Arg::new("deleted")
+                .long("deleted")
+                .short('d')
+                .help("Show staged files missing from the working tree")
+                .action(ArgAction::SetTrue),
+            Arg::new("null")
+                .short('z')
+                .help("Terminate lines with NUL instead of quoting paths")
+                .action(ArgAction::SetTrue),
+        ]);
+    let add_cmd = Command::new("add")
+        .about("Stage file contents for the next commit")
+        .args([
+            Arg::new("pathspec").num_args(0..).value_name("pathspec"),
+            Arg::new("all")
+                .long("all")
+                .short('A')
+                .help("Stage all changes, including new and deleted files")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("update"),
+            Arg::new("update")
+                .long("update")
+                .short('u')
+                .help("Only stage changes to tracked files")
+                .action(ArgAction::SetTrue),
+            Arg::new("patch")
+                .long("patch")
+                .short('p')
+                .help("Choose which hunks to stage")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["all", "update", "intent-to-add"]),
+            Arg::new("intent-to-add")
+                .long("intent-to-add")
+                .short('N')
+                .help("Record that new files will be added later, without their contents")
+                .action(ArgAction::SetTrue),
+            Arg::new("force")
+                .long("force")
+                .short('f')
+                .help("Allow adding ignored files")
+                .action(ArgAction::SetTrue),
+        ]);
+    let rm_cmd = Command::new("rm")
+        .about("Remove files from the index and the working tree")
+        .args([
+            Arg::new("pathspec").num_args(0..).value_name("pathspec"),
+            Arg::new("cached")
+                .long("cached")
+                .help("Only remove files from the index")
+                .action(ArgAction::SetTrue),
+            Arg::new("recursive")
+                .short('r')
+                .help("Allow removing directories")
+                .action(ArgAction::SetTrue),
+            Arg::new("force")
+                .long("force")
+                .short('f')
+                .help("Remove files even if they have uncommitted changes")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't list the removed files")
+                .action(ArgAction::SetTrue),
+        ]);
+    let show_ref_cmd = Command::new("show-ref")
+        .about("List references and the objects they point at")
+        .args([
+            Arg::new("pattern").num_args(0..).value_name("pattern"),
+            Arg::new("head")
+                .long("head")
+                .help("Also show HEAD")
+                .action(ArgAction::SetTrue),
+            Arg::new("heads")
+                .long("heads")
+                .help("Only show branches")
+                .action(ArgAction::SetTrue),
+            Arg::new("tags")
+                .long("tags")
+                .help("Only show tags")
+                .action(ArgAction::SetTrue),
+            Arg::new("dereference")
+                .long("dereference")
+                .short('d')
+                .help("Also show what annotated tags point at")
+                .action(ArgAction::SetTrue),
+            Arg::new("hash")
+                .long("hash")
+                .short('s')
+                .help("Only show object names, optionally abbreviated to <n> digits")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("0")
+                .value_parser(clap::value_parser!(usize))
+                .value_name("n"),
+            Arg::new("abbrev")
+                .long("abbrev")
+                .help("Abbreviate object names to <n> digits")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("7")
+                .value_parser(clap::value_parser!(usize))
+                .value_name("n"),
+            Arg::new("verify")
+                .long("verify")
+                .help("Only accept exact ref names, all of which must exist")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't print anything; only set the exit code")
+                .action(ArgAction::SetTrue),
+        ]);
+    let update_ref_cmd = Command::new("update-ref")
+        .about("Safely update or delete a reference")
+        .args([
+            Arg::new("ref").required(true),
+            Arg::new("new").value_name("newvalue"),
+            Arg::new("old").value_name("oldvalue"),
+            Arg::new("delete")
+                .short('d')
+                .help("Delete the ref; the optional second value is the expected old value")
+                .action(ArgAction::SetTrue),
+            Arg::new("message")
+                .short('m')
+                .help("Reason to record in the reflog")
+                .action(ArgAction::Set)
+                .value_name("reason"),
+            Arg::new("no-deref")
+                .long("no-deref")
+                .help("Update a symbolic ref itself rather than the ref it points at")
+                .action(ArgAction::SetTrue),
+        ]);
+    let symbolic_ref_cmd = Command::new("symbolic-ref")
+        .about("Read, change or delete a symbolic ref such as HEAD")
+        .args([
+            Arg::new("name").required(true),
+            Arg::new("ref").value_name("ref"),
+            Arg::new("delete")
+                .long("delete")
+                .short('d')
+                .help("Delete the symbolic ref")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("ref"),
+            Arg::new("message")
+                .short('m')
+                .help("Reason to record in the reflog")
+                .action(ArgAction::Set)
+                .value_name("reason"),
+            Arg::new("short")
+                .long("short")
+                .help("Shorten the ref name, e.g. refs/heads/main to main")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't complain when the ref is not symbolic")
+                .action(ArgAction::SetTrue),
+        ]);
+    let rev_parse_cmd = Command::new("rev-parse")
+        .about("Turn revision expressions into object names, or describe the repository")
+        .args([
+            Arg::new("revision").num_args(0..).value_name("revision"),
+            Arg::new("paths")
+                .num_args(0..)
+                .last(true)
+                .value_name("path"),
+            Arg::new("verify")
+                .long("verify")
+                .help("Require exactly one revision that names an object")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("With --verify, exit with status 1 instead of complaining")
+                .action(ArgAction::SetTrue),
+            Arg::new("short")
+                .long("short")
+                .help("Like --verify, but abbreviate the object name to at least <n> digits")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("0")
+                .value_parser(clap::value_parser!(usize))
+                .value_name("n"),
+            Arg::new("abbrev-ref")
+                .long("abbrev-ref")
+                .help("Print the short name of the ref each revision refers to")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("loose")
+                .value_parser(["strict", "loose"])
+                .conflicts_with("symbolic-full-name"),
+            Arg::new("symbolic-full-name")
+                .long("symbolic-full-name")
+                .help("Print the full name of the ref each revision refers to")
+                .action(ArgAction::SetTrue),
+            Arg::new("git-dir")
+                .long("git-dir")
+                .help("Print the path of the git directory")
+                .action(ArgAction::SetTrue),
+            Arg::new("absolute-git-dir")
+                .long("absolute-git-dir")
+                .help("Print the absolute path of the git directory")
+                .action(ArgAction::SetTrue),
+            Arg::new("show-toplevel")
+                .long("show-toplevel")
+                .help("Print the absolute path of the top of the work tree")
+                .action(ArgAction::SetTrue),
+            Arg::new("show-prefix")
+                .long("show-prefix")
+                .help("Print the current directory relative to the top of the work tree")
+                .action(ArgAction::SetTrue),
+            Arg::new("show-cdup")
+                .long("show-cdup")
+                .help("Print the path from the current directory up to the top of the work tree")
+                .action(ArgAction::SetTrue),
+            Arg::new("is-inside-work-tree")
+                .long("is-inside-work-tree")
+                .help("Print whether the current directory is inside the work tree")
+                .action(ArgAction::SetTrue),
+            Arg::new("is-bare-repository")
+                .long("is-bare-repository")
+                .help("Print whether the repository is bare")
+                .action(ArgAction::SetTrue),
+        ]);
+    let status_cmd = Command::new("status")
+        .about("Show what changed and what to do next")
+        .args([
+            Arg::new("pathspec").num_args(0..).value_name("pathspec"),
+            Arg::new("porcelain")
+                .long("porcelain")
+                .help("Machine-readable output in the given format")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("v1")
+                .value_parser(["v1", "v2"])
+                .value_name("version"),
+            Arg::new("branch")
+                .long("branch")
+                .short('b')
+                .help("Describe the branch in porcelain output")
+                .action(ArgAction::SetTrue),
+            Arg::new("null")
+                .short('z')
+                .help("Terminate porcelain entries with NUL instead of quoting paths")
+                .action(ArgAction::SetTrue),
+            Arg::new("untracked-files")
+                .long("untracked-files")
+                .short('u')
+                .help("Which untracked files to show")
+                .num_args(0..=1)
+                .default_missing_value("all")
+                .value_parser(EnumValueParser::<UntrackedMode>::new())
+                .value_name("mode"),
+            Arg::new("ignored")
+                .long("ignored")
+                .help("Also show ignored files")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-renames")
+                .long("no-renames")
+                .help("Don't detect staged renames")
+                .action(ArgAction::SetTrue),
+        ]);
+    let commit_cmd = Command::new("commit")
+        .about("Record the staged changes as a new commit")
+        .args([
+            Arg::new("message")
+                .long("message")
+                .short('m')
+                .help("Use the given message; several are joined as paragraphs")
+                .action(ArgAction::Append)
+                .value_name("msg")
+                .conflicts_with("file"),
+            Arg::new("file")
+                .long("file")
+                .short('F')
+                .help("Read the message from the given file, or stdin for -")
+                .action(ArgAction::Set)
+                .value_name("file"),
+            Arg::new("edit")
+                .long("edit")
+                .short('e')
+                .help("Edit the message even if it was given")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-edit")
+                .long("no-edit")
+                .help("Use the given or amended message without opening an editor")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("edit"),
+            Arg::new("amend")
+                .long("amend")
+                .help("Replace the tip of the current branch")
+                .action(ArgAction::SetTrue),
+            Arg::new("allow-empty")
+                .long("allow-empty")
+                .help("Allow a commit with the same tree as its parent")
+                .action(ArgAction::SetTrue),
+            Arg::new("author")
+                .long("author")
+                .help("Override the author")
+                .action(ArgAction::Set)
+                .value_name("author"),
+            Arg::new("date")
+                .long("date")
+                .help("Override the author date")
+                .action(ArgAction::Set)
+                .value_name("date"),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't print the commit summary")
+                .action(ArgAction::SetTrue),
+        ]);
+    let log_cmd = Command::new("log")
+        .about("Show the commit history")
+        .args([
+            Arg::new("revision").num_args(0..).value_name("revision"),
+            Arg::new("paths")
+                .num_args(0..)
+                .last(true)
+                .value_name("path"),
+            Arg::new("oneline")
+                .long("oneline")
+                .help("Show each commit on one line, as its abbreviated name and subject")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("format"),
+            Arg::new("format")
+                .long("format")
+                .visible_alias("pretty")
+                .help("Print commits in the given format: oneline, short, medium, full, fuller or a format string")
+                .action(ArgAction::Set)
+                .value_name("format"),
+            Arg::new("graph")
+                .long("graph")
+                .help("Draw the history graph next to the commits")
+                .action(ArgAction::SetTrue),
+            Arg::new("all")
+                .long("all")
+                .help("Show the history of every ref as well as HEAD")
+                .action(ArgAction::SetTrue),
+            Arg::new("max-count")
+                .long("max-count")
+                .short('n')
+                .help("Show at most <n> commits")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(usize))
+                .value_name("n"),
+            Arg::new("author")
+                .long("author")
+                .help("Only show commits by authors matching the regex; may be repeated")
+                .action(ArgAction::Append)
+                .value_name("pattern"),
+            Arg::new("grep")
+                .long("grep")
+                .help("Only show commits whose message matches the regex; may be repeated")
+                .action(ArgAction::Append)
+                .value_name("pattern"),
+            Arg::new("since")
+                .long("since")
+                .visible_alias("after")
+                .help("Only show commits more recent than the date")
+                .action(ArgAction::Set)
+                .value_name("date"),
+            Arg::new("until")
+                .long("until")
+                .visible_alias("before")
+                .help("Only show commits older than the date")
+                .action(ArgAction::Set)
+                .value_name("date"),
+            Arg::new("first-parent")
+                .long("first-parent")
+                .help("Only follow the first parent of merge commits")
+                .action(ArgAction::SetTrue),
+            Arg::new("decorate")
+                .long("decorate")
+                .help("Show the refs pointing at each commit")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-decorate")
+                .long("no-decorate")
+                .help("Don't show the refs pointing at each commit")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("decorate"),
+        ]);
+    let diff_cmd = Command::new("diff")
+        .about("Show changes between the work tree, the index, commits and blobs")
+        .args([
+            Arg::new("revision").num_args(0..).value_name("revision"),
+            Arg::new("paths")
+                .num_args(0..)
+                .last(true)
+                .value_name("path"),
+            Arg::new("cached")
+                .long("cached")
+                .visible_alias("staged")
+                .help("Compare the index with HEAD or the given commit")
+                .action(ArgAction::SetTrue),
+            Arg::new("unified")
+                .long("unified")
+                .short('U')
+                .help("Show <n> lines of context around each change")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(usize))
+                .value_name("n"),
+            Arg::new("patch")
+                .long("patch")
+                .short('p')
+                .help("Show the patch, also with --stat or --name-status")
+                .action(ArgAction::SetTrue),
+            Arg::new("stat")
+                .long("stat")
+                .help("Show how many lines changed in each file, fitted to <width> columns")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .value_name("width"),
+            Arg::new("summary")
+                .long("summary")
+                .help("List created, deleted and renamed files and mode changes")
+                .action(ArgAction::SetTrue),
+            Arg::new("name-status")
+                .long("name-status")
+                .help("Show only the changed paths and how they changed")
+                .action(ArgAction::SetTrue),
+            Arg::new("histogram")
+                .long("histogram")
+                .help("Use the histogram diff algorithm")
+                .action(ArgAction::SetTrue),
+            Arg::new("minimal")
+                .long("minimal")
+                .help("Spend extra time to find the smallest diff")
+                .action(ArgAction::SetTrue),
+            Arg::new("diff-algorithm")
+                .long("diff-algorithm")
+                .help("Use the given diff algorithm: myers, minimal or histogram")
+                .action(ArgAction::Set)
+                .value_name("algorithm")
+                .conflicts_with_all(["histogram", "minimal"]),
+            Arg::new("find-renames")
+                .long("find-renames")
+                .short('M')
+                .help("Detect renames, of files at least <n> similar")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .value_name("n"),
+            Arg::new("find-copies")
+                .long("find-copies")
+                .short('C')
+                .help("Detect copies as well as renames")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .value_name("n"),
+            Arg::new("find-copies-harder")
+                .long("find-copies-harder")
+                .help("Also consider unmodified files as the source of copies")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-renames")
+                .long("no-renames")
+                .help("Don't detect renames")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["find-renames", "find-copies", "find-copies-harder"]),
+            Arg::new("word-diff")
+                .long("word-diff")
+                .help("Show changed words: plain, color, porcelain or none")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("plain")
+                .value_name("mode"),
+            Arg::new("color-words")
+                .long("color-words")
+                .help("Show changed words in colour")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("word-diff"),
+            Arg::new("color-moved")
+                .long("color-moved")
+                .help("Colour moved lines: no, default, plain, blocks, zebra or dimmed-zebra")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("default")
+                .value_name("mode"),
+            Arg::new("no-color-moved")
+                .long("no-color-moved")
+                .help("Don't colour moved lines differently")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("color-moved"),
+        ]);
+    let branch_cmd = Command::new("branch")
+        .about("List, create, rename and delete branches")
+        .args([
+            Arg::new("args").num_args(0..).value_name("branch"),
+            Arg::new("delete")
+                .long("delete")
+                .short('d')
+                .help("Delete branches that are merged into their upstream or HEAD")
+                .action(ArgAction::SetTrue),
+            Arg::new("force-delete")
+                .short('D')
+                .help("Delete branches even if they are not merged")
+                .action(ArgAction::SetTrue),
+            Arg::new("move")
+                .long("move")
+                .short('m')
+                .help("Rename a branch, the current one if only the new name is given")
+                .action(ArgAction::SetTrue),
+            Arg::new("force-move")
+                .short('M')
+                .help("Rename a branch even if the new name is taken")
+                .action(ArgAction::SetTrue),
+            Arg::new("force")
+                .long("force")
+                .short('f')
+                .help("Reset a branch that already exists to the start point")
+                .action(ArgAction::SetTrue),
+            Arg::new("remotes")
+                .long("remotes")
+                .short('r')
+                .help("List or delete remote-tracking branches")
+                .action(ArgAction::SetTrue),
+            Arg::new("all")
+                .long("all")
+                .short('a')
+                .help("List both local and remote-tracking branches")
+                .action(ArgAction::SetTrue),
+            Arg::new("list")
+                .long("list")
+                .short('l')
+                .help("List branches, optionally only those matching the given patterns")
+                .action(ArgAction::SetTrue),
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("Show each branch's commit and upstream state; twice to name the upstream")
+                .action(ArgAction::Count),
+            Arg::new("track")
+                .long("track")
+                .short('t')
+                .help("Make the new branch track its start point")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-track")
+                .long("no-track")
+                .help("Don't set up an upstream for the new branch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("track"),
+            Arg::new("set-upstream-to")
+                .long("set-upstream-to")
+                .short('u')
+                .help("Make the branch track <upstream>")
+                .action(ArgAction::Set)
+                .value_name("upstream"),
+            Arg::new("unset-upstream")
+                .long("unset-upstream")
+                .help("Forget the branch's upstream")
+                .action(ArgAction::SetTrue),
+            Arg::new("show-current")
+                .long("show-current")
+                .help("Print the name of the current branch")
+                .action(ArgAction::SetTrue),
+            Arg::new("merged")
+                .long("merged")
+                .help("Only list branches merged into <commit> (HEAD by default)")
+                .num_args(0..=1)
+                .default_missing_value("HEAD")
+                .value_name("commit"),
+            Arg::new("no-merged")
+                .long("no-merged")
+                .help("Only list branches not merged into <commit> (HEAD by default)")
+                .num_args(0..=1)
+                .default_missing_value("HEAD")
+                .value_name("commit"),
+        ])
+        .group(
+            clap::ArgGroup::new("mode")
+                .args([
+                    "delete",
+                    "force-delete",
+                    "move",
+                    "force-move",
+                    "set-upstream-to",
+                    "unset-upstream",
+                    "show-current",
+                ])
+                .multiple(false),
+        );
+    let switch_cmd = Command::new("switch")
+        .about("Switch to another branch, updating the working tree")
+        .args([
+            Arg::new("target").value_name("branch"),
+            Arg::new("create")
+                .long("create")
+                .short('c')
+                .help("Create a new branch, starting at <branch> or HEAD, and switch to it")
+                .action(ArgAction::Set)
+                .value_name("new-branch"),
+            Arg::new("force-create")
+                .long("force-create")
+                .short('C')
+                .help("Like --create, but reset the branch if it exists")
+                .action(ArgAction::Set)
+                .value_name("new-branch")
+                .conflicts_with("create"),
+            Arg::new("detach")
+                .long("detach")
+                .short('d')
+                .help("Detach HEAD at the commit instead of switching to a branch")
+                .action(ArgAction::SetTrue),
+            Arg::new("orphan")
+                .long("orphan")
+                .help("Switch to a new branch without history, emptying the working tree")
+                .action(ArgAction::Set)
+                .value_name("new-branch")
+                .conflicts_with_all(["create", "force-create", "detach"]),
+            Arg::new("force")
+                .long("force")
+                .short('f')
+                .visible_alias("discard-changes")
+                .help("Throw away local changes instead of refusing to switch")
+                .action(ArgAction::SetTrue),
+            Arg::new("track")
+                .long("track")
+                .short('t')
+                .help("Make the new branch track its start point")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-track")
+                .long("no-track")
+                .help("Don't set up an upstream for the new branch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("track"),
+            Arg::new("no-guess")
+                .long("no-guess")
+                .help("Don't create a branch from a remote-tracking branch of the same name")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't report what happened")
+                .action(ArgAction::SetTrue),
+        ]);
+    let checkout_cmd = Command::new("checkout")
+        .about("Switch branches, detach HEAD at a commit, or restore files")
+        .args([
+            Arg::new("args")
+                .num_args(0..)
+                .value_name("branch or commit"),
+            Arg::new("paths")
+                .num_args(0..)
+                .last(true)
+                .value_name("path"),
+            Arg::new("branch")
+                .short('b')
+                .help("Create a new branch, starting at <commit> or HEAD, and switch to it")
+                .action(ArgAction::Set)
+                .value_name("new-branch"),
+            Arg::new("force-branch")
+                .short('B')
+                .help("Like -b, but reset the branch if it exists")
+                .action(ArgAction::Set)
+                .value_name("new-branch")
+                .conflicts_with("branch"),
+            Arg::new("detach")
+                .long("detach")
+                .help("Detach HEAD at the commit even if it is a branch")
+                .action(ArgAction::SetTrue),
+            Arg::new("orphan")
+                .long("orphan")
+                .help("Switch to a new branch without history, keeping the files")
+                .action(ArgAction::Set)
+                .value_name("new-branch")
+                .conflicts_with_all(["branch", "force-branch", "detach"]),
+            Arg::new("force")
+                .long("force")
+                .short('f')
+                .help("Throw away local changes instead of refusing to switch")
+                .action(ArgAction::SetTrue),
+            Arg::new("track")
+                .long("track")
+                .short('t')
+                .help("Make the new branch track its start point")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-track")
+                .long("no-track")
+                .help("Don't set up an upstream for the new branch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("track"),
+            Arg::new("no-guess")
+                .long("no-guess")
+                .help("Don't create a branch from a remote-tracking branch of the same name")
+                .action(ArgAction::SetTrue),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't report what happened")
+                .action(ArgAction::SetTrue),
+        ]);
+    let merge_cmd = Command::new("merge")
+        .about("Join another branch's history into the current branch")
+        .args([
+            Arg::new("commit")
+                .value_name("commit")
+                .required_unless_present_any(["abort", "continue"]),
+            Arg::new("message")
+                .long("message")
+                .short('m')
+                .help("Use the given message for the merge commit")
+                .action(ArgAction::Append)
+                .value_name("msg"),
+            Arg::new("ff")
+                .long("ff")
+                .help("Fast-forward when possible (the default)")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-ff")
+                .long("no-ff")
+                .help("Create a merge commit even when the branch could be fast-forwarded")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("ff"),
+            Arg::new("ff-only")
+                .long("ff-only")
+                .help("Refuse to merge unless the branch can be fast-forwarded")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["ff", "no-ff"]),
+            Arg::new("squash")
+                .long("squash")
+                .help("Update the working tree and index with the merged changes, without recording a merge")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("no-ff"),
+            Arg::new("no-commit")
+                .long("no-commit")
+                .help("Stop before creating the merge commit")
+                .action(ArgAction::SetTrue),
+            Arg::new("edit")
+                .long("edit")
+                .short('e')
+                .help("Edit the merge commit's message")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-edit")
+                .long("no-edit")
+                .help("Use the merge commit's message as is (the default)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("edit"),
+            Arg::new("allow-unrelated-histories")
+                .long("allow-unrelated-histories")
+                .help("Merge histories that have no commit in common")
+                .action(ArgAction::SetTrue),
+            Arg::new("abort")
+                .long("abort")
+                .help("Give up on the merge in progress and go back to how things were")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("continue")
+                .long("continue")
+                .help("Commit the merge in progress once its conflicts are resolved")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't report what happened")
+                .action(ArgAction::SetTrue),
+        ]);
+    let rebase_cmd = Command::new("rebase")
+        .about("Replay the current branch's commits on top of another commit")
+        .args([
+            Arg::new("upstream").value_name("upstream"),
+            Arg::new("branch").value_name("branch").requires("upstream"),
+            Arg::new("onto")
+                .long("onto")
+                .help("Replay the commits on top of this commit instead of the upstream")
+                .action(ArgAction::Set)
+                .value_name("newbase"),
+            Arg::new("interactive")
+                .long("interactive")
+                .short('i')
+                .help("Edit the list of commits to replay before starting")
+                .action(ArgAction::SetTrue),
+            Arg::new("autosquash")
+                .long("autosquash")
+                .help("Move fixup! and squash! commits after the commits they name")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-autosquash")
+                .long("no-autosquash")
+                .help("Leave fixup! and squash! commits where they are")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("autosquash"),
+            Arg::new("update-refs")
+                .long("update-refs")
+                .help("Also move the branches that point at replayed commits")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-update-refs")
+                .long("no-update-refs")
+                .help("Only move the current branch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("update-refs"),
+            Arg::new("continue")
+                .long("continue")
+                .help("Go on once the commit the rebase stopped at is dealt with")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("skip")
+                .long("skip")
+                .help("Leave out the commit the rebase stopped at and go on")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("abort")
+                .long("abort")
+                .help("Give up on the rebase and go back to the original branch")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+        ]);
+    let cherry_pick_cmd = Command::new("cherry-pick")
+        .about("Apply the changes of existing commits on top of the current branch")
+        .args([
+            Arg::new("commit")
+                .value_name("commit")
+                .num_args(1..)
+                .required_unless_present_any(["continue", "skip", "abort"]),
+            Arg::new("mainline")
+                .long("mainline")
+                .short('m')
+                .help("Pick a merge's changes relative to this parent, counting from 1")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(usize))
+                .value_name("parent-number"),
+            Arg::new("record-origin")
+                .short('x')
+                .help("Append a line saying which commit was cherry-picked to the message")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-commit")
+                .long("no-commit")
+                .short('n')
+                .help("Apply the changes to the index and working tree without committing them")
+                .action(ArgAction::SetTrue),
+            Arg::new("edit")
+                .long("edit")
+                .short('e')
+                .help("Edit the commit message before committing")
+                .action(ArgAction::SetTrue),
+            Arg::new("continue")
+                .long("continue")
+                .help("Commit the resolved conflicts and go on with the commits left")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("skip")
+                .long("skip")
+                .help("Leave out the commit that stopped and go on with the commits left")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("abort")
+                .long("abort")
+                .help("Give up and go back to where the cherry-pick started")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+        ]);
+    let revert_cmd = Command::new("revert")
+        .about("Record new commits undoing the changes of existing ones")
+        .args([
+            Arg::new("commit")
+                .value_name("commit")
+                .num_args(1..)
+                .required_unless_present_any(["continue", "skip", "abort"]),
+            Arg::new("mainline")
+                .long("mainline")
+                .short('m')
+                .help("Revert a merge's changes relative to this parent, counting from 1")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(usize))
+                .value_name("parent-number"),
+            Arg::new("no-commit")
+                .long("no-commit")
+                .short('n')
+                .help("Undo the changes in the index and working tree without committing")
+                .action(ArgAction::SetTrue),
+            Arg::new("edit")
+                .long("edit")
+                .short('e')
+                .help("Edit the commit message before committing (the default on a terminal)")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-edit")
+                .long("no-edit")
+                .help("Use the generated commit message as is")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("edit"),
+            Arg::new("continue")
+                .long("continue")
+                .help("Commit the resolved conflicts and go on with the commits left")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("skip")
+                .long("skip")
+                .help("Leave out the commit that stopped and go on with the commits left")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+            Arg::new("abort")
+                .long("abort")
+                .help("Give up and go back to where the revert started")
+                .action(ArgAction::SetTrue)
+                .exclusive(true),
+        ]);
+    let stash_quiet_arg = || {
+        Arg::new("quiet")
+            .long("quiet")
+            .short('q')
+            .help("Only report errors")
+            .action(ArgAction::SetTrue)
+    };
+    let stash_push_args = || {
+        [
+            Arg::new("message")
+                .long("message")
+                .short('m')
+                .help("Describe the entry with this message")
+                .action(ArgAction::Set)
+                .value_name("message"),
+            Arg::new("keep-index")
+                .long("keep-index")
+                .short('k')
+                .help("Leave the staged changes in the index and working tree")
+                .action(ArgAction::SetTrue),
+            Arg::new("include-untracked")
+                .long("include-untracked")
+                .short('u')
+                .help("Also stash untracked files and remove them")
+                .action(ArgAction::SetTrue),
+            stash_quiet_arg(),
+        ]
+    };
+    let stash_arg = || Arg::new("stash").value_name("stash");
+    let stash_index_arg = || {
+        Arg::new("index")
+            .long("index")
+            .help("Restore the staged changes to the index as well")
+            .action(ArgAction::SetTrue)
+    };
+    let stash_cmd = Command::new("stash")
+        .about("Put local changes aside and bring them back later")
+        .args(stash_push_args())
+        .args_conflicts_with_subcommands(true)
+        .subcommands([
+            Command::new("push")
+                .about("Save the local changes as a new entry and revert them (the default)")
+                .args(stash_push_args())
+                .arg(Arg::new("pathspec").num_args(0..).value_name("pathspec")),
+            Command::new("apply")
+                .about("Apply an entry's changes to the working tree")
+                .args([stash_index_arg(), stash_quiet_arg(), stash_arg()]),
+            Command::new("pop")
+                .about("Apply an entry's changes and drop it unless there were conflicts")
+                .args([stash_index_arg(), stash_quiet_arg(), stash_arg()]),
+            Command::new("list").about("List the entries, newest first"),
+            Command::new("show")
+                .about("Show the changes an entry records")
+                .args([
+                    Arg::new("patch")
+                        .long("patch")
+                        .short('p')
+                        .help("Show the patch instead of a diffstat")
+                        .action(ArgAction::SetTrue),
+                    stash_arg(),
+                ]),
+            Command::new("drop")
+                .about("Remove an entry")
+                .args([stash_quiet_arg(), stash_arg()]),
+            Command::new("branch")
+                .about("Create a branch where an entry was saved and apply it there")
+                .args([
+                    stash_quiet_arg(),
+                    Arg::new("branch").required(true),
+                    stash_arg(),
+                ]),
+            Command::new("clear").about("Remove every entry"),
+        ]);
+    let reset_quiet_arg = || {
+        Arg::new("quiet")
+            .long("quiet")
+            .short('q')
+            .help("Don't show what is about to change")
+            .action(ArgAction::SetTrue)
+    };
+    let reset_yes_arg = || {
+        Arg::new("yes")
+            .long("yes")
+            .short('y')
+            .help("Don't ask before throwing away changes that are in no commit")
+            .action(ArgAction::SetTrue)
+    };
+    let reset_cmd = Command::new("reset")
+        .about("Move HEAD to a commit, or unstage paths")
+        .args([
+            Arg::new("args").num_args(0..).value_name("commit or path"),
+            Arg::new("paths")
+                .num_args(0..)
+                .last(true)
+                .value_name("path"),
+            Arg::new("soft")
+                .long("soft")
+                .help("Only move HEAD, leaving the index and working tree alone")
+                .action(ArgAction::SetTrue),
+            Arg::new("mixed")
+                .long("mixed")
+                .help("Move HEAD and reset the index, leaving the working tree alone (the default)")
+                .action(ArgAction::SetTrue),
+            Arg::new("hard")
+                .long("hard")
+                .help("Move HEAD and reset the index and working tree, throwing away changes")
+                .action(ArgAction::SetTrue),
+            Arg::new("keep")
+                .long("keep")
+                .help("Move HEAD and update the files it changes, refusing to touch local changes")
+                .action(ArgAction::SetTrue),
+            Arg::new("merge")
+                .long("merge")
+                .help("Move HEAD and reset staged files, keeping unstaged changes")
+                .action(ArgAction::SetTrue),
+            reset_quiet_arg(),
+            reset_yes_arg(),
+        ])
+        .group(
+            clap::ArgGroup::new("mode")
+                .args(["soft", "mixed", "hard", "keep", "merge"])
+                .multiple(false),
+        );
+    let restore_cmd = Command::new("restore")
+        .about("Restore files in the working tree or the index")
+        .args([
+            Arg::new("pathspec").num_args(0..).value_name("pathspec"),
+            Arg::new("source")
+                .long("source")
+                .short('s')
+                .help(
+                    "Take the files from this tree-ish instead of the index, or HEAD with --staged",
+                )
+                .action(ArgAction::Set)
+                .value_name("tree-ish"),
+            Arg::new("staged")
+                .long("staged")
+                .short('S')
+                .help("Restore the index, unstaging changes")
+                .action(ArgAction::SetTrue),
+            Arg::new("worktree")
+                .long("worktree")
+                .short('W')
+                .help("Restore the working tree (the default unless --staged is given)")
+                .action(ArgAction::SetTrue),
+            reset_quiet_arg(),
+            reset_yes_arg(),
+        ]);
+    let config = Command::new("config")
+        .about("Get, set and list configuration variables")
+        .subcommand_required(true)
+        .subcommands([
+            Command::new("get")
+                .about("Print the value of a variable")
+                .arg(Arg::new("name").required(true)),
+            Command::new("get-all")
+                .about("Print every value of a multi-valued variable")
+                .arg(Arg::new("name").required(true)),
+            Command::new("set")
+                .about("Set a variable, keeping the rest of the file as it is")
+                .args([
+                    Arg::new("name").required(true),
+                    Arg::new("value").required(true),
+                    Arg::new("add")
+                        .long("add")
+                        .help("Add another value instead of replacing the existing one")
+                        .action(ArgAction::SetTrue),
+                ]),
+            Command::new("unset").about("Remove a variable").args([
+                Arg::new("name").required(true),
+                Arg::new("all")
+                    .long("all")
+                    .help("Remove every value of a multi-valued variable")
+                    .action(ArgAction::SetTrue),
+            ]),
+            Command::new("list").about("List every variable"),
+        ])
+        .args([
+            Arg::new("local")
+                .long("local")
+                .help("Use the repository's config file")
+                .action(ArgAction::SetTrue)
+                .global(true),
+            Arg::new("global")
+                .long("global")
+                .help("Use the user's config file")
+                .action(ArgAction::SetTrue)
+                .global(true),
+            Arg::new("system")
+                .long("system")
+                .help("Use the system-wide config file")
+                .action(ArgAction::SetTrue)
+                .global(true),
+            Arg::new("file")
+                .long("file")
+                .short('f')
+                .help("Use the given config file")
+                .action(ArgAction::Set)
+                .value_name("file")
+                .global(true),
+            Arg::new("show-origin")
+                .long("show-origin")
+                .help("Show the file each value was read from")
+                .action(ArgAction::SetTrue)
+                .global(true),
+            Arg::new("show-scope")
+                .long("show-scope")
+                .help("Show the scope each value was read from")
+                .action(ArgAction::SetTrue)
+                .global(true),
+            Arg::new("type")
+                .long("type")
+                .help("Interpret values as the given type")
+                .action(ArgAction::Set)
+                .value_parser(EnumValueParser::<ConfigType>::new())
+                .value_name("type")
+                .global(true),
+        ])
+        .group(
+            clap::ArgGroup::new("scope")
+                .args(["local", "global", "system", "file"])
+                .multiple(false),
+        );
 
     let cli = Command::new("pgit")
         .version(clap::crate_version!())
@@ -81,86 +1293,1247 @@ fn main() {
         .about("An alternative Git CLI which is actually understandable.")
         .subcommand(init)
         .subcommand(cat_file)
+        .subcommand(hash_object_cmd)
+        .subcommand(config)
+        .subcommand(ls_files_cmd)
+        .subcommand(add_cmd)
+        .subcommand(rm_cmd)
+        .subcommand(status_cmd)
+        .subcommand(show_ref_cmd)
+        .subcommand(update_ref_cmd)
+        .subcommand(symbolic_ref_cmd)
+        .subcommand(rev_parse_cmd)
+        .subcommand(commit_cmd)
+        .subcommand(log_cmd)
+        .subcommand(diff_cmd)
+        .subcommand(branch_cmd)
+        .subcommand(switch_cmd)
+        .subcommand(checkout_cmd)
+        .subcommand(merge_cmd)
+        .subcommand(rebase_cmd)
+        .subcommand(cherry_pick_cmd)
+        .subcommand(revert_cmd)
+        .subcommand(stash_cmd)
+        .subcommand(reset_cmd)
+        .subcommand(restore_cmd)
         .subcommand(Command::new("dbg").arg(Arg::new("arg").required(true)))
+        .args([
+            Arg::new("chdir")
+                .short('C')
+                .action(ArgAction::Append)
+                .help("Run as if pgit was started in <path>")
+                .value_name("path"),
+            Arg::new("git-dir")
+                .long("git-dir")
+                .action(ArgAction::Set)
+                .help("Use <path> as the repository instead of searching for one")
+                .value_name("path"),
+            Arg::new("work-tree")
+                .long("work-tree")
+                .action(ArgAction::Set)
+                .help("Use <path> as the working tree")
+                .value_name("path"),
+            Arg::new("no-pager")
+                .long("no-pager")
+                .short('P')
+                .action(ArgAction::SetTrue)
+                .help("Don't pipe output into a pager"),
+        ])
         .arg_required_else_help(true);
 
-    match cli.try_get_matches() {
-        Ok(matches) => match matches.subcommand_name() {
-            Some("dbg") => {
-                debug(matches.subcommand().unwrap().1.to_owned());
-            }
-            Some("init") => {
-                let args = matches.subcommand().unwrap().1.to_owned();
-                init_repo(
-                    Some(args.get_one::<bool>("quiet").unwrap().to_owned()),
-                    Some(args.get_one::<bool>("bare").unwrap().to_owned()),
-                    if args.get_one::<String>("gitdir").is_some() {
-                        if !args.get_one::<String>("gitdir").unwrap().starts_with('/')
-                            && !args
-                                .get_one::<String>("gitdir")
-                                .unwrap()
-                                .split_at(1)
-                                .1
-                                .starts_with(':')
-                        {
-                            Some(env::current_dir().unwrap().join(
-                                Path::new(args.get_one::<String>("gitdir").unwrap()).to_path_buf(),
-                            ))
-                        } else {
-                            Some(Path::new(args.get_one::<String>("gitdir").unwrap()).to_path_buf())
-                        }
-                    } else {
-                        None
-                    },
-                    if args.get_one::<String>("branch").is_some() {
-                        Some(args.get_one::<String>("branch").unwrap())
-                    } else {
-                        None
-                    },
-                    if args.get_one::<HashAlgo>("hashalgo").is_some() {
-                        Some(args.get_one::<HashAlgo>("hashalgo").unwrap().to_owned())
-                    } else {
-                        None
-                    },
-                );
-            }
-            Some("cat-file") => {
-                let args = matches.subcommand().unwrap().1.to_owned();
-
-                let return_type = if args.get_one::<bool>("pretty").unwrap().to_owned() {
-                    GetFileContentsReturnType::Contents
-                } else if args.get_one::<bool>("size").unwrap().to_owned() {
-                    GetFileContentsReturnType::Size
-                } else if args.get_one::<bool>("type").unwrap().to_owned() {
-                    GetFileContentsReturnType::Type
-                } else {
-                    GetFileContentsReturnType::NoReturn
-                };
+    let args = attach_diff_scores(&cli, env::args_os());
 
-                let output = get_file_contents(
-                    args.get_one::<String>("hash").unwrap().to_owned(),
-                    return_type,
-                );
+    match cli.clone().try_get_matches_from(&args) {
+        Ok(matches) => {
+            if let Err(err) = run(matches) {
+                let mut skin = MadSkin::default();
 
-                println!("{}", output);
+                skin.bold.set_fg(Red);
+                let _ = skin.write_text_on(&mut io::stderr(), format!("**Error:** {err}").as_str());
+                exit(err.exit_code());
             }
-            _ => unreachable!("All exception cases are handled by clap"),
-        },
+        }
         Err(err) => {
             if err.kind() == clap::error::ErrorKind::InvalidSubcommand {
-                let iter = err.context().next();
+                // Name the command that rejected it, e.g. `pgit config` for `pgit config nope`.
+                let command = subcommands(&cli, &args)
+                    .iter()
+                    .fold("pgit".to_owned(), |path, (_, command)| {
+                        format!("{path} {}", command.get_name())
+                    });
+                let invalid = err.context().find_map(|(kind, value)| {
+                    (kind == ContextKind::InvalidSubcommand).then(|| value.to_string())
+                });
 
-                match iter {
-                    Some((_, cmd)) => {
-                        println!("pgit: '{cmd}' is not a valid command. See 'pgit --help'.")
+                match invalid {
+                    Some(invalid) => eprintln!(
+                        "{command}: '{invalid}' is not a valid command. See '{command} --help'."
+                    ),
+                    None => {
+                        eprintln!("{command}: invalid command provided. See '{command} --help'.")
+                    }
+                }
+            } else if err.use_stderr() {
+                eprintln!("{}", err.render());
+            } else {
+                println!("{}", err.render());
+                return;
+            }
+
+            // Like git, a malformed command line exits with 129; `--help` is not an error.
+            exit(Error::Usage(err.to_string()).exit_code());
+        }
+    }
+}
+
+/// Finds the repository for the current directory, honouring `-C`, `--git-dir` and the `GIT_*`
+/// environment variables.
+fn open_repo() -> Result<Repository> {
+    Repository::discover(env::current_dir()?)
+}
+
+fn run(matches: ArgMatches) -> Result<()> {
+    // Like git, each `-C` is applied relative to the previous one.
+    for dir in matches.get_many::<String>("chdir").unwrap_or_default() {
+        env::set_current_dir(dir)?;
+    }
+
+    if let Some(git_dir) = matches.get_one::<String>("git-dir") {
+        env::set_var("GIT_DIR", git_dir);
+    }
+
+    if let Some(work_tree) = matches.get_one::<String>("work-tree") {
+        env::set_var("GIT_WORK_TREE", work_tree);
+    }
+
+    if matches.get_flag("no-pager") {
+        env::set_var("GIT_PAGER", "cat");
+    }
+
+    match matches.subcommand_name() {
+        Some("dbg") => {
+            let repo = open_repo()?;
+            debug(&repo, matches.subcommand().unwrap().1.to_owned())?;
+        }
+        Some("init") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+            init_repo(
+                &env::current_dir()?,
+                Some(args.get_one::<bool>("quiet").unwrap().to_owned()),
+                Some(args.get_one::<bool>("bare").unwrap().to_owned()),
+                if args.get_one::<String>("gitdir").is_some() {
+                    if !args.get_one::<String>("gitdir").unwrap().starts_with('/')
+                        && !args
+                            .get_one::<String>("gitdir")
+                            .unwrap()
+                            .split_at(1)
+                            .1
+                            .starts_with(':')
+                    {
+                        Some(
+                            env::current_dir()
+                                .unwrap()
+                                .join(Path::new(args.get_one::<String>("gitdir").unwrap())),
+                        )
+                    } else {
+                        Some(Path::new(args.get_one::<String>("gitdir").unwrap()).to_path_buf())
                     }
+                } else {
+                    None
+                },
+                if args.get_one::<String>("branch").is_some() {
+                    Some(args.get_one::<String>("branch").unwrap())
+                } else {
+                    None
+                },
+                if args.get_one::<HashAlgo>("hashalgo").is_some() {
+                    Some(args.get_one::<HashAlgo>("hashalgo").unwrap().to_owned())
+                } else {
+                    None
+                },
+            )?;
+        }
+        Some("cat-file") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let return_type = if args.get_one::<bool>("pretty").unwrap().to_owned() {
+                GetFileContentsReturnType::Contents
+            } else if args.get_one::<bool>("size").unwrap().to_owned() {
+                GetFileContentsReturnType::Size
+            } else if args.get_one::<bool>("type").unwrap().to_owned() {
+                GetFileContentsReturnType::Type
+            } else {
+                GetFileContentsReturnType::NoReturn
+            };
+
+            let repo = open_repo()?;
+            let output = get_file_contents(
+                &repo,
+                args.get_one::<String>("hash").unwrap().to_owned(),
+                return_type,
+            )?;
+
+            println!("{}", output);
+        }
+        Some("hash-object") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let hashes = hash_object(
+                &repo,
+                args.get_one::<ObjectType>("type").unwrap().to_owned(),
+                args.get_one::<bool>("write").unwrap().to_owned(),
+                args.get_one::<bool>("stdin").unwrap().to_owned(),
+                args.get_one::<bool>("stdin-paths").unwrap().to_owned(),
+                args.get_many::<String>("files")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+            )?;
+
+            for hash in hashes {
+                println!("{}", hash);
+            }
+        }
+        Some("ls-files") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let options = LsFilesOptions {
+                cached: args.get_flag("cached"),
+                stage: args.get_flag("stage"),
+                modified: args.get_flag("modified"),
+                others: args.get_flag("others"),
+                deleted: args.get_flag("deleted"),
+                null: args.get_flag("null"),
+            };
+
+            let end = if options.null { b'\0' } else { b'\n' };
+            let mut stdout = io::stdout().lock();
+
+            for mut line in ls_files(&repo, &options)? {
+                line.push(end);
+                stdout.write_all(&line)?;
+            }
+        }
+        Some("add") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let pathspec: Vec<String> = args
+                .get_many::<String>("pathspec")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let options = AddOptions {
+                all: args.get_flag("all"),
+                update: args.get_flag("update"),
+                patch: args.get_flag("patch"),
+                intent_to_add: args.get_flag("intent-to-add"),
+                force: args.get_flag("force"),
+            };
+
+            add(&repo, &pathspec, &options)?;
+        }
+        Some("rm") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let pathspec: Vec<String> = args
+                .get_many::<String>("pathspec")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let options = RmOptions {
+                cached: args.get_flag("cached"),
+                recursive: args.get_flag("recursive"),
+                force: args.get_flag("force"),
+            };
+
+            for path in rm(&repo, &pathspec, &options)? {
+                if !args.get_flag("quiet") {
+                    println!("rm '{}'", path);
+                }
+            }
+        }
+        Some("show-ref") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let patterns: Vec<String> = args
+                .get_many::<String>("pattern")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let options = ShowRefOptions {
+                head: args.get_flag("head"),
+                heads: args.get_flag("heads"),
+                tags: args.get_flag("tags"),
+                dereference: args.get_flag("dereference"),
+                hash_only: args
+                    .get_one::<usize>("hash")
+                    .map(|digits| Some(*digits).filter(|digits| *digits > 0)),
+                abbrev: args.get_one::<usize>("abbrev").copied(),
+                verify: args.get_flag("verify"),
+            };
+
+            let lines = show_ref(&repo, &patterns, &options)?;
+
+            if !args.get_flag("quiet") {
+                for line in &lines {
+                    println!("{}", line);
+                }
+            }
+
+            if lines.is_empty() {
+                exit(1);
+            }
+        }
+        Some("update-ref") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let name = args.get_one::<String>("ref").unwrap();
+            let new = args.get_one::<String>("new");
+            let old = args.get_one::<String>("old");
+            let deref = !args.get_flag("no-deref");
+
+            if args.get_flag("delete") {
+                // With -d the only other value is the expected old one.
+                if old.is_some() {
+                    return Err(Error::Usage(
+                        "update-ref -d takes at most one value, the expected old value".to_owned(),
+                    ));
+                }
+
+                delete_ref_command(&repo, name, new.map(String::as_str), deref)?;
+            } else {
+                let new = new.ok_or_else(|| {
+                    Error::Usage("update-ref needs a new value for the ref".to_owned())
+                })?;
+                let message = args
+                    .get_one::<String>("message")
+                    .map(String::as_str)
+                    .unwrap_or_default();
+
+                update_ref_command(&repo, name, new, old.map(String::as_str), message, deref)?;
+            }
+        }
+        Some("symbolic-ref") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let name = args.get_one::<String>("name").unwrap();
+
+            if args.get_flag("delete") {
+                match delete_symbolic_ref(&repo, name) {
+                    Err(_) if args.get_flag("quiet") => exit(1),
+                    result => result?,
+                }
+            } else if let Some(target) = args.get_one::<String>("ref") {
+                set_symbolic_ref(
+                    &repo,
+                    name,
+                    target,
+                    args.get_one::<String>("message").map(String::as_str),
+                )?;
+            } else {
+                match symbolic_ref_get(&repo, name, args.get_flag("short"))? {
+                    Some(target) => println!("{}", target),
+                    None if args.get_flag("quiet") => exit(1),
                     None => {
-                        println!("pgit: invalid command provided. See 'pgit --help'.")
+                        return Err(Error::InvalidArgument(format!(
+                            "ref {name} is not a symbolic ref"
+                        )))
                     }
                 }
+            }
+        }
+        Some("rev-parse") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+
+            for (flag, query) in [
+                ("git-dir", RepoQuery::GitDir),
+                ("absolute-git-dir", RepoQuery::AbsoluteGitDir),
+                ("show-toplevel", RepoQuery::ShowToplevel),
+                ("show-prefix", RepoQuery::ShowPrefix),
+                ("show-cdup", RepoQuery::ShowCdup),
+                ("is-inside-work-tree", RepoQuery::IsInsideWorkTree),
+                ("is-bare-repository", RepoQuery::IsBareRepository),
+            ] {
+                if args.get_flag(flag) {
+                    if let Some(answer) = repo_query(&repo, query)? {
+                        println!("{}", answer);
+                    }
+                }
+            }
+
+            let revisions: Vec<String> = args
+                .get_many::<String>("revision")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let short = args.get_one::<usize>("short").map(|digits| match digits {
+                0 => default_abbrev(&repo),
+                digits => *digits,
+            });
+            let options = RevParseOptions {
+                verify: args.get_flag("verify") || short.is_some(),
+                short,
+                symbolic: if args.get_flag("symbolic-full-name") {
+                    Some(SymbolicName::Full)
+                } else if args.get_one::<String>("abbrev-ref").is_some() {
+                    Some(SymbolicName::Abbreviated)
+                } else {
+                    None
+                },
+            };
+
+            let lines = match rev_parse(&repo, &revisions, &options) {
+                Err(Error::InvalidArgument(_)) if options.verify && args.get_flag("quiet") => {
+                    exit(1)
+                }
+                result => result?,
+            };
+
+            for line in &lines {
+                println!("{}", line);
+            }
+
+            if let Some(paths) = args.get_many::<String>("paths") {
+                println!("--");
+                for path in paths {
+                    println!("{}", path);
+                }
+            }
+        }
+        Some("status") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let pathspec: Vec<String> = args
+                .get_many::<String>("pathspec")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let options = StatusOptions {
+                untracked: args
+                    .get_one::<UntrackedMode>("untracked-files")
+                    .copied()
+                    .unwrap_or_default(),
+                ignored: args.get_flag("ignored"),
+                no_renames: args.get_flag("no-renames"),
+            };
+
+            let status = status(&repo, &pathspec, &options)?;
+            let null = args.get_flag("null");
+            let porcelain = args
+                .get_one::<String>("porcelain")
+                .map(String::as_str)
+                .or(null.then_some("v1"));
+
+            match porcelain {
+                Some("v2") => print!(
+                    "{}",
+                    status.porcelain_v2(
+                        args.get_flag("branch"),
+                        null,
+                        repo.object_format().hex_len(),
+                        &cwd_prefix(&repo)?
+                    )
+                ),
+                Some(_) => print!("{}", status.porcelain_v1(args.get_flag("branch"), null)),
+                None => status.print(&cwd_prefix(&repo)?),
+            }
+        }
+        Some("commit") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let edit = if args.get_flag("edit") {
+                Some(true)
+            } else if args.get_flag("no-edit") {
+                Some(false)
             } else {
-                println!("{}", err.render())
+                None
+            };
+            let options = CommitOptions {
+                messages: args
+                    .get_many::<String>("message")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                file: args.get_one::<String>("file").map(PathBuf::from),
+                edit,
+                amend: args.get_flag("amend"),
+                allow_empty: args.get_flag("allow-empty"),
+                author: args.get_one::<String>("author").cloned(),
+                date: args.get_one::<String>("date").cloned(),
+                reflog: None,
+            };
+
+            let summary = commit(&repo, &options)?;
+
+            if !args.get_flag("quiet") {
+                println!("{}", summary);
             }
         }
+        Some("log") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let revisions: Vec<String> = args
+                .get_many::<String>("revision")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let paths: Vec<String> = args
+                .get_many::<String>("paths")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let format = if args.get_flag("oneline") {
+                LogFormat::Oneline
+            } else {
+                match args.get_one::<String>("format") {
+                    Some(format) => LogFormat::parse(format)?,
+                    None => LogFormat::default(),
+                }
+            };
+            // Like git's `log.decorate=auto`, decorate only when a person is reading.
+            let decorate = if args.get_flag("decorate") {
+                true
+            } else if args.get_flag("no-decorate") {
+                false
+            } else {
+                match repo.config().get("log.decorate").as_deref() {
+                    Some("false" | "no" | "off" | "0") => false,
+                    Some("auto") | None => io::stdout().is_terminal(),
+                    Some(_) => true,
+                }
+            };
+            let date = |name: &str| {
+                args.get_one::<String>(name)
+                    .map(|date| parse_approxidate(date))
+                    .transpose()
+            };
+            let list = |name: &str| {
+                args.get_many::<String>(name)
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect()
+            };
+            let options = LogOptions {
+                format,
+                graph: args.get_flag("graph"),
+                all: args.get_flag("all"),
+                max_count: args.get_one::<usize>("max-count").copied(),
+                authors: list("author"),
+                greps: list("grep"),
+                since: date("since")?,
+                until: date("until")?,
+                first_parent: args.get_flag("first-parent"),
+                decorate,
+            };
+
+            let mut pager = Pager::start(&repo);
+
+            log(&repo, &revisions, &paths, &options, &mut pager)?;
+            pager.finish()?;
+        }
+        Some("diff") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let revisions: Vec<String> = args
+                .get_many::<String>("revision")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let paths: Vec<String> = args
+                .get_many::<String>("paths")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let config = repo.config();
+
+            let algorithm = if args.get_flag("histogram") {
+                DiffAlgorithm::Histogram
+            } else if args.get_flag("minimal") {
+                DiffAlgorithm::Minimal
+            } else {
+                match args.get_one::<String>("diff-algorithm") {
+                    Some(name) => {
+                        DiffAlgorithm::parse(name).map_err(|err| Error::Usage(err.to_string()))?
+                    }
+                    None => DiffAlgorithm::configured(&repo)?,
+                }
+            };
+            let context = match args.get_one::<usize>("unified") {
+                Some(context) => *context,
+                None => match config.get("diff.context") {
+                    Some(value) => value.parse().map_err(|_| {
+                        Error::InvalidArgument(format!("bad diff.context value `{value}`"))
+                    })?,
+                    None => 3,
+                },
+            };
+            let stat = args.get_one::<String>("stat");
+            let stat_width = match stat.filter(|width| !width.is_empty()) {
+                Some(width) => Some(
+                    width
+                        .parse()
+                        .map_err(|_| Error::Usage(format!("invalid --stat width `{width}`")))?,
+                ),
+                None => None,
+            };
+            let name_status = args.get_flag("name-status");
+
+            // `diff.renames` is on by default; `copies` turns on copy detection as well.
+            let score = |name: &str| match args.get_one::<String>(name) {
+                Some(score) if !score.is_empty() => parse_rename_score(score).map(Some),
+                Some(_) => Ok(Some(RENAME_THRESHOLD)),
+                None => Ok(None),
+            };
+            let find_copies_harder = args.get_flag("find-copies-harder");
+            let (mut renames, mut copies) = match config.get("diff.renames").as_deref() {
+                Some("copies" | "copy") => (Some(RENAME_THRESHOLD), true),
+                Some(value) if !parse_bool(value) => (None, false),
+                _ => (Some(RENAME_THRESHOLD), false),
+            };
+            if let Some(threshold) = score("find-copies")? {
+                (renames, copies) = (Some(threshold), true);
+            } else if find_copies_harder {
+                (renames, copies) = (renames.or(Some(RENAME_THRESHOLD)), true);
+            }
+            if let Some(threshold) = score("find-renames")? {
+                renames = Some(threshold);
+            }
+            if args.get_flag("no-renames") {
+                (renames, copies) = (None, false);
+            }
+
+            let word_diff = if args.get_flag("color-words") {
+                Some(WordDiff::Color)
+            } else {
+                match args.get_one::<String>("word-diff") {
+                    Some(mode) => WordDiff::parse(mode)?,
+                    None => None,
+                }
+            };
+            let color_moved = if args.get_flag("no-color-moved") {
+                ColorMoved::No
+            } else {
+                match args
+                    .get_one::<String>("color-moved")
+                    .cloned()
+                    .or_else(|| config.get("diff.colorMoved"))
+                {
+                    Some(mode) => ColorMoved::parse(&mode)?,
+                    None => ColorMoved::No,
+                }
+            };
+
+            let options = DiffOptions {
+                cached: args.get_flag("cached"),
+                algorithm,
+                context,
+                patch: args.get_flag("patch")
+                    || (stat.is_none() && !name_status && !args.get_flag("summary")),
+                stat: stat.is_some(),
+                stat_width,
+                summary: args.get_flag("summary"),
+                name_status,
+                renames,
+                copies,
+                find_copies_harder,
+                word_diff,
+                color_moved,
+            };
+
+            let output = diff(&repo, &revisions, &paths, &options)?;
+
+            page(&repo, output)?;
+        }
+        Some("branch") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let names: Vec<String> = args
+                .get_many::<String>("args")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let track = if args.get_flag("track") {
+                Some(true)
+            } else if args.get_flag("no-track") {
+                Some(false)
+            } else {
+                None
+            };
+            let list = args.get_flag("list")
+                || args.get_count("verbose") > 0
+                || args.get_flag("all")
+                || args.get_flag("remotes")
+                || args.contains_id("merged")
+                || args.contains_id("no-merged")
+                || names.is_empty();
+
+            if args.get_flag("show-current") {
+                if let Some(branch) = current_branch(&repo)? {
+                    println!("{}", branch);
+                }
+            } else if args.get_flag("delete") || args.get_flag("force-delete") {
+                if names.is_empty() {
+                    return Err(Error::InvalidArgument("branch name required".to_owned()));
+                }
+
+                let force = args.get_flag("force-delete") || args.get_flag("force");
+
+                for name in &names {
+                    println!(
+                        "{}",
+                        delete_branch(&repo, name, force, args.get_flag("remotes"))?
+                    );
+                }
+            } else if args.get_flag("move") || args.get_flag("force-move") {
+                let force = args.get_flag("force-move") || args.get_flag("force");
+
+                match names.as_slice() {
+                    [new] => rename_branch(&repo, None, new, force)?,
+                    [old, new] => rename_branch(&repo, Some(old), new, force)?,
+                    _ => {
+                        return Err(Error::InvalidArgument(
+                            "branch -m takes the new name, optionally after the old one".to_owned(),
+                        ))
+                    }
+                }
+            } else if let Some(upstream) = args.get_one::<String>("set-upstream-to") {
+                let branch = names.first().map(String::as_str);
+                println!("{}", set_upstream(&repo, branch, upstream)?);
+            } else if args.get_flag("unset-upstream") {
+                unset_upstream(&repo, names.first().map(String::as_str))?;
+            } else if list {
+                let options = BranchListOptions {
+                    remotes: args.get_flag("remotes"),
+                    all: args.get_flag("all"),
+                    verbose: args.get_count("verbose"),
+                    merged: args.get_one::<String>("merged").cloned(),
+                    no_merged: args.get_one::<String>("no-merged").cloned(),
+                    patterns: names,
+                };
+
+                let lines = list_branches(&repo, &options)?;
+
+                if !lines.is_empty() {
+                    page(&repo, lines.join("\n") + "\n")?;
+                }
+            } else {
+                if names.len() > 2 {
+                    return Err(Error::InvalidArgument(
+                        "branch takes the new branch's name and at most one start point".to_owned(),
+                    ));
+                }
+
+                let options = CreateBranchOptions {
+                    force: args.get_flag("force"),
+                    track,
+                };
+
+                let note =
+                    create_branch(&repo, &names[0], names.get(1).map(String::as_str), &options)?;
+
+                if let Some(note) = note {
+                    println!("{}", note);
+                }
+            }
+        }
+        Some(command @ ("switch" | "checkout")) => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let (create, force_create) = match command {
+                "switch" => ("create", "force-create"),
+                _ => ("branch", "force-branch"),
+            };
+            let options = SwitchOptions {
+                create: args
+                    .get_one::<String>(create)
+                    .or(args.get_one::<String>(force_create))
+                    .cloned(),
+                force_create: args.contains_id(force_create),
+                orphan: args.get_one::<String>("orphan").cloned(),
+                detach: args.get_flag("detach"),
+                force: args.get_flag("force"),
+                track: if args.get_flag("track") {
+                    Some(true)
+                } else if args.get_flag("no-track") {
+                    Some(false)
+                } else {
+                    None
+                },
+                guess: !args.get_flag("no-guess"),
+            };
+
+            let summary = if command == "switch" {
+                switch(
+                    &repo,
+                    args.get_one::<String>("target").map(String::as_str),
+                    &options,
+                )?
+            } else {
+                let list = |name: &str| -> Vec<String> {
+                    args.get_many::<String>(name)
+                        .unwrap_or_default()
+                        .cloned()
+                        .collect()
+                };
+
+                match checkout(&repo, &list("args"), &list("paths"), &options)? {
+                    Checkout::Switched(summary) => summary,
+                    Checkout::Paths(note) => {
+                        if !args.get_flag("quiet") {
+                            println!("{}", note);
+                        }
+                        return Ok(());
+                    }
+                }
+            };
+
+            if !args.get_flag("quiet") {
+                print!("{}", summary);
+            }
+        }
+        Some("merge") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+
+            if args.get_flag("abort") {
+                return merge_abort(&repo);
+            }
+
+            if args.get_flag("continue") {
+                let summary = merge_continue(&repo)?;
+                println!("{}", summary);
+                return Ok(());
+            }
+
+            let fast_forward = if args.get_flag("no-ff") {
+                FastForward::Never
+            } else if args.get_flag("ff-only") {
+                FastForward::Only
+            } else if args.get_flag("ff") {
+                FastForward::Allow
+            } else {
+                FastForward::configured(&repo)
+            };
+            let options = MergeCommandOptions {
+                messages: args
+                    .get_many::<String>("message")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                fast_forward,
+                squash: args.get_flag("squash"),
+                no_commit: args.get_flag("no-commit"),
+                edit: args.get_flag("edit"),
+                allow_unrelated_histories: args.get_flag("allow-unrelated-histories"),
+            };
+
+            let summary = merge(&repo, args.get_one::<String>("commit").unwrap(), &options)?;
+
+            if !args.get_flag("quiet") {
+                print!("{}", summary);
+            }
+
+            if summary.conflicted {
+                exit(1);
+            }
+        }
+        Some("rebase") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+
+            let summary = if args.get_flag("continue") {
+                rebase_continue(&repo)?
+            } else if args.get_flag("skip") {
+                rebase_skip(&repo)?
+            } else if args.get_flag("abort") {
+                return rebase_abort(&repo);
+            } else {
+                let interactive = args.get_flag("interactive");
+                let config = repo.config();
+                let options = RebaseOptions {
+                    onto: args.get_one::<String>("onto").cloned(),
+                    interactive,
+                    autosquash: args.get_flag("autosquash")
+                        || (!args.get_flag("no-autosquash")
+                            && interactive
+                            && config.get_bool("rebase.autoSquash").unwrap_or(false)),
+                    update_refs: args.get_flag("update-refs")
+                        || (!args.get_flag("no-update-refs")
+                            && config.get_bool("rebase.updateRefs").unwrap_or(false)),
+                };
+
+                rebase(
+                    &repo,
+                    args.get_one::<String>("upstream").map(String::as_str),
+                    args.get_one::<String>("branch").map(String::as_str),
+                    &options,
+                )?
+            };
+
+            print!("{}", summary);
+
+            if summary.failed {
+                exit(1);
+            }
+        }
+        Some(command @ ("cherry-pick" | "revert")) => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let action = if command == "revert" {
+                SequencerAction::Revert
+            } else {
+                SequencerAction::CherryPick
+            };
+
+            let summary = if args.get_flag("continue") {
+                sequencer_continue(&repo, action, io::stdin().is_terminal())?
+            } else if args.get_flag("skip") {
+                sequencer_skip(&repo, action)?
+            } else if args.get_flag("abort") {
+                sequencer_abort(&repo, action)?
+            } else {
+                // Like git, a revert's message is edited by default when run from a terminal.
+                let edit = args.get_flag("edit")
+                    || (action == SequencerAction::Revert
+                        && !args.get_flag("no-edit")
+                        && io::stdin().is_terminal());
+                let options = SequencerOptions {
+                    mainline: args.get_one::<usize>("mainline").copied(),
+                    record_origin: action == SequencerAction::CherryPick
+                        && args.get_flag("record-origin"),
+                    no_commit: args.get_flag("no-commit"),
+                    edit,
+                };
+                let revisions: Vec<String> = args
+                    .get_many::<String>("commit")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+
+                pick_revisions(&repo, action, &revisions, &options)?
+            };
+
+            print!("{}", summary);
+
+            if summary.failed {
+                exit(1);
+            }
+        }
+        Some("stash") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let stash = |args: &ArgMatches| args.get_one::<String>("stash").cloned();
+
+            let summary = match args.subcommand() {
+                Some(("apply", sub_args)) => stash_apply(
+                    &repo,
+                    stash(sub_args).as_deref(),
+                    sub_args.get_flag("index"),
+                )?,
+                Some(("pop", sub_args)) => stash_pop(
+                    &repo,
+                    stash(sub_args).as_deref(),
+                    sub_args.get_flag("index"),
+                )?,
+                Some(("list", _)) => return page(&repo, stash_list(&repo)?),
+                Some(("show", sub_args)) => {
+                    let output = stash_show(
+                        &repo,
+                        stash(sub_args).as_deref(),
+                        sub_args.get_flag("patch"),
+                    )?;
+
+                    return page(&repo, output);
+                }
+                Some(("drop", sub_args)) => stash_drop(&repo, stash(sub_args).as_deref())?,
+                Some(("branch", sub_args)) => stash_branch(
+                    &repo,
+                    sub_args.get_one::<String>("branch").unwrap(),
+                    stash(sub_args).as_deref(),
+                )?,
+                Some(("clear", _)) => return stash_clear(&repo),
+                push => {
+                    let push_args = push.map_or(&args, |(_, sub_args)| sub_args);
+                    let pathspec: Vec<String> = push_args
+                        .try_get_many::<String>("pathspec")
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default()
+                        .cloned()
+                        .collect();
+                    let options = StashOptions {
+                        message: push_args.get_one::<String>("message").cloned(),
+                        keep_index: push_args.get_flag("keep-index"),
+                        include_untracked: push_args.get_flag("include-untracked"),
+                    };
+
+                    stash_push(&repo, &pathspec, &options)?
+                }
+            };
+
+            let quiet = args
+                .subcommand()
+                .map_or(&args, |(_, sub_args)| sub_args)
+                .try_get_one::<bool>("quiet")
+                .ok()
+                .flatten()
+                .copied()
+                .unwrap_or_default();
+
+            if quiet {
+                if summary.failed {
+                    exit(1);
+                }
+                return Ok(());
+            }
+
+            print!("{}", summary);
+
+            if summary.show_status {
+                status(&repo, &[], &StatusOptions::default())?.print(&cwd_prefix(&repo)?);
+            }
+            if let Some(outcome) = &summary.outcome {
+                println!("{}", outcome);
+            }
+            if summary.failed {
+                exit(1);
+            }
+        }
+        Some(command @ ("reset" | "restore")) => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            let repo = open_repo()?;
+            let list = |name: &str| -> Vec<String> {
+                args.get_many::<String>(name)
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect()
+            };
+
+            let plan = if command == "reset" {
+                let mode = if args.get_flag("soft") {
+                    ResetMode::Soft
+                } else if args.get_flag("hard") {
+                    ResetMode::Hard
+                } else if args.get_flag("keep") {
+                    ResetMode::Keep
+                } else if args.get_flag("merge") {
+                    ResetMode::Merge
+                } else {
+                    ResetMode::Mixed
+                };
+
+                plan_reset(&repo, mode, &list("args"), &list("paths"))?
+            } else {
+                let options = RestoreOptions {
+                    source: args.get_one::<String>("source").cloned(),
+                    staged: args.get_flag("staged"),
+                    worktree: args.get_flag("worktree"),
+                };
+
+                plan_restore(&repo, &options, &list("pathspec"))?
+            };
+
+            let quiet = args.get_flag("quiet");
+            if !quiet {
+                plan.print(&repo, &cwd_prefix(&repo)?)?;
+            }
+
+            // Only ask when someone is there to answer; scripts get git's behaviour.
+            if !plan.lost.is_empty() && !args.get_flag("yes") && io::stdin().is_terminal() {
+                print!("\nThrow these changes away? [y/N] ");
+                io::stdout().flush()?;
+
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    println!("Nothing was changed.");
+                    exit(1);
+                }
+            }
+
+            let outcome = carry_out(&repo, &plan)?;
+
+            if let (Some(outcome), false) = (outcome, quiet) {
+                println!("{}", outcome);
+            }
+        }
+        Some("config") => {
+            let args = matches.subcommand().unwrap().1.to_owned();
+
+            // Config works outside a repository too, as long as no local file is involved.
+            let repo = open_repo().ok();
+            let scope = if args.get_flag("local") {
+                ConfigScope::Local
+            } else if args.get_flag("global") {
+                ConfigScope::Global
+            } else if args.get_flag("system") {
+                ConfigScope::System
+            } else if let Some(file) = args.get_one::<String>("file") {
+                ConfigScope::File(PathBuf::from(file))
+            } else {
+                ConfigScope::Merged
+            };
+            let output = ConfigOutput {
+                show_origin: args.get_flag("show-origin"),
+                show_scope: args.get_flag("show-scope"),
+                value_type: args.get_one::<ConfigType>("type").cloned(),
+            };
+
+            match args.subcommand() {
+                Some(("get", sub_args)) => {
+                    let name = sub_args.get_one::<String>("name").unwrap();
+
+                    match config_get(repo.as_ref(), &scope, name, &output)? {
+                        Some(value) => println!("{}", value),
+                        // Like git, a missing key is not an error but still exits non-zero.
+                        None => exit(1),
+                    }
+                }
+                Some(("get-all", sub_args)) => {
+                    let name = sub_args.get_one::<String>("name").unwrap();
+                    let values = config_get_all(repo.as_ref(), &scope, name, &output)?;
+
+                    if values.is_empty() {
+                        exit(1);
+                    }
+
+                    for value in values {
+                        println!("{}", value);
+                    }
+                }
+                Some(("set", sub_args)) => {
+                    config_set(
+                        repo.as_ref(),
+                        &scope,
+                        sub_args.get_one::<String>("name").unwrap(),
+                        sub_args.get_one::<String>("value").unwrap(),
+                        sub_args.get_flag("add"),
+                        &output.value_type,
+                    )?;
+                }
+                Some(("unset", sub_args)) => {
+                    let removed = config_unset(
+                        repo.as_ref(),
+                        &scope,
+                        sub_args.get_one::<String>("name").unwrap(),
+                        sub_args.get_flag("all"),
+                    )?;
+
+                    if removed == 0 {
+                        exit(5);
+                    }
+                }
+                Some(("list", _)) => {
+                    for line in config_list(repo.as_ref(), &scope, &output)? {
+                        println!("{}", line);
+                    }
+                }
+                _ => unreachable!("All exception cases are handled by clap"),
+            }
+        }
+        _ => unreachable!("All exception cases are handled by clap"),
     }
+
+    Ok(())
+}
+
+/// The subcommands named in `args` (a full command line), outermost first, with their positions.
+/// Each command's own options are skipped along with their values, as clap would parse them.
+fn subcommands<'c>(cli: &'c Command, args: &[OsString]) -> Vec<(usize, &'c Command)> {
+    let mut found = Vec::new();
+    let mut command = cli;
+    let mut i = 1;
+
+    while let Some(text) = args.get(i).and_then(|arg| arg.to_str()) {
+        let takes_value =
+            |arg: Option<&Arg>| arg.is_some_and(|arg| arg.get_action().takes_values());
+
+        if text == "--" {
+            break;
+        } else if let Some(long) = text.strip_prefix("--") {
+            let name = long.split('=').next().unwrap_or_default();
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name));
+
+            if !long.contains('=') && takes_value(arg) {
+                i += 1;
+            }
+        } else if let Some(shorts) = text.strip_prefix('-').filter(|shorts| !shorts.is_empty()) {
+            let mut chars = shorts.chars();
+            let short = chars.next();
+            let arg = command.get_arguments().find(|arg| arg.get_short() == short);
+
+            if chars.as_str().is_empty() && takes_value(arg) {
+                i += 1;
+            }
+        } else {
+            match command.find_subcommand(text) {
+                Some(subcommand) => {
+                    found.push((i, subcommand));
+                    command = subcommand;
+                }
+                None => break,
+            }
+        }
+
+        i += 1;
+    }
+
+    found
+}
+
+/// git accepts a similarity glued to `-M` and `-C` (`-M50%`), which clap would read as a cluster
+/// of short flags; spell those out as `--find-renames=50%` and `--find-copies=50%`. Only the
+/// arguments of `diff` are touched, not pgit's own options such as `-C <path>` before it.
+fn attach_diff_scores(cli: &Command, args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.collect();
+    let start = match subcommands(cli, &args).first() {
+        Some((position, command)) if command.get_name() == "diff" => position + 1,
+        _ => return args,
+    };
+
+    for arg in &mut args[start..] {
+        let Some(text) = arg.to_str() else {
+            continue;
+        };
+
+        if text == "--" {
+            break;
+        }
+
+        let long = match text.get(..2) {
+            Some("-M") => "--find-renames",
+            Some("-C") => "--find-copies",
+            _ => continue,
+        };
+        let score = text[2..].trim_start_matches('=');
+
+        if !score.is_empty() {
+            *arg = format!("{long}={score}").into();
+        }
+    }
+
+    args
 }
@@ -0,0 +1,1240 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    config::parse_bool,
+    diff::{
+        diff_lines, find_renames, is_binary, split_lines, DiffAlgorithm, Edit, RenameFile,
+        RENAME_THRESHOLD,
+    },
+    error::{Error, Result},
+    index::{Index, IndexEntry},
+    repository::Repository,
+    revision::{abbreviate, default_abbrev},
+    revwalk::{merge_bases, merge_bases_many},
+    structures::ObjectType,
+    tree::{commit_tree, flatten_tree, TreeFile},
+};
+
+/// The length of git's conflict markers, e.g. `<<<<<<<`.
+pub const MARKER_SIZE: usize = 7;
+
+type Files = BTreeMap<Vec<u8>, TreeFile>;
+
+/// How conflicting hunks are written into a file, as chosen with `merge.conflictStyle`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ConflictStyle {
+    /// Our side and their side of each conflict.
+    #[default]
+    Merge,
+    /// The merge base's version too, between the two sides.
+    Diff3,
+    /// Like `Diff3`, but lines both sides agree on at the edges of a conflict are moved out of
+    /// it.
+    Zdiff3,
+}
+
+impl ConflictStyle {
+    /// Parses a `merge.conflictStyle` value.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "merge" => Ok(Self::Merge),
+            "diff3" => Ok(Self::Diff3),
+            "zdiff3" => Ok(Self::Zdiff3),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown conflict style `{name}`: use merge, diff3 or zdiff3"
+            ))),
+        }
+    }
+}
+
+/// How two histories are merged.
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+    /// The minimum similarity (out of [`crate::diff::MAX_SCORE`]) for a deleted and an added
+    /// file to be taken as a rename, or `None` to not look for renames.
+    pub renames: Option<u32>,
+    pub algorithm: DiffAlgorithm,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            style: ConflictStyle::default(),
+            renames: Some(RENAME_THRESHOLD),
+            // Like git's `ort` strategy, lines are matched with the histogram algorithm.
+            algorithm: DiffAlgorithm::Histogram,
+        }
+    }
+}
+
+impl MergeOptions {
+    /// The options set with `merge.conflictStyle` and `merge.renames`, which defaults to
+    /// `diff.renames`.
+    pub fn configured(repo: &Repository) -> Result<Self> {
+        let config = repo.config();
+        let mut options = Self::default();
+
+        if let Some(style) = config.get("merge.conflictStyle") {
+            options.style = ConflictStyle::parse(&style)?;
+        }
+
+        let renames = config
+            .get("merge.renames")
+            .or_else(|| config.get("diff.renames"));
+
+        // `diff.renames=copies` still means renames are on.
+        if renames.is_some_and(|value| !value.eq_ignore_ascii_case("copies") && !parse_bool(&value))
+        {
+            options.renames = None;
+        }
+
+        Ok(options)
+    }
+}
+
+/// The names written after the conflict markers: `<<<<<<< ours`, `||||||| base` and
+/// `>>>>>>> theirs`.
+#[derive(Debug, Clone)]
+pub struct MergeLabels {
+    pub base: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+/// A file's merged content.
+#[derive(Debug, Clone)]
+pub struct ContentMerge {
+    /// The merged text, with conflict markers around the hunks that could not be merged.
+    pub data: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// A path the merge could not settle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub path: Vec<u8>,
+    /// What the merge base, our side and their side have there: index stages 1, 2 and 3.
+    pub stages: [Option<TreeFile>; 3],
+}
+
+/// The result of merging two trees.
+#[derive(Debug, Clone, Default)]
+pub struct TreeMerge {
+    /// The merged files. Conflicted paths hold what is left in the work tree, such as text with
+    /// conflict markers, so writing them out gives the same conflicted tree as git.
+    pub files: Files,
+    pub conflicts: Vec<Conflict>,
+    /// `Auto-merging` and `CONFLICT` notes, in path order.
+    pub messages: Vec<String>,
+}
+
+/// Merges the commit `theirs` into `ours` the way git's `ort` strategy does. When the histories
+/// have several best merge bases, those are merged first, recursively, into a virtual base;
+/// without any base the empty tree is used. Conflict markers are labelled with `ours_label` and
+/// `theirs_label`.
+pub fn merge_commits(
+    repo: &Repository,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+    options: &MergeOptions,
+) -> Result<TreeMerge> {
+    let bases = merge_bases(repo, ours, theirs)?;
+    let labels = MergeLabels {
+        base: match bases.as_slice() {
+            [] => "empty tree".to_owned(),
+            [base] => abbreviate(repo, base, default_abbrev(repo))?,
+            _ => "merged common ancestors".to_owned(),
+        },
+        ours: ours_label.to_owned(),
+        theirs: theirs_label.to_owned(),
+    };
+
+    merge_files(
+        repo,
+        &base_files(repo, &bases, options, 1)?,
+        &commit_files(repo, ours)?,
+        &commit_files(repo, theirs)?,
+        &labels,
+        options,
+        0,
+    )
+}
+
+/// Merges the changes from the tree `base` to the tree `theirs` into the tree `ours`, with
+/// `None` for an empty base. This is what replaying a single commit elsewhere comes down to.
+pub fn merge_trees(
+    repo: &Repository,
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    labels: &MergeLabels,
+    options: &MergeOptions,
+) -> Result<TreeMerge> {
+    let base = match base {
+        Some(base) => flatten_tree(repo, base)?,
+        None => Files::new(),
+    };
+
+    merge_files(
+        repo,
+        &base,
+        &flatten_tree(repo, ours)?,
+        &flatten_tree(repo, theirs)?,
+        labels,
+        options,
+        0,
+    )
+}
+
+/// Replaces the merged version of each conflicted path in the index with its stages.
+pub fn record_conflicts(repo: &Repository, conflicts: &[Conflict]) -> Result<()> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let mut index = Index::read(repo)?;
+
+    for conflict in conflicts {
+        index.remove(&conflict.path);
+
+        for (stage, file) in conflict.stages.iter().enumerate() {
+            if let Some(file) = file {
+                index.add(IndexEntry::new(
+                    conflict.path.clone(),
+                    file.mode,
+                    file.hash.clone(),
+                    stage as u8 + 1,
+                ));
+            }
+        }
+    }
+
+    index.write(repo)
+}
+
+fn commit_files(repo: &Repository, commit: &str) -> Result<Files> {
+    flatten_tree(repo, &commit_tree(repo, commit)?)
+}
+
+/// The files of the merge base: the single base's, or the result of merging several bases one
+/// after the other, each time against the bases they share with what was merged so far.
+/// Conflicts are kept in the virtual base, markers and all.
+fn base_files(
+    repo: &Repository,
+    bases: &[String],
+    options: &MergeOptions,
+    depth: usize,
+) -> Result<Files> {
+    let Some((first, rest)) = bases.split_first() else {
+        return Ok(Files::new());
+    };
+
+    let mut files = commit_files(repo, first)?;
+    let mut merged = vec![first.clone()];
+
+    for next in rest {
+        let inner_bases = merge_bases_many(repo, &merged, next)?;
+        let labels = MergeLabels {
+            base: "merged common ancestors".to_owned(),
+            ours: "Temporary merge branch 1".to_owned(),
+            theirs: "Temporary merge branch 2".to_owned(),
+        };
+
+        files = merge_files(
+            repo,
+            &base_files(repo, &inner_bases, options, depth + 1)?,
+            &files,
+            &commit_files(repo, next)?,
+            &labels,
+            options,
+            depth,
+        )?
+        .files;
+        merged.push(next.clone());
+    }
+
+    Ok(files)
+}
+
+/// A file as one side of the merge has it, along with where that side keeps it.
+type Side = Option<(Vec<u8>, TreeFile)>;
+
+/// The three versions of what ends up as one path of the result.
+#[derive(Debug, Default)]
+struct MergeItem {
+    base: Side,
+    ours: Side,
+    theirs: Side,
+}
+
+/// How a path of the result was settled.
+#[derive(Debug, Default)]
+struct Outcome {
+    /// What goes into the result tree and the work tree, if anything.
+    file: Option<TreeFile>,
+    /// The index stages, when the path is conflicted.
+    stages: Option<[Option<TreeFile>; 3]>,
+    messages: Vec<String>,
+}
+
+impl Outcome {
+    fn clean(file: Option<&TreeFile>) -> Self {
+        Self {
+            file: file.cloned(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Merges flattened trees. `depth` is 0 for the merge the user asked for and grows for the
+/// merges that build virtual merge bases, whose conflict markers are made longer so that they
+/// stand out from the outer merge's.
+fn merge_files(
+    repo: &Repository,
+    base: &Files,
+    ours: &Files,
+    theirs: &Files,
+    labels: &MergeLabels,
+    options: &MergeOptions,
+    depth: usize,
+) -> Result<TreeMerge> {
+    let ours_renames = renames(repo, base, ours, theirs, options)?;
+    let theirs_renames = renames(repo, base, theirs, ours, options)?;
+    let side = |files: &Files, path: &[u8]| -> Side {
+        files.get(path).map(|file| (path.to_vec(), file.clone()))
+    };
+
+    let mut items = BTreeMap::<Vec<u8>, MergeItem>::new();
+    let mut outcomes = BTreeMap::<Vec<u8>, Outcome>::new();
+    let mut used_ours = HashSet::<Vec<u8>>::new();
+    let mut used_theirs = HashSet::<Vec<u8>>::new();
+
+    // Files of the base are followed to wherever each side renamed them.
+    for (path, file) in base {
+        let ours_at = match ours_renames.get(path) {
+            Some(to) => Some(to.clone()),
+            None => ours.contains_key(path).then(|| path.clone()),
+        };
+        let theirs_at = match theirs_renames.get(path) {
+            Some(to) => Some(to.clone()),
+            None => theirs.contains_key(path).then(|| path.clone()),
+        };
+
+        used_ours.extend(ours_at.clone());
+        used_theirs.extend(theirs_at.clone());
+
+        if let (Some(ours_to), Some(theirs_to)) = (ours_renames.get(path), theirs_renames.get(path))
+        {
+            if ours_to != theirs_to {
+                let message = format!(
+                    "CONFLICT (rename/rename): {} renamed to {} in {} and to {} in {}.",
+                    show(path),
+                    show(ours_to),
+                    labels.ours,
+                    show(theirs_to),
+                    labels.theirs
+                );
+
+                outcomes.insert(
+                    path.clone(),
+                    Outcome {
+                        file: None,
+                        stages: Some([Some(file.clone()), None, None]),
+                        messages: Vec::new(),
+                    },
+                );
+
+                for (to, stage, files) in [(ours_to, 1, ours), (theirs_to, 2, theirs)] {
+                    let mut stages = [None, None, None];
+                    stages[stage] = files.get(to).cloned();
+
+                    outcomes.insert(
+                        to.clone(),
+                        Outcome {
+                            file: stages[stage].clone(),
+                            stages: Some(stages),
+                            messages: Vec::new(),
+                        },
+                    );
+                }
+
+                if let Some(outcome) = outcomes.get_mut(ours_to) {
+                    outcome.messages.push(message);
+                }
+                continue;
+            }
+        }
+
+        let target = if ours_renames.contains_key(path) {
+            ours_at.clone()
+        } else if theirs_renames.contains_key(path) {
+            theirs_at.clone()
+        } else {
+            None
+        };
+
+        items.insert(
+            target.unwrap_or_else(|| path.clone()),
+            MergeItem {
+                base: Some((path.clone(), file.clone())),
+                ours: ours_at.and_then(|at| side(ours, &at)),
+                theirs: theirs_at.and_then(|at| side(theirs, &at)),
+            },
+        );
+    }
+
+    for path in ours.keys().filter(|path| !used_ours.contains(*path)) {
+        let theirs = side(theirs, path).filter(|_| used_theirs.insert(path.clone()));
+
+        items.insert(
+            path.clone(),
+            MergeItem {
+                base: None,
+                ours: side(ours, path),
+                theirs,
+            },
+        );
+    }
+
+    for path in theirs.keys().filter(|path| !used_theirs.contains(*path)) {
+        items.entry(path.clone()).or_default().theirs = side(theirs, path);
+    }
+
+    for (path, item) in items {
+        let outcome = resolve(repo, &path, &item, labels, options, depth)?;
+        outcomes.insert(path, outcome);
+    }
+
+    move_files_out_of_the_way(&mut outcomes, ours, labels);
+
+    let mut merge = TreeMerge::default();
+
+    for (path, outcome) in outcomes {
+        merge.messages.extend(outcome.messages);
+
+        if let Some(stages) = outcome.stages {
+            merge.conflicts.push(Conflict {
+                path: path.clone(),
+                stages,
+            });
+        }
+
+        if let Some(file) = outcome.file {
+            merge.files.insert(path, file);
+        }
+    }
+
+    Ok(merge)
+}
+
+/// The renames from `base` to `side`, by source. Files the other side added at the same path
+/// are not taken as rename targets, so that they meet as an add/add instead.
+fn renames(
+    repo: &Repository,
+    base: &Files,
+    side: &Files,
+    other: &Files,
+    options: &MergeOptions,
+) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+    let Some(threshold) = options.renames else {
+        return Ok(HashMap::new());
+    };
+
+    let rename_file = |(path, file): (&Vec<u8>, &TreeFile)| RenameFile {
+        path: path.clone(),
+        mode: file.mode,
+        hash: file.hash.clone(),
+    };
+    let deleted: Vec<RenameFile> = base
+        .iter()
+        .filter(|(path, _)| !side.contains_key(*path))
+        .map(rename_file)
+        .collect();
+    let added: Vec<RenameFile> = side
+        .iter()
+        .filter(|(path, _)| !base.contains_key(*path) && !other.contains_key(*path))
+        .map(rename_file)
+        .collect();
+
+    if deleted.is_empty() || added.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    Ok(
+        find_renames(&deleted, &[], &added, threshold, false, |hash| {
+            repo.read_blob(hash)
+        })?
+        .into_iter()
+        .map(|rename| (rename.from, rename.to))
+        .collect(),
+    )
+}
+
+/// Settles one path of the result from its three versions.
+fn resolve(
+    repo: &Repository,
+    path: &[u8],
+    item: &MergeItem,
+    labels: &MergeLabels,
+    options: &MergeOptions,
+    depth: usize,
+) -> Result<Outcome> {
+    let file = |side: &Side| side.as_ref().map(|(_, file)| file.clone());
+    let (base, ours, theirs) = (file(&item.base), file(&item.ours), file(&item.theirs));
+    let stages = [base.clone(), ours.clone(), theirs.clone()];
+    let base_path = item.base.as_ref().map(|(path, _)| path.as_slice());
+    let renamed = |side: &Side| {
+        side.as_ref()
+            .is_some_and(|(side_path, _)| Some(side_path.as_slice()) != base_path)
+    };
+
+    // A rename on one side and a deletion on the other conflict even if nothing else changed.
+    let deleted_by = match (&ours, &theirs) {
+        (Some(_), None) if renamed(&item.ours) => Some(&labels.theirs),
+        (None, Some(_)) if renamed(&item.theirs) => Some(&labels.ours),
+        _ => None,
+    };
+
+    if let (Some(deleter), Some(base_path)) = (deleted_by, base_path) {
+        let renamer = if ours.is_some() {
+            &labels.ours
+        } else {
+            &labels.theirs
+        };
+
+        return Ok(Outcome {
+            file: ours.clone().or(theirs.clone()),
+            stages: Some(stages),
+            messages: vec![format!(
+                "CONFLICT (rename/delete): {} renamed to {} in {renamer}, but deleted in {deleter}.",
+                show(base_path),
+                show(path)
+            )],
+        });
+    }
+
+    if ours == theirs || base == theirs {
+        return Ok(Outcome::clean(ours.as_ref()));
+    }
+
+    if base == ours {
+        return Ok(Outcome::clean(theirs.as_ref()));
+    }
+
+    let (ours_file, theirs_file) = match (&ours, &theirs) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        _ => {
+            let (modifier, deleter) = if ours.is_some() {
+                (&labels.ours, &labels.theirs)
+            } else {
+                (&labels.theirs, &labels.ours)
+            };
+
+            return Ok(Outcome {
+                file: ours.clone().or(theirs.clone()),
+                stages: Some(stages),
+                messages: vec![format!(
+                    "CONFLICT (modify/delete): {} deleted in {deleter} and modified in {modifier}.  Version {modifier} of {} left in tree.",
+                    show(path),
+                    show(path)
+                )],
+            });
+        }
+    };
+
+    let kind = if base.is_some() { "content" } else { "add/add" };
+    let conflict = |file: &TreeFile, mut messages: Vec<String>| {
+        messages.push(format!(
+            "CONFLICT ({kind}): Merge conflict in {}",
+            show(path)
+        ));
+
+        Outcome {
+            file: Some(file.clone()),
+            stages: Some(stages.clone()),
+            messages,
+        }
+    };
+
+    let file_type = |mode: u32| mode & 0o170000;
+    let is_regular = |file: &TreeFile| file_type(file.mode) == 0o100000;
+
+    // Only regular files have content that can be merged line by line.
+    if !is_regular(ours_file) || !is_regular(theirs_file) {
+        return Ok(conflict(ours_file, Vec::new()));
+    }
+
+    let base_mode = base.as_ref().map(|base| base.mode);
+    let (mode, mode_conflict) = if Some(ours_file.mode) == base_mode {
+        (theirs_file.mode, false)
+    } else if Some(theirs_file.mode) == base_mode {
+        (ours_file.mode, false)
+    } else {
+        (ours_file.mode, ours_file.mode != theirs_file.mode)
+    };
+
+    let base_hash = base.as_ref().map(|base| base.hash.as_str());
+    let hash = if ours_file.hash == theirs_file.hash || base_hash == Some(&theirs_file.hash) {
+        ours_file.hash.clone()
+    } else if base_hash == Some(&ours_file.hash) {
+        theirs_file.hash.clone()
+    } else {
+        let base_data = match base_hash {
+            Some(hash) if base.as_ref().is_some_and(is_regular) => repo.read_blob(hash)?,
+            _ => Vec::new(),
+        };
+        let ours_data = repo.read_blob(&ours_file.hash)?;
+        let theirs_data = repo.read_blob(&theirs_file.hash)?;
+        let auto_merging = format!("Auto-merging {}", show(path));
+
+        if [&base_data, &ours_data, &theirs_data]
+            .iter()
+            .any(|data| is_binary(data))
+        {
+            let warning = format!(
+                "warning: Cannot merge binary files: {} ({} vs. {})",
+                show(path),
+                labels.ours,
+                labels.theirs
+            );
+
+            return Ok(conflict(ours_file, vec![warning, auto_merging]));
+        }
+
+        let messages = vec![auto_merging];
+
+        let file_labels = file_labels(item, labels);
+        let merged = merge_content(
+            &base_data,
+            &ours_data,
+            &theirs_data,
+            &file_labels,
+            options,
+            MARKER_SIZE + depth * 2,
+        );
+        let hash = repo.write_object(&ObjectType::Blob, &merged.data)?;
+        let file = TreeFile { mode, hash };
+
+        if merged.conflicts > 0 || mode_conflict {
+            return Ok(conflict(&file, messages));
+        }
+
+        return Ok(Outcome {
+            file: Some(file),
+            stages: None,
+            messages,
+        });
+    };
+
+    let file = TreeFile { mode, hash };
+
+    if mode_conflict {
+        return Ok(conflict(&file, Vec::new()));
+    }
+
+    Ok(Outcome::clean(Some(&file)))
+}
+
+/// Conflict marker labels for one file. When the sides keep the file at different paths, each
+/// label says which, as in `HEAD:old-name`.
+fn file_labels(item: &MergeItem, labels: &MergeLabels) -> MergeLabels {
+    let path = |side: &Side| side.as_ref().map(|(path, _)| path.clone());
+    let paths = [path(&item.base), path(&item.ours), path(&item.theirs)];
+
+    if paths
+        .iter()
+        .flatten()
+        .all(|path| Some(path) == paths[1].as_ref())
+    {
+        return labels.clone();
+    }
+
+    let label = |label: &str, path: &Option<Vec<u8>>| match path {
+        Some(path) => format!("{label}:{}", show(path)),
+        None => label.to_owned(),
+    };
+
+    MergeLabels {
+        base: label(&labels.base, &paths[0]),
+        ours: label(&labels.ours, &paths[1]),
+        theirs: label(&labels.theirs, &paths[2]),
+    }
+}
+
+/// A file and a directory cannot share a path, so when one side put a file where the result has
+/// a directory, the file is moved next to it as `path~<side>`.
+fn move_files_out_of_the_way(
+    outcomes: &mut BTreeMap<Vec<u8>, Outcome>,
+    ours: &Files,
+    labels: &MergeLabels,
+) {
+    let in_the_way: Vec<Vec<u8>> = outcomes
+        .iter()
+        .filter(|(_, outcome)| outcome.file.is_some())
+        .map(|(path, _)| path.clone())
+        .filter(|path| {
+            let mut prefix = path.clone();
+            prefix.push(b'/');
+
+            outcomes
+                .range(prefix.clone()..)
+                .take_while(|(other, _)| other.starts_with(&prefix))
+                .any(|(_, outcome)| outcome.file.is_some())
+        })
+        .collect();
+
+    for path in in_the_way {
+        let Some(mut outcome) = outcomes.remove(&path) else {
+            continue;
+        };
+        let file = outcome.file.clone();
+        let (label, stage) = if file.as_ref() == ours.get(&path) {
+            (&labels.ours, 1)
+        } else {
+            (&labels.theirs, 2)
+        };
+
+        let mut moved = path.clone();
+        moved.push(b'~');
+        moved.extend(label.replace('/', "_").bytes());
+
+        let mut stages = [None, None, None];
+        stages[stage] = file;
+
+        outcome.messages.push(format!(
+            "CONFLICT (file/directory): directory in the way of {} from {label}; moving it to {} instead.",
+            show(&path),
+            show(&moved)
+        ));
+        outcome.stages = Some(stages);
+        outcomes.insert(moved, outcome);
+    }
+}
+
+fn show(path: &[u8]) -> String {
+    String::from_utf8_lossy(path).into_owned()
+}
+
+/// A run of lines one side changed: the `base` lines of the merge base became its `side` lines.
+#[derive(Debug, Clone)]
+struct Change {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// The runs of lines that differ between `base` and `side`.
+fn changes(base: &[&[u8]], side: &[&[u8]], algorithm: DiffAlgorithm) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut open = None;
+    let (mut base_line, mut side_line) = (0, 0);
+
+    for edit in diff_lines(base, side, algorithm) {
+        match edit {
+            Edit::Equal(i, j) => {
+                if let Some((base_start, side_start)) = open.take() {
+                    changes.push(Change {
+                        base: base_start..i,
+                        side: side_start..j,
+                    });
+                }
+
+                (base_line, side_line) = (i + 1, j + 1);
+            }
+            _ => {
+                open.get_or_insert((base_line, side_line));
+            }
+        }
+    }
+
+    if let Some((base_start, side_start)) = open {
+        changes.push(Change {
+            base: base_start..base.len(),
+            side: side_start..side.len(),
+        });
+    }
+
+    changes
+}
+
+/// Merges the changes from `base` to `theirs` into `ours`, line by line, like git's xdiff merge.
+/// Changes on one side only are taken as they are, and so are changes both sides made the same
+/// way; overlapping or touching changes conflict. Conflicts are written with markers of
+/// `marker_size` characters in `options.style`. With the `merge` style, the lines both sides
+/// agree on inside a conflict are taken out of it; `zdiff3` only does so at its edges, and
+/// `diff3` never does.
+pub fn merge_content(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &MergeLabels,
+    options: &MergeOptions,
+    marker_size: usize,
+) -> ContentMerge {
+    let base = split_lines(base);
+    let ours = split_lines(ours);
+    let theirs = split_lines(theirs);
+    let ours_changes = changes(&base, &ours, options.algorithm);
+    let theirs_changes = changes(&base, &theirs, options.algorithm);
+
+    let mut merged = ContentMerge {
+        data: Vec::new(),
+        conflicts: 0,
+    };
+    let (mut i, mut k, mut base_line) = (0, 0, 0);
+
+    loop {
+        let (next_ours, next_theirs) = (ours_changes.get(i), theirs_changes.get(k));
+
+        match (next_ours, next_theirs) {
+            (None, None) => break,
+            (Some(change), other)
+                if other.is_none_or(|other| change.base.end < other.base.start) =>
+            {
+                push_lines(&mut merged.data, &base[base_line..change.base.start]);
+                push_lines(&mut merged.data, &ours[change.side.clone()]);
+                base_line = change.base.end;
+                i += 1;
+                continue;
+            }
+            (other, Some(change))
+                if other.is_none_or(|other| change.base.end < other.base.start) =>
+            {
+                push_lines(&mut merged.data, &base[base_line..change.base.start]);
+                push_lines(&mut merged.data, &theirs[change.side.clone()]);
+                base_line = change.base.end;
+                k += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        // The changes overlap: grow the region until no other change touches it.
+        let (ours_start, theirs_start) = (i, k);
+        let start = ours_changes[i].base.start.min(theirs_changes[k].base.start);
+        let mut end = ours_changes[i].base.end.max(theirs_changes[k].base.end);
+        (i, k) = (i + 1, k + 1);
+
+        loop {
+            if let Some(change) = ours_changes.get(i).filter(|c| c.base.start <= end) {
+                end = end.max(change.base.end);
+                i += 1;
+            } else if let Some(change) = theirs_changes.get(k).filter(|c| c.base.start <= end) {
+                end = end.max(change.base.end);
+                k += 1;
+            } else {
+                break;
+            }
+        }
+
+        // Each side's lines for the region: its changes, plus the base lines around them.
+        let side_range = |changes: &[Change]| {
+            let (first, last) = (&changes[0], &changes[changes.len() - 1]);
+            first.side.start - (first.base.start - start)..last.side.end + (end - last.base.end)
+        };
+        let ours_lines = &ours[side_range(&ours_changes[ours_start..i])];
+        let theirs_lines = &theirs[side_range(&theirs_changes[theirs_start..k])];
+
+        push_lines(&mut merged.data, &base[base_line..start]);
+        base_line = end;
+
+        if ours_lines == theirs_lines {
+            push_lines(&mut merged.data, ours_lines);
+            continue;
+        }
+
+        let base_lines = &base[start..end];
+        let mut conflict = |data: &mut Vec<u8>, ours: &[&[u8]], theirs: &[&[u8]]| {
+            let base = (options.style != ConflictStyle::Merge).then_some(base_lines);
+            push_conflict(data, ours, base, theirs, labels, marker_size);
+            merged.conflicts += 1;
+        };
+
+        match options.style {
+            ConflictStyle::Merge => {
+                let mut data = Vec::new();
+                let (mut ours_line, mut theirs_line) = (0, 0);
+
+                for change in changes(ours_lines, theirs_lines, options.algorithm) {
+                    push_lines(&mut data, &ours_lines[ours_line..change.base.start]);
+                    conflict(
+                        &mut data,
+                        &ours_lines[change.base.clone()],
+                        &theirs_lines[change.side.clone()],
+                    );
+                    (ours_line, theirs_line) = (change.base.end, change.side.end);
+                }
+
+                push_lines(&mut data, &ours_lines[ours_line..]);
+                debug_assert_eq!(
+                    ours_lines.len() - ours_line,
+                    theirs_lines.len() - theirs_line
+                );
+                merged.data.extend(data);
+            }
+            ConflictStyle::Diff3 => {
+                let mut data = Vec::new();
+                conflict(&mut data, ours_lines, theirs_lines);
+                merged.data.extend(data);
+            }
+            ConflictStyle::Zdiff3 => {
+                let prefix = ours_lines
+                    .iter()
+                    .zip(theirs_lines)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let suffix = ours_lines[prefix..]
+                    .iter()
+                    .rev()
+                    .zip(theirs_lines[prefix..].iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let mut data = Vec::new();
+
+                push_lines(&mut data, &ours_lines[..prefix]);
+                conflict(
+                    &mut data,
+                    &ours_lines[prefix..ours_lines.len() - suffix],
+                    &theirs_lines[prefix..theirs_lines.len() - suffix],
+                );
+                push_lines(&mut data, &ours_lines[ours_lines.len() - suffix..]);
+                merged.data.extend(data);
+            }
+        }
+    }
+
+    push_lines(&mut merged.data, &base[base_line..]);
+
+    merged
+}
+
+fn push_lines(data: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        data.extend_from_slice(line);
+    }
+}
+
+/// Writes one conflict. A side whose last line has no newline gets one, so that the next marker
+/// starts on a line of its own.
+fn push_conflict(
+    data: &mut Vec<u8>,
+    ours: &[&[u8]],
+    base: Option<&[&[u8]]>,
+    theirs: &[&[u8]],
+    labels: &MergeLabels,
+    marker_size: usize,
+) {
+    let section = |data: &mut Vec<u8>, marker: u8, label: &str, lines: &[&[u8]]| {
+        data.extend(std::iter::repeat_n(marker, marker_size));
+        if !label.is_empty() {
+            data.push(b' ');
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(b'\n');
+
+        push_lines(data, lines);
+        if data.last() != Some(&b'\n') {
+            data.push(b'\n');
+        }
+    };
+
+    section(data, b'<', &labels.ours, ours);
+    if let Some(base) = base {
+        section(data, b'|', &labels.base, base);
+    }
+    section(data, b'=', "", theirs);
+
+    data.extend(std::iter::repeat_n(b'>', marker_size));
+    if !labels.theirs.is_empty() {
+        data.push(b' ');
+        data.extend_from_slice(labels.theirs.as_bytes());
+    }
+    data.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestRepo;
+
+    const LINES: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+    fn labels() -> MergeLabels {
+        MergeLabels {
+            base: "base".to_owned(),
+            ours: "ours".to_owned(),
+            theirs: "theirs".to_owned(),
+        }
+    }
+
+    fn merge_text(base: &str, ours: &str, theirs: &str, style: ConflictStyle) -> (String, usize) {
+        let options = MergeOptions {
+            style,
+            ..MergeOptions::default()
+        };
+        let merged = merge_content(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            &labels(),
+            &options,
+            MARKER_SIZE,
+        );
+
+        (String::from_utf8(merged.data).unwrap(), merged.conflicts)
+    }
+
+    /// Merges `theirs` into `ours`, both made on top of `base`.
+    fn merge(
+        test: &TestRepo,
+        base: &[(&str, &str)],
+        ours: &[(&str, &str)],
+        theirs: &[(&str, &str)],
+    ) -> TreeMerge {
+        let base = test.commit(base, &[], "base");
+        let ours = test.commit(ours, &[&base], "ours");
+        let theirs = test.commit(theirs, &[&base], "theirs");
+
+        merge_commits(
+            &test.repo,
+            &ours,
+            &theirs,
+            "HEAD",
+            "theirs",
+            &MergeOptions::default(),
+        )
+        .unwrap()
+    }
+
+    fn file(test: &TestRepo, contents: &str) -> Option<TreeFile> {
+        Some(TreeFile {
+            mode: crate::index::MODE_BLOB,
+            hash: test.blob(contents),
+        })
+    }
+
+    fn contents(test: &TestRepo, merged: &TreeMerge, path: &str) -> String {
+        let hash = &merged.files[path.as_bytes()].hash;
+        String::from_utf8(test.repo.read_blob(hash).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn separate_changes_merge_cleanly() {
+        let base = LINES;
+        let ours = base.replace("two", "TWO");
+        let theirs = base.replace("nine", "NINE");
+
+        let (merged, conflicts) = merge_text(base, &ours, &theirs, ConflictStyle::Merge);
+        assert_eq!(conflicts, 0);
+        assert_eq!(merged, base.replace("two", "TWO").replace("nine", "NINE"));
+
+        let (merged, conflicts) = merge_text(base, &ours, &ours, ConflictStyle::Merge);
+        assert_eq!((merged, conflicts), (ours, 0));
+    }
+
+    #[test]
+    fn overlapping_changes_conflict() {
+        let base = LINES;
+        let ours = base.replace("five", "FIVE");
+        let theirs = base.replace("five", "5");
+
+        let (merged, conflicts) = merge_text(base, &ours, &theirs, ConflictStyle::Merge);
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            merged,
+            base.replace("five\n", "<<<<<<< ours\nFIVE\n=======\n5\n>>>>>>> theirs\n")
+        );
+
+        let (merged, _) = merge_text(base, &ours, &theirs, ConflictStyle::Diff3);
+        assert_eq!(
+            merged,
+            base.replace(
+                "five\n",
+                "<<<<<<< ours\nFIVE\n||||||| base\nfive\n=======\n5\n>>>>>>> theirs\n"
+            )
+        );
+    }
+
+    #[test]
+    fn zdiff3_moves_common_edges_out_of_the_conflict() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nX\nb2\nY\nc\n";
+        let theirs = "a\nX\nb3\nY\nc\n";
+
+        let (merged, conflicts) = merge_text(base, ours, theirs, ConflictStyle::Zdiff3);
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            merged,
+            "a\nX\n<<<<<<< ours\nb2\n||||||| base\nb\n=======\nb3\n>>>>>>> theirs\nY\nc\n"
+        );
+    }
+
+    #[test]
+    fn trees_merge_cleanly() {
+        let test = TestRepo::new("merge-clean");
+        let merged = merge(
+            &test,
+            &[("a", LINES), ("b", "b\n"), ("gone", "gone\n")],
+            &[("a", &LINES.replace("one", "ONE")), ("b", "b\n")],
+            &[
+                ("a", &LINES.replace("ten", "TEN")),
+                ("b", "b\n"),
+                ("gone", "gone\n"),
+                ("new", "new\n"),
+            ],
+        );
+
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.messages, ["Auto-merging a"]);
+        assert_eq!(
+            merged.files.keys().collect::<Vec<_>>(),
+            [b"a".as_slice(), b"b", b"new"]
+        );
+        assert_eq!(
+            contents(&test, &merged, "a"),
+            LINES.replace("one", "ONE").replace("ten", "TEN")
+        );
+    }
+
+    #[test]
+    fn content_conflicts_keep_all_three_stages() {
+        let test = TestRepo::new("merge-content");
+        let merged = merge(
+            &test,
+            &[("a", "base\n")],
+            &[("a", "ours\n")],
+            &[("a", "theirs\n")],
+        );
+
+        assert_eq!(
+            merged.messages,
+            ["Auto-merging a", "CONFLICT (content): Merge conflict in a"]
+        );
+        assert_eq!(
+            merged.conflicts,
+            [Conflict {
+                path: b"a".to_vec(),
+                stages: [
+                    file(&test, "base\n"),
+                    file(&test, "ours\n"),
+                    file(&test, "theirs\n")
+                ],
+            }]
+        );
+        assert_eq!(
+            contents(&test, &merged, "a"),
+            "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> theirs\n"
+        );
+    }
+
+    #[test]
+    fn files_added_on_both_sides_conflict() {
+        let test = TestRepo::new("merge-add-add");
+        let merged = merge(
+            &test,
+            &[("base", "base\n")],
+            &[("base", "base\n"), ("new", "ours\n")],
+            &[("base", "base\n"), ("new", "theirs\n")],
+        );
+
+        assert_eq!(
+            merged.messages,
+            [
+                "Auto-merging new",
+                "CONFLICT (add/add): Merge conflict in new"
+            ]
+        );
+        assert_eq!(
+            merged.conflicts[0].stages,
+            [None, file(&test, "ours\n"), file(&test, "theirs\n")]
+        );
+
+        let merged = merge(
+            &test,
+            &[("base", "base\n")],
+            &[("base", "base\n"), ("new", "same\n")],
+            &[("base", "base\n"), ("new", "same\n")],
+        );
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn modified_and_deleted_files_conflict() {
+        let test = TestRepo::new("merge-modify-delete");
+        let merged = merge(
+            &test,
+            &[("a", "base\n"), ("b", "b\n")],
+            &[("b", "b\n")],
+            &[("a", "changed\n"), ("b", "b\n")],
+        );
+
+        assert_eq!(
+            merged.messages,
+            ["CONFLICT (modify/delete): a deleted in HEAD and modified in theirs.  Version theirs of a left in tree."]
+        );
+        assert_eq!(
+            merged.conflicts[0].stages,
+            [file(&test, "base\n"), None, file(&test, "changed\n")]
+        );
+        assert_eq!(contents(&test, &merged, "a"), "changed\n");
+    }
+
+    #[test]
+    fn renames_follow_changes() {
+        let test = TestRepo::new("merge-rename");
+        let merged = merge(
+            &test,
+            &[("old", LINES)],
+            &[("new", LINES)],
+            &[("old", &LINES.replace("ten", "TEN"))],
+        );
+
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.files.keys().collect::<Vec<_>>(), [b"new"]);
+        assert_eq!(contents(&test, &merged, "new"), LINES.replace("ten", "TEN"));
+    }
+
+    #[test]
+    fn diverging_renames_conflict() {
+        let test = TestRepo::new("merge-rename-rename");
+        let merged = merge(
+            &test,
+            &[("old", LINES)],
+            &[("ours", LINES)],
+            &[("theirs", LINES)],
+        );
+
+        assert_eq!(
+            merged.messages,
+            ["CONFLICT (rename/rename): old renamed to ours in HEAD and to theirs in theirs."]
+        );
+        assert_eq!(
+            merged
+                .conflicts
+                .iter()
+                .map(|conflict| conflict.path.as_slice())
+                .collect::<Vec<_>>(),
+            [b"old".as_slice(), b"ours", b"theirs"]
+        );
+    }
+
+    #[test]
+    fn renamed_and_deleted_files_conflict() {
+        let test = TestRepo::new("merge-rename-delete");
+        let merged = merge(&test, &[("old", LINES)], &[("new", LINES)], &[]);
+
+        assert_eq!(
+            merged.messages,
+            ["CONFLICT (rename/delete): old renamed to new in HEAD, but deleted in theirs."]
+        );
+        assert_eq!(
+            merged.conflicts[0].stages,
+            [file(&test, LINES), file(&test, LINES), None]
+        );
+    }
+}
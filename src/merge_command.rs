@@ -0,0 +1,429 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+};
+
+use crate::{
+    branch::current_branch,
+    checkout::{require_clean_index, reset_merge, switch_trees, TreeUpdate},
+    commit::{commit, remove_merge_state, CommitOptions, CommitSummary},
+    config::parse_bool,
+    diff::RENAME_THRESHOLD,
+    diff_command::{diff, DiffOptions},
+    error::{Error, Result},
+    index::Index,
+    log::{format_date, DEFAULT_DATE},
+    merge::{merge_commits, record_conflicts, MergeOptions},
+    refs::{dwim_ref, read_ref, shorten_ref, update_ref},
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, resolve_commit},
+    revwalk::{ancestors, merge_bases},
+    tree::{commit_tree, write_files},
+};
+
+/// When `pgit merge` may simply move the branch forward to the merged commit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FastForward {
+    /// Fast-forward when possible, otherwise create a merge commit.
+    #[default]
+    Allow,
+    /// Always create a merge commit (`--no-ff`).
+    Never,
+    /// Refuse to merge unless it is a fast-forward (`--ff-only`).
+    Only,
+}
+
+impl FastForward {
+    /// The behaviour set with `merge.ff`.
+    pub fn configured(repo: &Repository) -> Self {
+        match repo.config().get("merge.ff") {
+            Some(value) if value.eq_ignore_ascii_case("only") => Self::Only,
+            Some(value) if !parse_bool(&value) => Self::Never,
+            _ => Self::Allow,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeCommandOptions {
+    /// Each `-m` paragraph of the merge commit's message, instead of `Merge branch '...'`.
+    pub messages: Vec<String>,
+    pub fast_forward: FastForward,
+    /// Update the index and work tree with the merged changes, but neither commit them nor
+    /// record a merge: the next commit is an ordinary one.
+    pub squash: bool,
+    /// Stop before creating the merge commit, as if the merge had conflicts.
+    pub no_commit: bool,
+    /// Open the editor on the merge commit's message.
+    pub edit: bool,
+    /// Merge histories that have no commit in common, starting from the empty tree.
+    pub allow_unrelated_histories: bool,
+}
+
+/// What `pgit merge` did, for the user, and whether conflicts are left to resolve.
+#[derive(Debug, Clone, Default)]
+pub struct MergeSummary {
+    pub messages: Vec<String>,
+    pub conflicted: bool,
+}
+
+impl Display for MergeSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for message in &self.messages {
+            writeln!(f, "{message}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `pgit merge`: joins the history of `target` into the current branch. The branch is
+/// fast-forwarded when it has nothing `target` lacks; otherwise the two are merged and the
+/// result is committed with both as parents. When the merge has conflicts, or with `no_commit`,
+/// the merge is left in progress: the conflicted paths have their base, our and their versions
+/// at index stages 1 to 3, and `MERGE_HEAD` and `MERGE_MSG` remember what to commit once the
+/// user has resolved them.
+pub fn merge(
+    repo: &Repository,
+    target: &str,
+    options: &MergeCommandOptions,
+) -> Result<MergeSummary> {
+    let git_dir = repo.git_dir();
+
+    if git_dir.join("MERGE_HEAD").exists() {
+        return Err(Error::InvalidArgument(
+            "You have not concluded your merge (MERGE_HEAD exists). Commit your changes with `pgit commit`, or give up with `pgit merge --abort`".to_owned(),
+        ));
+    }
+
+    if Index::read(repo)?.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "Merging is not possible because you have unmerged files. Fix them up in the work tree, then use `pgit add` to mark them as resolved".to_owned(),
+        ));
+    }
+
+    let theirs = resolve_commit(repo, target).map_err(|err| match err {
+        Error::UnknownRevision(_) => {
+            Error::Failed(format!("`{target}` is not something we can merge"))
+        }
+        err => err,
+    })?;
+    let message = if options.messages.is_empty() {
+        merge_message(repo, target)?
+    } else {
+        options.messages.join("\n\n") + "\n"
+    };
+    let reflog = |what: &str| format!("merge {target}: {what}");
+    let mut summary = MergeSummary::default();
+
+    let Some(head) = repo.head()? else {
+        // Merging into an unborn branch just gives it the merged history.
+        if options.squash || options.fast_forward == FastForward::Never {
+            return Err(Error::InvalidArgument(
+                "The current branch has no commits yet, so there is nothing to merge into"
+                    .to_owned(),
+            ));
+        }
+
+        switch_trees(
+            repo,
+            None,
+            Some(&commit_tree(repo, &theirs)?),
+            false,
+            TreeUpdate::Merge,
+        )?;
+        update_ref(repo, "HEAD", &theirs, None, &reflog("Fast-forward"), true)?;
+
+        return Ok(summary);
+    };
+
+    let bases = merge_bases(repo, &head, &theirs)?;
+    update_ref(repo, "ORIG_HEAD", &head, None, "", false)?;
+
+    if bases.contains(&theirs) {
+        summary.messages.push("Already up to date.".to_owned());
+        return Ok(summary);
+    }
+
+    if bases.is_empty() && !options.allow_unrelated_histories {
+        return Err(Error::InvalidArgument(
+            "refusing to merge unrelated histories (use --allow-unrelated-histories to merge them anyway)".to_owned(),
+        ));
+    }
+
+    let head_tree = commit_tree(repo, &head)?;
+    let abbrev = default_abbrev(repo);
+
+    if bases == [head.clone()] && options.fast_forward != FastForward::Never {
+        switch_trees(
+            repo,
+            Some(&head_tree),
+            Some(&commit_tree(repo, &theirs)?),
+            false,
+            TreeUpdate::Merge,
+        )?;
+
+        summary.messages.push(format!(
+            "Updating {}..{}",
+            abbreviate(repo, &head, abbrev)?,
+            abbreviate(repo, &theirs, abbrev)?
+        ));
+        summary.messages.push("Fast-forward".to_owned());
+
+        if options.squash {
+            fs::write(
+                git_dir.join("SQUASH_MSG"),
+                squash_message(repo, &head, &theirs)?,
+            )?;
+            summary
+                .messages
+                .push("Squash commit -- not updating HEAD".to_owned());
+        } else {
+            update_ref(
+                repo,
+                "HEAD",
+                &theirs,
+                Some(&head),
+                &reflog("Fast-forward"),
+                true,
+            )?;
+        }
+
+        summary.messages.extend(diffstat(repo, &head, &theirs)?);
+        return Ok(summary);
+    }
+
+    if options.fast_forward == FastForward::Only {
+        return Err(Error::InvalidArgument(
+            "Not possible to fast-forward, aborting.".to_owned(),
+        ));
+    }
+
+    require_clean_index(repo, Some(&head_tree), TreeUpdate::Merge)?;
+
+    let merged = merge_commits(
+        repo,
+        &head,
+        &theirs,
+        "HEAD",
+        target,
+        &MergeOptions::configured(repo)?,
+    )?;
+    let tree = write_files(repo, &merged.files)?;
+
+    switch_trees(
+        repo,
+        Some(&head_tree),
+        Some(&tree),
+        false,
+        TreeUpdate::Merge,
+    )?;
+    record_conflicts(repo, &merged.conflicts)?;
+    // Like git, remember the result with its conflict markers, for `pgit diff AUTO_MERGE`.
+    update_ref(repo, "AUTO_MERGE", &tree, None, "", false)?;
+
+    summary.messages.extend(merged.messages);
+    summary.conflicted = !merged.conflicts.is_empty();
+
+    let stopped = if summary.conflicted {
+        "Automatic merge failed; fix conflicts and then commit the result."
+    } else {
+        "Automatic merge went well; stopped before committing as requested"
+    };
+
+    if options.squash {
+        fs::write(
+            git_dir.join("SQUASH_MSG"),
+            squash_message(repo, &head, &theirs)?,
+        )?;
+        summary.messages.push(stopped.to_owned());
+        summary
+            .messages
+            .push("Squash commit -- not updating HEAD".to_owned());
+
+        return Ok(summary);
+    }
+
+    let mut merge_message = message.clone();
+    if summary.conflicted {
+        merge_message += "\n# Conflicts:\n";
+        for conflict in &merged.conflicts {
+            merge_message += &format!("#\t{}\n", String::from_utf8_lossy(&conflict.path));
+        }
+    }
+
+    fs::write(git_dir.join("MERGE_HEAD"), format!("{theirs}\n"))?;
+    fs::write(git_dir.join("MERGE_MSG"), merge_message)?;
+    fs::write(
+        git_dir.join("MERGE_MODE"),
+        if options.fast_forward == FastForward::Never {
+            "no-ff"
+        } else {
+            ""
+        },
+    )?;
+
+    if summary.conflicted || options.no_commit {
+        summary.messages.push(stopped.to_owned());
+        return Ok(summary);
+    }
+
+    let committed = commit(
+        repo,
+        &CommitOptions {
+            messages: vec![message],
+            edit: Some(options.edit),
+            reflog: Some(reflog("Merge made by the 'ort' strategy.")),
+            ..Default::default()
+        },
+    )?;
+
+    summary
+        .messages
+        .push("Merge made by the 'ort' strategy.".to_owned());
+    summary
+        .messages
+        .extend(diffstat(repo, &head, &committed.hash)?);
+
+    Ok(summary)
+}
+
+/// `pgit merge --continue`: commits the merge in progress once its conflicts are resolved.
+pub fn merge_continue(repo: &Repository) -> Result<CommitSummary> {
+    if !repo.git_dir().join("MERGE_HEAD").exists() {
+        return Err(Error::InvalidArgument(
+            "There is no merge in progress (MERGE_HEAD missing)".to_owned(),
+        ));
+    }
+
+    commit(repo, &CommitOptions::default())
+}
+
+/// `pgit merge --abort`: puts the index and work tree back as they were before the merge in
+/// progress. Changes that were not staged when the merge started are kept.
+pub fn merge_abort(repo: &Repository) -> Result<()> {
+    if !repo.git_dir().join("MERGE_HEAD").exists() {
+        return Err(Error::InvalidArgument(
+            "There is no merge to abort (MERGE_HEAD missing)".to_owned(),
+        ));
+    }
+
+    let head_tree = repo
+        .head()?
+        .map(|head| commit_tree(repo, &head))
+        .transpose()?;
+
+    reset_merge(repo, head_tree.as_deref())?;
+    remove_merge_state(repo)
+}
+
+/// The default message of a merge commit, worded like git's: `Merge branch 'topic'`, with
+/// ` into <branch>` unless the current branch is `main` or `master`.
+fn merge_message(repo: &Repository, target: &str) -> Result<String> {
+    let branch = match branch_ancestor(target) {
+        Some((name, early)) if read_ref(repo, &format!("refs/heads/{name}"))?.is_some() => {
+            Some((name, early))
+        }
+        _ => None,
+    };
+    let what = match (branch, dwim_ref(repo, target)?) {
+        (Some((name, true)), _) => format!("branch '{name}' (early part)"),
+        (Some((name, false)), _) => format!("branch '{name}'"),
+        (None, Some((full, _))) if full.starts_with("refs/heads/") => {
+            format!("branch '{}'", shorten_ref(&full))
+        }
+        (None, Some((full, _))) if full.starts_with("refs/remotes/") => {
+            format!("remote-tracking branch '{}'", shorten_ref(&full))
+        }
+        (None, Some((full, _))) if full.starts_with("refs/tags/") => {
+            format!("tag '{}'", shorten_ref(&full))
+        }
+        _ => format!("commit '{target}'"),
+    };
+    let into = match current_branch(repo)? {
+        Some(branch) if branch == "main" || branch == "master" => String::new(),
+        Some(branch) => format!(" into {branch}"),
+        None => " into HEAD".to_owned(),
+    };
+
+    Ok(format!("Merge {what}{into}\n"))
+}
+
+/// Splits `name^^` or `name~<n>` into `name` and whether it names an ancestor of `name` rather
+/// than `name` itself, the way git words the message when merging part of a branch.
+fn branch_ancestor(target: &str) -> Option<(&str, bool)> {
+    let carets = target.trim_end_matches('^');
+
+    if carets.len() < target.len() {
+        return Some((carets, true));
+    }
+
+    let (name, count) = target.rsplit_once('~')?;
+
+    if !count.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((name, count.is_empty() || count.bytes().any(|b| b != b'0')))
+}
+
+/// The `SQUASH_MSG` git prepares: every commit being squashed, newest first, in `git log`'s
+/// default format.
+fn squash_message(repo: &Repository, head: &str, theirs: &str) -> Result<String> {
+    let merged = ancestors(repo, head)?;
+    let mut commits = Vec::new();
+
+    for hash in ancestors(repo, theirs)?.difference(&merged) {
+        let meta = repo.read_object(hash)?.meta.unwrap_or_default();
+        let time = meta
+            .committer
+            .as_ref()
+            .map_or(0, |committer| committer.time);
+        commits.push((time, hash.clone(), meta));
+    }
+
+    commits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let mut message = "Squashed commit of the following:\n".to_owned();
+
+    for (_, hash, meta) in commits {
+        message += &format!("\ncommit {hash}\n");
+
+        if let Some(author) = &meta.author {
+            message += &format!(
+                "Author: {} <{}>\nDate:   {}\n",
+                author.name,
+                author.email.as_deref().unwrap_or_default(),
+                format_date(author, DEFAULT_DATE)
+            );
+        }
+
+        message.push('\n');
+        for line in meta.message.unwrap_or_default().trim_end().lines() {
+            message += format!("    {line}").trim_end();
+            message.push('\n');
+        }
+    }
+
+    Ok(message)
+}
+
+/// The `--stat` of what the merge brought in.
+fn diffstat(repo: &Repository, old: &str, new: &str) -> Result<Vec<String>> {
+    let stat = diff(
+        repo,
+        &[old.to_owned(), new.to_owned()],
+        &[],
+        &DiffOptions {
+            stat: true,
+            summary: true,
+            renames: Some(RENAME_THRESHOLD),
+            ..Default::default()
+        },
+    )?;
+
+    Ok(String::from_utf8_lossy(&stat)
+        .lines()
+        .map(str::to_owned)
+        .collect())
+}
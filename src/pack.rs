@@ -0,0 +1,707 @@
+use flate2::read::ZlibDecoder;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    error::{Error, Result},
+    repository::Repository,
+    structures::ObjectType,
+};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const IDX_HEADER_LEN: usize = 8;
+const FANOUT_LEN: usize = 256 * 4;
+
+/// How many bytes of delta bases each pack keeps around, git's default `core.deltaBaseCacheLimit`.
+const DELTA_BASE_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+
+/// How many deltas deep an object may be, git's default `pack.depth`. Reading a deeper chain
+/// gives up rather than recursing on through a broken or cyclic pack.
+const MAX_DELTA_DEPTH: usize = 50;
+
+/// The most zlib can inflate data by, for telling impossible entry sizes apart.
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// A resolved object kept to rebuild the deltas against it.
+type DeltaBase = (ObjectType, Rc<Vec<u8>>);
+
+/// A version 2 `.idx` file together with the `.pack` it describes. Names in the index are as
+/// long as the repository's object format says.
+pub struct PackIndex {
+    pack_path: PathBuf,
+    data: Vec<u8>,
+    count: usize,
+    hash_len: usize,
+    /// Objects already resolved as the base of an `OFS_DELTA`, by offset, so that reading a run of
+    /// objects from the same delta chain does not inflate the whole chain again for each.
+    bases: RefCell<HashMap<u64, DeltaBase>>,
+    bases_size: Cell<usize>,
+}
+
+impl fmt::Debug for PackIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PackIndex")
+            .field("pack_path", &self.pack_path)
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+impl PackIndex {
+    fn open(idx_path: &Path, hash_len: usize) -> Option<Self> {
+        let data = fs::read(idx_path).ok()?;
+
+        if data.len() < IDX_HEADER_LEN + FANOUT_LEN
+            || data[..4] != IDX_MAGIC
+            || read_u32(&data, 4)? != 2
+        {
+            return None;
+        }
+
+        let count = read_u32(&data, IDX_HEADER_LEN + 255 * 4)? as usize;
+
+        Some(Self {
+            pack_path: idx_path.with_extension("pack"),
+            data,
+            count,
+            hash_len,
+            bases: RefCell::default(),
+            bases_size: Cell::new(0),
+        })
+    }
+
+    fn cached_base(&self, offset: u64) -> Option<DeltaBase> {
+        self.bases.borrow().get(&offset).cloned()
+    }
+
+    fn cache_base(&self, offset: u64, obj_type: ObjectType, body: Rc<Vec<u8>>) {
+        let mut bases = self.bases.borrow_mut();
+
+        // Like git's `core.deltaBaseCacheLimit`, start over rather than grow without bound.
+        if self.bases_size.get() + body.len() > DELTA_BASE_CACHE_LIMIT {
+            bases.clear();
+            self.bases_size.set(0);
+        }
+
+        self.bases_size.set(self.bases_size.get() + body.len());
+        bases.insert(offset, (obj_type, body));
+    }
+
+    fn fanout(&self, byte: usize) -> Option<usize> {
+        Some(read_u32(&self.data, IDX_HEADER_LEN + byte * 4)? as usize)
+    }
+
+    fn name(&self, pos: usize) -> Option<&[u8]> {
+        let start = IDX_HEADER_LEN + FANOUT_LEN + pos * self.hash_len;
+        self.data.get(start..start + self.hash_len)
+    }
+
+    /// Binary searches the sorted name table for `hash`, returning its offset in the pack.
+    fn find_offset(&self, hash: &[u8]) -> Option<u64> {
+        let first = *hash.first()? as usize;
+        let mut lo = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)?
+        };
+        let mut hi = self.fanout(first)?;
+
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.name(mid)?.cmp(hash) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return self.offset(mid),
+            }
+        }
+
+        None
+    }
+
+    fn offset(&self, pos: usize) -> Option<u64> {
+        let offsets_start = IDX_HEADER_LEN + FANOUT_LEN + self.count * (self.hash_len + 4);
+        let offset = read_u32(&self.data, offsets_start + pos * 4)?;
+
+        if offset & 0x8000_0000 == 0 {
+            return Some(offset as u64);
+        }
+
+        // The MSB marks an index into the table of 64-bit offsets for packs over 2GiB.
+        let large_start = offsets_start + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        let bytes = self.data.get(large_start..large_start + 8)?;
+
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Reads every `.idx` under `objects/pack`. [`Repository::pack_indexes`] keeps the result, so each
+/// index is only read once however many objects are looked up.
+pub fn load_pack_indexes(repo: &Repository) -> Vec<PackIndex> {
+    let pack_dir = repo.objects_dir().join("pack");
+    let hash_len = repo.object_format().raw_len();
+
+    let entries = match fs::read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .filter_map(|path| PackIndex::open(&path, hash_len))
+        .collect()
+}
+
+/// Looks `hash` up in every pack under `objects/pack`, returning the object type and its fully
+/// undeltified body, or `None` when no pack contains it.
+pub fn read_packed_object(repo: &Repository, hash: &str) -> Result<Option<(ObjectType, Vec<u8>)>> {
+    read_packed(repo, hash, 0)
+}
+
+/// [`read_packed_object`] for an object `depth` deltas down a chain.
+fn read_packed(
+    repo: &Repository,
+    hash: &str,
+    depth: usize,
+) -> Result<Option<(ObjectType, Vec<u8>)>> {
+    let raw_hash = match hex::decode(hash) {
+        Ok(raw_hash) if raw_hash.len() == repo.object_format().raw_len() => raw_hash,
+        _ => return Ok(None),
+    };
+
+    for index in repo.pack_indexes() {
+        if let Some(offset) = index.find_offset(&raw_hash) {
+            let mut pack = File::open(&index.pack_path)?;
+
+            return read_entry(repo, index, &mut pack, offset, hash, depth).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether any pack under `objects/pack` contains `hash`, without inflating it.
+pub fn has_packed_object(repo: &Repository, hash: &str) -> Result<bool> {
+    let raw_hash = match hex::decode(hash) {
+        Ok(raw_hash) if raw_hash.len() == repo.object_format().raw_len() => raw_hash,
+        _ => return Ok(false),
+    };
+
+    Ok(repo
+        .pack_indexes()
+        .iter()
+        .any(|index| index.find_offset(&raw_hash).is_some()))
+}
+
+/// The names of all packed objects whose hex name starts with `prefix`, which must be at least
+/// two hex digits long.
+pub fn packed_objects_with_prefix(repo: &Repository, prefix: &str) -> Result<Vec<String>> {
+    let Some(first) = prefix
+        .get(..2)
+        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+    else {
+        return Ok(Vec::new());
+    };
+    let first = first as usize;
+    let mut names = Vec::new();
+
+    for index in repo.pack_indexes() {
+        let start = if first == 0 {
+            0
+        } else {
+            index.fanout(first - 1).unwrap_or(0)
+        };
+        let end = index.fanout(first).unwrap_or(0);
+
+        for pos in start..end {
+            if let Some(name) = index.name(pos).map(hex::encode) {
+                if name.starts_with(prefix) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+/// Reads the entry at `offset`, the object `hash` or (`depth` deltas down its chain) one of its
+/// delta bases, and resolves it.
+fn read_entry(
+    repo: &Repository,
+    index: &PackIndex,
+    pack: &mut File,
+    offset: u64,
+    hash: &str,
+    depth: usize,
+) -> Result<(ObjectType, Vec<u8>)> {
+    let invalid = || {
+        Error::corrupt(
+            hash,
+            &format!("packed entry in `{}` is invalid", index.pack_path.display()),
+        )
+    };
+
+    // A longer chain is either a broken pack or one whose deltas go round in a cycle.
+    if depth > MAX_DELTA_DEPTH {
+        return Err(Error::corrupt(
+            hash,
+            &format!("delta chain is longer than {MAX_DELTA_DEPTH}"),
+        ));
+    }
+
+    // The entry header is a size varint followed by at most a base hash or offset varint.
+    let mut header = [0u8; 64];
+    pack.seek(SeekFrom::Start(offset))?;
+    let header_len = pack.read(&mut header)?;
+    let header = &header[..header_len];
+
+    let mut pos = 0;
+    let mut byte = *header.get(pos).ok_or_else(invalid)?;
+    pos += 1;
+
+    let kind = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+
+    while byte & 0x80 != 0 {
+        byte = *header.get(pos).ok_or_else(invalid)?;
+        pos += 1;
+        size = add_varint_bits(size, byte, shift).ok_or_else(invalid)?;
+        shift += 7;
+    }
+
+    match kind {
+        OBJ_OFS_DELTA => {
+            byte = *header.get(pos).ok_or_else(invalid)?;
+            pos += 1;
+            let mut distance = (byte & 0x7f) as u64;
+
+            while byte & 0x80 != 0 {
+                byte = *header.get(pos).ok_or_else(invalid)?;
+                pos += 1;
+                distance = distance
+                    .checked_add(1)
+                    .filter(|distance| distance.leading_zeros() >= 7)
+                    .map(|distance| distance << 7 | (byte & 0x7f) as u64)
+                    .ok_or_else(invalid)?;
+            }
+
+            let delta = inflate(pack, offset + pos as u64, size).ok_or_else(invalid)?;
+            // A base always comes before its delta, which also rules out cycles.
+            let base_offset = offset
+                .checked_sub(distance)
+                .filter(|base_offset| *base_offset != offset)
+                .ok_or_else(invalid)?;
+            let (base_type, base) = match index.cached_base(base_offset) {
+                Some(base) => base,
+                None => {
+                    let (base_type, base) =
+                        read_entry(repo, index, pack, base_offset, hash, depth + 1)?;
+                    let base = Rc::new(base);
+                    index.cache_base(base_offset, base_type.clone(), base.clone());
+                    (base_type, base)
+                }
+            };
+
+            Ok((base_type, apply_delta(&base, &delta).ok_or_else(invalid)?))
+        }
+        OBJ_REF_DELTA => {
+            let hash_len = repo.object_format().raw_len();
+            let base_hash = hex::encode(header.get(pos..pos + hash_len).ok_or_else(invalid)?);
+            pos += hash_len;
+
+            let delta = inflate(pack, offset + pos as u64, size).ok_or_else(invalid)?;
+            let (base_type, base) = read_packed(repo, &base_hash, depth + 1)?.ok_or_else(|| {
+                Error::corrupt(hash, &format!("delta base `{base_hash}` is missing"))
+            })?;
+
+            Ok((base_type, apply_delta(&base, &delta).ok_or_else(invalid)?))
+        }
+        _ => {
+            let obj_type = match kind {
+                OBJ_COMMIT => ObjectType::Commit,
+                OBJ_TREE => ObjectType::Tree,
+                OBJ_BLOB => ObjectType::Blob,
+                OBJ_TAG => ObjectType::Tag,
+                _ => return Err(invalid()),
+            };
+
+            Ok((
+                obj_type,
+                inflate(pack, offset + pos as u64, size).ok_or_else(invalid)?,
+            ))
+        }
+    }
+}
+
+/// `value` with the low 7 bits of `byte` added at `shift`, or `None` if they do not fit, as in
+/// a varint too long for any real object.
+fn add_varint_bits(value: usize, byte: u8, shift: u32) -> Option<usize> {
+    let bits = (byte & 0x7f) as usize;
+
+    if shift >= usize::BITS || (bits << shift) >> shift != bits {
+        return None;
+    }
+
+    Some(value | bits << shift)
+}
+
+fn inflate(pack: &mut File, offset: u64, size: usize) -> Option<Vec<u8>> {
+    let available = pack.metadata().ok()?.len().checked_sub(offset)?;
+
+    // zlib cannot inflate data to more than about 1032 times its size, so a bigger size is a
+    // lie. Short of that, the size is only trusted as far as the data that is really there.
+    if size as u64 > available.saturating_mul(MAX_DEFLATE_RATIO) {
+        return None;
+    }
+
+    pack.seek(SeekFrom::Start(offset)).ok()?;
+
+    let mut decoder = ZlibDecoder::new(pack).take(size as u64 + 1);
+    let mut data = Vec::<u8>::with_capacity(size.min(available as usize));
+    decoder.read_to_end(&mut data).ok()?;
+
+    if data.len() != size {
+        return None;
+    }
+
+    Some(data)
+}
+
+fn read_delta_size(delta: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = *delta.get(*pos)?;
+        *pos += 1;
+        size = add_varint_bits(size, byte, shift)?;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = read_delta_size(delta, &mut pos)?;
+    let result_size = read_delta_size(delta, &mut pos)?;
+
+    if base_size != base.len() {
+        return None;
+    }
+
+    // The stated size is checked once the result is built; until then it is only trusted as far
+    // as the data the result is made of.
+    let mut result = Vec::<u8>::with_capacity(result_size.min(base.len() + delta.len()));
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            // Copy a range out of the base object.
+            let mut copy_offset = 0;
+            let mut copy_size = 0;
+
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    copy_offset |= (*delta.get(pos)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    copy_size |= (*delta.get(pos)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+
+            result.extend_from_slice(base.get(copy_offset..copy_offset + copy_size)?);
+        } else if op != 0 {
+            // Insert the next `op` literal bytes from the delta itself.
+            result.extend_from_slice(delta.get(pos..pos + op as usize)?);
+            pos += op as usize;
+        } else {
+            return None;
+        }
+    }
+
+    if result.len() != result_size {
+        return None;
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+    use crate::test_support::TestRepo;
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    /// A delta turning a `base_len`-byte base into a `result_len`-byte result with `ops`.
+    fn delta(base_len: usize, result_len: usize, ops: &[u8]) -> Vec<u8> {
+        [varint(base_len), varint(result_len), ops.to_vec()].concat()
+    }
+
+    /// Writes a pack and its index into `objects/pack`, one entry at a time.
+    #[derive(Default)]
+    struct PackWriter {
+        entries: Vec<u8>,
+        /// Each entry's raw name and offset in the pack.
+        objects: Vec<(Vec<u8>, u64)>,
+    }
+
+    impl PackWriter {
+        /// Appends an entry of `kind` with `data` and, for deltas, the base reference that
+        /// goes between the header and the data. Returns its offset.
+        fn add(&mut self, name: &str, kind: u8, size: usize, base: &[u8], data: &[u8]) -> u64 {
+            let offset = 12 + self.entries.len() as u64;
+
+            let mut header = vec![(kind << 4) | (size & 0x0f) as u8];
+            if size >> 4 != 0 {
+                header[0] |= 0x80;
+                header.extend(varint(size >> 4));
+            }
+
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+
+            self.entries.extend(header);
+            self.entries.extend(base);
+            self.entries.extend(encoder.finish().unwrap());
+            self.objects.push((hex::decode(name).unwrap(), offset));
+
+            offset
+        }
+
+        fn blob(&mut self, test: &TestRepo, body: &[u8]) -> (String, u64) {
+            let name = test.repo.hash_object(&ObjectType::Blob, body).unwrap();
+            let offset = self.add(&name, OBJ_BLOB, body.len(), &[], body);
+
+            (name, offset)
+        }
+
+        /// An `OFS_DELTA` against the entry at `base`, giving a blob of `body`.
+        fn ofs_delta(&mut self, test: &TestRepo, base: u64, body: &[u8], delta: &[u8]) -> u64 {
+            let name = test.repo.hash_object(&ObjectType::Blob, body).unwrap();
+            let mut distance = 12 + self.entries.len() as u64 - base;
+
+            // The big-endian varint git uses for offsets, with one added at each step.
+            let mut encoded = vec![(distance & 0x7f) as u8];
+            distance >>= 7;
+            while distance != 0 {
+                distance -= 1;
+                encoded.insert(0, 0x80 | (distance & 0x7f) as u8);
+                distance >>= 7;
+            }
+
+            self.add(&name, OBJ_OFS_DELTA, delta.len(), &encoded, delta)
+        }
+
+        /// Writes the pack and its version 2 index and opens the repository anew to see them.
+        fn finish(mut self, test: &TestRepo) -> Repository {
+            let dir = test.repo.objects_dir().join("pack");
+            fs::create_dir_all(&dir).unwrap();
+
+            let mut pack = b"PACK".to_vec();
+            pack.extend(2u32.to_be_bytes());
+            pack.extend((self.objects.len() as u32).to_be_bytes());
+            pack.extend(&self.entries);
+            pack.extend([0; 20]);
+            fs::write(dir.join("pack-test.pack"), pack).unwrap();
+
+            self.objects.sort();
+
+            let mut idx = IDX_MAGIC.to_vec();
+            idx.extend(2u32.to_be_bytes());
+            for byte in 0..=255u8 {
+                let count = self.objects.iter().filter(|(name, _)| name[0] <= byte);
+                idx.extend((count.count() as u32).to_be_bytes());
+            }
+            for (name, _) in &self.objects {
+                idx.extend(name);
+            }
+            idx.extend(vec![0; self.objects.len() * 4]);
+            for (_, offset) in &self.objects {
+                idx.extend((*offset as u32).to_be_bytes());
+            }
+            idx.extend([0; 40]);
+            fs::write(dir.join("pack-test.idx"), idx).unwrap();
+
+            Repository::open(test.repo.work_tree().unwrap()).unwrap()
+        }
+    }
+
+    #[test]
+    fn deltas_copy_from_the_base_and_insert_literals() {
+        let base = b"hello world";
+        let ops = [
+            0x90, 5, // Copy 5 bytes from offset 0.
+            3, b',', b' ', b'x', // Insert 3 bytes.
+            0x91, 6, 5, // Copy 5 bytes from offset 6.
+        ];
+
+        assert_eq!(
+            apply_delta(base, &delta(11, 13, &ops)).unwrap(),
+            b"hello, xworld"
+        );
+
+        // A copy with no size bytes copies 0x10000 bytes.
+        let base = vec![7; 0x10000];
+        assert_eq!(
+            apply_delta(&base, &delta(0x10000, 0x10000, &[0x80])),
+            Some(base)
+        );
+    }
+
+    #[test]
+    fn corrupt_deltas_are_rejected() {
+        let base = b"hello world";
+        let good = delta(11, 5, &[0x90, 5]);
+        assert!(apply_delta(base, &good).is_some());
+
+        for delta in [
+            // Truncated in the middle of a copy.
+            good[..good.len() - 1].to_vec(),
+            // A base of the wrong size.
+            delta(12, 5, &[0x90, 5]),
+            // A copy past the end of the base.
+            delta(11, 5, &[0x91, 8, 5]),
+            // An insert running past the end of the delta.
+            delta(11, 5, &[5, b'a']),
+            // The reserved opcode 0.
+            delta(11, 5, &[0]),
+            // A result of the wrong size.
+            delta(11, 6, &[0x90, 5]),
+            // A size varint too long for any object.
+            [vec![0xff; 10], vec![0x01], varint(5), vec![0x90, 5]].concat(),
+        ] {
+            assert_eq!(apply_delta(base, &delta), None, "{delta:?}");
+        }
+    }
+
+    #[test]
+    fn ofs_and_ref_deltas_resolve_through_the_pack() {
+        let test = TestRepo::new("pack-deltas");
+        let mut pack = PackWriter::default();
+
+        let (base_name, base) = pack.blob(&test, b"hello world");
+        let ofs = delta(11, 13, &[0x90, 5, 3, b',', b' ', b'x', 0x91, 6, 5]);
+        pack.ofs_delta(&test, base, b"hello, xworld", &ofs);
+
+        let ref_body = b"world, hello";
+        let ref_name = test.repo.hash_object(&ObjectType::Blob, ref_body).unwrap();
+        let ref_delta = delta(11, 12, &[0x91, 6, 5, 2, b',', b' ', 0x90, 5]);
+        pack.add(
+            &ref_name,
+            OBJ_REF_DELTA,
+            ref_delta.len(),
+            &hex::decode(&base_name).unwrap(),
+            &ref_delta,
+        );
+
+        let repo = pack.finish(&test);
+        let ofs_name = repo
+            .hash_object(&ObjectType::Blob, b"hello, xworld")
+            .unwrap();
+
+        assert_eq!(repo.read_blob(&base_name).unwrap(), b"hello world");
+        assert_eq!(repo.read_blob(&ofs_name).unwrap(), b"hello, xworld");
+        assert_eq!(repo.read_blob(&ref_name).unwrap(), ref_body);
+    }
+
+    #[test]
+    fn delta_chains_are_limited_in_depth() {
+        let test = TestRepo::new("pack-delta-depth");
+        let mut pack = PackWriter::default();
+
+        // Each delta copies the whole of its base and adds one byte.
+        let mut body = b"x".to_vec();
+        let (_, mut offset) = pack.blob(&test, &body);
+        let mut names = Vec::new();
+
+        for _ in 0..=MAX_DELTA_DEPTH {
+            let ops = [0x90, body.len() as u8, 1, b'x'];
+            let delta = delta(body.len(), body.len() + 1, &ops);
+            body.push(b'x');
+
+            offset = pack.ofs_delta(&test, offset, &body, &delta);
+            names.push(test.repo.hash_object(&ObjectType::Blob, &body).unwrap());
+        }
+
+        // A delta whose base is itself.
+        let looped = "11".repeat(20);
+        let delta = delta(1, 1, &[0x90, 1]);
+        pack.add(
+            &looped,
+            OBJ_REF_DELTA,
+            delta.len(),
+            &hex::decode(&looped).unwrap(),
+            &delta,
+        );
+
+        let repo = pack.finish(&test);
+        let too_deep = |hash: &str| match read_packed_object(&repo, hash) {
+            Err(Error::CorruptObject { reason, .. }) => reason.contains("delta chain is longer"),
+            _ => false,
+        };
+
+        // The deepest first: once its bases are cached, reading it no longer walks the chain.
+        assert!(too_deep(&names[MAX_DELTA_DEPTH]));
+        assert_eq!(
+            repo.read_blob(&names[MAX_DELTA_DEPTH - 1]).unwrap().len(),
+            MAX_DELTA_DEPTH + 1
+        );
+        assert!(too_deep(&looped));
+    }
+}
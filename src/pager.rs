@@ -0,0 +1,101 @@
+use std::{
+    env,
+    io::{self, BufWriter, IsTerminal, Write},
+    process::{Child, Command, Stdio},
+};
+
+use crate::{error::Result, repository::Repository};
+
+/// Where paged output goes while it is being generated: the user's pager, or stdout when there
+/// is none. Writes fail with `BrokenPipe` once the reader has gone away (the pager was quit, or
+/// stdout was piped into something like `head`), which callers take as a sign to stop rather
+/// than as an error.
+pub struct Pager {
+    out: BufWriter<Box<dyn Write>>,
+    child: Option<Child>,
+}
+
+impl Pager {
+    /// Starts the pager when stdout is a terminal. The pager is `GIT_PAGER`, `core.pager`,
+    /// `PAGER` or `less`, in that order; `cat` or an empty value turn paging off. Like git,
+    /// `less` is started with `LESS=FRX` unless the user set `LESS`, so short output does not
+    /// wait for a keypress and colours come through.
+    pub fn start(repo: &Repository) -> Self {
+        let stdout = || Self {
+            out: BufWriter::new(Box::new(io::stdout())),
+            child: None,
+        };
+
+        let pager = env::var("GIT_PAGER")
+            .ok()
+            .or_else(|| repo.config().get("core.pager"))
+            .or_else(|| env::var("PAGER").ok())
+            .unwrap_or_else(|| "less".to_owned());
+
+        if !io::stdout().is_terminal() || pager.trim().is_empty() || pager == "cat" {
+            return stdout();
+        }
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&pager).stdin(Stdio::piped());
+
+        if env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+        if env::var_os("LV").is_none() {
+            command.env("LV", "-c");
+        }
+
+        let Some((stdin, child)) = command
+            .spawn()
+            .ok()
+            .and_then(|mut child| Some((child.stdin.take()?, child)))
+        else {
+            return stdout();
+        };
+
+        Self {
+            out: BufWriter::new(Box::new(stdin)),
+            child: Some(child),
+        }
+    }
+
+    /// Flushes what is left and waits for the pager to be quit.
+    pub fn finish(self) -> Result<()> {
+        let Self { out, child } = self;
+
+        // Dropping the pipe lets the pager see the end of the output.
+        match out.into_inner() {
+            Err(err) if err.error().kind() != io::ErrorKind::BrokenPipe => {
+                return Err(err.into_error().into())
+            }
+            _ => {}
+        }
+
+        if let Some(mut child) = child {
+            child.wait()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes `text` to stdout, through the user's pager as [`Pager::start`] picks it.
+pub fn page(repo: &Repository, text: impl AsRef<[u8]>) -> Result<()> {
+    let mut pager = Pager::start(repo);
+
+    match pager.write_all(text.as_ref()) {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(err.into()),
+        _ => pager.finish(),
+    }
+}
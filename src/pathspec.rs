@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use crate::{
+    error::{Error, Result},
+    utils::{fnmatch, path_to_bytes},
+};
+
+/// One command line path argument, resolved to a path from the top of the work tree.
+#[derive(Debug, Clone)]
+pub struct PathspecItem {
+    /// The argument as the user typed it, for error messages.
+    pub original: String,
+    /// The `/`-separated path from the top of the work tree; empty for the whole tree.
+    pub path: Vec<u8>,
+    /// Whether `path` contains wildcards and is matched with `fnmatch` rather than literally.
+    pub glob: bool,
+}
+
+/// The paths a command was asked to act on. An empty pathspec matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct Pathspec {
+    pub items: Vec<PathspecItem>,
+}
+
+impl Pathspec {
+    /// Resolves `args`, which are relative to the current directory, given the current
+    /// directory's `prefix` inside the work tree (see [`crate::worktree::cwd_prefix`]).
+    pub fn new(work_tree: &Path, prefix: &[u8], args: &[String]) -> Result<Self> {
+        let mut items = Vec::new();
+
+        for arg in args {
+            let relative = match Path::new(arg).strip_prefix(work_tree) {
+                Ok(relative) => path_to_bytes(relative),
+                Err(_) if Path::new(arg).is_absolute() => {
+                    return Err(Error::InvalidArgument(format!(
+                        "`{arg}` is outside the repository"
+                    )))
+                }
+                Err(_) => [prefix, arg.as_bytes()].concat(),
+            };
+
+            let mut components = Vec::<&[u8]>::new();
+
+            for component in relative.split(|b| *b == b'/') {
+                match component {
+                    b"" | b"." => {}
+                    b".." => {
+                        if components.pop().is_none() {
+                            return Err(Error::InvalidArgument(format!(
+                                "`{arg}` is outside the repository"
+                            )));
+                        }
+                    }
+                    component => components.push(component),
+                }
+            }
+
+            let path = components.join(&b'/');
+
+            items.push(PathspecItem {
+                original: arg.to_owned(),
+                glob: path.iter().any(|b| matches!(b, b'*' | b'?' | b'[')),
+                path,
+            });
+        }
+
+        Ok(Self { items })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Whether `path` is selected, either as one of the paths or inside one of the directories.
+    pub fn matches(&self, path: &[u8]) -> bool {
+        self.is_empty() || self.items.iter().any(|item| item.matches(path))
+    }
+}
+
+impl PathspecItem {
+    pub fn matches(&self, path: &[u8]) -> bool {
+        if self.path.is_empty() || path == self.path.as_slice() {
+            return true;
+        }
+
+        if path.len() > self.path.len()
+            && path.starts_with(&self.path)
+            && path[self.path.len()] == b'/'
+        {
+            return true;
+        }
+
+        self.glob && fnmatch(&self.path, path)
+    }
+}
@@ -0,0 +1,1334 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    fs, io, process,
+};
+
+use crate::{
+    checkout::{switch_trees, TreeUpdate},
+    commit::{
+        cleanup_message, commit, configured_editor, run_editor, CommitOptions, CommitSummary,
+    },
+    diff::{diff_lines, is_binary, split_lines, DiffAlgorithm, Edit},
+    error::{Error, Result},
+    index::Index,
+    log::subject,
+    merge::{merge_trees, record_conflicts, MergeLabels, MergeOptions},
+    refs::{list_refs, ref_target, set_symbolic_ref, update_ref},
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, resolve_commit},
+    revwalk::{ancestors, commit_parents, merge_bases},
+    state::{read_state, remove_state_file, state_dir, write_state},
+    status::{status, upstream_ref, StatusOptions, UntrackedMode},
+    structures::User,
+    switch::{switch, SwitchOptions},
+    tree::{commit_tree, flatten_tree, write_files, write_tree, TreeFile},
+};
+
+/// The directory under `.git` where a rebase keeps its state between stops, laid out as git's
+/// does so that either tool can pick up a rebase the other started.
+const STATE_DIR: &str = "rebase-merge";
+
+/// The state files describing a stop, removed once the rebase moves on.
+const STOP_FILES: [&str; 4] = ["amend", "stopped-sha", "message", "author-script"];
+
+#[derive(Debug, Clone, Default)]
+pub struct RebaseOptions {
+    /// Replay the commits on top of this commit instead of the upstream (`--onto`).
+    pub onto: Option<String>,
+    /// Let the user edit the todo list before starting (`-i`).
+    pub interactive: bool,
+    /// Move `fixup! <subject>` and `squash! <subject>` commits after the commit they name, and
+    /// turn them into `fixup` and `squash` commands.
+    pub autosquash: bool,
+    /// Also move the other branches that point at replayed commits (`--update-refs`).
+    pub update_refs: bool,
+}
+
+/// What a rebase did until it finished or stopped, and whether it stopped on a failure (a
+/// conflict or a failed `exec`) rather than because it was asked to.
+#[derive(Debug, Clone, Default)]
+pub struct RebaseSummary {
+    pub messages: Vec<String>,
+    pub failed: bool,
+}
+
+impl Display for RebaseSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for message in &self.messages {
+            writeln!(f, "{message}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a todo line does with its commit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TodoCommand {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+impl TodoCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Pick => "pick",
+            Self::Reword => "reword",
+            Self::Edit => "edit",
+            Self::Squash => "squash",
+            Self::Fixup => "fixup",
+            Self::Drop => "drop",
+        }
+    }
+
+    fn parse(word: &str) -> Option<Self> {
+        match word {
+            "pick" | "p" => Some(Self::Pick),
+            "reword" | "r" => Some(Self::Reword),
+            "edit" | "e" => Some(Self::Edit),
+            "squash" | "s" => Some(Self::Squash),
+            "fixup" | "f" => Some(Self::Fixup),
+            "drop" | "d" => Some(Self::Drop),
+            _ => None,
+        }
+    }
+
+    fn melds(&self) -> bool {
+        matches!(self, Self::Squash | Self::Fixup)
+    }
+}
+
+/// One line of the todo list.
+#[derive(Debug, Clone, PartialEq)]
+enum TodoItem {
+    Commit {
+        command: TodoCommand,
+        hash: String,
+        subject: String,
+    },
+    Exec(String),
+    Break,
+    UpdateRef(String),
+}
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\" but keep only the previous
+#                    commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# b, break = stop here (continue rebase later with 'pgit rebase --continue')
+# d, drop <commit> = remove commit
+# u, update-ref <ref> = track a placeholder for the <ref> to be updated
+#                       to this position in the new commits. The <ref> is
+#                       updated at the end of the rebase
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+/// `pgit rebase`: replays the commits of the current branch that `upstream` lacks on top of
+/// `upstream`, or of `onto`, one cherry-pick at a time, and moves the branch to the result.
+/// With `branch`, that branch is checked out first. Without `upstream`, the branch's configured
+/// upstream is used.
+///
+/// The todo list and everything else needed to go on lives in `.git/rebase-merge`, so when a
+/// commit does not apply cleanly, or the todo list asks for a stop, the rebase waits for
+/// [`rebase_continue`], [`rebase_skip`] or [`rebase_abort`].
+pub fn rebase(
+    repo: &Repository,
+    upstream: Option<&str>,
+    branch: Option<&str>,
+    options: &RebaseOptions,
+) -> Result<RebaseSummary> {
+    if state_dir(repo, STATE_DIR).exists() {
+        return Err(Error::InvalidArgument(
+            "A rebase is already in progress. Go on with `pgit rebase --continue`, or give up with `pgit rebase --abort`".to_owned(),
+        ));
+    }
+
+    if let Some(branch) = branch {
+        switch(repo, Some(branch), &SwitchOptions::default())?;
+    }
+
+    require_clean_work_tree(repo)?;
+
+    let head_name = ref_target(repo, "HEAD")?;
+    let Some(head) = repo.head()? else {
+        return Err(Error::InvalidArgument(
+            "The current branch has no commits yet, so there is nothing to rebase".to_owned(),
+        ));
+    };
+
+    let upstream_name = match upstream {
+        Some(upstream) => upstream.to_owned(),
+        None => head_name
+            .strip_prefix("refs/heads/")
+            .and_then(|branch| upstream_ref(repo, branch))
+            .map(|(_, name)| name)
+            .ok_or_else(|| {
+                Error::InvalidArgument(
+                    "There is no tracking information for the current branch. Specify which branch you want to rebase against".to_owned(),
+                )
+            })?,
+    };
+    let upstream = resolve_commit(repo, &upstream_name)?;
+    let onto_name = options.onto.as_deref().unwrap_or(&upstream_name);
+    let onto = resolve_commit(repo, onto_name)?;
+    let commits = commits_to_replay(repo, &upstream, &head)?;
+
+    if !options.interactive && is_based_on(repo, &commits, &head, &onto)? {
+        let current = match head_name.strip_prefix("refs/heads/") {
+            Some(branch) => format!("Current branch {branch}"),
+            None => "HEAD".to_owned(),
+        };
+
+        return Ok(RebaseSummary {
+            messages: vec![format!("{current} is up to date.")],
+            failed: false,
+        });
+    }
+
+    // Commits whose change upstream already has would only come out empty.
+    let mut summary = RebaseSummary::default();
+    let mut applied = HashSet::new();
+    for hash in commits_to_replay(repo, &head, &upstream)? {
+        applied.extend(patch_id(repo, &hash)?);
+    }
+
+    let mut todo = Vec::new();
+    for hash in &commits {
+        if !applied.is_empty() && patch_id(repo, hash)?.is_some_and(|id| applied.contains(&id)) {
+            summary.messages.push(format!(
+                "warning: skipped previously applied commit {}",
+                abbreviate(repo, hash, default_abbrev(repo))?
+            ));
+            continue;
+        }
+
+        todo.push(TodoItem::Commit {
+            command: TodoCommand::Pick,
+            hash: hash.clone(),
+            subject: commit_subject(repo, hash)?,
+        });
+    }
+
+    if !summary.messages.is_empty() {
+        summary
+            .messages
+            .push("hint: use --reapply-cherry-picks to include skipped commits".to_owned());
+    }
+
+    if options.autosquash {
+        todo = autosquash(todo);
+    }
+
+    if options.update_refs {
+        todo = insert_update_refs(repo, todo, &head_name)?;
+    }
+
+    let dir = state_dir(repo, STATE_DIR);
+    fs::create_dir_all(&dir)?;
+
+    if options.interactive {
+        match edit_todo(repo, &todo, &upstream, &head, &onto) {
+            Ok(edited) if edited.is_empty() && !todo.is_empty() => {
+                fs::remove_dir_all(&dir)?;
+                return Err(Error::InvalidArgument("Nothing to do".to_owned()));
+            }
+            Ok(edited) => todo = edited,
+            Err(err) => {
+                fs::remove_dir_all(&dir)?;
+                return Err(err);
+            }
+        }
+
+        write_state(repo, STATE_DIR, "interactive", "")?;
+    }
+
+    let head_name = if head_name.starts_with("refs/") {
+        head_name
+    } else {
+        "detached HEAD".to_owned()
+    };
+
+    write_state(repo, STATE_DIR, "head-name", &format!("{head_name}\n"))?;
+    write_state(repo, STATE_DIR, "onto", &format!("{onto}\n"))?;
+    write_state(repo, STATE_DIR, "orig-head", &format!("{head}\n"))?;
+    write_state(repo, STATE_DIR, "done", "")?;
+    write_todo(repo, &todo)?;
+
+    let zero = "0".repeat(repo.object_format().hex_len());
+    let mut update_refs = String::new();
+    for item in &todo {
+        if let TodoItem::UpdateRef(name) = item {
+            let old = repo.resolve_ref(name)?.unwrap_or_else(|| zero.clone());
+            update_refs += &format!("{name}\n{old}\n{zero}\n");
+        }
+    }
+    if !update_refs.is_empty() {
+        write_state(repo, STATE_DIR, "update-refs", &update_refs)?;
+    }
+
+    update_ref(repo, "ORIG_HEAD", &head, None, "", false)?;
+    switch_trees(
+        repo,
+        Some(&commit_tree(repo, &head)?),
+        Some(&commit_tree(repo, &onto)?),
+        false,
+        TreeUpdate::Checkout,
+    )?;
+    update_ref(
+        repo,
+        "HEAD",
+        &onto,
+        None,
+        &format!("rebase (start): checkout {onto_name}"),
+        false,
+    )?;
+
+    run_todo(repo, summary)
+}
+
+/// `pgit rebase --continue`: commits what the user staged to resolve the stop, then goes on
+/// with the todo list.
+pub fn rebase_continue(repo: &Repository) -> Result<RebaseSummary> {
+    require_rebase(repo)?;
+
+    let mut index = Index::read(repo)?;
+
+    if index.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "You must edit all merge conflicts and then mark them as resolved using `pgit add`"
+                .to_owned(),
+        ));
+    }
+
+    let head = rebase_head(repo)?;
+    let staged = write_tree(repo, &mut index)? != commit_tree(repo, &head)?;
+    let mut summary = RebaseSummary::default();
+
+    if let Some(amend) = read_state(repo, STATE_DIR, "amend")? {
+        if staged {
+            if amend.trim() != head {
+                return Err(Error::InvalidArgument(
+                    "You have uncommitted changes in your work tree. Commit them first and then run `pgit rebase --continue` again".to_owned(),
+                ));
+            }
+
+            let committed = commit(
+                repo,
+                &CommitOptions {
+                    amend: true,
+                    reflog: Some(format!(
+                        "rebase (continue): {}",
+                        commit_subject(repo, &head)?
+                    )),
+                    ..Default::default()
+                },
+            )?;
+            summary.messages.push(committed.to_string());
+        }
+    } else if let Some(stopped) = read_state(repo, STATE_DIR, "stopped-sha")? {
+        let stopped = stopped.trim();
+        let message = read_state(repo, STATE_DIR, "message")?.unwrap_or_default();
+        let command = match read_items(repo, "done")?.last() {
+            Some(TodoItem::Commit { command, .. }) => *command,
+            _ => TodoCommand::Pick,
+        };
+        let todo = read_items(repo, "git-rebase-todo")?;
+
+        if command.melds() {
+            let (committed, _) = commit_squash(repo, command, stopped, &message, &todo)?;
+            summary.messages.push(committed.to_string());
+        } else if staged {
+            let (author, date) = read_author_script(repo)?;
+            let committed = commit(
+                repo,
+                &CommitOptions {
+                    messages: vec![message.clone()],
+                    edit: Some(true),
+                    author,
+                    date,
+                    reflog: Some(format!("rebase (continue): {}", subject(&message))),
+                    ..Default::default()
+                },
+            )?;
+            summary.messages.push(committed.to_string());
+        }
+    }
+
+    clear_stop(repo)?;
+    run_todo(repo, summary)
+}
+
+/// `pgit rebase --skip`: throws away the commit the rebase stopped at, along with whatever was
+/// done to resolve it, and goes on with the todo list.
+pub fn rebase_skip(repo: &Repository) -> Result<RebaseSummary> {
+    require_rebase(repo)?;
+
+    let head_tree = commit_tree(repo, &rebase_head(repo)?)?;
+    switch_trees(
+        repo,
+        Some(&head_tree),
+        Some(&head_tree),
+        true,
+        TreeUpdate::Merge,
+    )?;
+
+    clear_stop(repo)?;
+    run_todo(repo, RebaseSummary::default())
+}
+
+/// `pgit rebase --abort`: gives up on the rebase and goes back to the branch, and the commit,
+/// it started from.
+pub fn rebase_abort(repo: &Repository) -> Result<()> {
+    require_rebase(repo)?;
+
+    let head_name = read_state(repo, STATE_DIR, "head-name")?.unwrap_or_default();
+    let head_name = head_name.trim();
+    let orig_head = read_state(repo, STATE_DIR, "orig-head")?.unwrap_or_default();
+    let orig_head = orig_head.trim();
+
+    switch_trees(
+        repo,
+        Some(&commit_tree(repo, &rebase_head(repo)?)?),
+        Some(&commit_tree(repo, orig_head)?),
+        true,
+        TreeUpdate::Checkout,
+    )?;
+
+    if head_name.starts_with("refs/") {
+        set_symbolic_ref(
+            repo,
+            "HEAD",
+            head_name,
+            Some(&format!("rebase (abort): returning to {head_name}")),
+        )?;
+    } else {
+        update_ref(
+            repo,
+            "HEAD",
+            orig_head,
+            None,
+            &format!("rebase (abort): returning to {orig_head}"),
+            false,
+        )?;
+    }
+
+    remove_state(repo)
+}
+
+/// Works through the todo list until it is done, or until a command stops the rebase.
+fn run_todo(repo: &Repository, mut summary: RebaseSummary) -> Result<RebaseSummary> {
+    let mut todo = read_items(repo, "git-rebase-todo")?;
+
+    while !todo.is_empty() {
+        let item = todo.remove(0);
+
+        write_todo(repo, &todo)?;
+        let mut done = read_state(repo, STATE_DIR, "done")?.unwrap_or_default();
+        done += &format_items(repo, std::slice::from_ref(&item), false)?;
+        write_state(repo, STATE_DIR, "done", &done)?;
+
+        let go_on = match &item {
+            TodoItem::Commit {
+                command: TodoCommand::Drop,
+                ..
+            } => true,
+            TodoItem::Commit { command, hash, .. } => {
+                apply_commit(repo, *command, hash, &todo, &mut summary)?
+            }
+            TodoItem::Exec(command) => exec(repo, command, &mut summary)?,
+            TodoItem::Break => {
+                let head = rebase_head(repo)?;
+
+                summary.messages.push(format!(
+                    "Stopped at {} ({})",
+                    abbreviate(repo, &head, default_abbrev(repo))?,
+                    commit_subject(repo, &head)?
+                ));
+                false
+            }
+            TodoItem::UpdateRef(name) => {
+                record_update_ref(repo, name)?;
+                true
+            }
+        };
+
+        if !go_on {
+            return Ok(summary);
+        }
+    }
+
+    finish(repo, summary)
+}
+
+/// Cherry-picks the commit `hash` onto `HEAD` for `command`. Returns whether the rebase goes on:
+/// it stops on conflicts, and after an `edit`.
+fn apply_commit(
+    repo: &Repository,
+    command: TodoCommand,
+    hash: &str,
+    todo: &[TodoItem],
+    summary: &mut RebaseSummary,
+) -> Result<bool> {
+    let head = rebase_head(repo)?;
+    let head_tree = commit_tree(repo, &head)?;
+    let meta = repo.read_object(hash)?.meta.unwrap_or_default();
+    let message = meta.message.clone().unwrap_or_default();
+    let subject = subject(&message);
+    let parent = meta.parents.first();
+    let parent_tree = parent.map(|parent| commit_tree(repo, parent)).transpose()?;
+    let tree = commit_tree(repo, hash)?;
+    let short = abbreviate(repo, hash, default_abbrev(repo))?;
+
+    if matches!(command, TodoCommand::Pick | TodoCommand::Edit) && parent == Some(&head) {
+        // Nothing changed under the commit, so it can be kept as it is.
+        switch_trees(
+            repo,
+            Some(&head_tree),
+            Some(&tree),
+            false,
+            TreeUpdate::Merge,
+        )?;
+        update_ref(
+            repo,
+            "HEAD",
+            hash,
+            Some(&head),
+            "rebase: fast-forward",
+            false,
+        )?;
+    } else {
+        let labels = MergeLabels {
+            base: format!("parent of {short} ({subject})"),
+            ours: "HEAD".to_owned(),
+            theirs: format!("{short} ({subject})"),
+        };
+        let merged = merge_trees(
+            repo,
+            parent_tree.as_deref(),
+            &head_tree,
+            &tree,
+            &labels,
+            &MergeOptions::configured(repo)?,
+        )?;
+        let result = write_files(repo, &merged.files)?;
+
+        switch_trees(
+            repo,
+            Some(&head_tree),
+            Some(&result),
+            false,
+            TreeUpdate::Merge,
+        )?;
+        record_conflicts(repo, &merged.conflicts)?;
+
+        if let Some(author) = &meta.author {
+            write_author_script(repo, author)?;
+        }
+        write_state(repo, STATE_DIR, "message", &message)?;
+
+        if !merged.conflicts.is_empty() {
+            // Like git, only say how the files were merged when it did not go well.
+            summary.messages.extend(merged.messages);
+            write_state(repo, STATE_DIR, "stopped-sha", &format!("{hash}\n"))?;
+            update_ref(repo, "REBASE_HEAD", hash, None, "", false)?;
+            update_ref(repo, "AUTO_MERGE", &result, None, "", false)?;
+
+            summary.messages.extend([
+                format!("error: could not apply {short}... {subject}"),
+                "hint: Resolve all conflicts manually, mark them as resolved with".to_owned(),
+                "hint: \"pgit add/rm <conflicted_files>\", then run \"pgit rebase --continue\"."
+                    .to_owned(),
+                "hint: You can instead skip this commit: run \"pgit rebase --skip\".".to_owned(),
+                "hint: To abort and get back to the state before \"pgit rebase\", run \"pgit rebase --abort\".".to_owned(),
+                format!("Could not apply {short}... {subject}"),
+            ]);
+            summary.failed = true;
+
+            return Ok(false);
+        }
+
+        let became_empty = result == head_tree && parent_tree.as_ref() != Some(&tree);
+
+        if command.melds() {
+            let (committed, edited) = commit_squash(repo, command, hash, &message, todo)?;
+
+            if edited {
+                summary.messages.push(committed.to_string());
+            }
+        } else if !became_empty {
+            let (author, date) = read_author_script(repo)?;
+            let committed = commit(
+                repo,
+                &CommitOptions {
+                    messages: vec![message.clone()],
+                    edit: Some(command == TodoCommand::Reword),
+                    allow_empty: true,
+                    author,
+                    date,
+                    reflog: Some(format!("rebase ({}): {subject}", command.name())),
+                    ..Default::default()
+                },
+            )?;
+
+            if command == TodoCommand::Reword {
+                summary.messages.push(committed.to_string());
+            }
+        }
+    }
+
+    if command == TodoCommand::Edit {
+        let head = rebase_head(repo)?;
+
+        write_state(repo, STATE_DIR, "amend", &format!("{head}\n"))?;
+        write_state(repo, STATE_DIR, "stopped-sha", &format!("{hash}\n"))?;
+        update_ref(repo, "REBASE_HEAD", hash, None, "", false)?;
+
+        summary.messages.extend([
+            format!("Stopped at {short}...  {subject}"),
+            "You can amend the commit now, with".to_owned(),
+            String::new(),
+            "  pgit commit --amend".to_owned(),
+            String::new(),
+            "Once you are satisfied with your changes, run".to_owned(),
+            String::new(),
+            "  pgit rebase --continue".to_owned(),
+        ]);
+
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Melds the index into `HEAD` for a `squash` or `fixup` of the commit `hash`. The messages of a
+/// run of such commands are gathered in `message-squash`, and the editor is opened on them at
+/// the end of a run that has a `squash`. Returns the amended commit and whether the editor was
+/// opened.
+fn commit_squash(
+    repo: &Repository,
+    command: TodoCommand,
+    hash: &str,
+    message: &str,
+    todo: &[TodoItem],
+) -> Result<(CommitSummary, bool)> {
+    let head_message = repo
+        .read_object(&rebase_head(repo)?)?
+        .meta
+        .and_then(|meta| meta.message)
+        .unwrap_or_default();
+    let mut fixups = read_state(repo, STATE_DIR, "current-fixups")?.unwrap_or_default();
+    let count = fixups.lines().count() + 2;
+
+    let mut body = match read_state(repo, STATE_DIR, "message-squash")? {
+        Some(text) => text
+            .split_once('\n')
+            .map(|(_, body)| body.to_owned())
+            .unwrap_or_default(),
+        None => format!("# This is the 1st commit message:\n\n{head_message}"),
+    };
+
+    if command == TodoCommand::Squash {
+        body += &format!("\n# This is the commit message #{count}:\n\n");
+        body += &squash_text(message);
+    } else {
+        body += &format!("\n# The commit message #{count} will be skipped:\n\n");
+        body += &comment_lines(message);
+    }
+
+    let text = format!("# This is a combination of {count} commits.\n{body}");
+    fixups += &format!("{} {hash}\n", command.name());
+
+    let last = !matches!(
+        todo.first(),
+        Some(TodoItem::Commit { command, .. }) if command.melds()
+    );
+    let edit = last && fixups.lines().any(|line| line.starts_with("squash "));
+
+    if last {
+        for file in ["current-fixups", "message-squash"] {
+            remove_state_file(repo, STATE_DIR, file)?;
+        }
+    } else {
+        write_state(repo, STATE_DIR, "current-fixups", &fixups)?;
+        write_state(repo, STATE_DIR, "message-squash", &text)?;
+    }
+
+    let committed = commit(
+        repo,
+        &CommitOptions {
+            messages: vec![if edit {
+                text
+            } else {
+                cleanup_message(&text, true)
+            }],
+            edit: Some(edit),
+            amend: true,
+            allow_empty: true,
+            reflog: Some(format!(
+                "rebase ({}): {}",
+                command.name(),
+                subject(&head_message)
+            )),
+            ..Default::default()
+        },
+    )?;
+
+    Ok((committed, edit))
+}
+
+/// A squashed commit's message as it goes into the combined message. A `squash!` or `fixup!`
+/// subject only served to place the commit, so it is commented out.
+fn squash_text(message: &str) -> String {
+    let (subject, rest) = message.split_once('\n').unwrap_or((message, ""));
+
+    if ["squash! ", "fixup! ", "amend! "]
+        .iter()
+        .any(|prefix| subject.starts_with(prefix))
+    {
+        format!("# {subject}\n{rest}")
+    } else {
+        message.to_owned()
+    }
+}
+
+fn comment_lines(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                "#\n".to_owned()
+            } else {
+                format!("# {line}\n")
+            }
+        })
+        .collect()
+}
+
+/// Runs an `exec` command in the work tree. Its output is collected so that it shows up in
+/// order with the rest of the rebase's. Returns whether it succeeded.
+fn exec(repo: &Repository, command: &str, summary: &mut RebaseSummary) -> Result<bool> {
+    summary.messages.push(format!("Executing: {command}"));
+
+    let output = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(repo.require_work_tree()?)
+        .output()?;
+
+    for stream in [&output.stdout, &output.stderr] {
+        summary
+            .messages
+            .extend(String::from_utf8_lossy(stream).lines().map(str::to_owned));
+    }
+
+    if output.status.success() {
+        return Ok(true);
+    }
+
+    summary.messages.extend([
+        format!("warning: execution failed: {command}"),
+        "You can fix the problem, and then run".to_owned(),
+        String::new(),
+        "  pgit rebase --continue".to_owned(),
+        String::new(),
+    ]);
+    summary.failed = true;
+
+    Ok(false)
+}
+
+/// Notes that an `update-ref` line was reached: the ref will be moved to `HEAD` once the rebase
+/// is over.
+fn record_update_ref(repo: &Repository, name: &str) -> Result<()> {
+    let head = rebase_head(repo)?;
+    let mut refs = read_update_refs(repo)?;
+
+    for entry in refs.iter_mut().filter(|entry| entry.0 == name) {
+        entry.2 = head.clone();
+    }
+
+    write_update_refs(repo, &refs)
+}
+
+/// Moves the rebased branch to the new commits, returns to it and applies the `update-ref`s.
+fn finish(repo: &Repository, mut summary: RebaseSummary) -> Result<RebaseSummary> {
+    let head = rebase_head(repo)?;
+    let head_name = read_state(repo, STATE_DIR, "head-name")?.unwrap_or_default();
+    let head_name = head_name.trim();
+    let onto = read_state(repo, STATE_DIR, "onto")?.unwrap_or_default();
+
+    if head_name.starts_with("refs/") {
+        update_ref(
+            repo,
+            head_name,
+            &head,
+            None,
+            &format!("rebase (finish): {head_name} onto {}", onto.trim()),
+            false,
+        )?;
+        set_symbolic_ref(
+            repo,
+            "HEAD",
+            head_name,
+            Some(&format!("rebase (finish): returning to {head_name}")),
+        )?;
+    }
+
+    summary
+        .messages
+        .push(format!("Successfully rebased and updated {head_name}."));
+
+    let zero = "0".repeat(repo.object_format().hex_len());
+    let updated: Vec<(String, String, String)> = read_update_refs(repo)?
+        .into_iter()
+        .filter(|(_, _, new)| *new != zero)
+        .collect();
+
+    for (name, old, new) in &updated {
+        update_ref(
+            repo,
+            name,
+            new,
+            Some(old),
+            &format!("rebase (update-refs): {name}"),
+            false,
+        )?;
+    }
+
+    if !updated.is_empty() {
+        summary
+            .messages
+            .push("Updated the following refs with --update-refs:".to_owned());
+        summary
+            .messages
+            .extend(updated.iter().map(|(name, _, _)| format!("\t{name}")));
+    }
+
+    remove_state(repo)?;
+
+    Ok(summary)
+}
+
+/// The commits `upstream` lacks, parents first, leaving merges out as git does.
+fn commits_to_replay(repo: &Repository, upstream: &str, head: &str) -> Result<Vec<String>> {
+    let excluded = ancestors(repo, upstream)?;
+    let mut seen = HashSet::new();
+    let mut commits = Vec::new();
+    let mut pending = vec![(head.to_owned(), false)];
+
+    while let Some((commit, parents_done)) = pending.pop() {
+        if parents_done {
+            if commit_parents(repo, &commit)?.len() <= 1 {
+                commits.push(commit);
+            }
+            continue;
+        }
+
+        if excluded.contains(&commit) || !seen.insert(commit.clone()) {
+            continue;
+        }
+
+        pending.push((commit.clone(), true));
+        for parent in commit_parents(repo, &commit)?.into_iter().rev() {
+            pending.push((parent, false));
+        }
+    }
+
+    Ok(commits)
+}
+
+/// A fingerprint of the change the commit `hash` makes, like git's patch ID: two commits get the
+/// same one when they change the same lines of the same files, wherever those lines are.
+/// Merges have none.
+fn patch_id(repo: &Repository, hash: &str) -> Result<Option<String>> {
+    let parents = commit_parents(repo, hash)?;
+
+    if parents.len() > 1 {
+        return Ok(None);
+    }
+
+    let old = match parents.first() {
+        Some(parent) => flatten_tree(repo, &commit_tree(repo, parent)?)?,
+        None => BTreeMap::new(),
+    };
+    let new = flatten_tree(repo, &commit_tree(repo, hash)?)?;
+    let paths: BTreeSet<&Vec<u8>> = old.keys().chain(new.keys()).collect();
+    let mut patch = Vec::new();
+
+    for path in paths {
+        let (old_file, new_file) = (old.get(path), new.get(path));
+
+        if old_file == new_file {
+            continue;
+        }
+
+        patch.extend_from_slice(path);
+        patch.push(0);
+
+        let blob = |file: Option<&TreeFile>| match file {
+            Some(file) => repo.read_blob(&file.hash),
+            None => Ok(Vec::new()),
+        };
+        let (old_data, new_data) = (blob(old_file)?, blob(new_file)?);
+
+        if is_binary(&old_data) || is_binary(&new_data) {
+            for file in [old_file, new_file] {
+                patch.extend(file.map(|file| file.hash.as_bytes()).unwrap_or_default());
+                patch.push(0);
+            }
+            continue;
+        }
+
+        let (old_lines, new_lines) = (split_lines(&old_data), split_lines(&new_data));
+
+        // Whitespace is left out, as git does.
+        for edit in diff_lines(&old_lines, &new_lines, DiffAlgorithm::Myers) {
+            let (sign, line) = match edit {
+                Edit::Delete(i) => (b'-', old_lines[i]),
+                Edit::Insert(j) => (b'+', new_lines[j]),
+                Edit::Equal(..) => continue,
+            };
+
+            patch.push(sign);
+            patch.extend(line.iter().filter(|b| !b.is_ascii_whitespace()));
+            patch.push(b'\n');
+        }
+    }
+
+    Ok(Some(repo.object_format().hash(&patch)))
+}
+
+/// Whether replaying `commits` on `onto` would give them back unchanged: they already sit on
+/// top of `onto`, one after the other, up to `head`.
+fn is_based_on(repo: &Repository, commits: &[String], head: &str, onto: &str) -> Result<bool> {
+    if merge_bases(repo, onto, head)? != [onto.to_owned()] {
+        return Ok(false);
+    }
+
+    let replayed: HashSet<&str> = commits.iter().map(String::as_str).collect();
+
+    if head != onto && !replayed.contains(head) {
+        return Ok(false);
+    }
+
+    for commit in commits {
+        for parent in commit_parents(repo, commit)? {
+            if parent != onto && !replayed.contains(parent.as_str()) {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Moves each `fixup! <subject>` or `squash! <subject>` commit right after the earlier commit
+/// it names (by subject, by name, or by the start of its subject), after the ones already moved
+/// there.
+fn autosquash(todo: Vec<TodoItem>) -> Vec<TodoItem> {
+    let mut groups: Vec<(TodoItem, Vec<TodoItem>)> = Vec::new();
+
+    for item in todo {
+        let TodoItem::Commit { hash, subject, .. } = &item else {
+            groups.push((item, Vec::new()));
+            continue;
+        };
+
+        if let Some((command, target)) = fixup_target(subject) {
+            let find = |matches: &dyn Fn(&str, &str) -> bool| {
+                groups.iter().position(|(pick, _)| match pick {
+                    TodoItem::Commit { hash, subject, .. } => matches(hash, subject),
+                    _ => false,
+                })
+            };
+            let position = find(&|_, subject| subject == target)
+                .or_else(|| {
+                    find(&|hash, _| {
+                        target.len() >= 4
+                            && target.bytes().all(|b| b.is_ascii_hexdigit())
+                            && hash.starts_with(target)
+                    })
+                })
+                .or_else(|| find(&|_, subject| subject.starts_with(target)));
+
+            if let Some(position) = position {
+                groups[position].1.push(TodoItem::Commit {
+                    command,
+                    hash: hash.clone(),
+                    subject: subject.clone(),
+                });
+                continue;
+            }
+        }
+
+        groups.push((item, Vec::new()));
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|(pick, fixups)| std::iter::once(pick).chain(fixups))
+        .collect()
+}
+
+/// The command and target of a `fixup! ` or `squash! ` subject. Repeated prefixes, as in
+/// `fixup! fixup! subject`, all go.
+fn fixup_target(subject: &str) -> Option<(TodoCommand, &str)> {
+    let command = if subject.starts_with("fixup! ") {
+        TodoCommand::Fixup
+    } else if subject.starts_with("squash! ") {
+        TodoCommand::Squash
+    } else {
+        return None;
+    };
+
+    let mut target = subject;
+    while let Some(rest) = target
+        .strip_prefix("fixup! ")
+        .or_else(|| target.strip_prefix("squash! "))
+    {
+        target = rest;
+    }
+
+    Some((command, target))
+}
+
+/// Adds an `update-ref` line for every other local branch that points at a replayed commit,
+/// after the commit and anything melded into it.
+fn insert_update_refs(
+    repo: &Repository,
+    todo: Vec<TodoItem>,
+    head_name: &str,
+) -> Result<Vec<TodoItem>> {
+    let mut tips = HashMap::<String, Vec<String>>::new();
+
+    for branch in list_refs(repo, "refs/heads/")? {
+        if branch.name != head_name {
+            tips.entry(branch.hash).or_default().push(branch.name);
+        }
+    }
+
+    let mut items = Vec::new();
+    let mut pending = Vec::new();
+
+    for item in todo {
+        if let TodoItem::Commit { command, hash, .. } = &item {
+            if !command.melds() {
+                items.extend(pending.drain(..).map(TodoItem::UpdateRef));
+            }
+            pending.extend(tips.remove(hash).unwrap_or_default());
+        }
+
+        items.push(item);
+    }
+
+    items.extend(pending.into_iter().map(TodoItem::UpdateRef));
+
+    Ok(items)
+}
+
+/// Lets the user edit the todo list, and reads back what they left of it.
+fn edit_todo(
+    repo: &Repository,
+    todo: &[TodoItem],
+    upstream: &str,
+    head: &str,
+    onto: &str,
+) -> Result<Vec<TodoItem>> {
+    let abbrev = default_abbrev(repo);
+    let mut text = if todo.is_empty() {
+        "noop\n".to_owned()
+    } else {
+        format_items(repo, todo, true)?
+    };
+
+    text += &format!(
+        "\n# Rebase {}..{} onto {} ({} command{})\n#",
+        abbreviate(repo, upstream, abbrev)?,
+        abbreviate(repo, head, abbrev)?,
+        abbreviate(repo, onto, abbrev)?,
+        todo.len(),
+        if todo.len() == 1 { "" } else { "s" }
+    );
+    text += TODO_HELP;
+
+    let path = state_dir(repo, STATE_DIR).join("git-rebase-todo");
+    fs::write(&path, text)?;
+
+    let editor = std::env::var("GIT_SEQUENCE_EDITOR")
+        .ok()
+        .or_else(|| repo.config().get("sequence.editor"))
+        .unwrap_or_else(|| configured_editor(repo));
+    run_editor(&editor, &path)?;
+
+    let edited = parse_todo(repo, &fs::read_to_string(&path)?)?;
+
+    match edited.iter().find_map(|item| match item {
+        TodoItem::Commit { command, .. } if *command != TodoCommand::Drop => Some(*command),
+        _ => None,
+    }) {
+        Some(command) if command.melds() => Err(Error::InvalidArgument(format!(
+            "cannot '{}' without a previous commit",
+            command.name()
+        ))),
+        _ => Ok(edited),
+    }
+}
+
+/// Parses a todo list, resolving the commits it names. Blank lines, comments and `noop` are
+/// skipped.
+fn parse_todo(repo: &Repository, text: &str) -> Result<Vec<TodoItem>> {
+    let mut items = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let invalid = || Error::InvalidArgument(format!("invalid line {}: {line}", number + 1));
+
+        if line.is_empty() || line.starts_with('#') || line == "noop" {
+            continue;
+        }
+
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        let item = match word {
+            "exec" | "x" if !rest.is_empty() => TodoItem::Exec(rest.to_owned()),
+            "break" | "b" if rest.is_empty() => TodoItem::Break,
+            "update-ref" | "u" if rest.starts_with("refs/") => TodoItem::UpdateRef(rest.to_owned()),
+            _ => {
+                let command = TodoCommand::parse(word).ok_or_else(invalid)?;
+                let (commit, subject) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+                if commit.is_empty() || commit.starts_with('-') {
+                    return Err(invalid());
+                }
+
+                TodoItem::Commit {
+                    command,
+                    hash: resolve_commit(repo, commit).map_err(|_| invalid())?,
+                    subject: subject.trim().to_owned(),
+                }
+            }
+        };
+
+        items.push(item);
+    }
+
+    Ok(items)
+}
+
+/// Writes todo lines, naming commits in full or, for the user, abbreviated.
+fn format_items(repo: &Repository, items: &[TodoItem], abbreviated: bool) -> Result<String> {
+    let mut text = String::new();
+
+    for item in items {
+        text += &match item {
+            TodoItem::Commit {
+                command,
+                hash,
+                subject,
+            } => {
+                let name = if abbreviated {
+                    abbreviate(repo, hash, default_abbrev(repo))?
+                } else {
+                    hash.clone()
+                };
+                format!("{} {name} {subject}\n", command.name())
+            }
+            TodoItem::Exec(command) => format!("exec {command}\n"),
+            TodoItem::Break => "break\n".to_owned(),
+            TodoItem::UpdateRef(name) => format!("update-ref {name}\n"),
+        };
+    }
+
+    Ok(text)
+}
+
+fn write_todo(repo: &Repository, todo: &[TodoItem]) -> Result<()> {
+    write_state(
+        repo,
+        STATE_DIR,
+        "git-rebase-todo",
+        &format_items(repo, todo, false)?,
+    )
+}
+
+fn read_items(repo: &Repository, name: &str) -> Result<Vec<TodoItem>> {
+    parse_todo(
+        repo,
+        &read_state(repo, STATE_DIR, name)?.unwrap_or_default(),
+    )
+}
+
+/// The refs to move at the end, as `(name, old value, new value)`, the new value being all
+/// zeros until the rebase reaches their `update-ref` line.
+fn read_update_refs(repo: &Repository) -> Result<Vec<(String, String, String)>> {
+    let text = read_state(repo, STATE_DIR, "update-refs")?.unwrap_or_default();
+    let lines: Vec<&str> = text.lines().collect();
+
+    Ok(lines
+        .chunks(3)
+        .filter(|chunk| chunk.len() == 3)
+        .map(|chunk| {
+            (
+                chunk[0].to_owned(),
+                chunk[1].to_owned(),
+                chunk[2].to_owned(),
+            )
+        })
+        .collect())
+}
+
+fn write_update_refs(repo: &Repository, refs: &[(String, String, String)]) -> Result<()> {
+    let text: String = refs
+        .iter()
+        .map(|(name, old, new)| format!("{name}\n{old}\n{new}\n"))
+        .collect();
+
+    write_state(repo, STATE_DIR, "update-refs", &text)
+}
+
+/// Records the author of the commit being applied in `author-script`, shell syntax included,
+/// for when the commit is made after a stop.
+fn write_author_script(repo: &Repository, author: &User) -> Result<()> {
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "'\\''"));
+
+    write_state(
+        repo,
+        STATE_DIR,
+        "author-script",
+        &format!(
+            "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE={}\n",
+            quote(&author.name),
+            quote(author.email.as_deref().unwrap_or_default()),
+            quote(&format!("@{} {}", author.time, author.zone))
+        ),
+    )
+}
+
+/// The `Name <email>` identity and the date recorded by [`write_author_script`].
+fn read_author_script(repo: &Repository) -> Result<(Option<String>, Option<String>)> {
+    let Some(text) = read_state(repo, STATE_DIR, "author-script")? else {
+        return Ok((None, None));
+    };
+
+    let mut values = HashMap::new();
+
+    for line in text.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim_matches('\'').replace("'\\''", "'");
+            values.insert(key, value);
+        }
+    }
+
+    let author = match (
+        values.get("GIT_AUTHOR_NAME"),
+        values.get("GIT_AUTHOR_EMAIL"),
+    ) {
+        (Some(name), Some(email)) => Some(format!("{name} <{email}>")),
+        _ => None,
+    };
+
+    Ok((author, values.get("GIT_AUTHOR_DATE").cloned()))
+}
+
+/// Refuses to start while tracked files have changes, which replaying commits would mix up with
+/// theirs.
+fn require_clean_work_tree(repo: &Repository) -> Result<()> {
+    let status = status(
+        repo,
+        &[],
+        &StatusOptions {
+            untracked: UntrackedMode::No,
+            ..Default::default()
+        },
+    )?;
+
+    if status
+        .entries
+        .iter()
+        .any(|entry| entry.unstaged.is_some() || entry.conflict.is_some())
+    {
+        return Err(Error::InvalidArgument(
+            "cannot rebase: You have unstaged changes. Commit or stash them first".to_owned(),
+        ));
+    }
+
+    if status.entries.iter().any(|entry| entry.staged.is_some()) {
+        return Err(Error::InvalidArgument(
+            "cannot rebase: Your index contains uncommitted changes. Commit or stash them first"
+                .to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn require_rebase(repo: &Repository) -> Result<()> {
+    if state_dir(repo, STATE_DIR).is_dir() {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument("No rebase in progress".to_owned()))
+    }
+}
+
+/// The commit the rebase has got to.
+fn rebase_head(repo: &Repository) -> Result<String> {
+    repo.head()?
+        .ok_or_else(|| Error::InvalidArgument("HEAD does not point at a commit".to_owned()))
+}
+
+fn commit_subject(repo: &Repository, hash: &str) -> Result<String> {
+    let message = repo
+        .read_object(hash)?
+        .meta
+        .and_then(|meta| meta.message)
+        .unwrap_or_default();
+
+    Ok(subject(&message))
+}
+
+/// Forgets about the stop the rebase is resuming from.
+fn clear_stop(repo: &Repository) -> Result<()> {
+    for file in STOP_FILES {
+        remove_state_file(repo, STATE_DIR, file)?;
+    }
+
+    for file in ["REBASE_HEAD", "AUTO_MERGE"] {
+        remove_git_file(repo, file)?;
+    }
+
+    Ok(())
+}
+
+/// Removes all of the rebase's state.
+fn remove_state(repo: &Repository) -> Result<()> {
+    fs::remove_dir_all(state_dir(repo, STATE_DIR))?;
+
+    for file in ["REBASE_HEAD", "AUTO_MERGE"] {
+        remove_git_file(repo, file)?;
+    }
+
+    Ok(())
+}
+
+fn remove_git_file(repo: &Repository, name: &str) -> Result<()> {
+    match fs::remove_file(repo.git_dir().join(name)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    error::{Error, Result},
+    refs::{
+        delete_ref, list_refs, peel, read_symbolic_ref, resolve_ref, shorten_ref, update_ref, Ref,
+    },
+    repository::Repository,
+    revision::{abbreviate, resolve_revision},
+};
+
+/// Which refs `show-ref` lists and how it prints them.
+#[derive(Debug, Clone, Default)]
+pub struct ShowRefOptions {
+    /// Also show `HEAD`.
+    pub head: bool,
+    pub heads: bool,
+    pub tags: bool,
+    /// Follow annotated tags and show what they point at as `<name>^{}`.
+    pub dereference: bool,
+    /// Print only the object names, abbreviated to this many digits when given.
+    pub hash_only: Option<Option<usize>>,
+    /// Abbreviate object names to this many digits.
+    pub abbrev: Option<usize>,
+    /// Treat the patterns as exact ref names that must exist.
+    pub verify: bool,
+}
+
+/// Lists refs matching `patterns` as `<hash> <name>` lines. A pattern matches a ref whose name
+/// is the pattern or ends in `/<pattern>`; with `verify` only exact names are accepted and every
+/// one of them must exist.
+pub fn show_ref(
+    repo: &Repository,
+    patterns: &[String],
+    options: &ShowRefOptions,
+) -> Result<Vec<String>> {
+    let mut refs = Vec::<Ref>::new();
+
+    if options.verify {
+        for name in patterns {
+            let hash = match name.as_str() {
+                "HEAD" => resolve_ref(repo, name)?,
+                _ if name.starts_with("refs/") => resolve_ref(repo, name)?,
+                _ => None,
+            };
+            let hash =
+                hash.ok_or_else(|| Error::InvalidArgument(format!("'{name}' - not a valid ref")))?;
+
+            refs.push(Ref {
+                name: name.clone(),
+                hash,
+                peeled: None,
+            });
+        }
+    } else {
+        if options.head {
+            if let Some(hash) = resolve_ref(repo, "HEAD")? {
+                refs.push(Ref {
+                    name: "HEAD".to_owned(),
+                    hash,
+                    peeled: None,
+                });
+            }
+        }
+
+        for entry in list_refs(repo, "refs/")? {
+            let kind_selected = (!options.heads && !options.tags)
+                || (options.heads && entry.name.starts_with("refs/heads/"))
+                || (options.tags && entry.name.starts_with("refs/tags/"));
+            let pattern_selected = patterns.is_empty()
+                || patterns.iter().any(|pattern| {
+                    entry.name == *pattern || entry.name.ends_with(&format!("/{pattern}"))
+                });
+
+            if kind_selected && pattern_selected {
+                refs.push(entry);
+            }
+        }
+    }
+
+    let shorten = |hash: &str, len: Option<usize>| match len {
+        Some(len) => abbreviate(repo, hash, len),
+        None => Ok(hash.to_owned()),
+    };
+    let digits = options.hash_only.flatten().or(options.abbrev);
+
+    let mut lines = Vec::new();
+
+    for entry in refs {
+        let hash = shorten(&entry.hash, digits)?;
+
+        match options.hash_only {
+            Some(_) => lines.push(hash),
+            None => lines.push(format!("{hash} {}", entry.name)),
+        }
+
+        if options.dereference && entry.name.starts_with("refs/tags/") {
+            let peeled = match entry.peeled {
+                Some(peeled) => peeled,
+                None => peel(repo, &entry.hash)?,
+            };
+
+            if peeled != entry.hash {
+                let peeled = shorten(&peeled, digits)?;
+
+                match options.hash_only {
+                    Some(_) => lines.push(peeled),
+                    None => lines.push(format!("{peeled} {}^{{}}", entry.name)),
+                }
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Turns an `update-ref` value into an object name. The empty value is kept, since it means the
+/// ref must not exist yet.
+fn resolve_value(repo: &Repository, value: &str) -> Result<String> {
+    if value.is_empty() {
+        return Ok(String::new());
+    }
+
+    resolve_revision(repo, value).map_err(|err| match err {
+        Error::UnknownRevision(_) => Error::InvalidArgument(format!("{value}: not a valid SHA1")),
+        err => err,
+    })
+}
+
+/// Sets the ref `name` to `new`, optionally checking it currently holds `old`. Without `deref`
+/// a symbolic ref is overwritten instead of the ref it points at.
+pub fn update_ref_command(
+    repo: &Repository,
+    name: &str,
+    new: &str,
+    old: Option<&str>,
+    message: &str,
+    deref: bool,
+) -> Result<()> {
+    let new = resolve_value(repo, new)?;
+
+    if !repo.has_object(&new)? {
+        return Err(Error::ObjectNotFound(new));
+    }
+
+    let old = old.map(|old| resolve_value(repo, old)).transpose()?;
+
+    update_ref(repo, name, &new, old.as_deref(), message, deref)
+}
+
+/// Deletes the ref `name`, optionally checking it currently holds `old`.
+pub fn delete_ref_command(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    deref: bool,
+) -> Result<()> {
+    let old = old.map(|old| resolve_value(repo, old)).transpose()?;
+
+    delete_ref(repo, name, old.as_deref(), deref)
+}
+
+/// The ref the symbolic ref `name` points at, shortened with `short`. Returns `None` when `name`
+/// is not a symbolic ref.
+pub fn symbolic_ref_get(repo: &Repository, name: &str, short: bool) -> Result<Option<String>> {
+    Ok(read_symbolic_ref(repo, name)?.map(|target| {
+        if short {
+            shorten_ref(&target).to_owned()
+        } else {
+            target
+        }
+    }))
+}
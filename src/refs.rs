@@ -0,0 +1,936 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::parse_bool,
+    error::{Error, Result},
+    ident::{identity, Role},
+    repository::Repository,
+    structures::{ObjectType, User},
+};
+
+/// The contents of a single ref, before any symbolic ref is followed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValue {
+    /// An object name.
+    Direct(String),
+    /// `ref: <target>`, naming another ref.
+    Symbolic(String),
+}
+
+/// A ref and the object it ends up at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ref {
+    pub name: String,
+    pub hash: String,
+    /// For annotated tags, the object the tag (chain) points at, when `packed-refs` recorded it.
+    pub peeled: Option<String>,
+}
+
+/// Symbolic refs nested deeper than this are treated as a loop, as in git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The rules git tries, in order, to expand a short name such as `main` into a full ref name.
+const DWIM_RULES: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// Reads the ref `name` without following it. Loose refs take precedence over `packed-refs`.
+pub fn read_ref(repo: &Repository, name: &str) -> Result<Option<RefValue>> {
+    match fs::read_to_string(repo.ref_path(name)) {
+        Ok(contents) => {
+            let contents = contents.trim();
+
+            return Ok(Some(match contents.strip_prefix("ref:") {
+                Some(target) => RefValue::Symbolic(target.trim().to_owned()),
+                None => RefValue::Direct(contents.to_owned()),
+            }));
+        }
+        // A prefix of the name may be a ref file (`NotADirectory`), or the name a directory of
+        // refs; neither makes this a loose ref.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) if err.kind() == io::ErrorKind::NotADirectory => {}
+        Err(err) if err.kind() == io::ErrorKind::IsADirectory => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(packed_refs(repo)?
+        .remove(name)
+        .map(|packed| RefValue::Direct(packed.hash)))
+}
+
+/// Resolves `name` (e.g. `HEAD` or `refs/heads/main`) to an object name, following symbolic
+/// refs. Returns `None` for refs that do not exist yet, such as the branch of a freshly
+/// initialised repository.
+pub fn resolve_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let (target, value) = follow(repo, name)?;
+
+    match value {
+        Some(hash) if repo.object_format().is_valid_hex(&hash) => Ok(Some(hash)),
+        Some(_) => Err(Error::CorruptRef(format!(
+            "ref `{target}` does not contain a valid {} object name",
+            repo.object_format()
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// The ref `name` ends up at once symbolic refs are followed, whether or not it exists yet.
+/// For `HEAD` on a branch this is the branch's full name.
+pub fn ref_target(repo: &Repository, name: &str) -> Result<String> {
+    Ok(follow(repo, name)?.0)
+}
+
+/// Follows symbolic refs from `name` and returns the last ref reached along with its raw value.
+fn follow(repo: &Repository, name: &str) -> Result<(String, Option<String>)> {
+    let mut name = name.to_owned();
+
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref(repo, &name)? {
+            Some(RefValue::Symbolic(target)) => name = target,
+            Some(RefValue::Direct(hash)) => return Ok((name, Some(hash))),
+            None => return Ok((name, None)),
+        }
+    }
+
+    Err(Error::InvalidArgument(format!(
+        "symbolic ref `{name}` nests too deeply"
+    )))
+}
+
+/// The target of the symbolic ref `name`, or `None` if it holds an object name or is missing.
+pub fn read_symbolic_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    Ok(match read_ref(repo, name)? {
+        Some(RefValue::Symbolic(target)) => Some(target),
+        _ => None,
+    })
+}
+
+/// The refs in `packed-refs` by name, with the peeled values from its `^` lines.
+pub fn packed_refs(repo: &Repository) -> Result<BTreeMap<String, Ref>> {
+    let contents = match fs::read_to_string(repo.common_dir().join("packed-refs")) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut refs = BTreeMap::<String, Ref>::new();
+    let mut last: Option<String> = None;
+
+    for line in contents.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        // A `^` line peels the annotated tag on the line above it.
+        if let Some(peeled) = line.strip_prefix('^') {
+            if let Some(entry) = last.as_ref().and_then(|name| refs.get_mut(name)) {
+                entry.peeled = Some(peeled.trim().to_owned());
+            }
+            continue;
+        }
+
+        let (hash, name) = line
+            .split_once(' ')
+            .ok_or_else(|| Error::CorruptRef(format!("unexpected line `{line}` in packed-refs")))?;
+
+        refs.insert(
+            name.to_owned(),
+            Ref {
+                name: name.to_owned(),
+                hash: hash.to_owned(),
+                peeled: None,
+            },
+        );
+        last = Some(name.to_owned());
+    }
+
+    Ok(refs)
+}
+
+/// Every ref under `refs/` whose name starts with `prefix`, sorted by name. Loose refs shadow
+/// packed ones, symbolic refs are shown with the object they resolve to, and dangling symbolic
+/// refs are skipped.
+pub fn list_refs(repo: &Repository, prefix: &str) -> Result<Vec<Ref>> {
+    let mut refs: BTreeMap<String, Ref> = packed_refs(repo)?;
+
+    let mut loose = Vec::new();
+    collect_loose(&repo.common_dir().join("refs"), "refs/", &mut loose)?;
+    if repo.git_dir() != repo.common_dir() {
+        for dir in ["bisect", "worktree", "rewritten"] {
+            collect_loose(
+                &repo.git_dir().join("refs").join(dir),
+                &format!("refs/{dir}/"),
+                &mut loose,
+            )?;
+        }
+    }
+
+    for name in loose {
+        refs.remove(&name);
+
+        if let Some(hash) = resolve_ref(repo, &name)? {
+            refs.insert(
+                name.clone(),
+                Ref {
+                    name,
+                    hash,
+                    peeled: None,
+                },
+            );
+        }
+    }
+
+    Ok(refs
+        .into_values()
+        .filter(|entry| entry.name.starts_with(prefix))
+        .collect())
+}
+
+fn collect_loose(dir: &Path, prefix: &str, names: &mut Vec<String>) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+
+        if entry.file_type()?.is_dir() {
+            collect_loose(&entry.path(), &format!("{name}/"), names)?;
+        } else if !name.ends_with(".lock") {
+            names.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Follows an annotated tag (or a chain of them) to the object it finally points at. Other
+/// objects are returned unchanged.
+pub fn peel(repo: &Repository, hash: &str) -> Result<String> {
+    let mut object = repo.read_object(hash)?;
+
+    while object.obj_type == ObjectType::Tag {
+        let target = object
+            .meta
+            .and_then(|meta| meta.object)
+            .ok_or_else(|| Error::corrupt(hash, "tag has no target"))?;
+        object = repo.read_object(&target)?;
+    }
+
+    Ok(object.hash)
+}
+
+/// Expands a short ref name the way git does, trying `name`, `refs/name`, `refs/tags/name`,
+/// `refs/heads/name`, `refs/remotes/name` and `refs/remotes/name/HEAD` in turn. Returns the full
+/// name and the object it resolves to.
+pub fn dwim_ref(repo: &Repository, name: &str) -> Result<Option<(String, String)>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    for rule in DWIM_RULES {
+        let full_name = rule.replace("{}", name);
+
+        // Only pseudo refs such as `HEAD` or `ORIG_HEAD` live outside `refs/`.
+        if !full_name.starts_with("refs/") && !is_pseudo_ref(&full_name) {
+            continue;
+        }
+
+        if !check_ref_format(&full_name) {
+            continue;
+        }
+
+        if let Some(hash) = resolve_ref(repo, &full_name)? {
+            return Ok(Some((ref_target(repo, &full_name)?, hash)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The shortest unambiguous form of a full ref name, e.g. `main` for `refs/heads/main` or
+/// `origin/main` for `refs/remotes/origin/main`.
+pub fn shorten_ref(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Whether `name` is a ref that lives directly in the git directory, like `HEAD`, `ORIG_HEAD`
+/// or `MERGE_HEAD`.
+pub fn is_pseudo_ref(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+}
+
+/// Checks `name` against git's `check-ref-format` rules.
+pub fn check_ref_format(name: &str) -> bool {
+    if name.is_empty()
+        || name == "@"
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.contains("..")
+        || name.contains("@{")
+        || name.contains("//")
+    {
+        return false;
+    }
+
+    let bad_byte = name.bytes().any(|b| {
+        b < 0x20 || b == 0x7f || matches!(b, b' ' | b'~' | b'^' | b':' | b'?' | b'*' | b'[' | b'\\')
+    });
+
+    !bad_byte
+        && name
+            .split('/')
+            .all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+/// Sets the ref `name` to `new`. With `deref`, symbolic refs are followed so that updating `HEAD`
+/// moves the checked out branch; otherwise a symbolic ref is replaced by the object name.
+///
+/// `expected` is the value the ref must have for the update to go ahead: an all-zero name means
+/// the ref must not exist yet. The update is recorded in the reflogs of the ref and, when `HEAD`
+/// follows it, of `HEAD`.
+pub fn update_ref(
+    repo: &Repository,
+    name: &str,
+    new: &str,
+    expected: Option<&str>,
+    message: &str,
+    deref: bool,
+) -> Result<()> {
+    let target = if deref {
+        ref_target(repo, name)?
+    } else {
+        name.to_owned()
+    };
+
+    check_writable_name(repo, &target, None)?;
+
+    let lock = RefLock::acquire(repo, &target)?;
+    let old = current_value(repo, &target, deref)?;
+    check_expected(repo, &target, old.as_deref(), expected)?;
+
+    lock.commit(format!("{new}\n").as_bytes())?;
+
+    let old = old.unwrap_or_else(|| zero_hash(repo));
+    append_reflog(repo, &target, &old, new, message)?;
+
+    if target != "HEAD" && ref_target(repo, "HEAD")? == target {
+        append_reflog(repo, "HEAD", &old, new, message)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the ref `name`, both its loose file and any `packed-refs` entry, along with its reflog.
+/// `expected` works as in [`update_ref`].
+pub fn delete_ref(
+    repo: &Repository,
+    name: &str,
+    expected: Option<&str>,
+    deref: bool,
+) -> Result<()> {
+    let target = if deref {
+        ref_target(repo, name)?
+    } else {
+        name.to_owned()
+    };
+
+    let lock = RefLock::acquire(repo, &target)?;
+    let old = current_value(repo, &target, deref)?;
+    check_expected(repo, &target, old.as_deref(), expected)?;
+
+    if old.is_none() && read_ref(repo, &target)?.is_none() {
+        return Err(Error::InvalidArgument(format!(
+            "cannot delete ref '{target}': it does not exist"
+        )));
+    }
+
+    remove_packed_ref(repo, &target)?;
+
+    let path = repo.ref_path(&target);
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    lock.release();
+
+    let log = repo.reflog_path(&target);
+    match fs::remove_file(&log) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+
+    remove_empty_parents(repo, &path);
+    remove_empty_parents(repo, &log);
+
+    Ok(())
+}
+
+/// Renames the ref `old` to `new`, which must neither exist nor clash with other refs, keeping its
+/// reflog and recording the rename there with `message`. Nothing changes if `new` is taken. If
+/// `HEAD` was on `old` it follows the ref to `new`, and its reflog gets the pair of entries git
+/// writes for the ref disappearing and coming back.
+pub fn rename_ref(repo: &Repository, old: &str, new: &str, message: &str) -> Result<()> {
+    let hash = current_value(repo, old, true)?.ok_or_else(|| {
+        Error::InvalidArgument(format!("cannot rename ref '{old}': it does not exist"))
+    })?;
+    let head_follows = read_symbolic_ref(repo, "HEAD")?.as_deref() == Some(old);
+
+    if read_ref(repo, new)?.is_some() {
+        return Err(Error::InvalidArgument(format!(
+            "cannot rename ref '{old}': '{new}' already exists"
+        )));
+    }
+
+    // Nothing is deleted until `new` is known to be free; `old` itself is no obstacle since it
+    // goes away first.
+    check_writable_name(repo, new, Some(old))?;
+
+    let old_log = repo.reflog_path(old);
+    let history = match fs::read(&old_log) {
+        Ok(history) => Some(history),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    delete_ref(repo, old, Some(&hash), false)?;
+
+    if head_follows {
+        append_reflog(repo, "HEAD", &hash, &zero_hash(repo), message)?;
+    }
+
+    if let Some(history) = history {
+        let new_log = repo.reflog_path(new);
+
+        if let Some(parent) = new_log.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(new_log, history)?;
+    }
+
+    RefLock::acquire(repo, new)?.commit(format!("{hash}\n").as_bytes())?;
+    append_reflog(repo, new, &hash, &hash, message)?;
+
+    if head_follows {
+        RefLock::acquire(repo, "HEAD")?.commit(format!("ref: {new}\n").as_bytes())?;
+        append_reflog(repo, "HEAD", &zero_hash(repo), &hash, message)?;
+    }
+
+    Ok(())
+}
+
+/// Points the symbolic ref `name` at the ref `target`. With a `message`, the move is recorded in
+/// the reflog of `name`.
+pub fn set_symbolic_ref(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    message: Option<&str>,
+) -> Result<()> {
+    if name == "HEAD" && !target.starts_with("refs/") {
+        return Err(Error::InvalidArgument(format!(
+            "Refusing to point HEAD outside of refs/: `{target}`"
+        )));
+    }
+
+    if !check_ref_format(target) {
+        return Err(Error::InvalidArgument(format!(
+            "Refusing to set `{name}` to the invalid ref name `{target}`"
+        )));
+    }
+
+    check_writable_name(repo, name, None)?;
+
+    let lock = RefLock::acquire(repo, name)?;
+    let old = resolve_ref(repo, name)?;
+    lock.commit(format!("ref: {target}\n").as_bytes())?;
+
+    if let Some(message) = message {
+        let new = resolve_ref(repo, target)?;
+
+        if let Some(new) = new.or_else(|| old.clone()) {
+            append_reflog(
+                repo,
+                name,
+                &old.unwrap_or_else(|| zero_hash(repo)),
+                &new,
+                message,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes the symbolic ref `name` itself, leaving the ref it points at alone.
+pub fn delete_symbolic_ref(repo: &Repository, name: &str) -> Result<()> {
+    if name == "HEAD" {
+        return Err(Error::InvalidArgument(
+            "deleting HEAD is not allowed".to_owned(),
+        ));
+    }
+
+    if read_symbolic_ref(repo, name)?.is_none() {
+        return Err(Error::InvalidArgument(format!(
+            "ref `{name}` is not a symbolic ref"
+        )));
+    }
+
+    let lock = RefLock::acquire(repo, name)?;
+    fs::remove_file(repo.ref_path(name))?;
+    lock.release();
+
+    let log = repo.reflog_path(name);
+    match fs::remove_file(&log) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn zero_hash(repo: &Repository) -> String {
+    "0".repeat(repo.object_format().hex_len())
+}
+
+/// The object name `target` currently holds. Without `deref` a symbolic ref counts as holding
+/// the object it resolves to, as git compares old values after peeling symbolic refs.
+fn current_value(repo: &Repository, target: &str, deref: bool) -> Result<Option<String>> {
+    match (deref, read_ref(repo, target)?) {
+        (_, Some(RefValue::Direct(hash))) => Ok(Some(hash)),
+        (false, Some(RefValue::Symbolic(_))) => resolve_ref(repo, target),
+        _ => Ok(None),
+    }
+}
+
+fn check_expected(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    expected: Option<&str>,
+) -> Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+
+    let expected_missing = expected.is_empty() || expected == zero_hash(repo);
+
+    match old {
+        Some(_) if expected_missing => Err(Error::InvalidArgument(format!(
+            "cannot lock ref '{name}': reference already exists"
+        ))),
+        Some(old) if old != expected => Err(Error::InvalidArgument(format!(
+            "cannot lock ref '{name}': is at {old} but expected {expected}"
+        ))),
+        None if !expected_missing => Err(Error::InvalidArgument(format!(
+            "cannot lock ref '{name}': unable to resolve reference '{name}'"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Refuses names that are malformed or that clash with an existing ref as a file and a directory,
+/// e.g. creating `refs/heads/a/b` while `refs/heads/a` exists. A clash with `ignoring`, the ref
+/// about to be renamed away, does not count.
+pub fn check_writable_name(repo: &Repository, name: &str, ignoring: Option<&str>) -> Result<()> {
+    if !(name.starts_with("refs/") || is_pseudo_ref(name)) || !check_ref_format(name) {
+        return Err(Error::InvalidArgument(format!(
+            "`{name}` is not a valid ref name"
+        )));
+    }
+
+    let packed = packed_refs(repo)?;
+    let mut parent = String::new();
+
+    for component in name.split('/').take(name.split('/').count() - 1) {
+        parent += component;
+
+        let exists = repo.ref_path(&parent).is_file() || packed.contains_key(&parent);
+        if exists && ignoring != Some(parent.as_str()) {
+            return Err(Error::InvalidArgument(format!(
+                "cannot lock ref '{name}': '{parent}' exists; cannot create '{name}'"
+            )));
+        }
+
+        parent.push('/');
+    }
+
+    let children = format!("{name}/");
+    let mut below = Vec::new();
+    if repo.ref_path(name).is_dir() {
+        collect_loose(&repo.ref_path(name), &children, &mut below)?;
+    }
+    below.extend(
+        packed
+            .into_keys()
+            .filter(|packed| packed.starts_with(&children)),
+    );
+
+    if below.iter().any(|child| ignoring != Some(child.as_str())) {
+        return Err(Error::InvalidArgument(format!(
+            "cannot lock ref '{name}': there are refs below '{name}/'"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Drops `name` from `packed-refs`, rewriting the file through `packed-refs.lock`.
+fn remove_packed_ref(repo: &Repository, name: &str) -> Result<()> {
+    let path = repo.common_dir().join("packed-refs");
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut kept = String::new();
+    let mut removed = false;
+    let mut skipping = false;
+
+    for line in contents.lines() {
+        if line.starts_with('^') {
+            if !skipping {
+                kept += line;
+                kept.push('\n');
+            }
+            continue;
+        }
+
+        skipping = !line.starts_with('#')
+            && line.split_once(' ').map(|(_, ref_name)| ref_name) == Some(name);
+
+        if skipping {
+            removed = true;
+        } else {
+            kept += line;
+            kept.push('\n');
+        }
+    }
+
+    if !removed {
+        return Ok(());
+    }
+
+    let lock = lock_path(&path);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .map_err(|err| lock_error(&lock, err))?;
+    file.write_all(kept.as_bytes())?;
+    fs::rename(&lock, &path)?;
+
+    Ok(())
+}
+
+/// Removes the directories above `path` that were left empty, stopping below `refs/<kind>` and
+/// `logs/refs/<kind>` so that the standard layout stays in place.
+fn remove_empty_parents(repo: &Repository, path: &Path) {
+    let keep_depth = |dir: &Path| {
+        // A linked worktree's git directory is inside the common one, so try it first.
+        let Ok(relative) = dir
+            .strip_prefix(repo.git_dir())
+            .or_else(|_| dir.strip_prefix(repo.common_dir()))
+        else {
+            return true;
+        };
+        let depth = relative.components().count();
+
+        match relative.starts_with("logs") {
+            true => depth <= 3,
+            false => depth <= 2,
+        }
+    };
+
+    let mut dir = path.parent();
+
+    while let Some(current) = dir {
+        if keep_depth(current) || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Adds a line to `logs/<name>`. As with `core.logAllRefUpdates`' default, bare repositories
+/// only log refs that already have a reflog.
+pub fn append_reflog(
+    repo: &Repository,
+    name: &str,
+    old: &str,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    let path = repo.reflog_path(name);
+    let logged_by_default = name == "HEAD"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+    let enabled = match repo.config().get("core.logallrefupdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => true,
+        Some(value) => parse_bool(&value) && logged_by_default,
+        None => repo.work_tree().is_some() && logged_by_default,
+    };
+
+    if !enabled && !path.exists() {
+        return Ok(());
+    }
+
+    let committer = identity(repo, Role::Committer)?;
+    let message = message.lines().next().unwrap_or_default();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+
+    if message.is_empty() {
+        writeln!(log, "{old} {new} {committer}")?;
+    } else {
+        writeln!(log, "{old} {new} {committer}\t{message}")?;
+    }
+
+    Ok(())
+}
+
+/// One line of a reflog: a ref moving from `old` to `new`.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub committer: User,
+    pub message: String,
+}
+
+/// The reflog of `name`, newest entry first. Refs without a reflog have an empty one.
+pub fn read_reflog(repo: &Repository, name: &str) -> Result<Vec<ReflogEntry>> {
+    let contents = match fs::read_to_string(repo.reflog_path(name)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut entries = Vec::new();
+
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+
+        let (Some(old), Some(new), Some(committer)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(Error::CorruptRef(format!(
+                "unexpected line `{line}` in the reflog of `{name}`"
+            )));
+        };
+
+        entries.push(ReflogEntry {
+            old: old.to_owned(),
+            new: new.to_owned(),
+            committer: User::parse(committer)?,
+            message: message.to_owned(),
+        });
+    }
+
+    entries.reverse();
+
+    Ok(entries)
+}
+
+/// Removes the entry `name@{n}` from the reflog of `name`, like `git reflog delete --rewrite
+/// --updateref`: the next newer entry is rewritten to start where the removed one did, and when
+/// the newest entry goes the ref moves back to the one before it. A ref whose last entry is
+/// removed is deleted.
+pub fn delete_reflog_entry(repo: &Repository, name: &str, n: usize) -> Result<()> {
+    let mut entries = read_reflog(repo, name)?;
+
+    if n >= entries.len() {
+        return Err(Error::InvalidArgument(format!(
+            "log for `{name}` only has {} entries",
+            entries.len()
+        )));
+    }
+
+    let removed = entries.remove(n);
+    if let Some(newer) = n.checked_sub(1) {
+        entries[newer].old = removed.old;
+    }
+
+    let Some(newest) = entries.first() else {
+        return delete_ref(repo, name, None, false);
+    };
+
+    let lock = RefLock::acquire(repo, name)?;
+    let mut log = String::new();
+
+    for entry in entries.iter().rev() {
+        log += &format!("{} {} {}", entry.old, entry.new, entry.committer);
+        if !entry.message.is_empty() {
+            log += &format!("\t{}", entry.message);
+        }
+        log.push('\n');
+    }
+
+    fs::write(repo.reflog_path(name), log)?;
+
+    if n == 0 {
+        lock.commit(format!("{}\n", newest.new).as_bytes())
+    } else {
+        lock.release();
+        Ok(())
+    }
+}
+
+/// The `.lock` file a ref, `packed-refs` or the index is written to before being renamed into
+/// place.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+fn lock_error(lock: &Path, err: io::Error) -> Error {
+    if err.kind() == io::ErrorKind::AlreadyExists {
+        Error::InvalidArgument(format!(
+            "Unable to create '{}': File exists. Another pgit or git process seems to be running in this repository; if not, remove the file and try again",
+            lock.display()
+        ))
+    } else {
+        err.into()
+    }
+}
+
+/// An exclusively created `<ref>.lock`. Dropping it without committing removes it again, so a
+/// failed update leaves the ref untouched.
+pub(crate) struct RefLock {
+    path: PathBuf,
+    lock: PathBuf,
+    done: bool,
+}
+
+impl RefLock {
+    fn acquire(repo: &Repository, name: &str) -> Result<Self> {
+        let path = repo.ref_path(name);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Self::acquire_file(path)
+    }
+
+    /// Locks any file that is replaced through a `.lock` file, such as the index.
+    pub(crate) fn acquire_file(path: PathBuf) -> Result<Self> {
+        let lock = lock_path(&path);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+            .map_err(|err| lock_error(&lock, err))?;
+
+        Ok(Self {
+            path,
+            lock,
+            done: false,
+        })
+    }
+
+    /// Writes `contents` to the lock file and renames it over the locked file.
+    pub(crate) fn commit(mut self, contents: &[u8]) -> Result<()> {
+        fs::write(&self.lock, contents)?;
+        fs::rename(&self.lock, &self.path)?;
+        self.done = true;
+
+        Ok(())
+    }
+
+    fn release(self) {}
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestRepo;
+
+    fn messages(repo: &Repository, name: &str) -> Vec<String> {
+        read_reflog(repo, name)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect()
+    }
+
+    #[test]
+    fn renaming_onto_a_directory_of_refs_changes_nothing() {
+        let test = TestRepo::new("refs-rename-clash");
+        let commit = test.commit(&[("a", "a\n")], &[], "first");
+        test.set_ref("refs/heads/x", &commit, "branch: Created from main");
+        test.set_ref("refs/heads/y/z", &commit, "branch: Created from main");
+
+        let result = rename_ref(&test.repo, "refs/heads/x", "refs/heads/y", "renamed");
+        assert!(matches!(
+            result,
+            Err(Error::InvalidArgument(message)) if message.contains("there are refs below 'refs/heads/y/'")
+        ));
+        assert_eq!(
+            resolve_ref(&test.repo, "refs/heads/x").unwrap().as_deref(),
+            Some(commit.as_str())
+        );
+        assert_eq!(
+            messages(&test.repo, "refs/heads/x"),
+            ["branch: Created from main"]
+        );
+
+        test.set_ref("refs/heads/w", &commit, "branch: Created from main");
+        assert!(rename_ref(&test.repo, "refs/heads/w", "refs/heads/x/w", "renamed").is_err());
+        assert!(resolve_ref(&test.repo, "refs/heads/w").unwrap().is_some());
+    }
+
+    #[test]
+    fn refs_can_be_renamed_into_and_out_of_their_own_directory() {
+        let test = TestRepo::new("refs-rename-nested");
+        let commit = test.commit(&[("a", "a\n")], &[], "first");
+        test.set_ref("refs/heads/x", &commit, "branch: Created from main");
+
+        rename_ref(&test.repo, "refs/heads/x", "refs/heads/x/y", "down").unwrap();
+        assert!(resolve_ref(&test.repo, "refs/heads/x").unwrap().is_none());
+        rename_ref(&test.repo, "refs/heads/x/y", "refs/heads/x", "up").unwrap();
+
+        assert_eq!(
+            resolve_ref(&test.repo, "refs/heads/x").unwrap().as_deref(),
+            Some(commit.as_str())
+        );
+        assert_eq!(
+            messages(&test.repo, "refs/heads/x"),
+            ["up", "down", "branch: Created from main"]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    cell::OnceCell,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{get_merged_config, parse_bool, Config},
+    error::{Error, Result},
+    pack::{has_packed_object, load_pack_indexes, PackIndex},
+    refs,
+    structures::{read_raw_object, write_object, Object, ObjectType},
+    utils::HashAlgo,
+};
+
+/// An opened git repository. Every object, ref and config lookup goes through one of these rather
+/// than assuming the repository lives at `cwd/.git`.
+#[derive(Debug)]
+pub struct Repository {
+    git_dir: PathBuf,
+    /// Where the objects, shared refs and config live: `git_dir` itself, except in a linked
+    /// worktree whose `commondir` file points back at the main repository's git directory.
+    common_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    config: Config,
+    object_format: HashAlgo,
+    /// The pack indexes, read the first time an object is looked up in the packs.
+    packs: OnceCell<Vec<PackIndex>>,
+}
+
+impl Repository {
+    /// Opens the repository at `path`, which may be either a work tree containing `.git` or the
+    /// git directory of a bare repository.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        match find_git_dir(path)? {
+            Some((git_dir, work_tree)) => Self::from_parts(git_dir, work_tree),
+            None => Err(Error::NotARepository(path.to_path_buf())),
+        }
+    }
+
+    /// Finds the repository containing `start` the way git does: `GIT_DIR` and `GIT_WORK_TREE`
+    /// win if set, otherwise each parent directory is checked for a `.git` directory, a
+    /// `gitdir:` file or a bare repository layout. The walk stops at `GIT_CEILING_DIRECTORIES`
+    /// and, unless `GIT_DISCOVERY_ACROSS_FILESYSTEM` is set, at filesystem boundaries.
+    pub fn discover(start: impl AsRef<Path>) -> Result<Self> {
+        let start = start.as_ref();
+        let cwd = env::current_dir()?;
+        let start = if start.is_absolute() {
+            start.to_path_buf()
+        } else {
+            cwd.join(start)
+        };
+        let start = start.canonicalize().unwrap_or(start);
+
+        if let Some(git_dir) = env::var_os("GIT_DIR").filter(|dir| !dir.is_empty()) {
+            let git_dir = cwd.join(git_dir);
+
+            // Without GIT_WORK_TREE, git treats the current directory as the top of the work tree.
+            let work_tree = match env::var_os("GIT_WORK_TREE").filter(|dir| !dir.is_empty()) {
+                Some(work_tree) => Some(cwd.join(work_tree)),
+                None => Some(cwd),
+            };
+
+            return Self::from_parts(git_dir, work_tree);
+        }
+
+        let ceilings: Vec<PathBuf> = ceiling_directories()
+            .into_iter()
+            .filter(|ceiling| start.starts_with(ceiling) && start != *ceiling)
+            .collect();
+        let across_filesystems = env::var("GIT_DISCOVERY_ACROSS_FILESYSTEM")
+            .map(|value| parse_bool(&value))
+            .unwrap_or(false);
+        let start_device = device_of(&start);
+
+        let mut dir = start.as_path();
+
+        loop {
+            if let Some((git_dir, work_tree)) = find_git_dir(dir)? {
+                let mut repo = Self::from_parts(git_dir, work_tree)?;
+
+                if let Some(work_tree) = env::var_os("GIT_WORK_TREE").filter(|dir| !dir.is_empty())
+                {
+                    repo.work_tree = Some(cwd.join(work_tree));
+                }
+
+                return Ok(repo);
+            }
+
+            let parent = match dir.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+
+            // A ceiling directory is never searched itself, and neither is anything above it.
+            if ceilings.iter().any(|ceiling| ceiling.starts_with(parent)) {
+                break;
+            }
+
+            if !across_filesystems && device_of(parent) != start_device {
+                break;
+            }
+
+            dir = parent;
+        }
+
+        Err(Error::NotARepository(start))
+    }
+
+    /// Opens a repository whose git directory and work tree are already known. `core.bare` and
+    /// `core.worktree` in the repository's config override the given work tree.
+    pub fn from_parts(git_dir: PathBuf, mut work_tree: Option<PathBuf>) -> Result<Self> {
+        if !is_git_dir(&git_dir) {
+            return Err(Error::NotARepository(git_dir));
+        }
+
+        let config = get_merged_config(Some(&git_dir))?;
+
+        if let Some(configured) = config.get("core.worktree") {
+            work_tree = Some(git_dir.join(configured));
+        } else if config.get_bool("core.bare").unwrap_or(false) {
+            work_tree = None;
+        }
+
+        let object_format = match config.get("extensions.objectformat") {
+            Some(format) => HashAlgo::from_name(&format).ok_or_else(|| Error::ConfigParse {
+                path: git_dir.join("config"),
+                reason: format!("unknown object format `{format}`"),
+            })?,
+            None => HashAlgo::Sha1,
+        };
+
+        Ok(Self {
+            common_dir: common_dir(&git_dir),
+            git_dir,
+            work_tree,
+            config,
+            object_format,
+            packs: OnceCell::new(),
+        })
+    }
+
+    /// The git directory of this work tree, which holds `HEAD`, the index and the state of any
+    /// operation in progress.
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// The git directory shared by all worktrees of the repository. See [`common_dir`].
+    pub fn common_dir(&self) -> &Path {
+        &self.common_dir
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.common_dir.join("config")
+    }
+
+    /// The loose file of the ref `name`. Like git, `HEAD`, the other refs outside `refs/` and
+    /// those under `refs/bisect/`, `refs/worktree/` and `refs/rewritten/` belong to each
+    /// worktree; all other refs are shared.
+    pub fn ref_path(&self, name: &str) -> PathBuf {
+        self.ref_base(name).join(name)
+    }
+
+    /// The reflog of the ref `name`, kept next to the ref in the same git directory.
+    pub fn reflog_path(&self, name: &str) -> PathBuf {
+        self.ref_base(name).join("logs").join(name)
+    }
+
+    fn ref_base(&self, name: &str) -> &Path {
+        let per_worktree = !name.starts_with("refs/")
+            || ["refs/bisect/", "refs/worktree/", "refs/rewritten/"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix));
+
+        if per_worktree {
+            &self.git_dir
+        } else {
+            &self.common_dir
+        }
+    }
+
+    /// The checked out files, or `None` for a bare repository.
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn object_format(&self) -> &HashAlgo {
+        &self.object_format
+    }
+
+    pub fn objects_dir(&self) -> PathBuf {
+        self.common_dir.join("objects")
+    }
+
+    /// The indexes of the packs under `objects/pack`, read once and kept for the repository's
+    /// lifetime.
+    pub fn pack_indexes(&self) -> &[PackIndex] {
+        self.packs.get_or_init(|| load_pack_indexes(self))
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.git_dir.join("index")
+    }
+
+    /// The work tree, or an error for commands that cannot run in a bare repository.
+    pub fn require_work_tree(&self) -> Result<&Path> {
+        self.work_tree().ok_or_else(|| {
+            Error::InvalidArgument("this operation must be run in a work tree".to_owned())
+        })
+    }
+
+    pub fn read_object(&self, hash: &str) -> Result<Object> {
+        Object::new(self, hash.to_owned())
+    }
+
+    /// Whether the object exists, loose or packed, without reading it.
+    pub fn has_object(&self, hash: &str) -> Result<bool> {
+        if !self.object_format.is_valid_hex(hash) {
+            return Ok(false);
+        }
+
+        if self
+            .objects_dir()
+            .join(&hash[..2])
+            .join(&hash[2..])
+            .is_file()
+        {
+            return Ok(true);
+        }
+
+        has_packed_object(self, hash)
+    }
+
+    /// Reads the contents of a blob, failing if `hash` names any other kind of object.
+    pub fn read_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        match read_raw_object(self, hash)? {
+            (ObjectType::Blob, body) => Ok(body),
+            (obj_type, _) => Err(Error::InvalidArgument(format!(
+                "`{hash}` is a {obj_type}, not a blob"
+            ))),
+        }
+    }
+
+    /// Computes the name `body` would have as an object of `obj_type`, without storing it.
+    pub fn hash_object(&self, obj_type: &ObjectType, body: &[u8]) -> Result<String> {
+        write_object(self, obj_type, body, false)
+    }
+
+    /// Stores `body` as a loose object and returns its name.
+    pub fn write_object(&self, obj_type: &ObjectType, body: &[u8]) -> Result<String> {
+        write_object(self, obj_type, body, true)
+    }
+
+    /// Resolves `name` (e.g. `HEAD` or `refs/heads/main`) to an object name, following symbolic
+    /// refs. See [`refs::resolve_ref`].
+    pub fn resolve_ref(&self, name: &str) -> Result<Option<String>> {
+        refs::resolve_ref(self, name)
+    }
+
+    pub fn head(&self) -> Result<Option<String>> {
+        self.resolve_ref("HEAD")
+    }
+}
+
+/// Checks a single directory for a repository, returning its git directory and work tree.
+fn find_git_dir(dir: &Path) -> Result<Option<(PathBuf, Option<PathBuf>)>> {
+    let dot_git = dir.join(".git");
+
+    if dot_git.is_dir() && is_git_dir(&dot_git) {
+        return Ok(Some((dot_git, Some(dir.to_path_buf()))));
+    }
+
+    // Worktrees and submodules use a `.git` file pointing at the real git directory.
+    if dot_git.is_file() {
+        let contents = fs::read_to_string(&dot_git)?;
+        let target = contents
+            .trim()
+            .strip_prefix("gitdir:")
+            .ok_or_else(|| Error::NotARepository(dot_git.clone()))?
+            .trim();
+
+        return Ok(Some((dir.join(target), Some(dir.to_path_buf()))));
+    }
+
+    if is_git_dir(dir) {
+        return Ok(Some((dir.to_path_buf(), None)));
+    }
+
+    Ok(None)
+}
+
+fn ceiling_directories() -> Vec<PathBuf> {
+    let separator = if cfg!(windows) { ';' } else { ':' };
+
+    env::var("GIT_CEILING_DIRECTORIES")
+        .unwrap_or_default()
+        .split(separator)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.canonicalize().unwrap_or(dir))
+        .collect()
+}
+
+#[cfg(unix)]
+fn device_of(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Whether `path` has the `HEAD`, `objects` and `refs` layout of a git directory. A linked
+/// worktree's git directory only has its own `HEAD`; the rest is in its common directory.
+pub fn is_git_dir(path: &Path) -> bool {
+    let common_dir = common_dir(path);
+
+    path.join("HEAD").is_file()
+        && common_dir.join("objects").is_dir()
+        && common_dir.join("refs").is_dir()
+}
+
+/// The common directory of the git directory `git_dir`: the one its `commondir` file names,
+/// relative to `git_dir` unless absolute, or `git_dir` itself when there is no such file.
+pub fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(contents) if !contents.trim_end_matches(['\n', '\r']).is_empty() => {
+            git_dir.join(contents.trim_end_matches(['\n', '\r']))
+        }
+        _ => git_dir.to_path_buf(),
+    }
+}
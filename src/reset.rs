@@ -0,0 +1,742 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use termimad::{
+    crossterm::style::Color::{Green, Red, Yellow},
+    MadSkin,
+};
+
+use crate::{
+    checkout::{
+        checkout_worktree, local_changes_error, reset_hard, reset_index, reset_merge, switch_trees,
+        tree_files, TreeUpdate,
+    },
+    commit::remove_merge_state,
+    diff_command::{worktree_version, Contents},
+    error::{Error, Result},
+    index::Index,
+    log::subject,
+    pathspec::Pathspec,
+    refs::{ref_target, update_ref},
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, peel_to, resolve_commit, resolve_revision},
+    status::{print_section, tree_file, Change},
+    structures::ObjectType,
+    tree::{commit_tree, flatten_tree, TreeFile},
+    utils::quote_path,
+    worktree::{cwd_prefix, relative_path},
+};
+
+/// How much of the repository `pgit reset` makes match the commit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ResetMode {
+    /// Only `HEAD` moves.
+    Soft,
+    /// `HEAD` and the index.
+    #[default]
+    Mixed,
+    /// `HEAD`, the index and the work tree.
+    Hard,
+    /// `HEAD`, and the files that differ between the two commits as long as they have no local
+    /// changes.
+    Keep,
+    /// `HEAD`, and the staged files, keeping unstaged changes; for backing out of a merge.
+    Merge,
+}
+
+impl ResetMode {
+    fn flag(&self) -> &'static str {
+        match self {
+            Self::Soft => "--soft",
+            Self::Mixed => "--mixed",
+            Self::Hard => "--hard",
+            Self::Keep => "--keep",
+            Self::Merge => "--merge",
+        }
+    }
+
+    fn explanation(&self) -> &'static str {
+        match self {
+            Self::Soft => "moves `HEAD` only. The index and working tree are left as they are, so everything that differs from the commit shows up as staged.",
+            Self::Mixed => "moves `HEAD` and makes the index match the commit. The working tree is left as it is, so your changes are still there, unstaged.",
+            Self::Hard => "makes `HEAD`, the index and the working tree all match the commit. Changes to tracked files are thrown away; untracked files are left alone.",
+            Self::Keep => "moves `HEAD` and updates the files that differ between the two commits. Your local changes to other files are kept, unstaged.",
+            Self::Merge => "moves `HEAD` and replaces every staged file with the commit's version, keeping changes that were never staged. It is the way to back out of a merge.",
+        }
+    }
+}
+
+/// Where `pgit restore` copies files to, and from where.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// The tree-ish to take files from: the index by default, or `HEAD` with `staged`.
+    pub source: Option<String>,
+    pub staged: bool,
+    /// Restore the work tree; the default when `staged` is not given either.
+    pub worktree: bool,
+}
+
+/// What is about to happen to one path in the index or the work tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathChange {
+    pub path: Vec<u8>,
+    pub change: Change,
+    /// The path was conflicted, which the change settles.
+    pub conflicted: bool,
+}
+
+/// Which command carries out a plan.
+#[derive(Debug, Clone)]
+enum Action {
+    Reset {
+        mode: ResetMode,
+        /// The target commit and the revision it was named with, or `None` on an unborn branch.
+        commit: Option<(String, String)>,
+        paths: bool,
+    },
+    Restore {
+        staged: bool,
+        worktree: bool,
+    },
+}
+
+/// What `pgit reset` or `pgit restore` is about to do to `HEAD`, the index and the work tree,
+/// worked out before anything is touched so it can be shown first, and confirmed when it would
+/// throw work away.
+#[derive(Debug, Clone)]
+pub struct ResetPlan {
+    action: Action,
+    pathspec: Pathspec,
+    /// The tree the index and work tree are made to match.
+    tree: Option<String>,
+    /// The commit `HEAD` is on, and the one it moves to.
+    pub head: Option<(Option<String>, String)>,
+    pub index: Vec<PathChange>,
+    pub worktree: Vec<PathChange>,
+    /// Paths whose changes are in no commit and would be gone for good, not even left in the
+    /// index or work tree.
+    pub lost: Vec<Vec<u8>>,
+    /// The files a work tree only restore writes, `None` meaning the file goes away.
+    worktree_files: Vec<(Vec<u8>, Option<TreeFile>)>,
+    title: String,
+    explanation: String,
+}
+
+/// A path's versions before and after the plan, `None` meaning it is not there.
+struct PathState {
+    head: Option<TreeFile>,
+    index: Option<TreeFile>,
+    worktree: Option<TreeFile>,
+    conflicted: bool,
+}
+
+/// Works out what `pgit reset` would do. `args` are the commit (`HEAD` by default) and, like
+/// git, paths if it does not name one; `paths` are the ones given after `--`. With paths only
+/// the index entries of those paths are reset, and `HEAD` stays where it is.
+pub fn plan_reset(
+    repo: &Repository,
+    mode: ResetMode,
+    args: &[String],
+    paths: &[String],
+) -> Result<ResetPlan> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+
+    let (spec, paths) = match (paths.is_empty(), args) {
+        (false, []) => (None, paths.to_vec()),
+        (false, [spec]) => (Some(spec.clone()), paths.to_vec()),
+        (false, _) => {
+            return Err(Error::InvalidArgument(
+                "Only one commit is expected before `--`".to_owned(),
+            ))
+        }
+        (true, [first, rest @ ..]) if resolve_revision(repo, first).is_ok() => {
+            (Some(first.clone()), rest.to_vec())
+        }
+        (true, _) => {
+            // Like git, paths without `--` must exist, so a mistyped revision isn't taken for one.
+            if let Some(arg) = args.iter().find(|arg| !Path::new(arg).exists()) {
+                return Err(Error::InvalidArgument(format!(
+                    "`{arg}` is neither a commit nor a path in the working tree. Use `--` to separate paths from the commit"
+                )));
+            }
+
+            (None, args.to_vec())
+        }
+    };
+
+    if !paths.is_empty() && mode != ResetMode::Mixed {
+        return Err(Error::InvalidArgument(format!(
+            "Cannot do {} reset with paths: only the index can be reset for some paths. Use `pgit restore` for the working tree",
+            &mode.flag()[2..]
+        )));
+    }
+
+    if matches!(mode, ResetMode::Soft | ResetMode::Keep)
+        && repo.git_dir().join("MERGE_HEAD").exists()
+    {
+        return Err(Error::InvalidArgument(format!(
+            "Cannot do a {} reset in the middle of a merge: the merge would be forgotten but its changes kept. Use `--merge` to back out of it",
+            &mode.flag()[2..]
+        )));
+    }
+
+    let head = repo.head()?;
+    let commit = match (&spec, &head) {
+        (Some(spec), _) => Some((resolve_commit(repo, spec)?, spec.clone())),
+        (None, Some(head)) => Some((head.clone(), "HEAD".to_owned())),
+        (None, None) => None,
+    };
+    let tree = commit
+        .as_ref()
+        .map(|(commit, _)| commit_tree(repo, commit))
+        .transpose()?;
+    let pathspec = Pathspec::new(&work_tree, &cwd_prefix(repo)?, &paths)?;
+
+    let index = Index::read(repo)?;
+    let head_tree = head
+        .as_ref()
+        .map(|head| commit_tree(repo, head))
+        .transpose()?;
+    let head_files = tree_files(repo, head_tree.as_deref())?;
+    let target = tree_files(repo, tree.as_deref())?;
+    let candidates: BTreeSet<Vec<u8>> = if mode == ResetMode::Soft {
+        BTreeSet::new()
+    } else {
+        target
+            .keys()
+            .chain(index.entries.iter().map(|entry| &entry.path))
+            .filter(|path| pathspec.matches(path))
+            .cloned()
+            .collect()
+    };
+
+    if mode == ResetMode::Keep && index.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "You need to resolve your current index first".to_owned(),
+        ));
+    }
+
+    let mut plan = Plan::default();
+    let mut refused = Vec::new();
+
+    for path in candidates {
+        let state = path_state(repo, &index, &head_files, &path)?;
+        let target = target.get(&path).cloned();
+
+        let after = match mode {
+            ResetMode::Soft => unreachable!("a soft reset leaves every path alone"),
+            ResetMode::Mixed => (target, state.worktree.clone()),
+            ResetMode::Hard => (target.clone(), target),
+            ResetMode::Keep if state.head != target => {
+                if state.index != state.head || state.worktree != state.head {
+                    refused.push(path.clone());
+                }
+                (target.clone(), target)
+            }
+            ResetMode::Keep => (target, state.worktree.clone()),
+            ResetMode::Merge if !state.conflicted && state.index == target => {
+                (state.index.clone(), state.worktree.clone())
+            }
+            ResetMode::Merge => {
+                if !state.conflicted && state.index.is_some() && state.worktree != state.index {
+                    let path = String::from_utf8_lossy(&path);
+
+                    // Like git, a path that is only changed in the work tree cannot be merged.
+                    return Err(Error::InvalidArgument(if state.index == state.head {
+                        format!("Entry '{path}' not uptodate. Cannot merge.")
+                    } else {
+                        format!(
+                            "`{path}` has both staged and unstaged changes, which `--merge` cannot keep apart. Commit or stash them first, or use `--hard` to throw them away"
+                        )
+                    }));
+                }
+                (target.clone(), target)
+            }
+        };
+
+        plan.add(path, &state, after);
+    }
+
+    if !refused.is_empty() {
+        return Err(local_changes_error(&refused, TreeUpdate::Reset));
+    }
+
+    let short = |hash: &str| abbreviate(repo, hash, default_abbrev(repo));
+    let name = match ref_target(repo, "HEAD")?.strip_prefix("refs/heads/") {
+        Some(branch) => format!("`{branch}`"),
+        None => "`HEAD`".to_owned(),
+    };
+    let title = match (&commit, paths.is_empty()) {
+        (Some((commit, spec)), true) => {
+            format!("Resetting {name} to `{}` ({spec})", short(commit)?)
+        }
+        (Some((commit, _)), false) => {
+            format!("Unstaging the selected paths to match `{}`", short(commit)?)
+        }
+        (None, _) => format!("Unstaging everything on {name}, which has no commits yet"),
+    };
+    let explanation = if paths.is_empty() {
+        format!("`{}` {}", mode.flag(), mode.explanation())
+    } else {
+        "With paths, only their index entries are reset: `HEAD` and the working tree stay as they are.".to_owned()
+    };
+
+    Ok(ResetPlan {
+        head: match (&commit, paths.is_empty()) {
+            (Some((commit, _)), true) => Some((head, commit.clone())),
+            _ => None,
+        },
+        action: Action::Reset {
+            mode,
+            commit,
+            paths: !paths.is_empty(),
+        },
+        pathspec,
+        tree,
+        index: plan.index,
+        worktree: plan.worktree,
+        lost: plan.lost,
+        worktree_files: plan.worktree_files,
+        title,
+        explanation,
+    })
+}
+
+/// Works out what `pgit restore` would do to the paths `args` selects.
+pub fn plan_restore(
+    repo: &Repository,
+    options: &RestoreOptions,
+    args: &[String],
+) -> Result<ResetPlan> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+
+    if args.is_empty() {
+        return Err(Error::InvalidArgument(
+            "You must specify the paths to restore; use `.` for all of them".to_owned(),
+        ));
+    }
+
+    let staged = options.staged;
+    let worktree = options.worktree || !options.staged;
+    let pathspec = Pathspec::new(&work_tree, &cwd_prefix(repo)?, args)?;
+    let index = Index::read(repo)?;
+    let head = repo.head()?;
+    let head_tree = head
+        .as_ref()
+        .map(|head| commit_tree(repo, head))
+        .transpose()?;
+    let head_files = tree_files(repo, head_tree.as_deref())?;
+
+    let source = match &options.source {
+        Some(source) => Some((
+            peel_to(repo, &resolve_revision(repo, source)?, &ObjectType::Tree)?,
+            format!("`{source}`"),
+        )),
+        None if staged => head
+            .as_ref()
+            .map(|head| Ok::<_, Error>((commit_tree(repo, head)?, "`HEAD`".to_owned())))
+            .transpose()?,
+        None => None,
+    };
+    let (files, from) = match &source {
+        Some((tree, name)) => (flatten_tree(repo, tree)?, name.clone()),
+        None if staged => (Default::default(), "the empty tree".to_owned()),
+        None => {
+            let unmerged: Vec<String> = index
+                .entries
+                .iter()
+                .filter(|entry| entry.stage() != 0 && pathspec.matches(&entry.path))
+                .map(|entry| format!("path '{}' is unmerged", entry.path_str()))
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .collect();
+
+            if !unmerged.is_empty() {
+                return Err(Error::Failed(unmerged.join("\n")));
+            }
+
+            let files = index
+                .entries
+                .iter()
+                .filter(|entry| entry.stage() == 0 && !entry.intent_to_add())
+                .map(|entry| {
+                    (
+                        entry.path.clone(),
+                        TreeFile {
+                            mode: entry.mode,
+                            hash: entry.hash.clone(),
+                        },
+                    )
+                })
+                .collect();
+
+            (files, "the index".to_owned())
+        }
+    };
+
+    for item in &pathspec.items {
+        let known = files.keys().any(|path| item.matches(path))
+            || index.entries.iter().any(|entry| item.matches(&entry.path));
+
+        if !known {
+            return Err(Error::Failed(format!(
+                "pathspec '{}' did not match any file(s) known to git",
+                item.original
+            )));
+        }
+    }
+
+    let candidates: BTreeSet<Vec<u8>> = files
+        .keys()
+        .chain(index.entries.iter().map(|entry| &entry.path))
+        .filter(|path| pathspec.matches(path))
+        .cloned()
+        .collect();
+    let mut plan = Plan::default();
+
+    for path in candidates {
+        let state = path_state(repo, &index, &head_files, &path)?;
+        let file = files.get(&path).cloned();
+
+        let after = match (staged, worktree) {
+            (true, true) => (file.clone(), file),
+            (true, false) => (file, state.worktree.clone()),
+            // A conflicted path keeps its stages when only its file is restored.
+            _ if state.conflicted => (None, file),
+            _ => (state.index.clone(), file),
+        };
+
+        if !staged && !state.conflicted && after.1 != state.worktree {
+            plan.worktree_files.push((path.clone(), after.1.clone()));
+        }
+
+        plan.add(path, &state, after);
+    }
+
+    let places = match (staged, worktree) {
+        (true, true) => "the index and the working tree",
+        (true, false) => "the index",
+        _ => "the working tree",
+    };
+    let explanation = match (staged, worktree) {
+        (true, false) => "`--staged` unstages changes: the files in the working tree are left as they are.",
+        (false, _) => "Changes in the working tree that were never staged are thrown away; the index is left as it is.",
+        (true, true) => "Both the staged and the unstaged changes of these files are thrown away.",
+    };
+
+    Ok(ResetPlan {
+        action: Action::Restore { staged, worktree },
+        pathspec,
+        tree: source.map(|(tree, _)| tree),
+        head: None,
+        index: plan.index,
+        worktree: plan.worktree,
+        lost: plan.lost,
+        worktree_files: plan.worktree_files,
+        title: format!("Restoring {places} from {from}"),
+        explanation: explanation.to_owned(),
+    })
+}
+
+/// Carries out a plan made by [`plan_reset`] or [`plan_restore`]. Returns what to tell the user
+/// afterwards, if anything.
+pub fn carry_out(repo: &Repository, plan: &ResetPlan) -> Result<Option<String>> {
+    let tree = plan.tree.as_deref();
+
+    match &plan.action {
+        Action::Reset { paths: true, .. } => reset_index(repo, tree, &plan.pathspec)?,
+        Action::Reset { mode, commit, .. } => {
+            let head = repo.head()?;
+
+            match mode {
+                ResetMode::Soft => {}
+                ResetMode::Mixed => reset_index(repo, tree, &plan.pathspec)?,
+                ResetMode::Hard => reset_hard(repo, tree, &plan.pathspec)?,
+                ResetMode::Keep => {
+                    let head_tree = head
+                        .as_ref()
+                        .map(|head| commit_tree(repo, head))
+                        .transpose()?;
+                    switch_trees(repo, head_tree.as_deref(), tree, false, TreeUpdate::Reset)?;
+                    reset_index(repo, tree, &plan.pathspec)?;
+                }
+                ResetMode::Merge => reset_merge(repo, tree)?,
+            }
+
+            if let Some((commit, spec)) = commit {
+                if let Some(head) = &head {
+                    update_ref(repo, "ORIG_HEAD", head, None, "", false)?;
+                }
+                update_ref(
+                    repo,
+                    "HEAD",
+                    commit,
+                    None,
+                    &format!("reset: moving to {spec}"),
+                    true,
+                )?;
+            }
+
+            remove_merge_state(repo)?;
+
+            if let (ResetMode::Hard, Some((commit, _))) = (mode, commit) {
+                let message = repo
+                    .read_object(commit)?
+                    .meta
+                    .unwrap_or_default()
+                    .message
+                    .unwrap_or_default();
+
+                return Ok(Some(format!(
+                    "HEAD is now at {} {}",
+                    abbreviate(repo, commit, default_abbrev(repo))?,
+                    subject(&message)
+                )));
+            }
+        }
+        Action::Restore {
+            staged: true,
+            worktree: true,
+        } => reset_hard(repo, tree, &plan.pathspec)?,
+        Action::Restore { staged: true, .. } => reset_index(repo, tree, &plan.pathspec)?,
+        Action::Restore { .. } => checkout_worktree(repo, &plan.worktree_files)?,
+    }
+
+    Ok(None)
+}
+
+impl ResetPlan {
+    /// Whether carrying out the plan changes nothing at all.
+    pub fn is_noop(&self) -> bool {
+        self.index.is_empty()
+            && self.worktree.is_empty()
+            && self
+                .head
+                .as_ref()
+                .is_none_or(|(old, new)| old.as_deref() == Some(new.as_str()))
+    }
+
+    /// Prints the plan for people: where `HEAD` goes, then the files that change in the index and
+    /// in the working tree, and what would be lost. Paths are shown relative to `prefix`, the
+    /// current directory.
+    pub fn print(&self, repo: &Repository, prefix: &[u8]) -> Result<()> {
+        let show = |path: &[u8]| quote_path(&relative_path(path, prefix), false);
+        let short = |hash: &str| abbreviate(repo, hash, default_abbrev(repo));
+        let mut skin = MadSkin::default();
+        skin.bold.set_fg(Yellow);
+
+        let mut text = format!("## {}\n*{}*\n", self.title, self.explanation);
+
+        if let Some((old, new)) = &self.head {
+            text += "\n|-|-|-\n| | **Before** | **After** |\n|-|-|-\n";
+            text += &format!(
+                "| **HEAD** | {} | {} |\n|-|-|-\n",
+                match old {
+                    Some(old) => describe(repo, old, &short(old)?)?,
+                    None => "no commit yet".to_owned(),
+                },
+                describe(repo, new, &short(new)?)?
+            );
+        }
+
+        if self.is_noop() {
+            text += "\nNothing changes: everything already matches.\n";
+        }
+
+        skin.print_text(&text);
+
+        let mut changes_skin = MadSkin::default();
+        changes_skin.bold.set_fg(Green);
+        let lines = |changes: &[PathChange]| -> Vec<String> {
+            changes
+                .iter()
+                .map(|change| {
+                    let settled = if change.conflicted {
+                        " (conflict settled)"
+                    } else {
+                        ""
+                    };
+                    format!(
+                        "* **{}:** `{}`{settled}",
+                        change.change.description(),
+                        show(&change.path)
+                    )
+                })
+                .collect()
+        };
+
+        if !self.index.is_empty() {
+            print_section(
+                &changes_skin,
+                "Index",
+                "What the next commit will contain.",
+                &lines(&self.index),
+            );
+        }
+
+        if !self.worktree.is_empty() {
+            print_section(
+                &changes_skin,
+                "Working tree",
+                "The files on disk.",
+                &lines(&self.worktree),
+            );
+        }
+
+        if !self.lost.is_empty() {
+            let mut lost_skin = MadSkin::default();
+            lost_skin.bold.set_fg(Red);
+            let lines: Vec<String> = self
+                .lost
+                .iter()
+                .map(|path| format!("* `{}`", show(path)))
+                .collect();
+
+            print_section(
+                &lost_skin,
+                "**Changes that will be lost**",
+                "These changes are in no commit and will not be left in the index or working tree either, so they cannot be recovered.",
+                &lines,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects what a plan does, path by path.
+#[derive(Default)]
+struct Plan {
+    index: Vec<PathChange>,
+    worktree: Vec<PathChange>,
+    lost: Vec<Vec<u8>>,
+    worktree_files: Vec<(Vec<u8>, Option<TreeFile>)>,
+}
+
+impl Plan {
+    /// Records what becomes of `path`, which ends up as `after` in the index and the work tree.
+    fn add(
+        &mut self,
+        path: Vec<u8>,
+        state: &PathState,
+        after: (Option<TreeFile>, Option<TreeFile>),
+    ) {
+        let (index, worktree) = after;
+
+        if state.conflicted || index != state.index {
+            self.index.push(PathChange {
+                path: path.clone(),
+                change: change(state.index.as_ref(), index.as_ref()),
+                conflicted: state.conflicted,
+            });
+        }
+
+        if worktree != state.worktree {
+            self.worktree.push(PathChange {
+                path: path.clone(),
+                change: change(state.worktree.as_ref(), worktree.as_ref()),
+                conflicted: false,
+            });
+        }
+
+        // A version survives if it is committed in `HEAD` or is still around afterwards.
+        let lost = [&state.index, &state.worktree].into_iter().any(|version| {
+            version.is_some() && *version != state.head && *version != index && *version != worktree
+        });
+
+        if lost {
+            self.lost.push(path);
+        }
+    }
+}
+
+fn change(before: Option<&TreeFile>, after: Option<&TreeFile>) -> Change {
+    match (before, after) {
+        (None, Some(_)) => Change::Added,
+        (Some(_), None) => Change::Deleted,
+        (Some(before), Some(after)) if before.mode != after.mode => Change::TypeChanged,
+        _ => Change::Modified,
+    }
+}
+
+/// The versions of `path` in `HEAD`, the index and the work tree. The work tree file is only
+/// hashed when its stat data says it changed.
+fn path_state(
+    repo: &Repository,
+    index: &Index,
+    head_files: &BTreeMap<Vec<u8>, TreeFile>,
+    path: &[u8],
+) -> Result<PathState> {
+    let conflicted = index.entries_for(path).iter().any(|e| e.stage() != 0);
+    let entry = index.entry(path, 0).filter(|entry| !entry.intent_to_add());
+    let mut contents = Contents::new(repo);
+
+    Ok(PathState {
+        head: head_files.get(path).cloned(),
+        index: entry.map(tree_file),
+        worktree: match entry {
+            Some(entry) => worktree_version(repo, entry, &mut contents)?,
+            None => contents.worktree_file(path, None)?,
+        },
+        conflicted,
+    })
+}
+
+/// `abc1234 subject`, for the `HEAD` row of the plan.
+fn describe(repo: &Repository, commit: &str, short: &str) -> Result<String> {
+    let message = repo
+        .read_object(commit)?
+        .meta
+        .unwrap_or_default()
+        .message
+        .unwrap_or_default();
+
+    Ok(format!("`{short}` {}", subject(&message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, slice};
+
+    use super::*;
+    use crate::{
+        checkout::{switch_trees, TreeUpdate},
+        index::{IndexEntry, MODE_BLOB},
+        test_support::TestRepo,
+    };
+
+    #[test]
+    fn merge_refuses_local_changes_it_cannot_carry_over() {
+        let test = TestRepo::new("reset-merge-refusal");
+        let first = test.commit(&[("f", "1\n")], &[], "first");
+        let second = test.commit(&[("f", "2\n")], &[&first], "second");
+        test.set_ref("refs/heads/main", &second, "commit: second");
+
+        let tree = commit_tree(&test.repo, &second).unwrap();
+        switch_trees(&test.repo, None, Some(&tree), false, TreeUpdate::Checkout).unwrap();
+
+        let work_tree = test.repo.require_work_tree().unwrap().to_path_buf();
+        fs::write(work_tree.join("f"), "local\n").unwrap();
+
+        let refusal =
+            || match plan_reset(&test.repo, ResetMode::Merge, slice::from_ref(&first), &[]) {
+                Err(Error::InvalidArgument(message)) => message,
+                result => panic!("{:?}", result.map(|_| ())),
+            };
+
+        assert_eq!(refusal(), "Entry 'f' not uptodate. Cannot merge.");
+
+        let mut index = Index::read(&test.repo).unwrap();
+        index.add(IndexEntry::new(
+            b"f".to_vec(),
+            MODE_BLOB,
+            test.blob("staged\n"),
+            0,
+        ));
+        index.write(&test.repo).unwrap();
+
+        assert!(refusal().contains("has both staged and unstaged changes"));
+    }
+}
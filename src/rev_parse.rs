@@ -0,0 +1,157 @@
+use std::{env, path::Path};
+
+use crate::{
+    error::{Error, Result},
+    refs::shorten_ref,
+    repository::Repository,
+    revision::{abbreviate, parse_revision_arg, resolve_revision, symbolic_full_name},
+    worktree::cwd_prefix,
+};
+
+/// How `rev-parse` prints the refs it is given instead of object names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolicName {
+    /// `--symbolic-full-name`, e.g. `refs/heads/main`.
+    Full,
+    /// `--abbrev-ref`, e.g. `main`.
+    Abbreviated,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RevParseOptions {
+    /// Accept exactly one revision, and no ranges.
+    pub verify: bool,
+    /// Abbreviate object names to at least this many digits.
+    pub short: Option<usize>,
+    pub symbolic: Option<SymbolicName>,
+}
+
+/// The questions about the repository's location `rev-parse` can answer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepoQuery {
+    GitDir,
+    AbsoluteGitDir,
+    ShowToplevel,
+    ShowPrefix,
+    ShowCdup,
+    IsInsideWorkTree,
+    IsBareRepository,
+}
+
+/// Resolves each revision argument and returns the lines `git rev-parse` prints for them. Ranges
+/// print one line per commit, excluded ones prefixed with `^`.
+pub fn rev_parse(
+    repo: &Repository,
+    args: &[String],
+    options: &RevParseOptions,
+) -> Result<Vec<String>> {
+    if options.verify {
+        let [spec] = args else {
+            return Err(Error::InvalidArgument(
+                "Needed a single revision".to_owned(),
+            ));
+        };
+
+        let hash = resolve_revision(repo, spec).map_err(|err| match err {
+            Error::UnknownRevision(_) => {
+                Error::InvalidArgument("Needed a single revision".to_owned())
+            }
+            err => err,
+        })?;
+
+        return Ok(match options.symbolic {
+            Some(kind) => symbolic_name(repo, spec, kind)?.into_iter().collect(),
+            None => vec![format_hash(repo, &hash, options)?],
+        });
+    }
+
+    let mut lines = Vec::new();
+
+    for arg in args {
+        let items = match parse_revision_arg(repo, arg) {
+            Ok(items) => items,
+            // Like git, an argument naming a file is passed through untouched.
+            Err(Error::UnknownRevision(_)) if Path::new(arg).exists() => {
+                lines.push(arg.clone());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        for item in items {
+            let text = match options.symbolic {
+                Some(kind) => match &item.spec {
+                    Some(spec) => symbolic_name(repo, spec, kind)?,
+                    None => None,
+                },
+                None => Some(format_hash(repo, &item.hash, options)?),
+            };
+
+            if let Some(text) = text {
+                lines.push(format!("{}{text}", if item.negated { "^" } else { "" }));
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+fn format_hash(repo: &Repository, hash: &str, options: &RevParseOptions) -> Result<String> {
+    match options.short {
+        Some(len) => abbreviate(repo, hash, len),
+        None => Ok(hash.to_owned()),
+    }
+}
+
+fn symbolic_name(repo: &Repository, spec: &str, kind: SymbolicName) -> Result<Option<String>> {
+    Ok(symbolic_full_name(repo, spec)?.map(|full_name| match kind {
+        SymbolicName::Full => full_name,
+        SymbolicName::Abbreviated => shorten_ref(&full_name).to_owned(),
+    }))
+}
+
+/// Answers `query` about where the repository and the current directory are. Returns `None` when
+/// git prints nothing, e.g. `--show-cdup` from inside the git directory.
+pub fn repo_query(repo: &Repository, query: RepoQuery) -> Result<Option<String>> {
+    let cwd = env::current_dir()?;
+    let cwd = cwd.canonicalize().unwrap_or(cwd);
+    let git_dir = repo
+        .git_dir()
+        .canonicalize()
+        .unwrap_or_else(|_| repo.git_dir().to_path_buf());
+    let inside_work_tree = repo.work_tree().is_some_and(|work_tree| {
+        let work_tree = work_tree
+            .canonicalize()
+            .unwrap_or_else(|_| work_tree.to_path_buf());
+        cwd.starts_with(work_tree) && !cwd.starts_with(&git_dir)
+    });
+
+    Ok(match query {
+        RepoQuery::GitDir => Some(if git_dir == cwd.join(".git") {
+            ".git".to_owned()
+        } else if git_dir == cwd {
+            ".".to_owned()
+        } else {
+            git_dir.display().to_string()
+        }),
+        RepoQuery::AbsoluteGitDir => Some(git_dir.display().to_string()),
+        RepoQuery::ShowToplevel => {
+            let work_tree = repo.require_work_tree()?;
+            let work_tree = work_tree
+                .canonicalize()
+                .unwrap_or_else(|_| work_tree.to_path_buf());
+            Some(work_tree.display().to_string())
+        }
+        RepoQuery::ShowPrefix if inside_work_tree => {
+            Some(String::from_utf8_lossy(&cwd_prefix(repo)?).into_owned())
+        }
+        RepoQuery::ShowCdup if inside_work_tree => {
+            let depth = cwd_prefix(repo)?.iter().filter(|b| **b == b'/').count();
+            Some("../".repeat(depth))
+        }
+        RepoQuery::ShowPrefix => Some(String::new()),
+        RepoQuery::ShowCdup => None,
+        RepoQuery::IsInsideWorkTree => Some(inside_work_tree.to_string()),
+        RepoQuery::IsBareRepository => Some(repo.work_tree().is_none().to_string()),
+    })
+}
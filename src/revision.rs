@@ -0,0 +1,928 @@
+use std::{
+    collections::{BinaryHeap, HashSet},
+    fs,
+};
+
+use regex::Regex;
+
+use crate::{
+    error::{Error, Result},
+    ident::parse_approxidate,
+    index::Index,
+    pack::packed_objects_with_prefix,
+    refs::{dwim_ref, is_pseudo_ref, list_refs, peel, read_reflog, ref_target, resolve_ref},
+    repository::Repository,
+    revwalk::{commit_parents, merge_bases},
+    status::upstream_ref,
+    structures::ObjectType,
+    tree::find_tree_entry,
+    worktree::cwd_prefix,
+};
+
+/// The shortest abbreviated object name git accepts.
+pub const MIN_ABBREV: usize = 4;
+
+/// One commit of a revision range: `A..B` selects `B` and excludes everything reachable from the
+/// negated `A`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevisionItem {
+    pub hash: String,
+    pub negated: bool,
+    /// The expression the item was written as, if the user wrote it out; merge bases and the
+    /// parents from `rev^@` have none.
+    pub spec: Option<String>,
+}
+
+/// Resolves a revision expression to the object it names. Besides full and abbreviated object
+/// names and ref names, this understands the suffixes `~<n>`, `^<n>`, `^{<type>}`, `^{}` and
+/// `^{/<regex>}`, the reflog forms `<ref>@{<n>}` and `<ref>@{<date>}`, `@{-<n>}`, `@{upstream}`,
+/// `<rev>:<path>`, `:[<stage>:]<path>` for the index and `:/<regex>` for commit messages.
+pub fn resolve_revision(repo: &Repository, spec: &str) -> Result<String> {
+    let resolved = if let Some(rest) = spec.strip_prefix(':') {
+        resolve_index_path(repo, rest)
+    } else if let Some(colon) = path_separator(spec) {
+        resolve_tree_path(repo, &spec[..colon], &spec[colon + 1..])
+    } else {
+        resolve_expression(repo, spec)
+    };
+
+    // Report the whole expression, not the part of it that failed.
+    resolved.map_err(|err| match err {
+        Error::UnknownRevision(_) => Error::UnknownRevision(spec.to_owned()),
+        err => err,
+    })
+}
+
+/// Resolves a revision expression that must name a commit, peeling tags on the way.
+pub fn resolve_commit(repo: &Repository, spec: &str) -> Result<String> {
+    peel_to(repo, &resolve_revision(repo, spec)?, &ObjectType::Commit)
+}
+
+/// Follows tags, and a commit to its tree, until an object of `obj_type` is reached.
+pub fn peel_to(repo: &Repository, hash: &str, obj_type: &ObjectType) -> Result<String> {
+    let mut object = repo.read_object(hash)?;
+
+    loop {
+        if object.obj_type == *obj_type {
+            return Ok(object.hash);
+        }
+
+        let meta = object.meta.unwrap_or_default();
+        let next = match (&object.obj_type, obj_type) {
+            (ObjectType::Tag, _) => meta.object,
+            (ObjectType::Commit, ObjectType::Tree) => meta.tree,
+            _ => None,
+        };
+
+        match next {
+            Some(next) => object = repo.read_object(&next)?,
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "`{hash}` dereferences to a {}, not a {obj_type}",
+                    object.obj_type
+                )))
+            }
+        }
+    }
+}
+
+/// Expands a revision argument into the commits it selects: a single revision, a range `A..B`
+/// (either side defaulting to `HEAD`), a symmetric difference `A...B`, `^A`, or the parent forms
+/// `A^@`, `A^!` and `A^-<n>`.
+pub fn parse_revision_arg(repo: &Repository, arg: &str) -> Result<Vec<RevisionItem>> {
+    let item = |hash: String, negated: bool, spec: &str| RevisionItem {
+        hash,
+        negated,
+        spec: Some(spec.to_owned()),
+    };
+
+    if let Some(range) = resolve_range(repo, arg)? {
+        return Ok(range);
+    }
+
+    if let Some(rev) = arg.strip_suffix("^@") {
+        let commit = resolve_commit(repo, rev)?;
+
+        return Ok(commit_parents(repo, &commit)?
+            .into_iter()
+            .map(|parent| RevisionItem {
+                hash: parent,
+                negated: false,
+                spec: None,
+            })
+            .collect());
+    }
+
+    if let Some(rev) = arg.strip_suffix("^!") {
+        let commit = resolve_commit(repo, rev)?;
+        let mut items = vec![item(commit.clone(), false, rev)];
+
+        items.extend(
+            commit_parents(repo, &commit)?
+                .into_iter()
+                .map(|parent| RevisionItem {
+                    hash: parent,
+                    negated: true,
+                    spec: None,
+                }),
+        );
+
+        return Ok(items);
+    }
+
+    if let Some((rev, number)) = arg.rsplit_once("^-") {
+        if number.is_empty() || number.bytes().all(|b| b.is_ascii_digit()) {
+            let number = if number.is_empty() { "1" } else { number };
+            let commit = resolve_commit(repo, rev)?;
+            let parent = resolve_commit(repo, &format!("{rev}^{number}"))?;
+
+            return Ok(vec![
+                item(commit, false, rev),
+                RevisionItem {
+                    hash: parent,
+                    negated: true,
+                    spec: None,
+                },
+            ]);
+        }
+    }
+
+    if let Some(rev) = arg.strip_prefix('^') {
+        return Ok(vec![item(resolve_revision(repo, rev)?, true, rev)]);
+    }
+
+    Ok(vec![item(resolve_revision(repo, arg)?, false, arg)])
+}
+
+/// `A..B` and `A...B`. Like git, an argument whose sides do not both resolve is not a range after
+/// all, which keeps `HEAD:../file` working.
+fn resolve_range(repo: &Repository, arg: &str) -> Result<Option<Vec<RevisionItem>>> {
+    let Some(dots) = arg.find("..") else {
+        return Ok(None);
+    };
+
+    let symmetric = arg[dots + 2..].starts_with('.');
+    let (left, right) = (&arg[..dots], &arg[dots + if symmetric { 3 } else { 2 }..]);
+    let left = if left.is_empty() { "HEAD" } else { left };
+    let right = if right.is_empty() { "HEAD" } else { right };
+
+    // The sides are printed as written, but must lead to commits.
+    let resolve = |spec: &str| -> Result<(String, String)> {
+        let hash = resolve_revision(repo, spec)?;
+        let commit = peel_to(repo, &hash, &ObjectType::Commit)?;
+        Ok((hash, commit))
+    };
+
+    let (Ok((left_hash, left_commit)), Ok((right_hash, right_commit))) =
+        (resolve(left), resolve(right))
+    else {
+        return Ok(None);
+    };
+
+    let item = |hash: &str, negated: bool, spec: &str| RevisionItem {
+        hash: hash.to_owned(),
+        negated,
+        spec: Some(spec.to_owned()),
+    };
+
+    if !symmetric {
+        return Ok(Some(vec![
+            item(&right_hash, false, right),
+            item(&left_hash, true, left),
+        ]));
+    }
+
+    let mut items = vec![
+        item(&right_hash, false, right),
+        item(&left_hash, false, left),
+    ];
+
+    items.extend(
+        merge_bases(repo, &left_commit, &right_commit)?
+            .into_iter()
+            .map(|base| RevisionItem {
+                hash: base,
+                negated: true,
+                spec: None,
+            }),
+    );
+
+    Ok(Some(items))
+}
+
+/// The full name of the ref a revision expression refers to, e.g. `refs/heads/main` for `main`
+/// or `@{-1}`. Expressions that do not name a ref, such as `HEAD~2`, have none.
+pub fn symbolic_full_name(repo: &Repository, spec: &str) -> Result<Option<String>> {
+    let spec = expand_prior_checkout(repo, spec)?;
+    let spec = if spec == "@" { "HEAD" } else { &spec };
+
+    if let Some((base, mark)) = split_at_mark(spec) {
+        if is_upstream_mark(mark) {
+            return Ok(Some(upstream(repo, base)?));
+        }
+    }
+
+    Ok(dwim_ref(repo, spec)?.map(|(full_name, _)| full_name))
+}
+
+/// The branch that was checked out `n` checkouts ago, as recorded in `HEAD`'s reflog. This is what
+/// `@{-<n>}` and `-` refer to.
+pub fn prior_checkout(repo: &Repository, n: usize) -> Result<Option<String>> {
+    Ok(read_reflog(repo, "HEAD")?
+        .iter()
+        .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+        .filter_map(|moved| moved.split_once(" to "))
+        .map(|(from, _)| from.to_owned())
+        .nth(n.wrapping_sub(1)))
+}
+
+/// The shortest prefix of `hash`, at least `min_len` digits long, that names no other object.
+pub fn abbreviate(repo: &Repository, hash: &str, min_len: usize) -> Result<String> {
+    let mut len = min_len.clamp(MIN_ABBREV, hash.len().max(MIN_ABBREV));
+
+    while len < hash.len() && objects_with_prefix(repo, &hash[..len])?.len() > 1 {
+        len += 1;
+    }
+
+    Ok(hash[..len.min(hash.len())].to_owned())
+}
+
+/// The abbreviation length to use when the user did not ask for one: `core.abbrev`, or 7.
+pub fn default_abbrev(repo: &Repository) -> usize {
+    let hex_len = repo.object_format().hex_len();
+
+    match repo.config().get("core.abbrev").as_deref() {
+        Some("no") => hex_len,
+        Some(value) => value
+            .parse::<usize>()
+            .map_or(7, |len| len.clamp(MIN_ABBREV, hex_len)),
+        None => 7,
+    }
+}
+
+/// Where `rev:path` splits, skipping colons inside `^{...}` and `@{...}`.
+fn path_separator(spec: &str) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, b) in spec.bytes().enumerate() {
+        match b {
+            b'{' => depth += 1,
+            b'}' if depth > 0 => depth -= 1,
+            b':' if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// `rev:path`: the blob or tree at `path` in the tree of `rev`.
+fn resolve_tree_path(repo: &Repository, rev: &str, path: &str) -> Result<String> {
+    let tree = peel_to(repo, &resolve_expression(repo, rev)?, &ObjectType::Tree)?;
+    let full_path = repo_path(repo, path)?;
+
+    match find_tree_entry(repo, &tree, &full_path)? {
+        Some(entry) => Ok(entry.hash),
+        None => Err(Error::InvalidArgument(format!(
+            "path `{path}` does not exist in `{rev}`"
+        ))),
+    }
+}
+
+/// `:path`, `:<stage>:path` and `:/regex`.
+fn resolve_index_path(repo: &Repository, rest: &str) -> Result<String> {
+    if let Some(pattern) = rest.strip_prefix('/') {
+        let mut starts: Vec<String> = repo.head()?.into_iter().collect();
+
+        for entry in list_refs(repo, "refs/")? {
+            if let Ok(commit) = peel_to(repo, &entry.hash, &ObjectType::Commit) {
+                starts.push(commit);
+            }
+        }
+
+        return search_messages(repo, starts, pattern)?
+            .ok_or_else(|| Error::UnknownRevision(format!(":{rest}")));
+    }
+
+    let (stage, path) = match rest.as_bytes() {
+        [stage @ b'0'..=b'3', b':', ..] => (stage - b'0', &rest[2..]),
+        _ => (0, rest),
+    };
+    let full_path = repo_path(repo, path)?;
+    let index = Index::read(repo)?;
+
+    if let Some(entry) = index.entry(&full_path, stage) {
+        return Ok(entry.hash.clone());
+    }
+
+    Err(Error::InvalidArgument(
+        if index.entries_for(&full_path).is_empty() {
+            format!("path `{path}` is not in the index")
+        } else {
+            format!("path `{path}` is in the index, but not at stage {stage}")
+        },
+    ))
+}
+
+/// Turns a path from a revision expression into a path from the top of the work tree. Paths are
+/// taken from the top already, unless they start with `./` or `../`.
+fn repo_path(repo: &Repository, path: &str) -> Result<Vec<u8>> {
+    let relative = path == "." || path == ".." || path.starts_with("./") || path.starts_with("../");
+
+    if !relative {
+        return Ok(path.as_bytes().to_vec());
+    }
+
+    let full_path = format!("{}{path}", String::from_utf8_lossy(&cwd_prefix(repo)?));
+    let mut components = Vec::new();
+
+    for component in full_path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    return Err(Error::InvalidArgument(format!(
+                        "`{path}` is outside the repository"
+                    )));
+                }
+            }
+            component => components.push(component),
+        }
+    }
+
+    Ok(components.join("/").into_bytes())
+}
+
+/// Applies the `~<n>`, `^<n>` and `^{...}` suffixes, innermost first.
+fn resolve_expression(repo: &Repository, spec: &str) -> Result<String> {
+    if spec.ends_with('}') {
+        if let Some(open) = spec.rfind("^{") {
+            let base = resolve_expression(repo, &spec[..open])?;
+            return peel_expression(repo, spec, &base, &spec[open + 2..spec.len() - 1]);
+        }
+    }
+
+    let digits = spec.bytes().rev().take_while(u8::is_ascii_digit).count();
+    let (head, number) = spec.split_at(spec.len() - digits);
+
+    let Some(operator @ (b'~' | b'^')) = head.bytes().last() else {
+        return resolve_basic(repo, spec);
+    };
+
+    let count = match number {
+        "" => 1,
+        number => number
+            .parse::<usize>()
+            .map_err(|_| Error::UnknownRevision(spec.to_owned()))?,
+    };
+    let base = resolve_expression(repo, &head[..head.len() - 1])?;
+    let mut commit = peel_to(repo, &base, &ObjectType::Commit)?;
+
+    if operator == b'^' {
+        if count == 0 {
+            return Ok(commit);
+        }
+
+        return commit_parents(repo, &commit)?
+            .into_iter()
+            .nth(count - 1)
+            .ok_or_else(|| Error::UnknownRevision(spec.to_owned()));
+    }
+
+    for _ in 0..count {
+        commit = commit_parents(repo, &commit)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::UnknownRevision(spec.to_owned()))?;
+    }
+
+    Ok(commit)
+}
+
+/// `rev^{<type>}`, `rev^{}` and `rev^{/<regex>}`.
+fn peel_expression(repo: &Repository, spec: &str, base: &str, inner: &str) -> Result<String> {
+    if let Some(pattern) = inner.strip_prefix('/') {
+        let commit = peel_to(repo, base, &ObjectType::Commit)?;
+
+        return search_messages(repo, vec![commit], pattern)?
+            .ok_or_else(|| Error::UnknownRevision(spec.to_owned()));
+    }
+
+    let obj_type = match inner {
+        "" => return peel(repo, base),
+        "object" => return Ok(repo.read_object(base)?.hash),
+        "commit" => ObjectType::Commit,
+        "tree" => ObjectType::Tree,
+        "blob" => ObjectType::Blob,
+        "tag" => ObjectType::Tag,
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "`{spec}`: unknown object type `{inner}`"
+            )))
+        }
+    };
+
+    peel_to(repo, base, &obj_type)
+}
+
+/// A name without suffixes: an object name, a ref, a reflog entry or an upstream.
+fn resolve_basic(repo: &Repository, spec: &str) -> Result<String> {
+    let spec = expand_prior_checkout(repo, spec)?;
+    let spec = if spec == "@" { "HEAD" } else { &spec };
+
+    if repo.object_format().is_valid_hex(spec) {
+        return Ok(spec.to_ascii_lowercase());
+    }
+
+    if let Some((base, mark)) = split_at_mark(spec) {
+        if is_upstream_mark(mark) {
+            let full_name = upstream(repo, base)?;
+
+            return resolve_ref(repo, &full_name)?.ok_or_else(|| {
+                Error::InvalidArgument(format!("upstream branch `{full_name}` does not exist"))
+            });
+        }
+
+        return resolve_reflog(repo, base, mark);
+    }
+
+    if let Some((_, hash)) = dwim_ref(repo, spec)? {
+        return Ok(hash);
+    }
+
+    // `git describe` output, `<tag>-<n>-g<abbreviated name>`.
+    if let Some((_, abbrev)) = spec.rsplit_once("-g") {
+        if let Some(hash) = expand_abbreviation(repo, abbrev)? {
+            return Ok(hash);
+        }
+    }
+
+    expand_abbreviation(repo, spec)?.ok_or_else(|| Error::UnknownRevision(spec.to_owned()))
+}
+
+/// Replaces a leading `@{-<n>}` with the branch it stands for.
+fn expand_prior_checkout(repo: &Repository, spec: &str) -> Result<String> {
+    let Some((number, rest)) = spec
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.split_once('}'))
+    else {
+        return Ok(spec.to_owned());
+    };
+
+    let n = number
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| Error::UnknownRevision(spec.to_owned()))?;
+
+    match prior_checkout(repo, n)? {
+        Some(branch) => Ok(format!("{branch}{rest}")),
+        None => Err(Error::UnknownRevision(spec.to_owned())),
+    }
+}
+
+/// Splits `base@{mark}`.
+fn split_at_mark(spec: &str) -> Option<(&str, &str)> {
+    let inner = spec.strip_suffix('}')?;
+    let at = inner.rfind("@{")?;
+
+    Some((&inner[..at], &inner[at + 2..]))
+}
+
+fn is_upstream_mark(mark: &str) -> bool {
+    mark.eq_ignore_ascii_case("u") || mark.eq_ignore_ascii_case("upstream")
+}
+
+/// The full name of the ref the branch `base` (the current branch when empty) tracks.
+fn upstream(repo: &Repository, base: &str) -> Result<String> {
+    let branch = match base {
+        "" | "@" | "HEAD" => ref_target(repo, "HEAD")?
+            .strip_prefix("refs/heads/")
+            .map(str::to_owned)
+            .ok_or_else(|| Error::InvalidArgument("HEAD does not point to a branch".to_owned()))?,
+        _ => dwim_ref(repo, base)?
+            .and_then(|(full_name, _)| full_name.strip_prefix("refs/heads/").map(str::to_owned))
+            .ok_or_else(|| Error::InvalidArgument(format!("no such branch: `{base}`")))?,
+    };
+
+    upstream_ref(repo, &branch)
+        .map(|(full_name, _)| full_name)
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!("no upstream configured for branch `{branch}`"))
+        })
+}
+
+/// `ref@{<n>}` is the value `ref` had `n` updates ago, and `ref@{<date>}` the value it had at
+/// that time. Without a ref, the current branch's reflog is used.
+fn resolve_reflog(repo: &Repository, base: &str, mark: &str) -> Result<String> {
+    let name = if base.is_empty() {
+        ref_target(repo, "HEAD")?
+    } else if is_pseudo_ref(base) {
+        base.to_owned()
+    } else {
+        match dwim_ref(repo, base)? {
+            Some((full_name, _)) => full_name,
+            None => return Err(Error::UnknownRevision(base.to_owned())),
+        }
+    };
+
+    let entries = read_reflog(repo, &name)?;
+    let shown = if base.is_empty() { &name } else { base };
+
+    if let Ok(n) = mark.parse::<usize>() {
+        if let Some(entry) = entries.get(n) {
+            return Ok(entry.new.clone());
+        }
+
+        // One step past the oldest entry is the value the ref had before it, if it existed.
+        if let Some(oldest) = entries.last().filter(|_| n == entries.len()) {
+            if oldest.old.bytes().any(|b| b != b'0') {
+                return Ok(oldest.old.clone());
+            }
+        }
+
+        return Err(Error::InvalidArgument(format!(
+            "log for `{shown}` only has {} entries",
+            entries.len()
+        )));
+    }
+
+    let time = parse_approxidate(mark).map_err(|_| Error::UnknownRevision(mark.to_owned()))?;
+
+    entries
+        .iter()
+        .find(|entry| entry.committer.time <= time)
+        .or(entries.last())
+        .map(|entry| entry.new.clone())
+        .ok_or_else(|| Error::InvalidArgument(format!("log for `{shown}` is empty")))
+}
+
+/// Walks the history from `starts`, newest commit first, and returns the first commit whose
+/// message matches `pattern`. A leading `!-` negates the pattern and `!!` stands for a literal
+/// `!`.
+fn search_messages(
+    repo: &Repository,
+    starts: Vec<String>,
+    pattern: &str,
+) -> Result<Option<String>> {
+    let (negated, pattern) = match pattern.strip_prefix('!') {
+        None => (false, pattern),
+        Some(rest) if rest.starts_with('!') => (false, rest),
+        Some(rest) => match rest.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "`:/{pattern}`: unknown `!` modifier"
+                )))
+            }
+        },
+    };
+
+    let regex = Regex::new(pattern)
+        .map_err(|err| Error::InvalidArgument(format!("invalid regex `{pattern}`: {err}")))?;
+
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::<(u64, String, String, Vec<String>)>::new();
+    let mut pending = starts;
+
+    loop {
+        for hash in pending.drain(..) {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+
+            let meta = repo.read_object(&hash)?.meta.unwrap_or_default();
+            let time = meta.committer.map_or(0, |committer| committer.time);
+
+            queue.push((time, hash, meta.message.unwrap_or_default(), meta.parents));
+        }
+
+        let Some((_, hash, message, parents)) = queue.pop() else {
+            return Ok(None);
+        };
+
+        if regex.is_match(&message) != negated {
+            return Ok(Some(hash));
+        }
+
+        pending = parents;
+    }
+}
+
+/// Looks up an abbreviated object name, failing if more than one object starts with it.
+/// Strings that cannot be abbreviated names are not looked up.
+fn expand_abbreviation(repo: &Repository, prefix: &str) -> Result<Option<String>> {
+    if prefix.len() < MIN_ABBREV
+        || prefix.len() > repo.object_format().hex_len()
+        || !prefix.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Ok(None);
+    }
+
+    let prefix = prefix.to_ascii_lowercase();
+    let mut candidates = objects_with_prefix(repo, &prefix)?;
+
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(candidates.pop()),
+        _ => Err(ambiguous(repo, &prefix, &candidates)),
+    }
+}
+
+/// Every loose or packed object whose name starts with `prefix`.
+fn objects_with_prefix(repo: &Repository, prefix: &str) -> Result<Vec<String>> {
+    let mut names = packed_objects_with_prefix(repo, prefix)?;
+
+    if let Ok(entries) = fs::read_dir(repo.objects_dir().join(&prefix[..2])) {
+        for entry in entries {
+            let name = format!("{}{}", &prefix[..2], entry?.file_name().to_string_lossy());
+
+            if name.starts_with(prefix) && repo.object_format().is_valid_hex(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+/// The error for an ambiguous abbreviation, listing what it could mean as git does.
+fn ambiguous(repo: &Repository, prefix: &str, candidates: &[String]) -> Error {
+    let mut message = format!("short object ID `{prefix}` is ambiguous; the candidates are:");
+
+    for hash in candidates {
+        let short = &hash[..(prefix.len() + 1).max(7).min(hash.len())];
+        let description = match repo.read_object(hash) {
+            Ok(object) => {
+                let meta = object.meta.unwrap_or_default();
+
+                match object.obj_type {
+                    ObjectType::Commit => format!(
+                        "commit {} - {}",
+                        meta.committer
+                            .map(|committer| committer.format_time())
+                            .unwrap_or_default()
+                            .split(' ')
+                            .next()
+                            .unwrap_or_default(),
+                        meta.message
+                            .unwrap_or_default()
+                            .lines()
+                            .next()
+                            .unwrap_or_default()
+                    ),
+                    ObjectType::Tag => format!("tag {}", meta.tag_name.unwrap_or_default()),
+                    obj_type => obj_type.to_string(),
+                }
+            }
+            Err(_) => "bad object".to_owned(),
+        };
+
+        message += &format!("\n  {short} {description}");
+    }
+
+    Error::InvalidArgument(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{refs::append_reflog, test_support::TestRepo, tree::commit_tree};
+
+    /// `main` has `first`, `second`, a merge of `side` and `fourth`, in that order; `v1` tags
+    /// `second` and `v2` is an annotated tag of it.
+    struct History {
+        test: TestRepo,
+        first: String,
+        second: String,
+        side: String,
+        merge: String,
+        fourth: String,
+        tag: String,
+    }
+
+    fn history(name: &str) -> History {
+        let test = TestRepo::new(name);
+        let first = test.commit(&[("a", "1\n")], &[], "first");
+        let second = test.commit(&[("a", "2\n")], &[&first], "second");
+        let side = test.commit(&[("a", "1\n"), ("b", "b\n")], &[&first], "side work");
+        let merge = test.commit(
+            &[("a", "2\n"), ("b", "b\n")],
+            &[&second, &side],
+            "merge side",
+        );
+        let fourth = test.commit(&[("a", "4\n"), ("b", "b\n")], &[&merge], "fourth: fix typo");
+
+        for commit in [&first, &second, &merge, &fourth] {
+            test.set_ref("refs/heads/main", commit, "commit");
+        }
+        test.set_ref("refs/heads/side", &side, "branch: Created from main");
+        test.set_ref("refs/tags/v1", &second, "");
+
+        let tag = test
+            .repo
+            .write_object(
+                &ObjectType::Tag,
+                format!(
+                    "object {second}\ntype commit\ntag v2\ntagger A U Thor <author@example.com> 1600000000 +0000\n\nversion two\n"
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        test.set_ref("refs/tags/v2", &tag, "");
+
+        History {
+            test,
+            first,
+            second,
+            side,
+            merge,
+            fourth,
+            tag,
+        }
+    }
+
+    fn resolve(history: &History, spec: &str) -> Result<String> {
+        resolve_revision(&history.test.repo, spec)
+    }
+
+    #[test]
+    fn names_refs_and_abbreviations() {
+        let h = history("names");
+
+        assert_eq!(resolve(&h, "HEAD").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "@").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "main").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "refs/heads/side").unwrap(), h.side);
+        assert_eq!(resolve(&h, "v1").unwrap(), h.second);
+        assert_eq!(resolve(&h, &h.merge[..10]).unwrap(), h.merge);
+        assert_eq!(resolve(&h, &h.merge.to_uppercase()).unwrap(), h.merge);
+        assert!(matches!(
+            resolve(&h, "nope"),
+            Err(Error::UnknownRevision(spec)) if spec == "nope"
+        ));
+    }
+
+    #[test]
+    fn caret_selects_parents() {
+        let h = history("caret");
+
+        assert_eq!(resolve(&h, "HEAD^").unwrap(), h.merge);
+        assert_eq!(resolve(&h, "HEAD^1").unwrap(), h.merge);
+        assert_eq!(resolve(&h, "HEAD^0").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "HEAD^^").unwrap(), h.second);
+        assert_eq!(resolve(&h, "HEAD^^2").unwrap(), h.side);
+        assert_eq!(resolve(&h, "HEAD^^2^").unwrap(), h.first);
+        assert!(resolve(&h, "HEAD^2").is_err());
+        assert!(resolve(&h, "HEAD^^3").is_err());
+    }
+
+    #[test]
+    fn tilde_follows_first_parents() {
+        let h = history("tilde");
+
+        assert_eq!(resolve(&h, "HEAD~").unwrap(), h.merge);
+        assert_eq!(resolve(&h, "HEAD~2").unwrap(), h.second);
+        assert_eq!(resolve(&h, "HEAD~3").unwrap(), h.first);
+        assert_eq!(resolve(&h, "HEAD~0").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "HEAD~1^2~1").unwrap(), h.first);
+        assert_eq!(resolve(&h, "v2~1").unwrap(), h.first);
+        assert!(resolve(&h, "HEAD~4").is_err());
+    }
+
+    #[test]
+    fn braces_peel_objects() {
+        let h = history("peel");
+
+        assert_eq!(resolve(&h, "v2").unwrap(), h.tag);
+        assert_eq!(resolve(&h, "v2^{}").unwrap(), h.second);
+        assert_eq!(resolve(&h, "v2^{commit}").unwrap(), h.second);
+        assert_eq!(resolve(&h, "v2^{tag}").unwrap(), h.tag);
+        assert_eq!(resolve(&h, "v2^{object}").unwrap(), h.tag);
+        assert_eq!(
+            resolve(&h, "v2^{tree}").unwrap(),
+            commit_tree(&h.test.repo, &h.second).unwrap()
+        );
+        assert!(resolve(&h, "v1^{tag}").is_err());
+        assert!(resolve(&h, "HEAD^{bogus}").is_err());
+    }
+
+    #[test]
+    fn messages_are_searched_newest_first() {
+        let h = history("search");
+
+        assert_eq!(resolve(&h, ":/typo").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, ":/^s").unwrap(), h.side);
+        assert_eq!(resolve(&h, ":/!-typo").unwrap(), h.merge);
+        assert_eq!(resolve(&h, "HEAD~2^{/first}").unwrap(), h.first);
+        assert_eq!(resolve(&h, "HEAD^{/work}").unwrap(), h.side);
+        assert!(resolve(&h, ":/no such message").is_err());
+        assert!(resolve(&h, "HEAD~2^{/work}").is_err());
+    }
+
+    #[test]
+    fn at_braces_read_the_reflog() {
+        let h = history("reflog");
+
+        assert_eq!(resolve(&h, "main@{0}").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "main@{1}").unwrap(), h.merge);
+        assert_eq!(resolve(&h, "main@{3}").unwrap(), h.first);
+        assert_eq!(resolve(&h, "@{2}").unwrap(), h.second);
+        assert_eq!(resolve(&h, "main@{1}^2").unwrap(), h.side);
+        assert!(resolve(&h, "main@{4}").is_err());
+        assert!(resolve(&h, "nope@{1}").is_err());
+    }
+
+    #[test]
+    fn at_minus_is_a_prior_checkout() {
+        let h = history("prior");
+        let repo = &h.test.repo;
+
+        append_reflog(
+            repo,
+            "HEAD",
+            &h.fourth,
+            &h.side,
+            "checkout: moving from main to side",
+        )
+        .unwrap();
+        append_reflog(
+            repo,
+            "HEAD",
+            &h.side,
+            &h.fourth,
+            "checkout: moving from side to main",
+        )
+        .unwrap();
+
+        assert_eq!(resolve(&h, "@{-1}").unwrap(), h.side);
+        assert_eq!(resolve(&h, "@{-2}").unwrap(), h.fourth);
+        assert_eq!(resolve(&h, "@{-1}~1").unwrap(), h.first);
+        assert_eq!(
+            symbolic_full_name(repo, "@{-1}").unwrap().as_deref(),
+            Some("refs/heads/side")
+        );
+        assert!(resolve(&h, "@{-3}").is_err());
+        assert!(resolve(&h, "@{-0}").is_err());
+    }
+
+    #[test]
+    fn colon_looks_up_paths_in_trees() {
+        let h = history("paths");
+
+        assert_eq!(resolve(&h, "HEAD:a").unwrap(), h.test.blob("4\n"));
+        assert_eq!(resolve(&h, "HEAD~3:a").unwrap(), h.test.blob("1\n"));
+        assert_eq!(resolve(&h, "main@{1}:b").unwrap(), h.test.blob("b\n"));
+        assert!(resolve(&h, "HEAD~3:b").is_err());
+    }
+
+    #[test]
+    fn ranges_negate_their_left_side() {
+        let h = history("ranges");
+        let repo = &h.test.repo;
+        let selected = |arg: &str| -> Vec<(String, bool)> {
+            parse_revision_arg(repo, arg)
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.hash, item.negated))
+                .collect()
+        };
+
+        assert_eq!(
+            selected("side..main"),
+            [(h.fourth.clone(), false), (h.side.clone(), true)]
+        );
+        assert_eq!(
+            selected("side.."),
+            [(h.fourth.clone(), false), (h.side.clone(), true)]
+        );
+        assert_eq!(
+            selected("side...HEAD~2"),
+            [
+                (h.second.clone(), false),
+                (h.side.clone(), false),
+                (h.first.clone(), true)
+            ]
+        );
+        assert_eq!(selected("^side"), [(h.side.clone(), true)]);
+        assert_eq!(
+            selected("HEAD^^@"),
+            [(h.second.clone(), false), (h.side.clone(), false)]
+        );
+        assert_eq!(
+            selected("HEAD^^!"),
+            [
+                (h.merge.clone(), false),
+                (h.second.clone(), true),
+                (h.side.clone(), true)
+            ]
+        );
+        assert_eq!(
+            selected("HEAD^^-2"),
+            [(h.merge.clone(), false), (h.side.clone(), true)]
+        );
+    }
+}
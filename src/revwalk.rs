@@ -0,0 +1,302 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::{
+    error::{Error, Result},
+    repository::Repository,
+    structures::ObjectType,
+};
+
+/// The parents of the commit `hash`.
+pub fn commit_parents(repo: &Repository, hash: &str) -> Result<Vec<String>> {
+    Ok(commit_info(repo, hash)?.0)
+}
+
+/// The parents and committer timestamp of the commit `hash`.
+fn commit_info(repo: &Repository, hash: &str) -> Result<(Vec<String>, u64)> {
+    let object = repo.read_object(hash)?;
+
+    if object.obj_type != ObjectType::Commit {
+        return Err(Error::InvalidArgument(format!(
+            "`{hash}` is a {}, not a commit",
+            object.obj_type
+        )));
+    }
+
+    let meta = object.meta.unwrap_or_default();
+    let time = meta.committer.map(|committer| committer.time).unwrap_or(0);
+
+    Ok((meta.parents, time))
+}
+
+/// Every commit reachable from `hash`, including `hash` itself.
+pub fn ancestors(repo: &Repository, hash: &str) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![hash.to_owned()];
+
+    while let Some(commit) = pending.pop() {
+        if !seen.insert(commit.clone()) {
+            continue;
+        }
+
+        pending.extend(commit_parents(repo, &commit)?);
+    }
+
+    Ok(seen)
+}
+
+/// How many commits past the last one that still leads somewhere interesting [`uninteresting`]
+/// keeps walking, in case a commit's clock was behind its parent's. git uses the same margin.
+const SLOP: usize = 5;
+
+/// The commits a walk from `tips` has to stop at to leave out everything reachable from
+/// `negatives`, as for `tip ^negative` or `negative..tip`. Like git's `limit_list`, this walks
+/// all of them newest first, marking the parents of uninteresting commits as uninteresting too,
+/// and stops once nothing interesting is left to walk. Unlike [`ancestors`] of the negatives it
+/// costs about as much as the range itself, not the whole history below it.
+pub fn uninteresting(
+    repo: &Repository,
+    tips: &[String],
+    negatives: &[String],
+) -> Result<HashSet<String>> {
+    let mut marked: HashSet<String> = negatives.iter().cloned().collect();
+    // The parents of every commit queued so far, read along with its date.
+    let mut parents_of = HashMap::<String, Vec<String>>::new();
+    let mut walked = HashSet::new();
+    let mut queue = BinaryHeap::<(u64, String)>::new();
+
+    for hash in negatives.iter().chain(tips) {
+        if !parents_of.contains_key(hash) {
+            let (parents, time) = commit_info(repo, hash)?;
+            parents_of.insert(hash.clone(), parents);
+            queue.push((time, hash.clone()));
+        }
+    }
+
+    let mut slop = SLOP;
+
+    while let Some((_, commit)) = queue.pop() {
+        let parents = parents_of[&commit].clone();
+
+        for parent in &parents {
+            if !parents_of.contains_key(parent) {
+                let (grandparents, time) = commit_info(repo, parent)?;
+                parents_of.insert(parent.clone(), grandparents);
+                queue.push((time, parent.clone()));
+            }
+        }
+
+        if marked.contains(&commit) {
+            // Parents that were already walked as interesting (their clock was ahead) take
+            // the mark down to their own parents as well.
+            let mut pending = parents;
+
+            while let Some(parent) = pending.pop() {
+                if marked.insert(parent.clone()) && walked.contains(&parent) {
+                    pending.extend(parents_of[&parent].iter().cloned());
+                }
+            }
+
+            if queue.iter().any(|(_, hash)| !marked.contains(hash)) {
+                slop = SLOP;
+            } else {
+                slop -= 1;
+
+                if slop == 0 {
+                    break;
+                }
+            }
+        }
+
+        walked.insert(commit);
+    }
+
+    Ok(marked)
+}
+
+/// How many commits `local` has that `upstream` does not, and the other way round.
+pub fn ahead_behind(repo: &Repository, local: &str, upstream: &str) -> Result<(usize, usize)> {
+    let local = ancestors(repo, local)?;
+    let upstream = ancestors(repo, upstream)?;
+
+    Ok((
+        local.difference(&upstream).count(),
+        upstream.difference(&local).count(),
+    ))
+}
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// The best common ancestors of `one` and `two`: common ancestors that are not themselves
+/// ancestors of another common ancestor. Usually there is exactly one; unrelated histories have
+/// none.
+pub fn merge_bases(repo: &Repository, one: &str, two: &str) -> Result<Vec<String>> {
+    merge_bases_many(repo, &[one.to_owned()], two)
+}
+
+/// The best common ancestors of `two` and any of `ones`, as if `ones` were the parents of a
+/// single commit. Recursive merges use this to find the bases of a merged, virtual base.
+pub fn merge_bases_many(repo: &Repository, ones: &[String], two: &str) -> Result<Vec<String>> {
+    if ones.iter().any(|one| one == two) {
+        return Ok(vec![two.to_owned()]);
+    }
+
+    // Walk both histories newest first, painting each commit with the side(s) it is reachable
+    // from, as git's `paint_down_to_common` does. A commit reached from both sides is a
+    // candidate, and everything below it is stale.
+    let mut flags = HashMap::<String, u8>::new();
+    let mut times = HashMap::<String, u64>::new();
+    let mut queue = BinaryHeap::<(u64, String)>::new();
+    let mut candidates = Vec::new();
+
+    let seeds = ones
+        .iter()
+        .map(|one| (one.as_str(), PARENT1))
+        .chain([(two, PARENT2)]);
+
+    for (hash, flag) in seeds {
+        let (_, time) = commit_info(repo, hash)?;
+        times.insert(hash.to_owned(), time);
+        *flags.entry(hash.to_owned()).or_default() |= flag;
+        queue.push((time, hash.to_owned()));
+    }
+
+    while queue
+        .iter()
+        .any(|(_, hash)| flags.get(hash).copied().unwrap_or(0) & STALE == 0)
+    {
+        let Some((_, commit)) = queue.pop() else {
+            break;
+        };
+
+        let mut commit_flags = flags[&commit] & (PARENT1 | PARENT2 | STALE);
+
+        if commit_flags & (PARENT1 | PARENT2) == PARENT1 | PARENT2 {
+            if flags[&commit] & RESULT == 0 {
+                *flags.get_mut(&commit).unwrap() |= RESULT;
+                candidates.push(commit.clone());
+            }
+            commit_flags |= STALE;
+        }
+
+        for parent in commit_parents(repo, &commit)? {
+            let parent_flags = flags.entry(parent.clone()).or_default();
+
+            if *parent_flags & commit_flags == commit_flags {
+                continue;
+            }
+
+            *parent_flags |= commit_flags;
+
+            let time = match times.get(&parent) {
+                Some(time) => *time,
+                None => {
+                    let (_, time) = commit_info(repo, &parent)?;
+                    times.insert(parent.clone(), time);
+                    time
+                }
+            };
+            queue.push((time, parent));
+        }
+    }
+
+    // Candidates later reached from another candidate are not the best ones.
+    let candidates: Vec<String> = candidates
+        .into_iter()
+        .filter(|hash| flags[hash] & STALE == 0)
+        .collect();
+
+    if candidates.len() <= 1 {
+        return Ok(candidates);
+    }
+
+    // Drop candidates that are reachable from another candidate.
+    let mut bases = Vec::new();
+
+    for candidate in &candidates {
+        let mut redundant = false;
+
+        for other in candidates.iter().filter(|other| *other != candidate) {
+            if ancestors(repo, other)?.contains(candidate) {
+                redundant = true;
+                break;
+            }
+        }
+
+        if !redundant {
+            bases.push(candidate.clone());
+        }
+    }
+
+    Ok(bases)
+}
+
+/// Whether `ancestor` is reachable from `descendant` (a commit is its own ancestor).
+pub fn is_ancestor(repo: &Repository, ancestor: &str, descendant: &str) -> Result<bool> {
+    Ok(merge_bases(repo, ancestor, descendant)?
+        .iter()
+        .any(|base| base == ancestor))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+    use crate::test_support::TestRepo;
+
+    /// The commits reachable from `tip` without passing through `stop`.
+    fn walk(repo: &Repository, tip: &str, stop: &HashSet<String>) -> HashSet<String> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![tip.to_owned()];
+
+        while let Some(commit) = pending.pop() {
+            if !stop.contains(&commit) && seen.insert(commit.clone()) {
+                pending.extend(commit_parents(repo, &commit).unwrap());
+            }
+        }
+
+        seen
+    }
+
+    #[test]
+    fn ranges_stop_walking_below_the_excluded_side() {
+        let test = TestRepo::new("revwalk-range");
+        let repo = &test.repo;
+
+        let mut base = test.commit(&[("f", "0")], &[], "root");
+        for i in 1..20 {
+            base = test.commit(&[("f", &i.to_string())], &[&base], "old");
+        }
+
+        let side = test.commit(&[("g", "side")], &[&base], "side");
+        let main = test.commit(&[("f", "main")], &[&base], "main");
+        let merge = test.commit(&[("f", "main"), ("g", "side")], &[&main, &side], "merge");
+
+        let marked = uninteresting(repo, slice::from_ref(&merge), slice::from_ref(&side)).unwrap();
+        assert_eq!(
+            walk(repo, &merge, &marked),
+            HashSet::from([merge.clone(), main.clone()])
+        );
+        // The history below the range is never looked at.
+        assert!(marked.len() < 10);
+
+        // A commit whose clock is behind its parents' is only reached after they were walked
+        // as interesting, and takes the mark down to them then.
+        let skewed = repo
+            .write_object(
+                &ObjectType::Commit,
+                format!(
+                    "tree {}\nparent {merge}\nauthor A <a@x> 1 +0000\ncommitter A <a@x> 1 +0000\n\nskewed\n",
+                    test.tree(&[("f", "skewed")])
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let marked = uninteresting(repo, slice::from_ref(&main), &[skewed]).unwrap();
+        assert!(walk(repo, &main, &marked).is_empty());
+    }
+}
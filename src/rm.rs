@@ -0,0 +1,152 @@
+use crate::{
+    error::{Error, Result},
+    index::Index,
+    pathspec::Pathspec,
+    repository::Repository,
+    tree::head_files,
+    worktree::{cwd_prefix, entry_state, remove_worktree_file, WorktreeState},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct RmOptions {
+    /// Only unstage the files, leaving them in the work tree.
+    pub cached: bool,
+    /// Allow removing whole directories.
+    pub recursive: bool,
+    /// Skip the checks that protect uncommitted changes.
+    pub force: bool,
+}
+
+/// Removes the tracked files matched by `args` from the index and, unless `cached`, from the
+/// work tree. Returns the removed paths.
+pub fn rm(repo: &Repository, args: &[String], options: &RmOptions) -> Result<Vec<String>> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+
+    if args.is_empty() {
+        return Err(Error::InvalidArgument(
+            "No pathspec was given. Which files should I remove?".to_owned(),
+        ));
+    }
+
+    let pathspec = Pathspec::new(&work_tree, &cwd_prefix(repo)?, args)?;
+    let mut index = Index::read(repo)?;
+
+    let mut paths: Vec<Vec<u8>> = Vec::new();
+
+    for item in &pathspec.items {
+        let matched: Vec<&Vec<u8>> = index
+            .entries
+            .iter()
+            .map(|entry| &entry.path)
+            .filter(|path| item.matches(path))
+            .collect();
+
+        if matched.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "pathspec '{}' did not match any files",
+                item.original
+            )));
+        }
+
+        let only_inside = !item.glob && matched.iter().all(|path| **path != item.path);
+
+        if only_inside && !options.recursive {
+            return Err(Error::InvalidArgument(format!(
+                "not removing '{}' recursively without -r",
+                item.original
+            )));
+        }
+
+        paths.extend(matched.into_iter().cloned());
+    }
+
+    paths.sort();
+    paths.dedup();
+
+    if !options.force {
+        check_removable(repo, &index, &paths, options.cached)?;
+    }
+
+    for path in &paths {
+        index.remove(path);
+
+        if !options.cached {
+            remove_worktree_file(repo, path)?;
+        }
+    }
+
+    index.write(repo)?;
+
+    Ok(paths
+        .iter()
+        .map(|path| String::from_utf8_lossy(path).to_string())
+        .collect())
+}
+
+/// Refuses to lose changes that exist nowhere else: staged content that differs from `HEAD`, or
+/// work tree content that differs from the index.
+fn check_removable(
+    repo: &Repository,
+    index: &Index,
+    paths: &[Vec<u8>],
+    cached: bool,
+) -> Result<()> {
+    let head = head_files(repo)?;
+
+    let mut staged_and_local = Vec::new();
+    let mut staged = Vec::new();
+    let mut local = Vec::new();
+
+    for path in paths {
+        let Some(entry) = index.entry(path, 0) else {
+            continue;
+        };
+
+        if entry.intent_to_add() {
+            continue;
+        }
+
+        let staged_differs = head
+            .get(path)
+            .is_none_or(|file| file.hash != entry.hash || file.mode != entry.mode);
+        let local_differs = entry_state(repo, entry)? == WorktreeState::Modified;
+        let name = String::from_utf8_lossy(path).to_string();
+
+        if staged_differs && local_differs {
+            staged_and_local.push(name);
+        } else if !cached && staged_differs {
+            staged.push(name);
+        } else if !cached && local_differs {
+            local.push(name);
+        }
+    }
+
+    let mut problems = Vec::new();
+
+    if !staged_and_local.is_empty() {
+        problems.push(format!(
+            "the following files have staged content different from both the file and the HEAD:\n    {}\n(use -f to force removal)",
+            staged_and_local.join("\n    ")
+        ));
+    }
+
+    if !staged.is_empty() {
+        problems.push(format!(
+            "the following files have changes staged in the index:\n    {}\n(use --cached to keep the file, or -f to force removal)",
+            staged.join("\n    ")
+        ));
+    }
+
+    if !local.is_empty() {
+        problems.push(format!(
+            "the following files have local modifications:\n    {}\n(use --cached to keep the file, or -f to force removal)",
+            local.join("\n    ")
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument(problems.join("\n")))
+    }
+}
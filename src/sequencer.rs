@@ -0,0 +1,750 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    fs, io,
+};
+
+use crate::{
+    checkout::{require_clean_index, reset_merge, switch_trees, TreeUpdate},
+    commit::{cleanup_message, commit, remove_merge_state, CommitOptions},
+    config::{read_config_file, set_config_value, ConfigLocation},
+    error::{Error, Result},
+    index::Index,
+    log::subject,
+    merge::{merge_trees, record_conflicts, MergeLabels, MergeOptions},
+    refs::update_ref,
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, parse_revision_arg, peel_to, resolve_commit},
+    revwalk::{ancestors, commit_parents},
+    state::{read_state, state_dir, write_state},
+    structures::ObjectType,
+    tree::{commit_tree, write_files},
+};
+
+/// The directory under `.git` where a cherry-pick or revert of several commits keeps the commits
+/// it has yet to apply, laid out as git's `sequencer` directory.
+const STATE_DIR: &str = "sequencer";
+
+/// Whether the sequencer applies commits or undoes them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequencerAction {
+    CherryPick,
+    Revert,
+}
+
+impl SequencerAction {
+    /// The command, as the user types it.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::CherryPick => "cherry-pick",
+            Self::Revert => "revert",
+        }
+    }
+
+    /// The word starting the action's lines in the todo list.
+    fn todo_word(&self) -> &'static str {
+        match self {
+            Self::CherryPick => "pick",
+            Self::Revert => "revert",
+        }
+    }
+
+    /// The file remembering the commit a stopped action was working on.
+    fn head_file(&self) -> &'static str {
+        match self {
+            Self::CherryPick => "CHERRY_PICK_HEAD",
+            Self::Revert => "REVERT_HEAD",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SequencerOptions {
+    /// The parent (counting from 1) whose side of a merge is kept (`-m`).
+    pub mainline: Option<usize>,
+    /// Append `(cherry picked from commit …)` to the message (`-x`).
+    pub record_origin: bool,
+    /// Apply the changes to the index and work tree without committing them.
+    pub no_commit: bool,
+    /// Let the user edit each commit message.
+    pub edit: bool,
+}
+
+/// What a cherry-pick or revert did until it finished or stopped, and whether it stopped on a
+/// conflict.
+#[derive(Debug, Clone, Default)]
+pub struct SequencerSummary {
+    pub messages: Vec<String>,
+    pub failed: bool,
+}
+
+impl Display for SequencerSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for message in &self.messages {
+            writeln!(f, "{message}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One line of the todo list.
+#[derive(Debug, Clone, PartialEq)]
+struct TodoItem {
+    action: SequencerAction,
+    hash: String,
+}
+
+/// `pgit cherry-pick` and `pgit revert`: applies, or undoes, the commits `revisions` select on
+/// top of `HEAD`, one commit each. Single commits are taken in the order given; ranges are
+/// walked oldest first for a cherry-pick and newest first for a revert.
+///
+/// A conflict stops the sequence with the conflicted paths at index stages 1 to 3 and the
+/// commit in `CHERRY_PICK_HEAD` or `REVERT_HEAD`; when several commits were selected, the ones
+/// left are kept in `.git/sequencer` for `--continue`.
+pub fn pick_revisions(
+    repo: &Repository,
+    action: SequencerAction,
+    revisions: &[String],
+    options: &SequencerOptions,
+) -> Result<SequencerSummary> {
+    if state_dir(repo, STATE_DIR).exists() {
+        let name = match read_todo(repo)?.first() {
+            Some(item) => item.action.name(),
+            None => action.name(),
+        };
+
+        return Err(Error::InvalidArgument(format!(
+            "A {name} is already in progress. Go on with `pgit {name} --continue`, or give up with `pgit {name} --abort`"
+        )));
+    }
+
+    if Index::read(repo)?.has_conflicts() {
+        let doing = match action {
+            SequencerAction::CherryPick => "Cherry-picking",
+            SequencerAction::Revert => "Reverting",
+        };
+
+        return Err(Error::InvalidArgument(format!(
+            "{doing} is not possible because you have unmerged files. Fix them up in the work tree, then use `pgit add` to mark them as resolved"
+        )));
+    }
+
+    if options.mainline == Some(0) {
+        return Err(Error::InvalidArgument(
+            "the mainline parent number must be greater than zero".to_owned(),
+        ));
+    }
+
+    let (commits, walked) = selected_commits(repo, action, revisions)?;
+
+    if commits.is_empty() {
+        return Err(Error::InvalidArgument("empty commit set passed".to_owned()));
+    }
+
+    for hash in &commits {
+        let parents = commit_parents(repo, hash)?.len();
+
+        match options.mainline {
+            None if parents > 1 => {
+                return Err(Error::InvalidArgument(format!(
+                    "commit `{hash}` is a merge but no -m option was given"
+                )))
+            }
+            Some(mainline) if parents > 1 && mainline > parents => {
+                return Err(Error::InvalidArgument(format!(
+                    "commit `{hash}` does not have parent {mainline}"
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    let head = repo.head()?;
+
+    if !options.no_commit {
+        let head_tree = head
+            .as_ref()
+            .map(|head| commit_tree(repo, head))
+            .transpose()?;
+        require_clean_index(repo, head_tree.as_deref(), TreeUpdate::Merge)?;
+    }
+
+    let todo: Vec<TodoItem> = commits
+        .into_iter()
+        .map(|hash| TodoItem { action, hash })
+        .collect();
+
+    // Like git, only a single commit named on its own is picked without the sequencer.
+    if walked || revisions.len() > 1 {
+        fs::create_dir_all(state_dir(repo, STATE_DIR))?;
+        write_state(
+            repo,
+            STATE_DIR,
+            "head",
+            &format!("{}\n", head.unwrap_or_default()),
+        )?;
+        write_options(repo, options)?;
+    }
+
+    run_todo(repo, todo, options, SequencerSummary::default())
+}
+
+/// `--continue`: commits what the user staged to resolve the stop, then goes on with the commits
+/// left. The editor is opened on the message when `edit` is set.
+pub fn sequencer_continue(
+    repo: &Repository,
+    action: SequencerAction,
+    edit: bool,
+) -> Result<SequencerSummary> {
+    let stopped = stopped_at(repo)?;
+    let sequencing = state_dir(repo, STATE_DIR).exists();
+
+    if stopped.is_none() && !sequencing {
+        return Err(no_operation(action));
+    }
+
+    if Index::read(repo)?.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "You must edit all merge conflicts and then mark them as resolved using `pgit add`"
+                .to_owned(),
+        ));
+    }
+
+    let options = read_options(repo)?;
+    let mut summary = SequencerSummary::default();
+
+    if stopped.is_some() {
+        let edit = edit || options.edit;
+        // Without the editor, the `# Conflicts:` comments have to be taken out here.
+        let messages = match fs::read_to_string(repo.git_dir().join("MERGE_MSG")) {
+            Ok(message) if !edit => vec![cleanup_message(&message, true)],
+            Ok(_) => Vec::new(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let committed = commit(
+            repo,
+            &CommitOptions {
+                messages,
+                edit: Some(edit),
+                ..Default::default()
+            },
+        )?;
+        summary.messages.push(committed.to_string());
+    }
+
+    if !sequencing {
+        return Ok(summary);
+    }
+
+    let mut todo = read_todo(repo)?;
+    if !todo.is_empty() {
+        todo.remove(0);
+    }
+
+    run_todo(repo, todo, &options, summary)
+}
+
+/// `--skip`: throws away the changes of the commit the sequence stopped at and goes on with the
+/// commits left.
+pub fn sequencer_skip(repo: &Repository, action: SequencerAction) -> Result<SequencerSummary> {
+    let sequencing = state_dir(repo, STATE_DIR).exists();
+
+    if stopped_at(repo)?.is_none() && !sequencing {
+        return Err(no_operation(action));
+    }
+
+    let head = repo.head()?;
+    reset_to(repo, head.as_deref(), head.as_deref())?;
+
+    if !sequencing {
+        return Ok(SequencerSummary::default());
+    }
+
+    let mut todo = read_todo(repo)?;
+    if !todo.is_empty() {
+        todo.remove(0);
+    }
+
+    run_todo(
+        repo,
+        todo,
+        &read_options(repo)?,
+        SequencerSummary::default(),
+    )
+}
+
+/// `--abort`: gives up on the cherry-pick or revert and goes back to the commit it started
+/// from. Changes that were not staged when it started are kept.
+pub fn sequencer_abort(repo: &Repository, action: SequencerAction) -> Result<SequencerSummary> {
+    let head = repo.head()?;
+    let target = if state_dir(repo, STATE_DIR).exists() {
+        let original = read_state(repo, STATE_DIR, "head")?.unwrap_or_default();
+        let safety = read_state(repo, STATE_DIR, "abort-safety")?.unwrap_or_default();
+
+        if safety.trim() != head.as_deref().unwrap_or_default() {
+            // Commits were made since the sequence stopped; rewinding would lose them.
+            fs::remove_dir_all(state_dir(repo, STATE_DIR))?;
+            return Ok(SequencerSummary {
+                messages: vec![
+                    "warning: You seem to have moved HEAD. Not rewinding, check your HEAD!"
+                        .to_owned(),
+                ],
+                failed: false,
+            });
+        }
+
+        Some(original.trim().to_owned()).filter(|original| !original.is_empty())
+    } else if stopped_at(repo)?.is_some() {
+        head.clone()
+    } else {
+        return Err(no_operation(action));
+    };
+
+    reset_to(repo, head.as_deref(), target.as_deref())?;
+
+    match fs::remove_dir_all(state_dir(repo, STATE_DIR)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(SequencerSummary::default()),
+    }
+}
+
+/// Moves `HEAD` from `head` to `target` the way `git reset --merge` does, keeping the changes
+/// that were never staged, and forgets about the commit that stopped.
+fn reset_to(repo: &Repository, head: Option<&str>, target: Option<&str>) -> Result<()> {
+    let tree = target.map(|target| commit_tree(repo, target)).transpose()?;
+
+    reset_merge(repo, tree.as_deref())?;
+
+    if let (Some(head), Some(target)) = (head, target) {
+        update_ref(repo, "ORIG_HEAD", head, None, "", false)?;
+        update_ref(
+            repo,
+            "HEAD",
+            target,
+            Some(head),
+            &format!("reset: moving to {target}"),
+            true,
+        )?;
+    }
+
+    remove_merge_state(repo)
+}
+
+/// Applies the todo list until it is done or a commit stops the sequence. The sequencer
+/// directory, when there is one, is kept up to date after every commit.
+fn run_todo(
+    repo: &Repository,
+    mut todo: Vec<TodoItem>,
+    options: &SequencerOptions,
+    mut summary: SequencerSummary,
+) -> Result<SequencerSummary> {
+    let sequencing = state_dir(repo, STATE_DIR).exists();
+
+    while let Some(item) = todo.first() {
+        let go_on = apply(repo, item, options, &mut summary)?;
+
+        if go_on {
+            todo.remove(0);
+        }
+
+        if sequencing {
+            write_todo(repo, &todo)?;
+            write_state(
+                repo,
+                STATE_DIR,
+                "abort-safety",
+                &format!("{}\n", repo.head()?.unwrap_or_default()),
+            )?;
+        }
+
+        if !go_on {
+            return Ok(summary);
+        }
+    }
+
+    if sequencing {
+        fs::remove_dir_all(state_dir(repo, STATE_DIR))?;
+    }
+
+    Ok(summary)
+}
+
+/// Merges the change of one commit, or its reverse, into `HEAD` and commits the result. Returns
+/// whether the sequence goes on: it stops on conflicts, and when the change turns out to be
+/// there already.
+fn apply(
+    repo: &Repository,
+    item: &TodoItem,
+    options: &SequencerOptions,
+    summary: &mut SequencerSummary,
+) -> Result<bool> {
+    let TodoItem { action, hash } = item;
+    let name = action.name();
+    let head = repo.head()?;
+    let head_tree = match &head {
+        Some(head) => commit_tree(repo, head)?,
+        None => write_files(repo, &Default::default())?,
+    };
+
+    // Committing the result would sweep up whatever else is staged.
+    if !options.no_commit {
+        require_clean_index(repo, Some(&head_tree), TreeUpdate::Merge)?;
+    }
+
+    let meta = repo.read_object(hash)?.meta.unwrap_or_default();
+    let original = meta.message.clone().unwrap_or_default();
+    let original_subject = subject(&original);
+    let parent = match (meta.parents.len(), options.mainline) {
+        (2.., Some(mainline)) => meta.parents.get(mainline - 1),
+        _ => meta.parents.first(),
+    };
+    let parent_tree = parent.map(|parent| commit_tree(repo, parent)).transpose()?;
+    let tree = commit_tree(repo, hash)?;
+    let short = abbreviate(repo, hash, default_abbrev(repo))?;
+    let label = format!("{short} ({original_subject})");
+
+    let (base, theirs, labels, message) = match action {
+        SequencerAction::CherryPick => {
+            let message = if options.record_origin {
+                record_origin(&original, hash)
+            } else {
+                original.clone()
+            };
+            let labels = MergeLabels {
+                base: format!("parent of {label}"),
+                ours: "HEAD".to_owned(),
+                theirs: label,
+            };
+
+            (parent_tree, Some(tree), labels, message)
+        }
+        SequencerAction::Revert => {
+            let mut message =
+                format!("Revert \"{original_subject}\"\n\nThis reverts commit {hash}");
+            if let (2.., Some(parent)) = (meta.parents.len(), parent) {
+                message += &format!(", reversing\nchanges made to {parent}");
+            }
+            message += ".\n";
+
+            let labels = MergeLabels {
+                base: label.clone(),
+                ours: "HEAD".to_owned(),
+                theirs: format!("parent of {label}"),
+            };
+
+            (Some(tree), parent_tree, labels, message)
+        }
+    };
+
+    let theirs = match theirs {
+        Some(theirs) => theirs,
+        None => write_files(repo, &Default::default())?,
+    };
+    let merged = merge_trees(
+        repo,
+        base.as_deref(),
+        &head_tree,
+        &theirs,
+        &labels,
+        &MergeOptions::configured(repo)?,
+    )?;
+    let result = write_files(repo, &merged.files)?;
+
+    switch_trees(
+        repo,
+        Some(&head_tree),
+        Some(&result),
+        false,
+        TreeUpdate::Merge,
+    )?;
+    record_conflicts(repo, &merged.conflicts)?;
+
+    let git_dir = repo.git_dir();
+    summary.messages.extend(merged.messages);
+
+    if !merged.conflicts.is_empty() {
+        let mut merge_message = message;
+        merge_message += "\n# Conflicts:\n";
+        for conflict in &merged.conflicts {
+            merge_message += &format!("#\t{}\n", String::from_utf8_lossy(&conflict.path));
+        }
+
+        fs::write(git_dir.join("MERGE_MSG"), merge_message)?;
+        if !options.no_commit {
+            fs::write(git_dir.join(action.head_file()), format!("{hash}\n"))?;
+        }
+        update_ref(repo, "AUTO_MERGE", &result, None, "", false)?;
+
+        let verb = match action {
+            SequencerAction::CherryPick => "apply",
+            SequencerAction::Revert => "revert",
+        };
+        summary.messages.push(format!(
+            "error: could not {verb} {short}... {original_subject}"
+        ));
+
+        if options.no_commit {
+            summary.messages.extend([
+                "hint: after resolving the conflicts, mark the corrected paths".to_owned(),
+                "hint: with 'pgit add <paths>' or 'pgit rm <paths>'".to_owned(),
+            ]);
+            summary.failed = true;
+
+            return Ok(false);
+        }
+
+        summary.messages.extend([
+            "hint: After resolving the conflicts, mark them with".to_owned(),
+            "hint: \"pgit add/rm <pathspec>\", then run".to_owned(),
+            format!("hint: \"pgit {name} --continue\"."),
+            format!("hint: You can instead skip this commit with \"pgit {name} --skip\"."),
+            format!("hint: To abort and get back to the state before \"pgit {name}\","),
+            format!("hint: run \"pgit {name} --abort\"."),
+        ]);
+        summary.failed = true;
+
+        return Ok(false);
+    }
+
+    if options.no_commit {
+        fs::write(git_dir.join("MERGE_MSG"), message)?;
+        return Ok(true);
+    }
+
+    if result == head_tree && *action == SequencerAction::Revert {
+        fs::write(git_dir.join("MERGE_MSG"), message)?;
+        summary.messages.push("nothing to commit".to_owned());
+        summary.failed = true;
+
+        return Ok(false);
+    }
+
+    if result == head_tree {
+        fs::write(git_dir.join("MERGE_MSG"), message)?;
+        fs::write(git_dir.join(action.head_file()), format!("{hash}\n"))?;
+
+        summary.messages.extend([
+            "The previous cherry-pick is now empty, possibly due to conflict resolution."
+                .to_owned(),
+            "If you wish to commit it anyway, use:".to_owned(),
+            String::new(),
+            "    pgit commit --allow-empty".to_owned(),
+            String::new(),
+            "Otherwise, please use 'pgit cherry-pick --skip'".to_owned(),
+        ]);
+        summary.failed = true;
+
+        return Ok(false);
+    }
+
+    // A cherry-pick keeps the author of the commit; a revert is the user's own.
+    let author = match action {
+        SequencerAction::CherryPick => meta.author.as_ref(),
+        SequencerAction::Revert => None,
+    };
+    let committed = commit(
+        repo,
+        &CommitOptions {
+            reflog: Some(format!("{name}: {}", subject(&message))),
+            messages: vec![message],
+            edit: Some(options.edit),
+            author: author.map(|author| {
+                format!(
+                    "{} <{}>",
+                    author.name,
+                    author.email.as_deref().unwrap_or_default()
+                )
+            }),
+            date: author.map(|author| format!("@{} {}", author.time, author.zone)),
+            ..Default::default()
+        },
+    )?;
+    summary.messages.push(committed.to_string());
+
+    Ok(true)
+}
+
+/// The commits `revisions` select, and whether history had to be walked for them. Without
+/// negated revisions they are the commits named, in the order given; otherwise the history
+/// between them is walked, oldest first for a cherry-pick and newest first for a revert, as git
+/// does.
+fn selected_commits(
+    repo: &Repository,
+    action: SequencerAction,
+    revisions: &[String],
+) -> Result<(Vec<String>, bool)> {
+    let mut items = Vec::new();
+    for revision in revisions {
+        items.extend(parse_revision_arg(repo, revision)?);
+    }
+
+    let mut excluded = HashSet::new();
+    let mut tips = Vec::new();
+    for item in items {
+        let hash = peel_to(repo, &item.hash, &ObjectType::Commit)?;
+
+        if item.negated {
+            excluded.extend(ancestors(repo, &hash)?);
+        } else {
+            tips.push(hash);
+        }
+    }
+
+    if excluded.is_empty() {
+        return Ok((tips, false));
+    }
+
+    // Parents come before their children, so that each commit applies on top of what it was
+    // made on.
+    let mut seen = HashSet::new();
+    let mut commits = Vec::new();
+    let mut pending: Vec<(String, bool)> = tips.into_iter().rev().map(|tip| (tip, false)).collect();
+
+    while let Some((commit, parents_done)) = pending.pop() {
+        if parents_done {
+            commits.push(commit);
+            continue;
+        }
+
+        if excluded.contains(&commit) || !seen.insert(commit.clone()) {
+            continue;
+        }
+
+        pending.push((commit.clone(), true));
+        for parent in commit_parents(repo, &commit)?.into_iter().rev() {
+            pending.push((parent, false));
+        }
+    }
+
+    if action == SequencerAction::Revert {
+        commits.reverse();
+    }
+
+    Ok((commits, true))
+}
+
+/// Appends the `(cherry picked from commit …)` line of `-x`: right under a closing block of
+/// trailers such as `Signed-off-by:`, or as a paragraph of its own.
+fn record_origin(message: &str, hash: &str) -> String {
+    let mut message = message.trim_end().to_owned();
+    let has_trailers = message.split_once("\n\n").is_some_and(|(_, body)| {
+        let last = body.rsplit("\n\n").next().unwrap_or_default();
+
+        last.lines().all(|line| {
+            line.starts_with("(cherry picked from commit ")
+                || line.split_once(": ").is_some_and(|(key, _)| {
+                    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                })
+        })
+    });
+
+    message += if has_trailers { "\n" } else { "\n\n" };
+    message += &format!("(cherry picked from commit {hash})\n");
+
+    message
+}
+
+/// The commit a stopped cherry-pick or revert was working on, if there is one.
+fn stopped_at(repo: &Repository) -> Result<Option<String>> {
+    for action in [SequencerAction::CherryPick, SequencerAction::Revert] {
+        match fs::read_to_string(repo.git_dir().join(action.head_file())) {
+            Ok(contents) => return Ok(Some(contents.trim().to_owned())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(None)
+}
+
+fn no_operation(action: SequencerAction) -> Error {
+    Error::InvalidArgument(format!(
+        "There is no cherry-pick or revert in progress, so there is nothing to {}",
+        action.name()
+    ))
+}
+
+fn write_todo(repo: &Repository, todo: &[TodoItem]) -> Result<()> {
+    let abbrev = default_abbrev(repo);
+    let mut text = String::new();
+
+    for item in todo {
+        let meta = repo.read_object(&item.hash)?.meta.unwrap_or_default();
+
+        text += &format!(
+            "{} {} {}\n",
+            item.action.todo_word(),
+            abbreviate(repo, &item.hash, abbrev)?,
+            subject(meta.message.as_deref().unwrap_or_default())
+        );
+    }
+
+    write_state(repo, STATE_DIR, "todo", &text)
+}
+
+fn read_todo(repo: &Repository) -> Result<Vec<TodoItem>> {
+    let text = read_state(repo, STATE_DIR, "todo")?.unwrap_or_default();
+    let mut todo = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || Error::InvalidArgument(format!("invalid line {}: {line}", number + 1));
+        let mut words = line.split_whitespace();
+        let action = match words.next() {
+            Some("pick" | "p") => SequencerAction::CherryPick,
+            Some("revert") => SequencerAction::Revert,
+            _ => return Err(invalid()),
+        };
+        let hash = resolve_commit(repo, words.next().ok_or_else(invalid)?)?;
+
+        todo.push(TodoItem { action, hash });
+    }
+
+    Ok(todo)
+}
+
+/// Saves the options that differ from the defaults in `sequencer/opts`, in git's config format,
+/// for `--continue` and `--skip` to pick up.
+fn write_options(repo: &Repository, options: &SequencerOptions) -> Result<()> {
+    let path = state_dir(repo, STATE_DIR).join("opts");
+
+    for (name, set) in [
+        ("no-commit", options.no_commit),
+        ("edit", options.edit),
+        ("record-origin", options.record_origin),
+    ] {
+        if set {
+            set_config_value(&path, &format!("options.{name}"), "true", false)?;
+        }
+    }
+
+    if let Some(mainline) = options.mainline {
+        set_config_value(&path, "options.mainline", &mainline.to_string(), false)?;
+    }
+
+    Ok(())
+}
+
+fn read_options(repo: &Repository) -> Result<SequencerOptions> {
+    let config = read_config_file(
+        &state_dir(repo, STATE_DIR).join("opts"),
+        ConfigLocation::Command,
+        Some(repo.git_dir()),
+    )?;
+
+    Ok(SequencerOptions {
+        mainline: config
+            .get("options.mainline")
+            .and_then(|mainline| mainline.parse().ok()),
+        record_origin: config.get_bool("options.record-origin").unwrap_or(false),
+        no_commit: config.get_bool("options.no-commit").unwrap_or(false),
+        edit: config.get_bool("options.edit").unwrap_or(false),
+    })
+}
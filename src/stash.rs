@@ -0,0 +1,544 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+};
+
+use crate::{
+    add::stage_path,
+    checkout::{reset_hard, switch_trees, TreeUpdate},
+    diff::RENAME_THRESHOLD,
+    diff_command::{diff, DiffOptions},
+    error::{Error, Result},
+    ident::{identity, Role},
+    ignore::IgnoreRules,
+    index::{Index, IndexEntry, MODE_GITLINK},
+    log::subject,
+    merge::{merge_trees, record_conflicts, MergeLabels, MergeOptions},
+    pathspec::Pathspec,
+    refs::{
+        delete_ref, delete_reflog_entry, dwim_ref, read_reflog, ref_target, resolve_ref, update_ref,
+    },
+    repository::Repository,
+    revision::{abbreviate, default_abbrev, resolve_commit},
+    structures::ObjectType,
+    switch::{switch, SwitchOptions},
+    tree::{commit_tree, flatten_tree, write_files, write_tree},
+    utils::path_from_bytes,
+    worktree::{
+        checkout_file, cwd_prefix, entry_state, remove_worktree_file, untracked_files,
+        WorktreeState,
+    },
+};
+
+/// The ref pointing at the newest stash entry. Its reflog is the stack of entries, so
+/// `stash@{1}` is the one saved before it.
+const STASH_REF: &str = "refs/stash";
+
+/// What `pgit stash push` saves.
+#[derive(Debug, Clone, Default)]
+pub struct StashOptions {
+    /// Describes the entry instead of the `WIP on` line naming `HEAD`.
+    pub message: Option<String>,
+    /// Leave what is staged in the index and work tree.
+    pub keep_index: bool,
+    /// Also save untracked files, and remove them from the work tree.
+    pub include_untracked: bool,
+}
+
+/// What a stash command did, for the user. When `show_status` is set the caller follows the
+/// messages with the status of the work tree, then with `outcome`.
+#[derive(Debug, Clone, Default)]
+pub struct StashSummary {
+    pub messages: Vec<String>,
+    pub show_status: bool,
+    /// Whether the entry was dropped or kept, once a pop is over.
+    pub outcome: Option<String>,
+    pub failed: bool,
+}
+
+impl Display for StashSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for message in &self.messages {
+            writeln!(f, "{message}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A stash entry as it was named on the command line.
+struct StashEntry {
+    name: String,
+    commit: String,
+    /// The commit `HEAD` was on, the index as a commit and, with untracked files, those.
+    parents: Vec<String>,
+    /// Where the entry is in the reflog of `refs/stash`, or `None` for a stash-like commit
+    /// found some other way, which cannot be dropped.
+    position: Option<usize>,
+}
+
+/// `pgit stash push`: saves the local changes to the paths `args` selects (all of them when
+/// there are none) as a new stash entry, and reverts those paths to `HEAD`.
+///
+/// The entry is stored the way git does, so either tool can apply it: a commit of the work tree
+/// whose parents are `HEAD`, a commit of the index and, with untracked files, a root commit of
+/// those. `refs/stash` points at it and its reflog keeps the older entries.
+pub fn stash_push(
+    repo: &Repository,
+    args: &[String],
+    options: &StashOptions,
+) -> Result<StashSummary> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+    let pathspec = Pathspec::new(&work_tree, &cwd_prefix(repo)?, args)?;
+    let index = Index::read(repo)?;
+
+    if let Some(entry) = index.entries.iter().find(|entry| entry.stage() != 0) {
+        return Err(Error::InvalidArgument(format!(
+            "{}: needs merge",
+            entry.path_str()
+        )));
+    }
+
+    let Some(head) = repo.head()? else {
+        return Err(Error::InvalidArgument(
+            "You do not have the initial commit yet".to_owned(),
+        ));
+    };
+    let head_tree = commit_tree(repo, &head)?;
+    let head_files = flatten_tree(repo, &head_tree)?;
+
+    let untracked: Vec<Vec<u8>> = if options.include_untracked {
+        untracked_files(repo, &index, Some(&mut IgnoreRules::new(repo)?))?
+            .into_iter()
+            .filter(|path| pathspec.matches(path))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    for item in &pathspec.items {
+        let known = head_files.keys().any(|path| item.matches(path))
+            || index.entries.iter().any(|entry| item.matches(&entry.path))
+            || untracked.iter().any(|path| item.matches(path));
+
+        if !known {
+            return Err(Error::Failed(format!(
+                "pathspec '{}' did not match any file(s) known to git\nDid you forget to `pgit add`?",
+                item.original
+            )));
+        }
+    }
+
+    let mut staged_index = index.clone();
+    let i_tree = write_tree(repo, &mut staged_index)?;
+    let i_files = flatten_tree(repo, &i_tree)?;
+    let staged = head_files
+        .keys()
+        .chain(i_files.keys())
+        .filter(|path| pathspec.matches(path))
+        .any(|path| head_files.get(path) != i_files.get(path));
+
+    // The work tree is saved as the index with the selected paths' unstaged changes added.
+    let mut worktree_index = index.clone();
+    let mut unstaged = false;
+
+    for entry in index
+        .entries
+        .iter()
+        .filter(|entry| pathspec.matches(&entry.path) && entry.mode != MODE_GITLINK)
+    {
+        match entry_state(repo, entry)? {
+            WorktreeState::Unchanged => continue,
+            WorktreeState::Modified => stage_path(repo, &mut worktree_index, &entry.path, false)?,
+            WorktreeState::Deleted => {
+                worktree_index.remove(&entry.path);
+            }
+        }
+
+        unstaged = true;
+    }
+
+    if !staged && !unstaged && untracked.is_empty() {
+        return Ok(StashSummary {
+            messages: vec!["No local changes to save".to_owned()],
+            ..Default::default()
+        });
+    }
+
+    let branch = match ref_target(repo, "HEAD")?.strip_prefix("refs/heads/") {
+        Some(branch) => branch.to_owned(),
+        None => "(no branch)".to_owned(),
+    };
+    let head_message = repo
+        .read_object(&head)?
+        .meta
+        .unwrap_or_default()
+        .message
+        .unwrap_or_default();
+    let on = format!(
+        "{branch}: {} {}",
+        abbreviate(repo, &head, default_abbrev(repo))?,
+        subject(&head_message)
+    );
+
+    let mut parents = vec![head.clone()];
+    let i_commit = write_commit(repo, &i_tree, &parents, &format!("index on {on}\n"))?;
+    parents.push(i_commit);
+
+    if !untracked.is_empty() {
+        let mut untracked_index = Index::default();
+        for path in &untracked {
+            stage_path(repo, &mut untracked_index, path, false)?;
+        }
+
+        let u_tree = write_tree(repo, &mut untracked_index)?;
+        parents.push(write_commit(
+            repo,
+            &u_tree,
+            &[],
+            &format!("untracked files on {on}\n"),
+        )?);
+    }
+
+    let message = match &options.message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {on}"),
+    };
+    let w_tree = write_tree(repo, &mut worktree_index)?;
+    let stash = write_commit(repo, &w_tree, &parents, &message)?;
+
+    // `refs/stash` is not logged by default, but its reflog is the list of entries.
+    let log = repo.reflog_path(STASH_REF);
+    if let Some(parent) = log.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log)?;
+    update_ref(repo, STASH_REF, &stash, None, &message, false)?;
+
+    for path in &untracked {
+        remove_worktree_file(repo, path)?;
+    }
+
+    let target = if options.keep_index {
+        &i_tree
+    } else {
+        &head_tree
+    };
+    reset_hard(repo, Some(target), &pathspec)?;
+
+    Ok(StashSummary {
+        messages: vec![format!("Saved working directory and index state {message}")],
+        ..Default::default()
+    })
+}
+
+/// `pgit stash apply`: merges the changes of the stash entry `stash` (the newest by default)
+/// into the work tree. Only a conflict-free merge touches the index: with `restore_index` the
+/// staged changes of the entry are staged again, otherwise only files it added are.
+pub fn stash_apply(
+    repo: &Repository,
+    stash: Option<&str>,
+    restore_index: bool,
+) -> Result<StashSummary> {
+    let stash = find_stash(repo, stash)?;
+
+    apply(repo, &stash, restore_index)
+}
+
+/// `pgit stash pop`: applies the stash entry like [`stash_apply`] and drops it, unless applying
+/// it ran into conflicts.
+pub fn stash_pop(
+    repo: &Repository,
+    stash: Option<&str>,
+    restore_index: bool,
+) -> Result<StashSummary> {
+    let stash = find_stash(repo, stash)?;
+    let position = require_reference(&stash)?;
+    let mut summary = apply(repo, &stash, restore_index)?;
+
+    summary.outcome = Some(if summary.failed {
+        "The stash entry is kept in case you need it again.".to_owned()
+    } else {
+        drop_entry(repo, &stash, position)?
+    });
+
+    Ok(summary)
+}
+
+/// `pgit stash drop`: removes the stash entry `stash`, the newest by default.
+pub fn stash_drop(repo: &Repository, stash: Option<&str>) -> Result<StashSummary> {
+    let stash = find_stash(repo, stash)?;
+    let position = require_reference(&stash)?;
+
+    Ok(StashSummary {
+        outcome: Some(drop_entry(repo, &stash, position)?),
+        ..Default::default()
+    })
+}
+
+/// `pgit stash branch`: creates the branch `name` at the commit the stash entry was saved on,
+/// switches to it and applies the entry there with its index, which cannot conflict. The entry
+/// is dropped afterwards.
+pub fn stash_branch(repo: &Repository, name: &str, stash: Option<&str>) -> Result<StashSummary> {
+    let stash = find_stash(repo, stash)?;
+    let switched = switch(
+        repo,
+        Some(&stash.parents[0]),
+        &SwitchOptions {
+            create: Some(name.to_owned()),
+            ..Default::default()
+        },
+    )?;
+
+    let mut summary = apply(repo, &stash, true)?;
+    summary
+        .messages
+        .splice(0..0, switched.to_string().lines().map(str::to_owned));
+
+    if let (false, Some(position)) = (summary.failed, stash.position) {
+        summary.outcome = Some(drop_entry(repo, &stash, position)?);
+    }
+
+    Ok(summary)
+}
+
+/// `pgit stash list`: one line per entry, newest first.
+pub fn stash_list(repo: &Repository) -> Result<String> {
+    let mut output = String::new();
+
+    for (n, entry) in read_reflog(repo, STASH_REF)?.iter().enumerate() {
+        output += &format!("stash@{{{n}}}: {}\n", entry.message);
+    }
+
+    Ok(output)
+}
+
+/// `pgit stash show`: the changes the stash entry `stash` records against the commit it was
+/// saved on, as a diffstat or with `patch` as a patch. Untracked files are not shown.
+pub fn stash_show(repo: &Repository, stash: Option<&str>, patch: bool) -> Result<Vec<u8>> {
+    let stash = find_stash(repo, stash)?;
+    let options = DiffOptions {
+        context: 3,
+        patch,
+        stat: !patch,
+        renames: Some(RENAME_THRESHOLD),
+        ..Default::default()
+    };
+
+    diff(
+        repo,
+        &[stash.parents[0].clone(), stash.commit.clone()],
+        &[],
+        &options,
+    )
+}
+
+/// `pgit stash clear`: drops every stash entry.
+pub fn stash_clear(repo: &Repository) -> Result<()> {
+    if resolve_ref(repo, STASH_REF)?.is_some() {
+        delete_ref(repo, STASH_REF, None, false)?;
+    }
+
+    Ok(())
+}
+
+fn apply(repo: &Repository, stash: &StashEntry, restore_index: bool) -> Result<StashSummary> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+    let mut index = Index::read(repo)?;
+
+    if index.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "cannot apply a stash in the middle of a merge".to_owned(),
+        ));
+    }
+
+    let c_tree = write_tree(repo, &mut index)?;
+    index.write(repo)?;
+
+    let b_tree = commit_tree(repo, &stash.parents[0])?;
+    let i_tree = commit_tree(repo, &stash.parents[1])?;
+    let w_tree = commit_tree(repo, &stash.commit)?;
+    let labels = MergeLabels {
+        base: "Stash base".to_owned(),
+        ours: "Updated upstream".to_owned(),
+        theirs: "Stashed changes".to_owned(),
+    };
+    let options = MergeOptions::configured(repo)?;
+    let mut summary = StashSummary {
+        show_status: true,
+        ..Default::default()
+    };
+
+    // The staged changes are replayed onto the index on their own first, and must apply cleanly.
+    let index_tree = if restore_index && b_tree != i_tree && c_tree != i_tree {
+        let merged = merge_trees(repo, Some(&b_tree), &c_tree, &i_tree, &labels, &options)?;
+
+        if !merged.conflicts.is_empty() {
+            return Err(Error::InvalidArgument(
+                "conflicts in index. Try without --index.".to_owned(),
+            ));
+        }
+
+        Some(write_files(repo, &merged.files)?)
+    } else {
+        None
+    };
+
+    let merged = merge_trees(repo, Some(&b_tree), &c_tree, &w_tree, &labels, &options)?;
+    let result = write_files(repo, &merged.files)?;
+
+    switch_trees(repo, Some(&c_tree), Some(&result), false, TreeUpdate::Merge)?;
+    record_conflicts(repo, &merged.conflicts)?;
+    summary.messages.extend(merged.messages);
+
+    if !merged.conflicts.is_empty() {
+        if restore_index {
+            summary.messages.push("Index was not unstashed.".to_owned());
+        }
+        summary.failed = true;
+    } else if let Some(index_tree) = &index_tree {
+        stage_tree(repo, index_tree, false)?;
+    } else {
+        stage_tree(repo, &c_tree, true)?;
+    }
+
+    if let Some(untracked) = stash.parents.get(2) {
+        let mut restored = true;
+
+        for (path, file) in flatten_tree(repo, &commit_tree(repo, untracked)?)? {
+            let full_path = work_tree.join(path_from_bytes(&path));
+
+            if full_path.symlink_metadata().is_ok() {
+                summary.messages.push(format!(
+                    "{} already exists, no checkout",
+                    String::from_utf8_lossy(&path)
+                ));
+                restored = false;
+                continue;
+            }
+
+            checkout_file(repo, &path, file.mode, &file.hash)?;
+        }
+
+        if !restored {
+            summary
+                .messages
+                .push("error: could not restore untracked files from stash".to_owned());
+            summary.failed = true;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Stages `tree` without touching the work tree. With `keep_new`, files staged that the tree
+/// does not have stay staged, so that files a stash entry added are not left untracked.
+fn stage_tree(repo: &Repository, tree: &str, keep_new: bool) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    let files = flatten_tree(repo, tree)?;
+
+    if !keep_new {
+        let gone: Vec<Vec<u8>> = index
+            .entries
+            .iter()
+            .filter(|entry| !files.contains_key(&entry.path))
+            .map(|entry| entry.path.clone())
+            .collect();
+
+        for path in gone {
+            index.remove(&path);
+        }
+    }
+
+    for (path, file) in files {
+        let staged = index
+            .entry(&path, 0)
+            .is_some_and(|entry| entry.mode == file.mode && entry.hash == file.hash);
+
+        if !staged {
+            index.add(IndexEntry::new(path, file.mode, file.hash, 0));
+        }
+    }
+
+    index.write(repo)
+}
+
+/// Resolves a stash entry named like git does: `stash@{<n>}` or any stash-like commit, just
+/// `<n>` for `refs/stash@{<n>}`, and nothing for the newest entry.
+fn find_stash(repo: &Repository, stash: Option<&str>) -> Result<StashEntry> {
+    let name = match stash {
+        None if resolve_ref(repo, STASH_REF)?.is_none() => {
+            return Err(Error::Failed("No stash entries found.".to_owned()));
+        }
+        None => format!("{STASH_REF}@{{0}}"),
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{STASH_REF}@{{{n}}}")
+        }
+        Some(name) => name.to_owned(),
+    };
+
+    let commit = resolve_commit(repo, &name)
+        .map_err(|_| Error::Failed(format!("{name} is not a valid reference")))?;
+    let parents = repo.read_object(&commit)?.meta.unwrap_or_default().parents;
+
+    if !(2..=3).contains(&parents.len()) {
+        return Err(Error::InvalidArgument(format!(
+            "'{name}' is not a stash-like commit"
+        )));
+    }
+
+    let is_stash_ref = |base: &str| -> Result<bool> {
+        Ok(dwim_ref(repo, base)?.is_some_and(|(full_name, _)| full_name == STASH_REF))
+    };
+    let position = match name
+        .strip_suffix('}')
+        .and_then(|rest| rest.rsplit_once("@{"))
+    {
+        Some((base, n)) if !base.is_empty() && is_stash_ref(base)? => n.parse().ok(),
+        Some(_) => None,
+        None if is_stash_ref(&name)? => Some(0),
+        None => None,
+    };
+
+    Ok(StashEntry {
+        name,
+        commit,
+        parents,
+        position,
+    })
+}
+
+fn require_reference(stash: &StashEntry) -> Result<usize> {
+    stash
+        .position
+        .ok_or_else(|| Error::Failed(format!("'{}' is not a stash reference", stash.name)))
+}
+
+fn drop_entry(repo: &Repository, stash: &StashEntry, position: usize) -> Result<String> {
+    delete_reflog_entry(repo, STASH_REF, position)?;
+
+    Ok(format!("Dropped {} ({})", stash.name, stash.commit))
+}
+
+/// Writes a commit made by the current user, as git does for the commits of a stash entry. Like
+/// git, the message is taken as is: only the index and untracked commits end in a newline.
+fn write_commit(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    message: &str,
+) -> Result<String> {
+    let author = identity(repo, Role::Author)?;
+    let committer = identity(repo, Role::Committer)?;
+
+    let mut body = format!("tree {tree}\n");
+    for parent in parents {
+        body += &format!("parent {parent}\n");
+    }
+    body += &format!("author {author}\ncommitter {committer}\n\n{message}");
+
+    repo.write_object(&ObjectType::Commit, body.as_bytes())
+}
@@ -0,0 +1,30 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::{error::Result, repository::Repository};
+
+/// The directory in the git directory where an operation that stops halfway keeps its state
+/// until it is continued, such as `rebase-merge` for a rebase or `sequencer` for a cherry-pick or
+/// revert.
+pub(crate) fn state_dir(repo: &Repository, dir: &str) -> PathBuf {
+    repo.git_dir().join(dir)
+}
+
+/// The file `name` in the state directory `dir`, or `None` if it was not written.
+pub(crate) fn read_state(repo: &Repository, dir: &str, name: &str) -> Result<Option<String>> {
+    match fs::read_to_string(state_dir(repo, dir).join(name)) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub(crate) fn write_state(repo: &Repository, dir: &str, name: &str, contents: &str) -> Result<()> {
+    Ok(fs::write(state_dir(repo, dir).join(name), contents)?)
+}
+
+pub(crate) fn remove_state_file(repo: &Repository, dir: &str, name: &str) -> Result<()> {
+    match fs::remove_file(state_dir(repo, dir).join(name)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,926 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+};
+
+use termimad::{
+    crossterm::style::Color::{Green, Red, Yellow},
+    MadSkin,
+};
+
+use crate::{
+    diff::{find_renames, RenameFile, RENAME_THRESHOLD},
+    error::Result,
+    ignore::IgnoreRules,
+    index::{mode_from_metadata, Index, IndexEntry, MODE_GITLINK},
+    pathspec::Pathspec,
+    refs::ref_target,
+    repository::Repository,
+    revwalk::ahead_behind,
+    tree::{head_files, TreeFile},
+    utils::{path_from_bytes, quote_path},
+    worktree::{
+        cwd_prefix, entry_state, ignored_files, relative_path, untracked_files, WorktreeState,
+    },
+};
+
+/// How a path changed between two of `HEAD`, the index and the work tree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// A file became a symlink or submodule, or the other way round.
+    TypeChanged,
+    Renamed,
+}
+
+impl Change {
+    /// The letter used in short and porcelain output.
+    pub fn code(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+            Change::Renamed => 'R',
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Change::Added => "new file",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+            Change::TypeChanged => "typechange",
+            Change::Renamed => "renamed",
+        }
+    }
+}
+
+/// Which sides of a conflicted path still have a version, named from the point of view of the
+/// current branch ("us") and the commit being merged in ("them").
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    BothDeleted,
+    AddedByUs,
+    DeletedByThem,
+    AddedByThem,
+    DeletedByUs,
+    BothAdded,
+    BothModified,
+}
+
+impl Conflict {
+    /// Classifies a conflict by which of stages 1 (base), 2 (ours) and 3 (theirs) exist.
+    fn from_stages(stages: &[Option<TreeFile>; 3]) -> Self {
+        match (
+            stages[0].is_some(),
+            stages[1].is_some(),
+            stages[2].is_some(),
+        ) {
+            (true, false, false) => Conflict::BothDeleted,
+            (false, true, false) => Conflict::AddedByUs,
+            (true, true, false) => Conflict::DeletedByThem,
+            (false, false, true) => Conflict::AddedByThem,
+            (true, false, true) => Conflict::DeletedByUs,
+            (false, true, true) => Conflict::BothAdded,
+            _ => Conflict::BothModified,
+        }
+    }
+
+    /// The two letter code used in short and porcelain output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Conflict::BothDeleted => "DD",
+            Conflict::AddedByUs => "AU",
+            Conflict::DeletedByThem => "UD",
+            Conflict::AddedByThem => "UA",
+            Conflict::DeletedByUs => "DU",
+            Conflict::BothAdded => "AA",
+            Conflict::BothModified => "UU",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Conflict::BothDeleted => "both deleted",
+            Conflict::AddedByUs => "added by us",
+            Conflict::DeletedByThem => "deleted by them",
+            Conflict::AddedByThem => "added by them",
+            Conflict::DeletedByUs => "deleted by us",
+            Conflict::BothAdded => "both added",
+            Conflict::BothModified => "both modified",
+        }
+    }
+}
+
+/// A tracked path that differs somewhere between `HEAD`, the index and the work tree.
+#[derive(Debug, Clone, Default)]
+pub struct StatusEntry {
+    pub path: Vec<u8>,
+    /// For a staged rename, the path in `HEAD` the file was moved from.
+    pub orig_path: Option<Vec<u8>>,
+    /// The rename's similarity, from 0 to 100.
+    pub score: u32,
+    /// The change between `HEAD` and the index.
+    pub staged: Option<Change>,
+    /// The change between the index and the work tree.
+    pub unstaged: Option<Change>,
+    pub conflict: Option<Conflict>,
+    /// The file in `HEAD` (for a rename, at `orig_path`).
+    pub head: Option<TreeFile>,
+    /// The stage 0 index entry.
+    pub index: Option<TreeFile>,
+    /// The base, ours and theirs versions of a conflicted path.
+    pub stages: [Option<TreeFile>; 3],
+    /// The mode of the file in the work tree, or 0 if it is missing.
+    pub worktree_mode: u32,
+}
+
+/// An operation that stopped part way and is waiting for the user.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Merge,
+    Rebase {
+        /// The branch being rebased, if the rebase started from one.
+        branch: Option<String>,
+        onto: Option<String>,
+        interactive: bool,
+    },
+    CherryPick(String),
+    Revert(String),
+}
+
+/// The branch `HEAD` is on and how it compares with its upstream.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    /// The upstream's short name, e.g. `origin/main`.
+    pub name: String,
+    /// Commits only on the branch and only on the upstream, or `None` if the upstream ref is gone.
+    pub ahead_behind: Option<(usize, usize)>,
+}
+
+/// Which untracked files `status` looks for, like git's `--untracked-files`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum UntrackedMode {
+    No,
+    /// Untracked directories are shown as a whole.
+    #[default]
+    Normal,
+    /// Every untracked file is shown individually.
+    All,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StatusOptions {
+    pub untracked: UntrackedMode,
+    /// Also list the files ignored by `.gitignore` and friends.
+    pub ignored: bool,
+    pub no_renames: bool,
+}
+
+/// A snapshot of the repository's state, as shown by `pgit status`.
+#[derive(Debug, Clone)]
+pub struct Status {
+    /// The branch `HEAD` points at, or `None` when detached.
+    pub branch: Option<String>,
+    /// The commit `HEAD` resolves to, or `None` on an unborn branch.
+    pub head: Option<String>,
+    pub upstream: Option<Upstream>,
+    pub operation: Option<Operation>,
+    pub entries: Vec<StatusEntry>,
+    pub untracked: Vec<Vec<u8>>,
+    pub ignored: Vec<Vec<u8>>,
+}
+
+/// Compares `HEAD`, the index and the work tree for the paths matched by `args`.
+pub fn status(repo: &Repository, args: &[String], options: &StatusOptions) -> Result<Status> {
+    let work_tree = repo.require_work_tree()?.to_path_buf();
+    let pathspec = Pathspec::new(&work_tree, &cwd_prefix(repo)?, args)?;
+    let index = Index::read(repo)?;
+    let head_tree = head_files(repo)?;
+
+    let mut entries = BTreeMap::<Vec<u8>, StatusEntry>::new();
+
+    // Every path in the index, with its stages gathered together.
+    let mut by_path = BTreeMap::<&[u8], Vec<&IndexEntry>>::new();
+    for entry in index
+        .entries
+        .iter()
+        .filter(|entry| pathspec.matches(&entry.path))
+    {
+        by_path.entry(&entry.path).or_default().push(entry);
+    }
+
+    for (path, stages) in &by_path {
+        let mut status = StatusEntry {
+            path: path.to_vec(),
+            head: head_tree.get(*path).cloned(),
+            worktree_mode: worktree_mode(repo, path)?,
+            ..Default::default()
+        };
+
+        if stages.iter().any(|entry| entry.stage() > 0) {
+            for entry in stages.iter().filter(|entry| entry.stage() > 0) {
+                status.stages[entry.stage() as usize - 1] = Some(tree_file(entry));
+            }
+            status.conflict = Some(Conflict::from_stages(&status.stages));
+            entries.insert(path.to_vec(), status);
+            continue;
+        }
+
+        let entry = stages[0];
+
+        if entry.intent_to_add() {
+            status.unstaged = Some(if status.worktree_mode == 0 {
+                Change::Deleted
+            } else {
+                Change::Added
+            });
+            entries.insert(path.to_vec(), status);
+            continue;
+        }
+
+        status.index = Some(tree_file(entry));
+        status.staged = match &status.head {
+            None => Some(Change::Added),
+            Some(head) if head.hash == entry.hash && head.mode == entry.mode => None,
+            Some(head) if file_type(head.mode) != file_type(entry.mode) => {
+                Some(Change::TypeChanged)
+            }
+            Some(_) => Some(Change::Modified),
+        };
+        status.unstaged = match entry_state(repo, entry)? {
+            WorktreeState::Unchanged => None,
+            WorktreeState::Deleted => Some(Change::Deleted),
+            WorktreeState::Modified
+                if status.worktree_mode != 0
+                    && file_type(status.worktree_mode) != file_type(entry.mode) =>
+            {
+                Some(Change::TypeChanged)
+            }
+            WorktreeState::Modified => Some(Change::Modified),
+        };
+
+        if status.staged.is_some() || status.unstaged.is_some() {
+            entries.insert(path.to_vec(), status);
+        }
+    }
+
+    for (path, file) in &head_tree {
+        if by_path.contains_key(path.as_slice()) || !pathspec.matches(path) {
+            continue;
+        }
+
+        entries.insert(
+            path.clone(),
+            StatusEntry {
+                path: path.clone(),
+                staged: Some(Change::Deleted),
+                head: Some(file.clone()),
+                ..Default::default()
+            },
+        );
+    }
+
+    if !options.no_renames && repo.config().get_bool("status.renames").unwrap_or(true) {
+        pair_renames(repo, &mut entries)?;
+    }
+
+    let mut ignore = IgnoreRules::new(repo)?;
+
+    let untracked = match options.untracked {
+        UntrackedMode::No => Vec::new(),
+        mode => {
+            let files: Vec<Vec<u8>> = untracked_files(repo, &index, Some(&mut ignore))?
+                .into_iter()
+                .filter(|path| pathspec.matches(path))
+                .collect();
+
+            if mode == UntrackedMode::Normal {
+                collapse_untracked(&index, files)
+            } else {
+                files
+            }
+        }
+    };
+
+    let ignored = if options.ignored {
+        ignored_files(
+            repo,
+            &index,
+            &mut ignore,
+            options.untracked == UntrackedMode::All,
+        )?
+        .into_iter()
+        .filter(|path| pathspec.matches(path))
+        .collect()
+    } else {
+        Vec::new()
+    };
+
+    let target = ref_target(repo, "HEAD")?;
+    let branch = target.strip_prefix("refs/heads/").map(str::to_owned);
+    let head = repo.head()?;
+
+    let upstream = match (&branch, &head) {
+        (Some(branch), Some(head)) => match upstream_ref(repo, branch) {
+            Some((full_name, name)) => Some(Upstream {
+                name,
+                ahead_behind: match repo.resolve_ref(&full_name)? {
+                    Some(upstream) => Some(ahead_behind(repo, head, &upstream)?),
+                    None => None,
+                },
+            }),
+            None => None,
+        },
+        _ => None,
+    };
+
+    Ok(Status {
+        branch,
+        head,
+        upstream,
+        operation: operation_in_progress(repo)?,
+        entries: entries.into_values().collect(),
+        untracked,
+        ignored,
+    })
+}
+
+pub(crate) fn tree_file(entry: &IndexEntry) -> TreeFile {
+    TreeFile {
+        mode: entry.mode,
+        hash: entry.hash.clone(),
+    }
+}
+
+/// The kind of object a mode describes, so that `100644` to `100755` is not a type change.
+pub(crate) fn file_type(mode: u32) -> u32 {
+    mode & 0o170000
+}
+
+/// The mode the work tree file at `path` would be staged with, or 0 if it does not exist.
+fn worktree_mode(repo: &Repository, path: &[u8]) -> Result<u32> {
+    let full_path = repo.require_work_tree()?.join(path_from_bytes(path));
+
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() => Ok(if full_path.join(".git").exists() {
+            MODE_GITLINK
+        } else {
+            0
+        }),
+        Ok(metadata) => Ok(mode_from_metadata(&metadata)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Turns staged deletions and additions of similar files into renames.
+fn pair_renames(repo: &Repository, entries: &mut BTreeMap<Vec<u8>, StatusEntry>) -> Result<()> {
+    let rename_file = |entry: &StatusEntry, side: &Option<TreeFile>| {
+        side.as_ref().map(|file| RenameFile {
+            path: entry.path.clone(),
+            mode: file.mode,
+            hash: file.hash.clone(),
+        })
+    };
+    let deleted: Vec<RenameFile> = entries
+        .values()
+        .filter(|entry| entry.staged == Some(Change::Deleted))
+        .filter_map(|entry| rename_file(entry, &entry.head))
+        .collect();
+    let added: Vec<RenameFile> = entries
+        .values()
+        .filter(|entry| entry.staged == Some(Change::Added))
+        .filter_map(|entry| rename_file(entry, &entry.index))
+        .collect();
+
+    if deleted.is_empty() || added.is_empty() {
+        return Ok(());
+    }
+
+    let renames = find_renames(&deleted, &[], &added, RENAME_THRESHOLD, false, |hash| {
+        repo.read_blob(hash)
+    })?;
+
+    for rename in renames {
+        let Some(source) = entries.remove(&rename.from) else {
+            continue;
+        };
+
+        if let Some(target) = entries.get_mut(&rename.to) {
+            target.staged = Some(Change::Renamed);
+            target.orig_path = Some(rename.from);
+            target.score = rename.score;
+            target.head = source.head;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the files of untracked directories with the directory itself, the way git shows
+/// them by default. A directory is collapsed at the highest level that has no tracked files.
+fn collapse_untracked(index: &Index, files: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut tracked_dirs = BTreeSet::<&[u8]>::new();
+
+    for entry in &index.entries {
+        for (i, b) in entry.path.iter().enumerate() {
+            if *b == b'/' {
+                tracked_dirs.insert(&entry.path[..i]);
+            }
+        }
+    }
+
+    let mut collapsed = Vec::<Vec<u8>>::new();
+
+    for path in files {
+        let untracked_dir = path
+            .iter()
+            .enumerate()
+            .filter(|(i, b)| **b == b'/' && *i + 1 < path.len())
+            .map(|(i, _)| i)
+            .find(|i| !tracked_dirs.contains(&path[..*i]));
+
+        let shown = match untracked_dir {
+            Some(i) => path[..=i].to_vec(),
+            None => path,
+        };
+
+        if collapsed.last() != Some(&shown) {
+            collapsed.push(shown);
+        }
+    }
+
+    collapsed
+}
+
+/// The ref that `branch` tracks according to `branch.<name>.remote` and `branch.<name>.merge`,
+/// as its full name and its short display name.
+pub fn upstream_ref(repo: &Repository, branch: &str) -> Option<(String, String)> {
+    let config = repo.config();
+    let remote = config.get(&format!("branch.{branch}.remote"))?;
+    let merge = config.get(&format!("branch.{branch}.merge"))?;
+    let merge_branch = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+
+    if remote == "." {
+        return Some((
+            format!("refs/heads/{merge_branch}"),
+            merge_branch.to_owned(),
+        ));
+    }
+
+    Some((
+        format!("refs/remotes/{remote}/{merge_branch}"),
+        format!("{remote}/{merge_branch}"),
+    ))
+}
+
+/// Detects a merge, rebase, cherry-pick or revert that is waiting to be continued.
+pub fn operation_in_progress(repo: &Repository) -> Result<Option<Operation>> {
+    let git_dir = repo.git_dir();
+    let read = |name: &str| {
+        fs::read_to_string(git_dir.join(name))
+            .ok()
+            .map(|contents| contents.trim().to_owned())
+    };
+
+    for (dir, interactive) in [("rebase-merge", true), ("rebase-apply", false)] {
+        if git_dir.join(dir).is_dir() {
+            let branch = read(&format!("{dir}/head-name"))
+                .filter(|name| name != "detached HEAD")
+                .map(|name| name.trim_start_matches("refs/heads/").to_owned());
+
+            return Ok(Some(Operation::Rebase {
+                branch,
+                onto: read(&format!("{dir}/onto")),
+                interactive: interactive && git_dir.join(dir).join("interactive").exists(),
+            }));
+        }
+    }
+
+    if git_dir.join("MERGE_HEAD").exists() {
+        return Ok(Some(Operation::Merge));
+    }
+
+    if let Some(commit) = read("CHERRY_PICK_HEAD") {
+        return Ok(Some(Operation::CherryPick(commit)));
+    }
+
+    if let Some(commit) = read("REVERT_HEAD") {
+        return Ok(Some(Operation::Revert(commit)));
+    }
+
+    Ok(None)
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(7)]
+}
+
+impl Status {
+    fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.conflict.is_some())
+    }
+
+    fn has_staged(&self) -> bool {
+        self.entries.iter().any(|entry| entry.staged.is_some())
+    }
+
+    fn has_unstaged(&self) -> bool {
+        self.entries.iter().any(|entry| entry.unstaged.is_some())
+    }
+
+    /// The `--porcelain=v1` output. With `branch` a `##` header line describes the branch; with
+    /// `nul` entries end in NUL and paths are not quoted.
+    pub fn porcelain_v1(&self, branch: bool, nul: bool) -> String {
+        let end = if nul { '\0' } else { '\n' };
+        let path = |path: &[u8]| {
+            if nul {
+                String::from_utf8_lossy(path).into_owned()
+            } else {
+                quote_path(path, true)
+            }
+        };
+        let mut output = String::new();
+
+        if branch {
+            output += "## ";
+            output += &match (&self.branch, &self.head) {
+                (Some(branch), None) => format!("No commits yet on {branch}"),
+                (Some(branch), Some(_)) => branch.clone(),
+                (None, _) => "HEAD (no branch)".to_owned(),
+            };
+
+            if let Some(upstream) = &self.upstream {
+                output += &format!("...{}", upstream.name);
+
+                match upstream.ahead_behind {
+                    None => output += " [gone]",
+                    Some((0, 0)) => {}
+                    Some((ahead, 0)) => output += &format!(" [ahead {ahead}]"),
+                    Some((0, behind)) => output += &format!(" [behind {behind}]"),
+                    Some((ahead, behind)) => {
+                        output += &format!(" [ahead {ahead}, behind {behind}]")
+                    }
+                }
+            }
+
+            output.push(end);
+        }
+
+        for entry in &self.entries {
+            let code = match entry.conflict {
+                Some(conflict) => conflict.code().to_owned(),
+                None => format!(
+                    "{}{}",
+                    entry.staged.map_or(' ', |change| change.code()),
+                    entry.unstaged.map_or(' ', |change| change.code())
+                ),
+            };
+
+            match (&entry.orig_path, nul) {
+                (Some(orig), false) => {
+                    output += &format!("{code} {} -> {}", path(orig), path(&entry.path))
+                }
+                (Some(orig), true) => {
+                    output += &format!("{code} {}{end}{}", path(&entry.path), path(orig))
+                }
+                (None, _) => output += &format!("{code} {}", path(&entry.path)),
+            }
+
+            output.push(end);
+        }
+
+        for (prefix, paths) in [("??", &self.untracked), ("!!", &self.ignored)] {
+            for file in paths {
+                output += &format!("{prefix} {}{end}", path(file));
+            }
+        }
+
+        output
+    }
+
+    /// The `--porcelain=v2` output, which adds modes and object names to every entry and, with
+    /// `branch`, `# branch.*` header lines. Unlike v1, paths are relative to `prefix`, the current
+    /// directory.
+    pub fn porcelain_v2(&self, branch: bool, nul: bool, hex_len: usize, prefix: &[u8]) -> String {
+        let end = if nul { '\0' } else { '\n' };
+        let separator = if nul { '\0' } else { '\t' };
+        let path = |path: &[u8]| {
+            let path = relative_path(path, prefix);
+
+            if nul {
+                String::from_utf8_lossy(&path).into_owned()
+            } else {
+                quote_path(&path, false)
+            }
+        };
+        let zero = "0".repeat(hex_len);
+        let mode = |file: &Option<TreeFile>| {
+            file.as_ref()
+                .map_or("000000".to_owned(), |file| format!("{:06o}", file.mode))
+        };
+        let hash =
+            |file: &Option<TreeFile>| file.as_ref().map_or(zero.clone(), |file| file.hash.clone());
+        let mut output = String::new();
+
+        if branch {
+            output += &format!(
+                "# branch.oid {}{end}",
+                self.head.as_deref().unwrap_or("(initial)")
+            );
+            output += &format!(
+                "# branch.head {}{end}",
+                self.branch.as_deref().unwrap_or("(detached)")
+            );
+
+            if let Some(upstream) = &self.upstream {
+                output += &format!("# branch.upstream {}{end}", upstream.name);
+
+                if let Some((ahead, behind)) = upstream.ahead_behind {
+                    output += &format!("# branch.ab +{ahead} -{behind}{end}");
+                }
+            }
+        }
+
+        for entry in &self.entries {
+            let submodule = if [&entry.head, &entry.index]
+                .iter()
+                .any(|file| file.as_ref().is_some_and(|file| file.mode == MODE_GITLINK))
+            {
+                "S..."
+            } else {
+                "N..."
+            };
+
+            if let Some(conflict) = entry.conflict {
+                output += &format!(
+                    "u {} {submodule} {} {} {} {:06o} {} {} {} {}{end}",
+                    conflict.code(),
+                    mode(&entry.stages[0]),
+                    mode(&entry.stages[1]),
+                    mode(&entry.stages[2]),
+                    entry.worktree_mode,
+                    hash(&entry.stages[0]),
+                    hash(&entry.stages[1]),
+                    hash(&entry.stages[2]),
+                    path(&entry.path)
+                );
+                continue;
+            }
+
+            let code = format!(
+                "{}{}",
+                entry.staged.map_or('.', |change| change.code()),
+                entry.unstaged.map_or('.', |change| change.code())
+            );
+            let fields = format!(
+                "{code} {submodule} {} {} {:06o} {} {}",
+                mode(&entry.head),
+                mode(&entry.index),
+                entry.worktree_mode,
+                hash(&entry.head),
+                hash(&entry.index)
+            );
+
+            match &entry.orig_path {
+                Some(orig) => {
+                    output += &format!(
+                        "2 {fields} R{} {}{separator}{}{end}",
+                        entry.score,
+                        path(&entry.path),
+                        path(orig)
+                    )
+                }
+                None => output += &format!("1 {fields} {}{end}", path(&entry.path)),
+            }
+        }
+
+        for (prefix, paths) in [("?", &self.untracked), ("!", &self.ignored)] {
+            for file in paths {
+                output += &format!("{prefix} {}{end}", path(file));
+            }
+        }
+
+        output
+    }
+
+    /// Prints the status for people: what state the repository is in, what changed, and which
+    /// command to run next. Paths are shown relative to `prefix`, the current directory.
+    pub fn print(&self, prefix: &[u8]) {
+        let show = |path: &[u8]| quote_path(&relative_path(path, prefix), false);
+        let mut skin = MadSkin::default();
+        skin.bold.set_fg(Yellow);
+
+        let mut text = match (&self.branch, &self.head) {
+            (Some(branch), _) => format!("**On branch** `{branch}`\n"),
+            (None, Some(head)) => format!("**HEAD detached at** `{}`\n", short(head)),
+            (None, None) => "**HEAD detached**\n".to_owned(),
+        };
+
+        if self.head.is_none() {
+            text += "\nNo commits yet: this branch has no history.\n";
+        }
+
+        if let Some(upstream) = &self.upstream {
+            let name = &upstream.name;
+
+            text += "\n";
+            text += &match upstream.ahead_behind {
+                None => format!(
+                    "Your branch tracks `{name}`, but that branch no longer exists upstream.\n"
+                ),
+                Some((0, 0)) => format!("Your branch is up to date with `{name}`.\n"),
+                Some((ahead, 0)) => format!(
+                    "Your branch is **{ahead}** commit{} ahead of `{name}`; they have not been published yet.\n",
+                    plural(ahead)
+                ),
+                Some((0, behind)) => format!(
+                    "Your branch is **{behind}** commit{} behind `{name}`. Run `pgit merge {name}` to fast-forward it.\n",
+                    plural(behind)
+                ),
+                Some((ahead, behind)) => format!(
+                    "Your branch and `{name}` have diverged: **{ahead}** local and **{behind}** upstream commit{}. Combine them with `pgit merge {name}` or `pgit rebase {name}`.\n",
+                    plural(behind)
+                ),
+            };
+        }
+
+        if let Some(operation) = &self.operation {
+            text += "\n";
+            text += &self.operation_hint(operation);
+        }
+
+        skin.print_text(&text);
+
+        let mut staged_skin = MadSkin::default();
+        staged_skin.bold.set_fg(Green);
+        let mut unstaged_skin = MadSkin::default();
+        unstaged_skin.bold.set_fg(Red);
+
+        if self.has_conflicts() {
+            let lines: Vec<String> = self
+                .entries
+                .iter()
+                .filter_map(|entry| {
+                    let conflict = entry.conflict?;
+                    Some(format!(
+                        "* **{}:** `{}`",
+                        conflict.description(),
+                        show(&entry.path)
+                    ))
+                })
+                .collect();
+
+            print_section(
+                &unstaged_skin,
+                "Unmerged paths",
+                "Edit these files to fix the conflicts, then mark each one as resolved with `pgit add <file>` (or `pgit rm <file>` to delete it).",
+                &lines,
+            );
+        }
+
+        if self.has_staged() {
+            let lines: Vec<String> = self
+                .entries
+                .iter()
+                .filter_map(|entry| {
+                    let change = entry.staged?;
+                    Some(match &entry.orig_path {
+                        Some(orig) => format!(
+                            "* **{}:** `{}` → `{}`",
+                            change.description(),
+                            show(orig),
+                            show(&entry.path)
+                        ),
+                        None => format!("* **{}:** `{}`", change.description(), show(&entry.path)),
+                    })
+                })
+                .collect();
+
+            print_section(
+                &staged_skin,
+                "Changes to be committed",
+                "These will go into the next commit. Run `pgit commit` to record them, or `pgit restore --staged <file>` to unstage a file.",
+                &lines,
+            );
+        }
+
+        if self.has_unstaged() {
+            let lines: Vec<String> = self
+                .entries
+                .iter()
+                .filter_map(|entry| {
+                    let change = entry.unstaged?;
+                    Some(format!(
+                        "* **{}:** `{}`",
+                        change.description(),
+                        show(&entry.path)
+                    ))
+                })
+                .collect();
+
+            print_section(
+                &unstaged_skin,
+                "Changes not staged for commit",
+                "Run `pgit add <file>` to include these in the next commit, or `pgit restore <file>` to throw the changes away.",
+                &lines,
+            );
+        }
+
+        if !self.untracked.is_empty() {
+            let lines: Vec<String> = self
+                .untracked
+                .iter()
+                .map(|path| format!("* `{}`", show(path)))
+                .collect();
+
+            print_section(
+                &unstaged_skin,
+                "Untracked files",
+                "pgit is not tracking these yet. Run `pgit add <file>` to start tracking one, or list it in `.gitignore` to hide it.",
+                &lines,
+            );
+        }
+
+        if !self.ignored.is_empty() {
+            let lines: Vec<String> = self
+                .ignored
+                .iter()
+                .map(|path| format!("* `{}`", show(path)))
+                .collect();
+
+            print_section(
+                &unstaged_skin,
+                "Ignored files",
+                "These match a `.gitignore` pattern, so pgit leaves them alone.",
+                &lines,
+            );
+        }
+
+        let summary = if self.has_conflicts() {
+            None
+        } else if self.has_staged() {
+            Some("Ready to commit: run `pgit commit` to record the staged changes.")
+        } else if self.has_unstaged() || !self.untracked.is_empty() {
+            Some("Nothing is staged for the next commit yet. Use `pgit add <file>` to stage changes.")
+        } else if self.head.is_none() {
+            Some("Nothing to commit yet. Create some files and stage them with `pgit add <file>`.")
+        } else {
+            Some("Nothing to commit, the working tree is clean.")
+        };
+
+        if let Some(summary) = summary {
+            skin.print_text(&format!("\n{summary}"));
+        }
+    }
+
+    fn operation_hint(&self, operation: &Operation) -> String {
+        let conflicts = self.has_conflicts();
+
+        match operation {
+            Operation::Merge if conflicts => "**A merge is in progress** and some files have conflicts. Fix them, stage them with `pgit add <file>`, then run `pgit commit` to finish the merge. To give up and go back, run `pgit merge --abort`.\n".to_owned(),
+            Operation::Merge => "**A merge is in progress** and all conflicts are fixed. Run `pgit commit` to finish the merge.\n".to_owned(),
+            Operation::Rebase { branch, onto, interactive } => {
+                let kind = if *interactive { "An interactive rebase" } else { "A rebase" };
+                let branch = branch.as_deref().map(|branch| format!(" of `{branch}`")).unwrap_or_default();
+                let onto = onto.as_deref().map(|onto| format!(" onto `{}`", short(onto))).unwrap_or_default();
+                let next = if conflicts {
+                    "Fix the conflicts, stage them with `pgit add <file>`, then run `pgit rebase --continue`"
+                } else {
+                    "Run `pgit rebase --continue` when you are ready to go on"
+                };
+
+                format!("**{kind}{branch}{onto} is in progress.** {next}. Use `pgit rebase --skip` to drop the current commit, or `pgit rebase --abort` to go back to where you started.\n")
+            }
+            Operation::CherryPick(commit) | Operation::Revert(commit) => {
+                let (doing, command) = match operation {
+                    Operation::CherryPick(_) => ("cherry-picking", "cherry-pick"),
+                    _ => ("reverting", "revert"),
+                };
+                let next = if conflicts {
+                    "Fix the conflicts, stage them with `pgit add <file>`, then run"
+                } else {
+                    "All conflicts are fixed; run"
+                };
+
+                format!("**You are {doing} commit** `{}`. {next} `pgit {command} --continue`. To give up, run `pgit {command} --abort`.\n", short(commit))
+            }
+        }
+    }
+}
+
+pub(crate) fn print_section(skin: &MadSkin, title: &str, hint: &str, lines: &[String]) {
+    skin.print_text(&format!("\n## {title}\n*{hint}*\n\n{}\n", lines.join("\n")));
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
@@ -1,26 +1,292 @@
-use chrono::DateTime;
-use flate2::read::ZlibDecoder;
-use hex;
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use std::{
-    fmt::{Display, Formatter, Result},
-    fs::File,
-    io::Read,
-    path::PathBuf,
-    process::exit,
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::{Read, Write},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use termimad::{crossterm::style::Color::Red, MadSkin};
+use termimad::MadSkin;
 
-use crate::utils::{get_git_dir_path, pad_mode_with_zero};
+use crate::{
+    error::{Error, Result},
+    pack::read_packed_object,
+    repository::Repository,
+    utils::pad_mode_with_zero,
+};
+
+/// Returns the inflated `"<type> <size>\0<body>"` bytes of an object, reading the loose file when
+/// there is one and falling back to the packfiles otherwise.
+fn read_object_data(repo: &Repository, hash: &str) -> Result<Vec<u8>> {
+    // Abbreviated names are expanded by the revision resolver before they get here.
+    if !repo.object_format().is_valid_hex(hash) {
+        return Err(Error::InvalidArgument(format!(
+            "`{hash}` is not a valid object name"
+        )));
+    }
+
+    let object_path = repo.objects_dir().join(&hash[..2]).join(&hash[2..]);
+
+    if let Ok(object) = File::open(object_path) {
+        let mut decoder = ZlibDecoder::new(object);
+        let mut data = Vec::<u8>::new();
+
+        decoder
+            .read_to_end(&mut data)
+            .map_err(|_| Error::corrupt(hash, "zlib stream is invalid"))?;
+
+        return Ok(data);
+    }
+
+    match read_packed_object(repo, hash)? {
+        Some((obj_type, body)) => {
+            let mut data = format!("{} {}\0", obj_type, body.len()).into_bytes();
+            data.extend(body);
+            Ok(data)
+        }
+        None => Err(Error::ObjectNotFound(hash.to_owned())),
+    }
+}
+
+/// Hashes `body` as an object of `obj_type`, and when `write` is set stores it as a loose object.
+/// Returns the hex object name either way.
+pub fn write_object(
+    repo: &Repository,
+    obj_type: &ObjectType,
+    body: &[u8],
+    write: bool,
+) -> Result<String> {
+    let mut data = format!("{} {}\0", obj_type, body.len()).into_bytes();
+    data.extend_from_slice(body);
+
+    let hash = repo.object_format().hash(&data);
+
+    if !write {
+        return Ok(hash);
+    }
+
+    let object_dir = repo.objects_dir().join(&hash[..2]);
+    let object_path = object_dir.join(&hash[2..]);
+
+    if object_path.exists() {
+        return Ok(hash);
+    }
+
+    fs::create_dir_all(&object_dir)?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&data)?;
+    let compressed = encoder.finish()?;
+
+    // Write to a temporary file first so readers never observe a partially written object.
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let tmp_path = object_dir.join(format!("tmp_obj_{}_{}", process::id(), nanos));
+
+    fs::write(&tmp_path, compressed)?;
+
+    if let Ok(metadata) = fs::metadata(&tmp_path) {
+        let mut permissions = metadata.permissions();
+        permissions.set_readonly(true);
+        let _ = fs::set_permissions(&tmp_path, permissions);
+    }
+
+    if let Err(err) = fs::rename(&tmp_path, &object_path) {
+        let _ = fs::remove_file(&tmp_path);
 
-fn handler<T>(msg: &str) -> T {
-    let mut skin = MadSkin::default();
+        // Losing a race against another writer of the same object is fine.
+        if !object_path.exists() {
+            return Err(err.into());
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Reads an object's type and raw body without parsing the body, e.g. to get a blob's bytes.
+pub fn read_raw_object(repo: &Repository, hash: &str) -> Result<(ObjectType, Vec<u8>)> {
+    let data = read_object_data(repo, hash)?;
+    let (header, body) = split_object_data(hash, &data)?;
+
+    let header = String::from_utf8_lossy(header);
+    let header_type = header.split_whitespace().next().unwrap_or_default();
+    let obj_type = ObjectType::from_str(header_type, false).map_err(|_| {
+        Error::BadHeader(format!("object `{hash}` has unknown type `{header_type}`"))
+    })?;
+
+    Ok((obj_type, body.to_vec()))
+}
+
+/// Splits inflated object data into its `"<type> <size>"` header and its body.
+fn split_object_data<'a>(hash: &str, data: &'a [u8]) -> Result<(&'a [u8], &'a [u8])> {
+    match data.iter().position(|n| n == &0) {
+        Some(nul) => Ok((&data[..nul], &data[nul + 1..])),
+        None => Err(Error::corrupt(hash, "missing NUL after object header")),
+    }
+}
+
+/// Reads the `<mode> <name>\0<raw hash>` records of a tree body. Entry sizes are not stored in
+/// trees, so they are left as zero.
+fn parse_tree_entries(repo: &Repository, hash: &str, body: &[u8]) -> Result<Vec<Object>> {
+    let hash_len = repo.object_format().raw_len();
+
+    let mut entries = Vec::<Object>::new();
+    let mut cursor = 0;
+
+    while cursor < body.len() {
+        let space = body[cursor..]
+            .iter()
+            .position(|n| n == &b' ')
+            .ok_or_else(|| Error::corrupt(hash, "tree entry is missing its mode"))?;
+        let mode = String::from_utf8_lossy(&body[cursor..cursor + space]).to_string();
+        cursor += space + 1;
+
+        let nul = body[cursor..]
+            .iter()
+            .position(|n| n == &0)
+            .ok_or_else(|| Error::corrupt(hash, "tree entry is missing its name"))?;
+        let filename = body[cursor..cursor + nul].to_vec();
+        cursor += nul + 1;
+
+        let raw_hash = body
+            .get(cursor..cursor + hash_len)
+            .ok_or_else(|| Error::corrupt(hash, "tree entry hash is truncated"))?;
+        cursor += hash_len;
+
+        let entry_mode = EntryMode::from_mode(&mode)
+            .ok_or_else(|| Error::corrupt(hash, "tree entry has an unknown mode"))?;
+
+        entries.push(Object {
+            obj_type: entry_mode.object_type(),
+            hash: hex::encode(raw_hash),
+            size: 0,
+            meta: Some(Meta {
+                mode: Some(mode),
+                entry_mode: Some(entry_mode),
+                filename: Some(filename),
+                ..Default::default()
+            }),
+        });
+    }
 
-    skin.bold.set_fg(Red);
-    skin.print_text(format!("**Error:** {msg}").as_str());
-    exit(1);
+    Ok(entries)
 }
 
-#[derive(Debug, PartialEq)]
+/// Splits a commit or tag body into its headers, in order, and the message after the first blank
+/// line. Continuation lines (starting with a space) are folded into the preceding header's value.
+fn parse_headers(text: &str) -> Result<(Vec<(String, String)>, String)> {
+    let mut headers = Vec::<(String, String)>::new();
+
+    let (head, message) = match text.split_once("\n\n") {
+        Some((head, message)) => (head, message.to_owned()),
+        None => (text.trim_end_matches('\n'), String::new()),
+    };
+
+    for line in head.split('\n') {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            match headers.last_mut() {
+                Some((_, value)) => {
+                    value.push('\n');
+                    value.push_str(continuation);
+                }
+                None => {
+                    return Err(Error::BadHeader(String::from(
+                        "continuation line before any header",
+                    )))
+                }
+            }
+        } else {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            headers.push((key.to_owned(), value.to_owned()));
+        }
+    }
+
+    Ok((headers, message))
+}
+
+fn parse_commit(hash: &str, body: &[u8]) -> Result<Meta> {
+    let (headers, message) = parse_headers(&String::from_utf8_lossy(body))?;
+    let mut meta = Meta {
+        message: Some(message),
+        ..Default::default()
+    };
+
+    for (key, value) in headers {
+        match key.as_str() {
+            "tree" => meta.tree = Some(value),
+            "parent" => meta.parents.push(value),
+            "author" => meta.author = Some(User::parse(&value)?),
+            "committer" => meta.committer = Some(User::parse(&value)?),
+            "encoding" => meta.encoding = Some(value),
+            "mergetag" => meta.mergetags.push(value),
+            "gpgsig" => meta.gpgsig = Some(value),
+            "gpgsig-sha256" => meta.gpgsig_sha256 = Some(value),
+            _ => meta.extra_headers.push((key, value)),
+        }
+    }
+
+    if meta.tree.is_none() {
+        return Err(Error::corrupt(hash, "commit has no tree"));
+    }
+    if meta.author.is_none() {
+        return Err(Error::corrupt(hash, "commit has no author"));
+    }
+    if meta.committer.is_none() {
+        return Err(Error::corrupt(hash, "commit has no committer"));
+    }
+
+    Ok(meta)
+}
+
+fn parse_tag(hash: &str, body: &[u8]) -> Result<Meta> {
+    let (headers, mut message) = parse_headers(&String::from_utf8_lossy(body))?;
+    let mut meta = Meta::default();
+
+    for (key, value) in headers {
+        match key.as_str() {
+            "object" => meta.object = Some(value),
+            "type" => {
+                meta.target_type = Some(
+                    ObjectType::from_str(&value, false)
+                        .map_err(|_| Error::corrupt(hash, "tag points at an unknown type"))?,
+                )
+            }
+            "tag" => meta.tag_name = Some(value),
+            "tagger" => meta.tagger = Some(User::parse(&value)?),
+            "gpgsig" => meta.gpgsig = Some(value),
+            "gpgsig-sha256" => meta.gpgsig_sha256 = Some(value),
+            _ => meta.extra_headers.push((key, value)),
+        }
+    }
+
+    // Tag signatures are appended to the message rather than stored in a header.
+    for marker in [
+        "-----BEGIN PGP SIGNATURE-----",
+        "-----BEGIN PGP MESSAGE-----",
+        "-----BEGIN SSH SIGNATURE-----",
+        "-----BEGIN SIGNED MESSAGE-----",
+    ] {
+        if let Some(start) = message.find(marker) {
+            meta.gpgsig = Some(message[start..].to_owned());
+            message.truncate(start);
+            break;
+        }
+    }
+
+    meta.message = Some(message);
+
+    if meta.object.is_none() || meta.target_type.is_none() || meta.tag_name.is_none() {
+        return Err(Error::corrupt(hash, "tag is missing its target"));
+    }
+
+    Ok(meta)
+}
+
+#[derive(Debug, Clone, ValueEnum, PartialEq)]
 pub enum ObjectType {
     Blob,
     Tree,
@@ -29,7 +295,7 @@ pub enum ObjectType {
 }
 
 impl Display for ObjectType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             ObjectType::Blob => {
                 write!(f, "blob")
@@ -47,7 +313,61 @@ impl Display for ObjectType {
     }
 }
 
-#[derive(Debug)]
+/// The kind of a tree entry, as encoded by its mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryMode {
+    Tree,
+    Blob,
+    Executable,
+    Symlink,
+    Gitlink,
+}
+
+impl EntryMode {
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "40000" | "040000" => Some(EntryMode::Tree),
+            "100644" | "100664" | "100640" => Some(EntryMode::Blob),
+            "100755" => Some(EntryMode::Executable),
+            "120000" => Some(EntryMode::Symlink),
+            "160000" => Some(EntryMode::Gitlink),
+            _ => None,
+        }
+    }
+
+    /// The type of the object an entry of this kind points at.
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            EntryMode::Tree => ObjectType::Tree,
+            EntryMode::Gitlink => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
+}
+
+impl Display for EntryMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryMode::Tree => {
+                write!(f, "tree")
+            }
+            EntryMode::Blob => {
+                write!(f, "blob")
+            }
+            EntryMode::Executable => {
+                write!(f, "executable blob")
+            }
+            EntryMode::Symlink => {
+                write!(f, "symlink")
+            }
+            EntryMode::Gitlink => {
+                write!(f, "submodule commit")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct User {
     pub name: String,
     pub email: Option<String>,
@@ -55,15 +375,83 @@ pub struct User {
     pub zone: String,
 }
 
-#[derive(Debug)]
+impl User {
+    /// Parses an identity line of the form `Full Name <email> <timestamp> <zone>`.
+    pub fn parse(line: &str) -> Result<Self> {
+        let (name, rest) = line
+            .split_once('<')
+            .ok_or_else(|| Error::BadHeader(format!("identity `{line}` has no email")))?;
+        let (email, rest) = rest
+            .rsplit_once('>')
+            .ok_or_else(|| Error::BadHeader(format!("identity `{line}` has no email")))?;
+
+        let mut rest = rest.split_whitespace();
+
+        let time = rest
+            .next()
+            .and_then(|time| time.parse().ok())
+            .ok_or_else(|| Error::BadHeader(format!("identity `{line}` has no timestamp")))?;
+        let zone = rest.next().unwrap_or("+0000");
+
+        Ok(Self {
+            name: name.trim().to_owned(),
+            email: Some(email.to_owned()),
+            time,
+            zone: zone.to_owned(),
+        })
+    }
+
+    /// Formats the timestamp as local time in the identity's own zone.
+    pub fn format_time(&self) -> String {
+        let offset = DateTime::parse_from_str(&format!("0 {}", self.zone), "%s %z")
+            .map(|zoned| *zoned.offset())
+            .unwrap_or(FixedOffset::east_opt(0).unwrap());
+
+        match DateTime::from_timestamp(self.time as i64, 0) {
+            Some(time) => format!(
+                "{} {}",
+                time.with_timezone(&offset).format("%Y-%m-%d %H:%M:%S"),
+                self.zone
+            ),
+            None => format!("{} {}", self.time, self.zone),
+        }
+    }
+}
+
+/// Formats the identity the way it is stored in commit and tag headers.
+impl Display for User {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email.as_deref().unwrap_or_default(),
+            self.time,
+            self.zone
+        )
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Meta {
     pub objects: Option<Vec<Object>>,
     pub tree: Option<String>,
-    pub parent: Option<String>,
+    pub parents: Vec<String>,
     pub author: Option<User>,
     pub committer: Option<User>,
+    pub encoding: Option<String>,
+    pub mergetags: Vec<String>,
+    pub gpgsig: Option<String>,
+    pub gpgsig_sha256: Option<String>,
+    pub extra_headers: Vec<(String, String)>,
+    pub message: Option<String>,
+    pub object: Option<String>,
+    pub target_type: Option<ObjectType>,
+    pub tag_name: Option<String>,
+    pub tagger: Option<User>,
     pub mode: Option<String>,
-    pub filename: Option<String>,
+    pub entry_mode: Option<EntryMode>,
+    pub filename: Option<Vec<u8>>,
 }
 
 #[derive(Debug)]
@@ -75,345 +463,253 @@ pub struct Object {
 }
 
 impl Object {
-    pub fn new(hash: String) -> Self {
-        let object_path = get_git_dir_path()
-            .join("objects")
-            .join(&hash[..2])
-            .join(&hash[2..]);
-
-        let object =
-            File::open(object_path).unwrap_or_else(|_| handler::<File>("Could not read file"));
-
-        let mut decoder = ZlibDecoder::new(object);
-        let mut data = Vec::<u8>::new();
+    pub fn new(repo: &Repository, hash: String) -> Result<Self> {
+        let data = read_object_data(repo, &hash)?;
+        let (header, body) = split_object_data(&hash, &data)?;
 
-        let _ = decoder.read_to_end(&mut data);
+        let header = String::from_utf8_lossy(header);
 
-        let mut data = data.split(|n| n == &0).to_owned();
+        let mut header = header.split_whitespace();
 
-        let header = String::from_utf8_lossy(
-            data.next()
-                .unwrap_or_else(|| handler::<&[u8]>("Git object is malformed")),
-        );
+        let header_type = header
+            .next()
+            .ok_or_else(|| Error::BadHeader(format!("object `{hash}` has an empty header")))?;
 
-        let mut header = header.trim().split_whitespace();
+        let type_ = ObjectType::from_str(header_type, false).map_err(|_| {
+            Error::BadHeader(format!("object `{hash}` has unknown type `{header_type}`"))
+        })?;
 
-        let header_type = &header
+        let size = header
             .next()
-            .unwrap_or_else(|| handler::<&'static str>("Git object header is malformed"))
-            .to_owned()
-            .as_str()
-            .to_owned();
-
-        let type_ = if header_type == "blob" {
-            ObjectType::Blob
-        } else if header_type == "tree" {
-            ObjectType::Tree
-        } else if header_type == "tag" {
-            ObjectType::Tag
-        } else {
-            ObjectType::Commit
-        };
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| Error::BadHeader(format!("object `{hash}` has an invalid size")))?;
 
         let tree_meta = if type_ == ObjectType::Tree {
-            let mut tree_body = String::from_utf8_lossy(
-                data.next()
-                    .unwrap_or_else(|| handler::<&[u8]>("Git object body is malformed")),
-            )
-            .to_string();
-
-            loop {
-                let r = data.next();
-                if r == None {
-                    break;
-                } else {
-                    tree_body += "\x00";
-                    let r = r.unwrap_or_else(|| handler::<&[u8]>("Git object data is invalid"));
-                    tree_body += &hex::encode(&r[0..20]);
-                    tree_body += &String::from_utf8_lossy(&r[20..]).to_string();
-                }
-            }
-
-            let mut tree_objs = Vec::<Object>::new();
-
-            let mut mode_filename = String::new();
-            let mut next_mode_filename = String::new();
-            let mut obj_hash = String::new();
-
-            for part in tree_body.split("\x00") {
-                if part.len() < 20 {
-                    mode_filename = part.to_string();
-                } else if part.len() == 20 {
-                    obj_hash = part.to_string();
-                } else {
-                    obj_hash = part[0..40].to_string();
-                    if !next_mode_filename.is_empty() {
-                        mode_filename = next_mode_filename;
-                    }
-                    next_mode_filename = part[40..].to_string();
-                }
-
-                if !mode_filename.is_empty() && !obj_hash.is_empty() {
-                    tree_objs.push(Object {
-                        obj_type: ObjectType::Blob,
-                        hash: obj_hash.clone(),
-                        size: 69,
-                        meta: Some(Meta {
-                            objects: None,
-                            tree: None,
-                            parent: None,
-                            author: None,
-                            committer: None,
-                            mode: Some(mode_filename.split_once(" ").unwrap().0.to_string()),
-                            filename: Some(mode_filename.split_once(" ").unwrap().1.to_string()),
-                        }),
-                    });
-                }
-            }
-
-            Some(tree_objs)
+            Some(parse_tree_entries(repo, &hash, body)?)
         } else {
             None
         };
 
-        let commit_meta = if type_ == ObjectType::Commit {
-            let dets = String::from_utf8_lossy(
-                data.next()
-                    .unwrap_or_else(|| handler::<&[u8]>("Git object body is malformed")),
-            )
-            .to_string();
-            let mut dets = dets.split("\n");
-
-            let tree = dets
-                .next()
-                .unwrap_or_else(|| handler::<&str>("Unable to read tree data from commit object"))
-                .split_once(" ")
-                .unwrap_or_else(|| {
-                    handler::<(&str, &str)>("Unable to read tree data from commit object")
-                })
-                .1;
-            let parent = dets
-                .next()
-                .unwrap_or_else(|| handler::<&str>("Unable to read parent data from commit object"))
-                .split_once(" ")
-                .unwrap_or_else(|| {
-                    handler::<(&str, &str)>("Unable to read parent data from commit object")
-                })
-                .1;
-            let author = dets
-                .next()
-                .unwrap_or_else(|| handler::<&str>("Unable to read author data from commit object"))
-                .split_once(" ")
-                .unwrap_or_else(|| {
-                    handler::<(&str, &str)>("Unable to read author data from commit object")
-                })
-                .1;
-            let committer = dets
-                .next()
-                .unwrap_or_else(|| {
-                    handler::<&str>("Unable to read committer data from commit object")
-                })
-                .split_once(" ")
-                .unwrap_or_else(|| {
-                    handler::<(&str, &str)>("Unable to read committer data from commit object")
-                })
-                .1;
-
-            let mut author = author.split_whitespace();
-            let mut committer = committer.split_whitespace();
-
-            let author_name = author
-                .next()
-                .unwrap_or_else(|| handler::<&str>("Unable to get author name from commit object"));
-            let author_email = author.next().unwrap_or_else(|| {
-                handler::<&str>("Unable to get author email from commit object")
-            });
-            let author_time = author
-                .next()
-                .unwrap_or_else(|| handler::<&str>("Unable to get author time from commit object"));
-            let author_zone = author
-                .next()
-                .unwrap_or_else(|| handler::<&str>("Unable to get author zone from commit object"));
-            let committer_name = committer.next().unwrap_or_else(|| {
-                handler::<&str>("Unable to get committer name from commit object")
-            });
-            let committer_email = committer.next().unwrap_or_else(|| {
-                handler::<&str>("Unable to get committer email from commit object")
-            });
-            let committer_time = committer.next().unwrap_or_else(|| {
-                handler::<&str>("Unable to get committer time from commit object")
-            });
-            let committer_zone = committer.next().unwrap_or_else(|| {
-                handler::<&str>("Unable to get committer zone from commit object")
-            });
-
-            let author_email = &author_email[1..author_email.len() - 1];
-            let committer_email = &committer_email[1..author_email.len() - 1];
-
-            let author = User {
-                name: author_name.to_owned(),
-                email: Some(author_email.to_owned()),
-                time: author_time.parse().unwrap(),
-                zone: author_zone.to_owned(),
-            };
-            let committer = User {
-                name: committer_name.to_owned(),
-                email: Some(committer_email.to_owned()),
-                time: committer_time.parse().unwrap(),
-                zone: committer_zone.to_owned(),
-            };
-
-            (
-                Some(tree.to_owned()),
-                Some(parent.to_owned()),
-                Some(author),
-                Some(committer),
-            )
+        let meta = if type_ == ObjectType::Commit {
+            parse_commit(&hash, body)?
+        } else if type_ == ObjectType::Tag {
+            parse_tag(&hash, body)?
         } else {
-            (None, None, None, None)
+            Meta {
+                objects: tree_meta,
+                ..Default::default()
+            }
         };
 
-        Self {
+        Ok(Self {
             obj_type: type_,
-            hash: hash,
-            size: header.next().unwrap().to_owned().parse().unwrap(),
-            meta: Some(Meta {
-                objects: tree_meta,
-                tree: commit_meta.0,
-                parent: commit_meta.1,
-                author: commit_meta.2,
-                committer: commit_meta.3,
-                mode: None,
-                filename: None,
-            }),
-        }
-    }
-
-    fn get_path(&self) -> PathBuf {
-        get_git_dir_path()
-            .join("objects")
-            .join(&self.hash[..2])
-            .join(&self.hash[2..])
+            hash,
+            size,
+            meta: Some(meta),
+        })
     }
 
-    pub fn get_contents(&self) -> String {
-        let path = self.get_path();
+    /// A one-line, human readable summary of the object, used when another object refers to it.
+    pub fn describe(&self, repo: &Repository) -> Result<String> {
+        let meta = self.meta.as_ref().unwrap();
 
-        let object = File::open(path).unwrap_or_else(|_| handler::<File>("Could not read file"));
-        let mut decoder = ZlibDecoder::new(object);
-        let mut contents = Vec::<u8>::new();
-
-        let _ = decoder.read_to_end(&mut contents);
+        Ok(match self.obj_type {
+            ObjectType::Commit => {
+                let author = meta.author.as_ref().unwrap();
+                format!(
+                    "commit `{}` \"{}\" by {} on {}",
+                    self.hash,
+                    meta.message
+                        .as_deref()
+                        .unwrap_or_default()
+                        .lines()
+                        .next()
+                        .unwrap_or_default(),
+                    author.name,
+                    author.format_time(),
+                )
+            }
+            ObjectType::Tree => format!(
+                "tree `{}` with {} entries",
+                self.hash,
+                meta.objects.as_ref().map(|o| o.len()).unwrap_or(0)
+            ),
+            ObjectType::Blob => format!("blob `{}` of {} bytes", self.hash, self.size),
+            ObjectType::Tag => format!(
+                "tag `{}` ({}), which points at {}",
+                meta.tag_name.as_deref().unwrap_or_default(),
+                self.hash,
+                Object::new(repo, meta.object.clone().unwrap())?.describe(repo)?,
+            ),
+        })
+    }
 
-        let mut contents = contents.split(|n| n == &0);
-        contents.next();
+    pub fn get_contents(&self, repo: &Repository) -> Result<String> {
+        let contents = read_object_data(repo, &self.hash)?;
+        let (_, body) = split_object_data(&self.hash, &contents)?;
 
         if self.obj_type == ObjectType::Tree {
-            let mut tree_body = String::from_utf8_lossy(
-                contents
-                    .next()
-                    .unwrap_or_else(|| handler::<&[u8]>("Git object body is malformed")),
-            )
-            .to_string();
-
-            loop {
-                let r = contents.next();
-                if r == None {
-                    break;
-                } else {
-                    tree_body += "\n";
-                    let r = r.unwrap_or_else(|| handler::<&[u8]>("Git object body is malformed"));
-                    tree_body += &hex::encode(&r[0..20]);
-                    tree_body += &String::from_utf8_lossy(&r[20..]).to_string();
-                }
-            }
-
-            return tree_body;
+            return Ok(parse_tree_entries(repo, &self.hash, body)?
+                .iter()
+                .map(|entry| {
+                    let meta = entry.meta.as_ref().unwrap();
+                    format!(
+                        "{} {} {}\t{}",
+                        pad_mode_with_zero(meta.mode.as_ref().unwrap().to_owned()),
+                        entry.obj_type,
+                        entry.hash,
+                        String::from_utf8_lossy(meta.filename.as_ref().unwrap()),
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"));
         }
 
-        String::from_utf8_lossy(
-            contents
-                .next()
-                .unwrap_or_else(|| handler::<&[u8]>("Could not read file contents")),
-        )
-        .trim()
-        .to_owned()
+        Ok(String::from_utf8_lossy(body).trim().to_owned())
     }
 
-    pub fn print_contents(&self) {
+    pub fn print_contents(&self, repo: &Repository) -> Result<()> {
         let mut skin = MadSkin::default();
         if self.obj_type == ObjectType::Commit {
             skin.bold.set_fg(termimad::crossterm::style::Color::Green);
 
-            skin.print_text(
-                format!(
-                    r#"|- |-
-| **Tree** | {} |
-|- |-
-| **Parent** | {} |
-|- |-
+            let meta = self.meta.as_ref().unwrap();
+            let author = meta.author.as_ref().unwrap();
+            let committer = meta.committer.as_ref().unwrap();
+
+            let mut text = format!(
+                "|- |-\n| **Tree** | {} |\n|- |-\n",
+                meta.tree.as_ref().unwrap()
+            );
+
+            if meta.parents.is_empty() {
+                text += "| **Parent** | none, this is a root commit |\n";
+            } else if meta.parents.len() == 1 {
+                text += format!("| **Parent** | {} |\n", meta.parents[0]).as_str();
+            } else {
+                for (i, parent) in meta.parents.iter().enumerate() {
+                    text += format!("| **Parent {}** | {} |\n", i + 1, parent).as_str();
+                }
+            }
+
+            text += format!(
+                r#"|- |-
 
 **Author**: {} `{}`
 **Committer**: {} `{}`
 
-**Committed at**: {} {}
-**Authored at**: {} {}
+**Authored at**: {}
+**Committed at**: {}
 "#,
-                    self.meta.as_ref().unwrap().tree.as_ref().unwrap(),
-                    self.meta.as_ref().unwrap().parent.as_ref().unwrap(),
-                    self.meta.as_ref().unwrap().author.as_ref().unwrap().name,
-                    self.meta
-                        .as_ref()
-                        .unwrap()
-                        .author
-                        .as_ref()
-                        .unwrap()
-                        .email
-                        .as_ref()
-                        .unwrap(),
-                    self.meta.as_ref().unwrap().committer.as_ref().unwrap().name,
-                    self.meta
-                        .as_ref()
-                        .unwrap()
-                        .committer
-                        .as_ref()
-                        .unwrap()
-                        .email
-                        .as_ref()
-                        .unwrap(),
-                    DateTime::from_timestamp(
-                        self.meta
-                            .as_ref()
-                            .unwrap()
-                            .author
-                            .as_ref()
-                            .unwrap()
-                            .time
-                            .try_into()
-                            .unwrap(),
-                        0
-                    )
-                    .unwrap()
-                    .format("%Y-%m-%d %H:%M:%S"),
-                    self.meta.as_ref().unwrap().author.as_ref().unwrap().zone,
-                    DateTime::from_timestamp(
-                        self.meta
-                            .as_ref()
-                            .unwrap()
-                            .committer
-                            .as_ref()
-                            .unwrap()
-                            .time
-                            .try_into()
-                            .unwrap(),
-                        0
-                    )
-                    .unwrap()
-                    .format("%Y-%m-%d %H:%M:%S"),
-                    self.meta.as_ref().unwrap().committer.as_ref().unwrap().zone,
+                author.name,
+                author.email.as_deref().unwrap_or_default(),
+                committer.name,
+                committer.email.as_deref().unwrap_or_default(),
+                author.format_time(),
+                committer.format_time(),
+            )
+            .as_str();
+
+            if meta.parents.len() > 1 {
+                text += format!(
+                    "\nThis is a **merge commit** of {} parents.\n",
+                    meta.parents.len()
                 )
-                .as_str(),
+                .as_str();
+            }
+
+            if let Some(encoding) = &meta.encoding {
+                text += format!("**Encoding**: {}\n", encoding).as_str();
+            }
+
+            if meta.gpgsig.is_some() {
+                text += "**Signed**: yes (`gpgsig`)\n";
+            }
+
+            if meta.gpgsig_sha256.is_some() {
+                text += "**Signed**: yes (`gpgsig-sha256`)\n";
+            }
+
+            for mergetag in &meta.mergetags {
+                let (headers, _) = parse_headers(mergetag)?;
+                let tag_header = |key: &str| {
+                    headers
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| v.as_str())
+                        .unwrap_or_default()
+                        .to_owned()
+                };
+
+                text += format!(
+                    "**Merged tag**: `{}` pointing at {}\n",
+                    tag_header("tag"),
+                    tag_header("object"),
+                )
+                .as_str();
+            }
+
+            for (key, value) in &meta.extra_headers {
+                text += format!("**{}**: {}\n", key, value).as_str();
+            }
+
+            text += format!(
+                "\n**Message**:\n```\n{}\n```\n",
+                meta.message.as_deref().unwrap_or_default().trim_end()
+            )
+            .as_str();
+
+            skin.print_text(&text);
+        } else if self.obj_type == ObjectType::Tag {
+            skin.bold.set_fg(termimad::crossterm::style::Color::Green);
+
+            let meta = self.meta.as_ref().unwrap();
+            let object = meta.object.as_ref().unwrap();
+
+            let mut text = format!(
+                r#"|- |-
+| **Tag** | {} |
+|- |-
+| **Object** | {} |
+| **Type** | {} |
+|- |-
+"#,
+                meta.tag_name.as_ref().unwrap(),
+                object,
+                meta.target_type.as_ref().unwrap(),
             );
+
+            if let Some(tagger) = &meta.tagger {
+                text += format!(
+                    "\n**Tagger**: {} `{}`\n**Tagged at**: {}\n",
+                    tagger.name,
+                    tagger.email.as_deref().unwrap_or_default(),
+                    tagger.format_time(),
+                )
+                .as_str();
+            }
+
+            if meta.gpgsig.is_some() || meta.gpgsig_sha256.is_some() {
+                text += "**Signed**: yes\n";
+            }
+
+            for (key, value) in &meta.extra_headers {
+                text += format!("**{}**: {}\n", key, value).as_str();
+            }
+
+            text += format!(
+                "\n**Points at**: {}\n",
+                Object::new(repo, object.to_owned())?.describe(repo)?
+            )
+            .as_str();
+
+            text += format!(
+                "\n**Message**:\n```\n{}\n```\n",
+                meta.message.as_deref().unwrap_or_default().trim_end()
+            )
+            .as_str();
+
+            skin.print_text(&text);
         } else if self.obj_type == ObjectType::Tree {
             let mut table = String::from(
                 r#"|- |- |- |-
@@ -423,9 +719,11 @@ impl Object {
             for obj in self.meta.as_ref().unwrap().objects.as_ref().unwrap() {
                 table += format!(
                     "\n| **{}** | {} | {} | {} |",
-                    obj.meta.as_ref().unwrap().filename.as_ref().unwrap(),
-                    obj.obj_type,
-                    pad_mode_with_zero(obj.meta.as_ref().unwrap().mode.as_ref().unwrap().to_owned()),
+                    String::from_utf8_lossy(obj.meta.as_ref().unwrap().filename.as_ref().unwrap()),
+                    obj.meta.as_ref().unwrap().entry_mode.as_ref().unwrap(),
+                    pad_mode_with_zero(
+                        obj.meta.as_ref().unwrap().mode.as_ref().unwrap().to_owned()
+                    ),
                     obj.hash,
                 )
                 .as_str();
@@ -434,7 +732,9 @@ impl Object {
             table += "\n|- |- |- |-";
             skin.print_text(&table);
         } else {
-            skin.print_text(format!("```\n{}\n```", self.get_contents()).as_str());
+            skin.print_text(format!("```\n{}\n```", self.get_contents(repo)?).as_str());
         }
+
+        Ok(())
     }
 }
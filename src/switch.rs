@@ -0,0 +1,650 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    branch::{check_branch_name, current_branch, setup_tracking},
+    checkout::{checkout_paths, switch_trees, TreeUpdate},
+    config::parse_bool,
+    diff_command::{diff, DiffOptions},
+    error::{Error, Result},
+    log::subject,
+    refs::{dwim_ref, list_refs, resolve_ref, set_symbolic_ref, update_ref},
+    repository::Repository,
+    revision::{
+        abbreviate, default_abbrev, peel_to, prior_checkout, resolve_commit, resolve_revision,
+    },
+    revwalk::{ahead_behind, ancestors, commit_parents},
+    status::{operation_in_progress, upstream_ref, Operation},
+    structures::ObjectType,
+    tree::commit_tree,
+};
+
+/// At most this many commits are named when warning about commits left behind, as in git.
+const LOST_COMMITS_SHOWN: usize = 4;
+
+/// How `pgit switch` and `pgit checkout` move `HEAD`.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchOptions {
+    /// Create this branch at the start point and switch to it (`-c`, or `-b` for checkout).
+    pub create: Option<String>,
+    /// Let `create` reset a branch that already exists (`-C`/`-B`).
+    pub force_create: bool,
+    /// Switch to this new branch without any history (`--orphan`).
+    pub orphan: Option<String>,
+    /// Detach `HEAD` at the commit even if it names a branch.
+    pub detach: bool,
+    /// Throw away local changes instead of refusing to switch.
+    pub force: bool,
+    /// Whether a new branch tracks its start point; see [`crate::branch::CreateBranchOptions`].
+    pub track: Option<bool>,
+    /// Turn a name that is not a branch yet into a new branch tracking the remote-tracking
+    /// branch of the same name, when exactly one remote has one.
+    pub guess: bool,
+}
+
+/// What switching did, for the user: the local changes that were carried over, in
+/// `--name-status` form, followed by notes such as `Switched to branch 'main'`.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchSummary {
+    pub local_changes: String,
+    pub messages: Vec<String>,
+}
+
+impl Display for SwitchSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.local_changes)?;
+
+        for message in &self.messages {
+            writeln!(f, "{message}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What `pgit checkout` ended up doing.
+#[derive(Debug, Clone)]
+pub enum Checkout {
+    Switched(SwitchSummary),
+    /// Files were restored; the note says how many and from where.
+    Paths(String),
+}
+
+/// Where to move `HEAD`, once the command line has been made sense of.
+enum Target {
+    Branch(String),
+    Detach {
+        commit: String,
+        /// The revision as the user wrote it, for the reflog.
+        spec: String,
+    },
+    Create {
+        name: String,
+        start: String,
+        reset: bool,
+    },
+    Orphan {
+        name: String,
+        /// `pgit switch --orphan` empties the index and work tree; checkout keeps them.
+        keep_files: bool,
+    },
+    /// `pgit checkout` without arguments only reports on the current branch.
+    Stay,
+}
+
+/// `pgit switch`: moves to the branch `target`, or with the options to a new branch or a
+/// detached commit, updating the index and work tree on the way.
+pub fn switch(
+    repo: &Repository,
+    target: Option<&str>,
+    options: &SwitchOptions,
+) -> Result<SwitchSummary> {
+    if let Some(operation) = operation_in_progress(repo)? {
+        let doing = match operation {
+            Operation::Merge => "merging",
+            Operation::Rebase { .. } => "rebasing",
+            Operation::CherryPick(_) => "cherry-picking",
+            Operation::Revert(_) => "reverting",
+        };
+
+        return Err(Error::InvalidArgument(format!(
+            "Cannot switch branch while {doing}: finish or abort it first"
+        )));
+    }
+
+    let target = target.map(|target| expand_dash(repo, target)).transpose()?;
+
+    let target = if let Some(name) = &options.orphan {
+        if target.is_some() {
+            return Err(Error::InvalidArgument(
+                "`--orphan` cannot take a start point".to_owned(),
+            ));
+        }
+
+        Target::Orphan {
+            name: name.clone(),
+            keep_files: false,
+        }
+    } else if let Some(name) = &options.create {
+        Target::Create {
+            name: name.clone(),
+            start: target.unwrap_or_else(|| "HEAD".to_owned()),
+            reset: options.force_create,
+        }
+    } else if options.detach {
+        let spec = target.unwrap_or_else(|| "HEAD".to_owned());
+
+        Target::Detach {
+            commit: resolve_commit(repo, &spec)?,
+            spec,
+        }
+    } else {
+        let Some(name) = target else {
+            return Err(Error::InvalidArgument(
+                "Missing branch or commit argument".to_owned(),
+            ));
+        };
+
+        if resolve_ref(repo, &format!("refs/heads/{name}"))?.is_some() {
+            Target::Branch(name)
+        } else if let Some(target) = guess_remote_branch(repo, &name, options)? {
+            target
+        } else {
+            let kind = match dwim_ref(repo, &name)? {
+                Some((full_name, _)) if full_name.starts_with("refs/tags/") => "tag",
+                Some((full_name, _)) if full_name.starts_with("refs/remotes/") => "remote branch",
+                _ => {
+                    resolve_commit(repo, &name).map_err(|_| {
+                        Error::InvalidArgument(format!("Invalid reference: `{name}`"))
+                    })?;
+                    "commit"
+                }
+            };
+
+            return Err(Error::InvalidArgument(format!(
+                "A branch is expected, got {kind} `{name}`. To look at it without a branch, run `pgit switch --detach {name}`"
+            )));
+        }
+    };
+
+    switch_to(repo, target, options)
+}
+
+/// `pgit checkout`: switches like `pgit switch`, but also detaches at commits and tags on its
+/// own, and restores files when given paths. `args` are the arguments before `--` and `paths`
+/// the ones after it.
+pub fn checkout(
+    repo: &Repository,
+    args: &[String],
+    paths: &[String],
+    options: &SwitchOptions,
+) -> Result<Checkout> {
+    let creates = options.create.is_some() || options.orphan.is_some();
+
+    if !paths.is_empty() || (args.len() > 1 && !creates) {
+        if creates || options.detach {
+            return Err(Error::InvalidArgument(
+                "Cannot switch branches and check out paths at the same time".to_owned(),
+            ));
+        }
+
+        // Without `--`, the first argument is a source only if it names a tree.
+        let (source, paths) = match (paths.is_empty(), args) {
+            (false, []) => (None, paths.to_vec()),
+            (false, [source]) => (Some(source), paths.to_vec()),
+            (false, _) => {
+                return Err(Error::InvalidArgument(
+                    "Only one reference is expected before `--`".to_owned(),
+                ))
+            }
+            (true, [first, rest @ ..]) if tree_ish(repo, first)?.is_some() => {
+                (Some(first), rest.to_vec())
+            }
+            (true, _) => (None, args.to_vec()),
+        };
+
+        return restore(repo, source.map(String::as_str), &paths);
+    }
+
+    if args.len() > 1 {
+        return Err(Error::InvalidArgument(
+            "A new branch takes at most one start point".to_owned(),
+        ));
+    }
+
+    let arg = args.first().map(|arg| expand_dash(repo, arg)).transpose()?;
+
+    let target = if let Some(name) = &options.orphan {
+        if let Some(start) = &arg {
+            let commit = resolve_commit(repo, start)?;
+            let old = repo.head()?;
+            switch_trees(
+                repo,
+                old.map(|old| commit_tree(repo, &old))
+                    .transpose()?
+                    .as_deref(),
+                Some(&commit_tree(repo, &commit)?),
+                options.force,
+                TreeUpdate::Checkout,
+            )?;
+        }
+
+        Target::Orphan {
+            name: name.clone(),
+            keep_files: true,
+        }
+    } else if let Some(name) = &options.create {
+        Target::Create {
+            name: name.clone(),
+            start: arg.unwrap_or_else(|| "HEAD".to_owned()),
+            reset: options.force_create,
+        }
+    } else {
+        match arg {
+            None if options.detach => Target::Detach {
+                commit: resolve_commit(repo, "HEAD")?,
+                spec: "HEAD".to_owned(),
+            },
+            None => Target::Stay,
+            Some(name)
+                if !options.detach
+                    && resolve_ref(repo, &format!("refs/heads/{name}"))?.is_some() =>
+            {
+                Target::Branch(name)
+            }
+            Some(name) => match resolve_commit(repo, &name) {
+                Ok(commit) => Target::Detach { commit, spec: name },
+                Err(_) if !options.detach => match guess_remote_branch(repo, &name, options)? {
+                    Some(target) => target,
+                    // Not a revision, so it must be a path to restore from the index.
+                    None => return restore(repo, None, &[name]),
+                },
+                Err(err) => return Err(err),
+            },
+        }
+    };
+
+    Ok(Checkout::Switched(switch_to(repo, target, options)?))
+}
+
+/// Restores `paths` from `source` or the index, returning git's note about it.
+fn restore(repo: &Repository, source: Option<&str>, paths: &[String]) -> Result<Checkout> {
+    let tree = match source {
+        Some(source) => Some(
+            tree_ish(repo, source)?
+                .ok_or_else(|| Error::InvalidArgument(format!("Invalid reference: `{source}`")))?,
+        ),
+        None => None,
+    };
+
+    let count = checkout_paths(repo, tree.as_deref(), paths)?;
+    let from = match &tree {
+        Some(tree) => abbreviate(repo, tree, default_abbrev(repo))?,
+        None => "the index".to_owned(),
+    };
+
+    Ok(Checkout::Paths(format!(
+        "Updated {count} path{} from {from}",
+        if count == 1 { "" } else { "s" }
+    )))
+}
+
+/// The tree `spec` names, through a commit or tag if need be, or `None` if it names no tree.
+fn tree_ish(repo: &Repository, spec: &str) -> Result<Option<String>> {
+    let Ok(hash) = resolve_revision(repo, spec) else {
+        return Ok(None);
+    };
+
+    match commit_tree(repo, &hash) {
+        Ok(tree) => Ok(Some(tree)),
+        Err(Error::InvalidArgument(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// `-` is the branch (or commit) checked out before the current one, like `@{-1}`.
+fn expand_dash(repo: &Repository, arg: &str) -> Result<String> {
+    if arg != "-" {
+        return Ok(arg.to_owned());
+    }
+
+    prior_checkout(repo, 1)?.ok_or_else(|| {
+        Error::InvalidArgument("There is no previous branch to go back to".to_owned())
+    })
+}
+
+/// A new branch `name` tracking `<remote>/<name>`, if `guess` is on and exactly one remote has
+/// such a branch.
+fn guess_remote_branch(
+    repo: &Repository,
+    name: &str,
+    options: &SwitchOptions,
+) -> Result<Option<Target>> {
+    if !options.guess || check_branch_name(name).is_err() {
+        return Ok(None);
+    }
+
+    let suffix = format!("/{name}");
+    let matches: Vec<String> = list_refs(repo, "refs/remotes/")?
+        .into_iter()
+        .filter_map(|entry| {
+            let short = entry.name.strip_prefix("refs/remotes/")?;
+            let remote = short.strip_suffix(&suffix)?;
+            (!remote.is_empty() && !remote.contains('/')).then(|| short.to_owned())
+        })
+        .collect();
+
+    Ok(match matches.as_slice() {
+        [start] => Some(Target::Create {
+            name: name.to_owned(),
+            start: start.clone(),
+            reset: false,
+        }),
+        _ => None,
+    })
+}
+
+/// Moves the index, work tree and `HEAD` to `target` and describes what happened.
+fn switch_to(repo: &Repository, target: Target, options: &SwitchOptions) -> Result<SwitchSummary> {
+    let old_head = repo.head()?;
+    let old_branch = current_branch(repo)?;
+    let abbrev = default_abbrev(repo);
+    let mut summary = SwitchSummary::default();
+
+    let new_commit = match &target {
+        Target::Branch(name) => resolve_ref(repo, &format!("refs/heads/{name}"))?,
+        Target::Detach { commit, .. } => Some(commit.clone()),
+        Target::Create { start, .. } => {
+            Some(resolve_commit(repo, start).map_err(|err| match err {
+                Error::UnknownRevision(_) => Error::InvalidArgument(format!(
+                    "`{start}` is not a commit and a branch cannot be created from it"
+                )),
+                err => err,
+            })?)
+        }
+        Target::Orphan { .. } => None,
+        Target::Stay => old_head.clone(),
+    };
+
+    if let Target::Create { name, .. } | Target::Orphan { name, .. } = &target {
+        check_branch_name(name)?;
+
+        let reset = matches!(target, Target::Create { reset: true, .. });
+
+        if !reset && resolve_ref(repo, &format!("refs/heads/{name}"))?.is_some() {
+            return Err(Error::InvalidArgument(format!(
+                "A branch named `{name}` already exists"
+            )));
+        }
+    }
+
+    let keep_files = matches!(
+        target,
+        Target::Orphan {
+            keep_files: true,
+            ..
+        } | Target::Stay
+    );
+
+    if !keep_files {
+        switch_trees(
+            repo,
+            old_head
+                .as_deref()
+                .map(|head| commit_tree(repo, head))
+                .transpose()?
+                .as_deref(),
+            new_commit
+                .as_deref()
+                .map(|commit| commit_tree(repo, commit))
+                .transpose()?
+                .as_deref(),
+            options.force,
+            TreeUpdate::Checkout,
+        )?;
+    }
+
+    if let (false, Some(commit)) = (options.force, &new_commit) {
+        summary.local_changes = String::from_utf8_lossy(&diff(
+            repo,
+            std::slice::from_ref(commit),
+            &[],
+            &DiffOptions {
+                name_status: true,
+                ..Default::default()
+            },
+        )?)
+        .into_owned();
+    }
+
+    if let (None, Some(old)) = (&old_branch, &old_head) {
+        if new_commit.as_ref() != Some(old) {
+            summary
+                .messages
+                .extend(left_behind(repo, old, new_commit.as_deref())?);
+        }
+    }
+
+    let from = match (&old_branch, &old_head) {
+        (Some(branch), _) => branch.clone(),
+        (None, Some(head)) => head.clone(),
+        (None, None) => "HEAD".to_owned(),
+    };
+    let reflog = |to: &str| format!("checkout: moving from {from} to {to}");
+
+    match &target {
+        Target::Branch(name) => {
+            set_symbolic_ref(
+                repo,
+                "HEAD",
+                &format!("refs/heads/{name}"),
+                Some(&reflog(name)),
+            )?;
+
+            summary
+                .messages
+                .push(if old_branch.as_deref() == Some(name) {
+                    format!("Already on '{name}'")
+                } else {
+                    format!("Switched to branch '{name}'")
+                });
+        }
+        Target::Detach { commit, spec } => {
+            // Moving a detached `HEAD` onto the commit it is at already changes nothing.
+            if old_branch.is_some() || old_head.as_ref() != Some(commit) {
+                update_ref(repo, "HEAD", commit, None, &reflog(spec), false)?;
+            }
+
+            let advice = repo
+                .config()
+                .get("advice.detachedhead")
+                .is_none_or(|value| parse_bool(&value));
+
+            if old_branch.is_some() && !options.detach && advice {
+                summary.messages.push(detached_advice(spec));
+            }
+
+            summary.messages.push(format!(
+                "HEAD is now at {}",
+                describe(repo, commit, abbrev)?
+            ));
+        }
+        Target::Create { name, start, reset } => {
+            let full_name = format!("refs/heads/{name}");
+            let exists = resolve_ref(repo, &full_name)?.is_some();
+            let commit = new_commit.as_deref().unwrap_or_default();
+            let message = if exists {
+                format!("branch: Reset to {start}")
+            } else {
+                format!("branch: Created from {start}")
+            };
+
+            update_ref(repo, &full_name, commit, None, &message, false)?;
+
+            if let Some((_, upstream)) = setup_tracking(repo, name, start, options.track)? {
+                summary
+                    .messages
+                    .push(format!("branch '{name}' set up to track '{upstream}'."));
+            }
+
+            set_symbolic_ref(repo, "HEAD", &full_name, Some(&reflog(name)))?;
+
+            summary.messages.push(if exists && *reset {
+                format!("Switched to and reset branch '{name}'")
+            } else {
+                format!("Switched to a new branch '{name}'")
+            });
+        }
+        Target::Orphan { name, .. } => {
+            set_symbolic_ref(repo, "HEAD", &format!("refs/heads/{name}"), None)?;
+            summary
+                .messages
+                .push(format!("Switched to a new branch '{name}'"));
+        }
+        Target::Stay => {}
+    }
+
+    if let Some(branch) = current_branch(repo)? {
+        if let Some(report) = upstream_report(repo, &branch)? {
+            summary.messages.push(report);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// `abc1234 subject`, how git names a commit in checkout messages.
+fn describe(repo: &Repository, commit: &str, abbrev: usize) -> Result<String> {
+    let object = repo.read_object(commit)?;
+    let message = object
+        .meta
+        .and_then(|meta| meta.message)
+        .unwrap_or_default();
+
+    Ok(format!(
+        "{} {}",
+        abbreviate(repo, commit, abbrev)?,
+        subject(&message)
+    ))
+}
+
+fn detached_advice(spec: &str) -> String {
+    format!(
+        "Note: switching to '{spec}'.
+
+You are in 'detached HEAD' state: you can look around and make commits, but they will not
+belong to any branch, and switching away leaves them behind. To keep commits you make here,
+create a branch for them:
+
+  pgit switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  pgit switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+"
+    )
+}
+
+/// Leaving the detached commit `old` for `new`: warns about the commits that no branch, tag or
+/// other ref can reach any more, or otherwise says where `HEAD` was.
+fn left_behind(repo: &Repository, old: &str, new: Option<&str>) -> Result<Vec<String>> {
+    let abbrev = default_abbrev(repo);
+    let mut reachable = HashSet::new();
+
+    for tip in list_refs(repo, "refs/")?
+        .into_iter()
+        .map(|entry| entry.hash)
+        .chain(new.map(str::to_owned))
+    {
+        let Ok(commit) = peel_to(repo, &tip, &ObjectType::Commit) else {
+            continue;
+        };
+
+        if !reachable.contains(&commit) {
+            reachable.extend(ancestors(repo, &commit)?);
+        }
+    }
+
+    // Newest first, following the history down from `old`.
+    let mut lost = Vec::new();
+    let mut pending = vec![old.to_owned()];
+    let mut seen = HashSet::new();
+
+    while let Some(commit) = pending.pop() {
+        if reachable.contains(&commit) || !seen.insert(commit.clone()) {
+            continue;
+        }
+
+        let parents = commit_parents(repo, &commit)?;
+        pending.extend(parents.into_iter().rev());
+        lost.push(commit);
+    }
+
+    if lost.is_empty() {
+        return Ok(vec![format!(
+            "Previous HEAD position was {}",
+            describe(repo, old, abbrev)?
+        )]);
+    }
+
+    let (them, commits) = if lost.len() == 1 {
+        ("it", "commit")
+    } else {
+        ("them", "commits")
+    };
+    let mut text = format!(
+        "Warning: you are leaving {} {commits} behind, not connected to\nany of your branches:\n\n",
+        lost.len()
+    );
+
+    for commit in lost.iter().take(LOST_COMMITS_SHOWN) {
+        text += &format!("  {}\n", describe(repo, commit, abbrev)?);
+    }
+
+    if lost.len() > LOST_COMMITS_SHOWN {
+        text += &format!(" ... and {} more.\n", lost.len() - LOST_COMMITS_SHOWN);
+    }
+
+    text += &format!(
+        "\nIf you want to keep {them} by creating a new branch, this may be a good time\nto do so with:\n\n pgit branch <new-branch-name> {}\n",
+        abbreviate(repo, old, abbrev)?
+    );
+
+    Ok(vec![text])
+}
+
+/// How `branch` compares with its upstream, as git reports it after switching.
+fn upstream_report(repo: &Repository, branch: &str) -> Result<Option<String>> {
+    let Some((full_name, name)) = upstream_ref(repo, branch) else {
+        return Ok(None);
+    };
+    let (Some(local), Some(upstream)) = (
+        resolve_ref(repo, &format!("refs/heads/{branch}"))?,
+        repo.resolve_ref(&full_name)?,
+    ) else {
+        return Ok(Some(format!(
+            "Your branch is based on '{name}', but the upstream is gone.\n  (use \"pgit branch --unset-upstream\" to fixup)"
+        )));
+    };
+
+    let commits = |count: usize| match count {
+        1 => "1 commit".to_owned(),
+        count => format!("{count} commits"),
+    };
+
+    Ok(Some(match ahead_behind(repo, &local, &upstream)? {
+        (0, 0) => format!("Your branch is up to date with '{name}'."),
+        (ahead, 0) => format!("Your branch is ahead of '{name}' by {}.", commits(ahead)),
+        (0, behind) => format!(
+            "Your branch is behind '{name}' by {}, and can be fast-forwarded.\n  (use \"pgit merge {name}\" to update your local branch)",
+            commits(behind)
+        ),
+        (ahead, behind) => format!(
+            "Your branch and '{name}' have diverged,\nand have {ahead} and {behind} different commits each, respectively.\n  (use \"pgit merge {name}\" or \"pgit rebase {name}\" to bring them together)"
+        ),
+    }))
+}
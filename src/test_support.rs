@@ -0,0 +1,94 @@
+//! A scratch repository for the unit tests, built through the library itself.
+
+use std::{cell::Cell, collections::BTreeMap, env, fs, path::PathBuf};
+
+use crate::{
+    index::MODE_BLOB,
+    init::init_repo,
+    refs::update_ref,
+    repository::Repository,
+    structures::ObjectType,
+    tree::{write_files, TreeFile},
+};
+
+/// A repository in a temporary directory, removed again when the test is done. Commits get
+/// fixed, increasing timestamps so that their names are the same on every run.
+pub struct TestRepo {
+    pub repo: Repository,
+    dir: PathBuf,
+    time: Cell<i64>,
+}
+
+impl TestRepo {
+    /// Creates an empty repository on `main`. `name` keeps concurrently running tests apart.
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("pgit-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        init_repo(&dir, Some(true), Some(false), None, Some("main"), None).unwrap();
+        let config = dir.join(".git").join("config");
+        let mut contents = fs::read_to_string(&config).unwrap();
+        contents += "[user]\n\tname = A U Thor\n\temail = author@example.com\n";
+        fs::write(&config, contents).unwrap();
+
+        Self {
+            repo: Repository::open(&dir).unwrap(),
+            dir,
+            time: Cell::new(1_600_000_000),
+        }
+    }
+
+    pub fn blob(&self, contents: &str) -> String {
+        self.repo
+            .write_object(&ObjectType::Blob, contents.as_bytes())
+            .unwrap()
+    }
+
+    /// Writes a tree holding `files`, given as `(path, contents)` pairs.
+    pub fn tree(&self, files: &[(&str, &str)]) -> String {
+        let files: BTreeMap<Vec<u8>, TreeFile> = files
+            .iter()
+            .map(|(path, contents)| {
+                (
+                    path.as_bytes().to_vec(),
+                    TreeFile {
+                        mode: MODE_BLOB,
+                        hash: self.blob(contents),
+                    },
+                )
+            })
+            .collect();
+
+        write_files(&self.repo, &files).unwrap()
+    }
+
+    /// Writes a commit of `files` on top of `parents`.
+    pub fn commit(&self, files: &[(&str, &str)], parents: &[&str], message: &str) -> String {
+        let time = self.time.get() + 60;
+        self.time.set(time);
+
+        let mut body = format!("tree {}\n", self.tree(files));
+        for parent in parents {
+            body += &format!("parent {parent}\n");
+        }
+        body += &format!(
+            "author A U Thor <author@example.com> {time} +0000\ncommitter A U Thor <author@example.com> {time} +0000\n\n{message}\n"
+        );
+
+        self.repo
+            .write_object(&ObjectType::Commit, body.as_bytes())
+            .unwrap()
+    }
+
+    /// Points the ref `name` at `hash`, recording `message` in its reflog.
+    pub fn set_ref(&self, name: &str, hash: &str, message: &str) {
+        update_ref(&self.repo, name, hash, None, message, false).unwrap();
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
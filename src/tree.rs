@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    error::{Error, Result},
+    index::{verify_path, CacheTree, Index, IndexEntry},
+    repository::Repository,
+    structures::{EntryMode, ObjectType},
+};
+
+const MODE_TREE: u32 = 0o40000;
+
+/// A file recorded in a tree, identified by its full path in a flattened tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeFile {
+    pub mode: u32,
+    pub hash: String,
+}
+
+/// Every file (and submodule) reachable from the tree `hash`, keyed by its `/`-separated path.
+/// Trees with paths that could not be checked out safely, such as `..` or `.git`, are refused.
+pub fn flatten_tree(repo: &Repository, hash: &str) -> Result<BTreeMap<Vec<u8>, TreeFile>> {
+    let mut files = BTreeMap::new();
+
+    flatten_into(repo, hash, &[], &mut files)?;
+
+    Ok(files)
+}
+
+fn flatten_into(
+    repo: &Repository,
+    hash: &str,
+    prefix: &[u8],
+    files: &mut BTreeMap<Vec<u8>, TreeFile>,
+) -> Result<()> {
+    let tree = repo.read_object(hash)?;
+
+    if tree.obj_type != ObjectType::Tree {
+        return Err(Error::InvalidArgument(format!(
+            "`{hash}` is a {}, not a tree",
+            tree.obj_type
+        )));
+    }
+
+    let entries = tree.meta.and_then(|meta| meta.objects).unwrap_or_default();
+
+    for entry in entries {
+        let meta = entry.meta.unwrap_or_default();
+        let mut path = prefix.to_vec();
+        path.extend(meta.filename.unwrap_or_default());
+
+        if !verify_path(&path) {
+            return Err(Error::corrupt(
+                hash,
+                &format!("invalid path `{}`", String::from_utf8_lossy(&path)),
+            ));
+        }
+
+        if meta.entry_mode == Some(EntryMode::Tree) {
+            path.push(b'/');
+            flatten_into(repo, &entry.hash, &path, files)?;
+            continue;
+        }
+
+        let mode = u32::from_str_radix(meta.mode.as_deref().unwrap_or_default(), 8)
+            .map_err(|_| Error::corrupt(hash, "tree entry has an invalid mode"))?;
+
+        files.insert(
+            path,
+            TreeFile {
+                mode,
+                hash: entry.hash,
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Looks the `/`-separated `path` up in the tree `hash`. Directories are found too, with the tree
+/// mode `040000`; an empty path names the tree itself.
+pub fn find_tree_entry(repo: &Repository, hash: &str, path: &[u8]) -> Result<Option<TreeFile>> {
+    let mut found = TreeFile {
+        mode: MODE_TREE,
+        hash: hash.to_owned(),
+    };
+
+    for component in path.split(|b| *b == b'/').filter(|c| !c.is_empty()) {
+        if found.mode != MODE_TREE {
+            return Ok(None);
+        }
+
+        let tree = repo.read_object(&found.hash)?;
+        let entry = tree
+            .meta
+            .and_then(|meta| meta.objects)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|entry| {
+                entry
+                    .meta
+                    .as_ref()
+                    .and_then(|meta| meta.filename.as_deref())
+                    == Some(component)
+            });
+
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+
+        let meta = entry.meta.unwrap_or_default();
+        let mode = u32::from_str_radix(meta.mode.as_deref().unwrap_or_default(), 8)
+            .map_err(|_| Error::corrupt(hash, "tree entry has an invalid mode"))?;
+
+        found = TreeFile {
+            mode,
+            hash: entry.hash,
+        };
+    }
+
+    Ok(Some(found))
+}
+
+/// The tree of the commit `hash`, peeling annotated tags on the way.
+pub fn commit_tree(repo: &Repository, hash: &str) -> Result<String> {
+    let mut object = repo.read_object(hash)?;
+
+    while object.obj_type == ObjectType::Tag {
+        let target = object
+            .meta
+            .and_then(|meta| meta.object)
+            .ok_or_else(|| Error::corrupt(hash, "tag has no target"))?;
+        object = repo.read_object(&target)?;
+    }
+
+    match object.obj_type {
+        ObjectType::Commit => object
+            .meta
+            .and_then(|meta| meta.tree)
+            .ok_or_else(|| Error::corrupt(hash, "commit has no tree")),
+        ObjectType::Tree => Ok(object.hash),
+        _ => Err(Error::InvalidArgument(format!(
+            "`{hash}` does not point at a commit"
+        ))),
+    }
+}
+
+/// The files of the commit `HEAD` points at, or nothing on an unborn branch.
+pub fn head_files(repo: &Repository) -> Result<BTreeMap<Vec<u8>, TreeFile>> {
+    match repo.head()? {
+        Some(head) => flatten_tree(repo, &commit_tree(repo, &head)?),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Writes the tree objects for the index's stage 0 entries and returns the root tree's name.
+/// Directories whose `TREE` cache entry is still valid are reused without rehashing, and the
+/// cache is brought up to date for the next caller. Intent-to-add entries are left out.
+pub fn write_tree(repo: &Repository, index: &mut Index) -> Result<String> {
+    if index.has_conflicts() {
+        return Err(Error::InvalidArgument(
+            "the index has unmerged entries; resolve the conflicts first".to_owned(),
+        ));
+    }
+
+    let cache = index.cache_tree.take();
+    let mut position = 0;
+    let tree = build_tree(
+        repo,
+        &index.entries,
+        &mut position,
+        &[],
+        Vec::new(),
+        cache.as_ref(),
+    )?;
+    let hash = tree.hash.clone().unwrap_or_default();
+
+    index.cache_tree = Some(tree);
+
+    Ok(hash)
+}
+
+/// Writes the tree objects for a flattened tree, as returned by [`flatten_tree`], and returns the
+/// root tree's name.
+pub fn write_files(repo: &Repository, files: &BTreeMap<Vec<u8>, TreeFile>) -> Result<String> {
+    let entries: Vec<IndexEntry> = files
+        .iter()
+        .map(|(path, file)| IndexEntry::new(path.clone(), file.mode, file.hash.clone(), 0))
+        .collect();
+    let tree = build_tree(repo, &entries, &mut 0, &[], Vec::new(), None)?;
+
+    Ok(tree.hash.unwrap_or_default())
+}
+
+/// Builds the tree for the directory `prefix` from the entries starting at `position`,
+/// advancing `position` past every entry inside the directory.
+fn build_tree(
+    repo: &Repository,
+    entries: &[IndexEntry],
+    position: &mut usize,
+    prefix: &[u8],
+    name: Vec<u8>,
+    cache: Option<&CacheTree>,
+) -> Result<CacheTree> {
+    let start = *position;
+
+    if let Some(cache) = cache.filter(|cache| cache.entry_count >= 0) {
+        let end = start + cache.entry_count as usize;
+        let covers_directory = end <= entries.len()
+            && entries[start..end]
+                .iter()
+                .all(|entry| entry.path.starts_with(prefix))
+            && entries
+                .get(end)
+                .is_none_or(|entry| !entry.path.starts_with(prefix));
+
+        if let (true, Some(hash)) = (covers_directory, &cache.hash) {
+            if repo.has_object(hash)? {
+                *position = end;
+                return Ok(CacheTree {
+                    name,
+                    ..cache.clone()
+                });
+            }
+        }
+    }
+
+    // `(name, mode, hash)`, with directories compared as if their name ended in `/`.
+    let mut tree_entries = Vec::<(Vec<u8>, u32, String)>::new();
+    let mut subtrees = Vec::<CacheTree>::new();
+    let mut invalid = false;
+
+    while let Some(entry) = entries.get(*position) {
+        let Some(rest) = entry.path.strip_prefix(prefix) else {
+            break;
+        };
+
+        if let Some(slash) = rest.iter().position(|b| *b == b'/') {
+            let dir = rest[..slash].to_vec();
+            let mut sub_prefix = prefix.to_vec();
+            sub_prefix.extend_from_slice(&dir);
+            sub_prefix.push(b'/');
+
+            let sub_cache =
+                cache.and_then(|cache| cache.subtrees.iter().find(|sub| sub.name == dir));
+            let subtree = build_tree(repo, entries, position, &sub_prefix, dir.clone(), sub_cache)?;
+
+            invalid |= subtree.entry_count < 0;
+            tree_entries.push((dir, MODE_TREE, subtree.hash.clone().unwrap_or_default()));
+            subtrees.push(subtree);
+            continue;
+        }
+
+        *position += 1;
+
+        if entry.intent_to_add() {
+            invalid = true;
+            continue;
+        }
+
+        tree_entries.push((rest.to_vec(), entry.mode, entry.hash.clone()));
+    }
+
+    tree_entries.sort_by(|a, b| {
+        let key = |(name, mode, _): &(Vec<u8>, u32, String)| {
+            let mut key = name.clone();
+            if *mode == MODE_TREE {
+                key.push(b'/');
+            }
+            key
+        };
+        key(a).cmp(&key(b))
+    });
+
+    let mut body = Vec::<u8>::new();
+
+    for (name, mode, hash) in &tree_entries {
+        body.extend_from_slice(format!("{:o} ", mode).as_bytes());
+        body.extend_from_slice(name);
+        body.push(0);
+        body.extend(
+            hex::decode(hash).map_err(|_| Error::corrupt(hash, "index entry has a bad name"))?,
+        );
+    }
+
+    let hash = repo.write_object(&ObjectType::Tree, &body)?;
+    let entry_count = if invalid {
+        -1
+    } else {
+        (*position - start) as i32
+    };
+
+    Ok(CacheTree {
+        name,
+        entry_count,
+        hash: Some(hash),
+        subtrees,
+    })
+}
@@ -1,85 +1,290 @@
+use clap::ValueEnum;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
 use std::{
-    collections::HashMap,
-    env,
-    path::{Path, PathBuf},
-    process::exit,
+    ffi::OsStr,
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
 };
 
-use clap::ValueEnum;
+#[derive(Debug, Clone, ValueEnum, PartialEq)]
+pub enum HashAlgo {
+    Sha1,
+    Sha256,
+}
+
+unsafe impl Send for HashAlgo {}
+unsafe impl Sync for HashAlgo {}
 
-pub fn get_git_dir_path() -> PathBuf {
-    let cwd = env::current_dir();
-    const GIT_DIR: &str = ".git";
+impl Display for HashAlgo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgo::Sha1 => write!(f, "sha1"),
+            HashAlgo::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl HashAlgo {
+    pub fn hash(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgo::Sha1 => hex::encode(Sha1::digest(data)),
+            HashAlgo::Sha256 => hex::encode(Sha256::digest(data)),
+        }
+    }
 
-    match cwd {
-        Ok(path) => {
-            return Path::new(path.as_os_str()).join(GIT_DIR);
+    /// Parses the value of `extensions.objectformat`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Some(HashAlgo::Sha1),
+            "sha256" => Some(HashAlgo::Sha256),
+            _ => None,
         }
-        Err(_) => {
-            eprintln!("error occured in init::check_git_dir()");
-            exit(1);
+    }
+
+    /// The length of a binary object name, as stored in trees and pack indexes.
+    pub fn raw_len(&self) -> usize {
+        match self {
+            HashAlgo::Sha1 => 20,
+            HashAlgo::Sha256 => 32,
         }
     }
+
+    /// The length of a full hex object name.
+    pub fn hex_len(&self) -> usize {
+        self.raw_len() * 2
+    }
+
+    /// Whether `name` is a full object name in this format.
+    pub fn is_valid_hex(&self, name: &str) -> bool {
+        name.len() == self.hex_len() && name.bytes().all(|b| b.is_ascii_hexdigit())
+    }
 }
 
-pub fn merge_hashmaps(
-    hm_1: HashMap<String, HashMap<String, Option<String>>>,
-    hm_2: HashMap<String, HashMap<String, Option<String>>>,
-) -> HashMap<String, HashMap<String, Option<String>>> {
-    let mut final_hash: HashMap<String, HashMap<String, Option<String>>> = HashMap::new();
+pub fn pad_mode_with_zero(text: String) -> String {
+    if text.len() < 6 {
+        return String::from("0") + text.as_str();
+    }
 
-    for (key, val) in hm_1 {
-        if final_hash.contains_key(&key) {
-            let mut inner_final_hash: HashMap<String, Option<String>> = HashMap::new();
+    text
+}
 
-            for (key_i, val_i) in val {
-                inner_final_hash.insert(key_i, val_i);
-            }
+/// Matches `text` against a git wildcard pattern with pathname semantics: `*` and `?` do not
+/// cross `/`, while `**` between slashes (or at either end) matches any number of directories.
+pub fn wildmatch(pattern: &str, text: &[u8], case_insensitive: bool) -> bool {
+    wildmatch_bytes(pattern.as_bytes(), text, case_insensitive, true)
+}
 
-            for (key_i, val_i) in &final_hash[&key] {
-                inner_final_hash.insert(key_i.to_string(), val_i.clone());
-            }
+/// Like [`wildmatch`] without pathname semantics, so `*` and `?` also match `/`. This is how
+/// pathspecs such as `*.rs` match files in subdirectories.
+pub fn fnmatch(pattern: &[u8], text: &[u8]) -> bool {
+    wildmatch_bytes(pattern, text, false, false)
+}
 
-            final_hash.insert(key, inner_final_hash);
+fn wildmatch_bytes(pattern: &[u8], text: &[u8], case_insensitive: bool, pathname: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if case_insensitive {
+            a.eq_ignore_ascii_case(&b)
         } else {
-            final_hash.insert(key, val);
+            a == b
         }
-    }
+    };
+
+    let mut p = 0;
+    let mut t = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let mut end = p;
+                while end < pattern.len() && pattern[end] == b'*' {
+                    end += 1;
+                }
+
+                let starts_segment = p == 0 || pattern[p - 1] == b'/';
+                let ends_segment = end == pattern.len() || pattern[end] == b'/';
+
+                if pathname && end - p >= 2 && starts_segment && ends_segment {
+                    if end == pattern.len() {
+                        return true;
+                    }
+
+                    // `**/` matches zero or more leading directories.
+                    let rest = &pattern[end + 1..];
+                    if wildmatch_bytes(rest, &text[t..], case_insensitive, pathname) {
+                        return true;
+                    }
+
+                    return (t..text.len()).any(|i| {
+                        text[i] == b'/'
+                            && wildmatch_bytes(rest, &text[i + 1..], case_insensitive, pathname)
+                    });
+                }
 
-    for (key, val) in hm_2 {
-        if final_hash.contains_key(&key) {
-            let mut inner_final_hash: HashMap<String, Option<String>> = HashMap::new();
+                let rest = &pattern[end..];
+                let mut i = t;
 
-            for (key_i, val_i) in val {
-                inner_final_hash.insert(key_i, val_i);
+                loop {
+                    if wildmatch_bytes(rest, &text[i..], case_insensitive, pathname) {
+                        return true;
+                    }
+
+                    if i == text.len() || (pathname && text[i] == b'/') {
+                        return false;
+                    }
+
+                    i += 1;
+                }
+            }
+            b'?' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
             }
+            b'[' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+
+                let mut i = p + 1;
+                let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+                if negated {
+                    i += 1;
+                }
+
+                let mut matched = false;
+                let mut first = true;
+
+                while i < pattern.len() && (first || pattern[i] != b']') {
+                    first = false;
+
+                    let mut low = pattern[i];
+                    if low == b'\\' && i + 1 < pattern.len() {
+                        i += 1;
+                        low = pattern[i];
+                    }
+
+                    if pattern.get(i + 1) == Some(&b'-')
+                        && pattern.get(i + 2).is_some_and(|c| *c != b']')
+                    {
+                        let high = pattern[i + 2];
+                        let c = text[t];
+                        let in_range = |c: u8| (low..=high).contains(&c);
+
+                        if in_range(c)
+                            || (case_insensitive
+                                && (in_range(c.to_ascii_lowercase())
+                                    || in_range(c.to_ascii_uppercase())))
+                        {
+                            matched = true;
+                        }
+                        i += 3;
+                    } else {
+                        if eq(low, text[t]) {
+                            matched = true;
+                        }
+                        i += 1;
+                    }
+                }
+
+                // An unterminated class matches nothing.
+                if i >= pattern.len() || matched == negated {
+                    return false;
+                }
 
-            for (key_i, val_i) in &final_hash[&key] {
-                inner_final_hash.insert(key_i.to_string(), val_i.clone());
+                p = i + 1;
+                t += 1;
             }
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || !eq(pattern[p + 1], text[t]) {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || !eq(c, text[t]) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
 
-            final_hash.insert(key, inner_final_hash);
-        } else {
-            final_hash.insert(key, val);
+    t == text.len()
+}
+
+/// Quotes a path the way git prints it when `core.quotePath` is on: paths with control
+/// characters, `"`, `\` or non-ASCII bytes are wrapped in double quotes with C-style escapes.
+/// `quote_space` also quotes paths containing spaces, as `status --porcelain` does.
+pub fn quote_path(path: &[u8], quote_space: bool) -> String {
+    String::from_utf8_lossy(&quote_path_bytes(path, quote_space, true)).into_owned()
+}
+
+/// Like [`quote_path`], but with `quote_non_ascii` off (`core.quotePath=false`) bytes above
+/// 0x7f are kept as they are, and only control characters, `"` and `\` make a path quoted.
+pub fn quote_path_bytes(path: &[u8], quote_space: bool, quote_non_ascii: bool) -> Vec<u8> {
+    let escaped = |b: u8| b < 0x20 || b == 0x7f || (quote_non_ascii && b > 0x7f);
+    let needs_quotes = path
+        .iter()
+        .any(|b| escaped(*b) || matches!(b, b'"' | b'\\') || (quote_space && *b == b' '));
+
+    if !needs_quotes {
+        return path.to_vec();
+    }
+
+    let mut quoted = b"\"".to_vec();
+
+    for b in path {
+        match b {
+            b'\x07' => quoted.extend(b"\\a"),
+            b'\x08' => quoted.extend(b"\\b"),
+            b'\t' => quoted.extend(b"\\t"),
+            b'\n' => quoted.extend(b"\\n"),
+            b'\x0b' => quoted.extend(b"\\v"),
+            b'\x0c' => quoted.extend(b"\\f"),
+            b'\r' => quoted.extend(b"\\r"),
+            b'"' => quoted.extend(b"\\\""),
+            b'\\' => quoted.extend(b"\\\\"),
+            b if escaped(*b) => quoted.extend(format!("\\{b:03o}").bytes()),
+            b => quoted.push(*b),
         }
     }
 
-    return final_hash;
+    quoted.push(b'"');
+    quoted
 }
 
-#[derive(Debug, Clone, ValueEnum, PartialEq)]
-pub enum HashAlgo {
-    Sha1,
-    Sha256,
+/// The file system path for a path as git stores it in trees and the index. On unix any bytes
+/// make a valid path, so nothing is lost; elsewhere invalid UTF-8 is replaced.
+pub fn path_from_bytes(path: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        PathBuf::from(OsStr::from_bytes(path))
+    }
+
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(path).into_owned())
+    }
 }
 
-unsafe impl Send for HashAlgo {}
-unsafe impl Sync for HashAlgo {}
+/// The bytes git stores for a file system path or file name, the inverse of [`path_from_bytes`].
+pub fn path_to_bytes(path: impl AsRef<OsStr>) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
 
-pub fn pad_mode_with_zero(text: String) -> String {
-    if text.len() < 6 {
-        return String::from("0") + text.as_str();
+        path.as_ref().as_bytes().to_vec()
     }
 
-    text
+    #[cfg(not(unix))]
+    {
+        path.as_ref().to_string_lossy().into_owned().into_bytes()
+    }
 }
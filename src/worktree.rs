@@ -0,0 +1,424 @@
+use std::{
+    fs::{self, Metadata},
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    ignore::IgnoreRules,
+    index::{
+        index_path_for, verify_path, Index, IndexEntry, MODE_EXECUTABLE, MODE_GITLINK, MODE_SYMLINK,
+    },
+    repository::Repository,
+    structures::ObjectType,
+    utils::{path_from_bytes, path_to_bytes},
+};
+
+/// How a staged path compares with the file in the work tree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorktreeState {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+/// The bytes git hashes for a work tree file: its contents, or the target of a symlink.
+pub fn read_worktree_file(path: &Path, metadata: &Metadata) -> Result<Vec<u8>> {
+    if metadata.file_type().is_symlink() {
+        return Ok(path_to_bytes(fs::read_link(path)?));
+    }
+
+    Ok(fs::read(path)?)
+}
+
+/// Hashes a work tree file as the blob it would be staged as, without writing it.
+pub fn hash_worktree_file(repo: &Repository, path: &Path, metadata: &Metadata) -> Result<String> {
+    let contents = read_worktree_file(path, metadata)?;
+
+    repo.hash_object(&ObjectType::Blob, &contents)
+}
+
+/// Compares a stage 0 entry against the work tree. Matching stat data is trusted; otherwise the
+/// file is rehashed, so merely touching a file does not count as a modification.
+pub fn entry_state(repo: &Repository, entry: &IndexEntry) -> Result<WorktreeState> {
+    let work_tree = repo.require_work_tree()?;
+    let path = work_tree.join(path_from_bytes(&entry.path));
+
+    if entry.assume_valid() || entry.skip_worktree() {
+        return Ok(WorktreeState::Unchanged);
+    }
+
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(WorktreeState::Deleted),
+    };
+
+    // Submodules are compared by their checked out commit, which is outside what pgit tracks.
+    if entry.mode == MODE_GITLINK {
+        return Ok(if metadata.is_dir() {
+            WorktreeState::Unchanged
+        } else {
+            WorktreeState::Modified
+        });
+    }
+
+    if metadata.is_dir() {
+        return Ok(WorktreeState::Deleted);
+    }
+
+    if entry.mode == MODE_SYMLINK && !metadata.file_type().is_symlink() {
+        return Ok(WorktreeState::Modified);
+    }
+
+    if entry.stat_matches(&metadata) {
+        return Ok(WorktreeState::Unchanged);
+    }
+
+    let filemode = repo.config().get_bool("core.filemode").unwrap_or(true);
+
+    if filemode && entry.mode != crate::index::mode_from_metadata(&metadata) {
+        return Ok(WorktreeState::Modified);
+    }
+
+    if entry.size != metadata.len() as u32 {
+        return Ok(WorktreeState::Modified);
+    }
+
+    if hash_worktree_file(repo, &path, &metadata)? == entry.hash {
+        Ok(WorktreeState::Unchanged)
+    } else {
+        Ok(WorktreeState::Modified)
+    }
+}
+
+/// Writes the blob `hash` to the work tree at `path` as a file of the given index `mode`, and
+/// returns the new file's metadata for the index entry. Whatever is in the way, a file where a
+/// directory has to go or a directory where the file goes, is removed first, so callers must
+/// have made sure nothing valuable is there. Symlinks become plain files holding their target
+/// when `core.symlinks` is off, and a submodule is checked out as an empty directory. Paths
+/// that could end up outside the work tree or inside `.git` are refused.
+pub fn checkout_file(repo: &Repository, path: &[u8], mode: u32, hash: &str) -> Result<Metadata> {
+    let work_tree = repo.require_work_tree()?;
+    require_valid_path(path)?;
+    let full_path = work_tree.join(path_from_bytes(path));
+
+    for (slash, _) in path.iter().enumerate().filter(|(_, b)| **b == b'/') {
+        let dir = work_tree.join(path_from_bytes(&path[..slash]));
+
+        if fs::symlink_metadata(&dir).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(&dir)?;
+        }
+    }
+
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() => {
+            if mode != MODE_GITLINK {
+                fs::remove_dir_all(&full_path)?;
+            }
+        }
+        Ok(_) => fs::remove_file(&full_path)?,
+        Err(_) => {}
+    }
+
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if mode == MODE_GITLINK {
+        fs::create_dir_all(&full_path)?;
+        return Ok(fs::symlink_metadata(&full_path)?);
+    }
+
+    let contents = repo.read_blob(hash)?;
+
+    if mode == MODE_SYMLINK && repo.config().get_bool("core.symlinks").unwrap_or(true) {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(path_from_bytes(&contents), &full_path)?;
+            return Ok(fs::symlink_metadata(&full_path)?);
+        }
+    }
+
+    fs::write(&full_path, &contents)?;
+
+    #[cfg(unix)]
+    if mode == MODE_EXECUTABLE {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Everyone who may read the file may run it, as git does under the umask.
+        let mut permissions = fs::metadata(&full_path)?.permissions();
+        permissions.set_mode(permissions.mode() | (permissions.mode() & 0o444) >> 2);
+        fs::set_permissions(&full_path, permissions)?;
+    }
+
+    Ok(fs::symlink_metadata(&full_path)?)
+}
+
+/// Deletes the work tree file at `path` along with any directories it leaves empty. Submodule
+/// checkouts are left alone.
+pub fn remove_worktree_file(repo: &Repository, path: &[u8]) -> Result<()> {
+    let work_tree = repo.require_work_tree()?;
+    require_valid_path(path)?;
+    let full_path = work_tree.join(path_from_bytes(path));
+
+    if full_path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Ok(());
+    }
+
+    match fs::remove_file(&full_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut dir = full_path.parent();
+
+    while let Some(current) = dir {
+        if current == work_tree || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+
+    Ok(())
+}
+
+fn require_valid_path(path: &[u8]) -> Result<()> {
+    if verify_path(path) {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument(format!(
+            "Invalid path `{}`",
+            String::from_utf8_lossy(path)
+        )))
+    }
+}
+
+/// Lists the work tree files that are not in the index, as index-style paths in sorted order.
+/// Nested repositories are reported once, as their directory with a trailing `/`. With `ignore`,
+/// ignored files are left out and ignored directories are not descended into.
+pub fn untracked_files(
+    repo: &Repository,
+    index: &Index,
+    ignore: Option<&mut IgnoreRules>,
+) -> Result<Vec<Vec<u8>>> {
+    let mut walker = Walker {
+        index,
+        ignore,
+        expand_ignored: false,
+        files: Vec::new(),
+        ignored: Vec::new(),
+    };
+
+    walker.walk(repo.require_work_tree()?, &[], false)?;
+    walker.files.sort();
+
+    Ok(walker.files)
+}
+
+/// Lists the untracked paths that `ignore` excludes, in sorted order. An ignored directory without
+/// tracked files inside is reported once, with a trailing `/`, unless `expand` asks for each file.
+pub fn ignored_files(
+    repo: &Repository,
+    index: &Index,
+    ignore: &mut IgnoreRules,
+    expand: bool,
+) -> Result<Vec<Vec<u8>>> {
+    let mut walker = Walker {
+        index,
+        ignore: Some(ignore),
+        expand_ignored: expand,
+        files: Vec::new(),
+        ignored: Vec::new(),
+    };
+
+    walker.walk(repo.require_work_tree()?, &[], false)?;
+    walker.ignored.sort();
+
+    Ok(walker.ignored)
+}
+
+/// Sorts the untracked paths of the work tree into those `ignore` excludes and the rest.
+struct Walker<'a> {
+    index: &'a Index,
+    ignore: Option<&'a mut IgnoreRules>,
+    /// List the files inside ignored directories instead of the directories themselves.
+    expand_ignored: bool,
+    files: Vec<Vec<u8>>,
+    ignored: Vec<Vec<u8>>,
+}
+
+impl Walker<'_> {
+    /// Walks `dir`, whose index path is `prefix`. Everything below an ignored directory
+    /// (`parent_ignored`) is ignored too.
+    fn walk(&mut self, dir: &Path, prefix: &[u8], parent_ignored: bool) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = path_to_bytes(entry.file_name());
+
+            if name == b".git" {
+                continue;
+            }
+
+            let mut path = prefix.to_vec();
+            path.extend_from_slice(&name);
+
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                if !self.index.entries_for(&path).is_empty() {
+                    // A directory staged as a gitlink.
+                    continue;
+                }
+
+                let dir_ignored = parent_ignored || self.is_ignored(&path, true);
+                path.push(b'/');
+
+                if dir_ignored && !self.expand_ignored {
+                    // Tracked files can still live below an ignored directory.
+                    let has_tracked = self
+                        .index
+                        .entries
+                        .iter()
+                        .any(|entry| entry.path.starts_with(&path));
+
+                    if !has_tracked {
+                        self.ignored.push(path);
+                        continue;
+                    }
+                }
+
+                if entry.path().join(".git").exists() {
+                    if dir_ignored {
+                        self.ignored.push(path);
+                    } else {
+                        self.files.push(path);
+                    }
+                    continue;
+                }
+
+                self.walk(&entry.path(), &path, dir_ignored)?;
+            } else if self.index.entries_for(&path).is_empty() {
+                if parent_ignored || self.is_ignored(&path, false) {
+                    self.ignored.push(path);
+                } else {
+                    self.files.push(path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_ignored(&mut self, path: &[u8], is_dir: bool) -> bool {
+        self.ignore
+            .as_deref_mut()
+            .is_some_and(|ignore| ignore.is_ignored(path, is_dir))
+    }
+}
+
+/// Where the current directory sits inside the work tree, as an index path prefix ending in `/`
+/// (empty at the top level). Commands use it to show paths relative to where they were run.
+pub fn cwd_prefix(repo: &Repository) -> Result<Vec<u8>> {
+    let work_tree = repo.require_work_tree()?;
+    let work_tree = work_tree
+        .canonicalize()
+        .unwrap_or_else(|_| work_tree.to_path_buf());
+    let cwd = std::env::current_dir()?;
+    let cwd = cwd.canonicalize().unwrap_or(cwd);
+
+    Ok(match index_path_for(&work_tree, &cwd) {
+        Some(mut path) => {
+            path.push(b'/');
+            path
+        }
+        None => Vec::new(),
+    })
+}
+
+/// Rewrites the index path `path` relative to the directory `prefix` (as returned by
+/// [`cwd_prefix`]), climbing out with `../` where needed.
+pub fn relative_path(path: &[u8], prefix: &[u8]) -> Vec<u8> {
+    let mut common = 0;
+
+    // Only whole directory components can be shared.
+    for (i, b) in prefix.iter().enumerate() {
+        if path.get(i) != Some(b) {
+            break;
+        }
+        if *b == b'/' {
+            common = i + 1;
+        }
+    }
+
+    let mut relative = Vec::new();
+    let ups = prefix[common..].iter().filter(|b| **b == b'/').count();
+
+    for _ in 0..ups {
+        relative.extend_from_slice(b"../");
+    }
+
+    relative.extend_from_slice(&path[common..]);
+
+    if relative.is_empty() {
+        relative.extend_from_slice(b"./");
+    }
+
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{index::MODE_BLOB, test_support::TestRepo};
+
+    /// `café` in Latin-1, which is not valid UTF-8.
+    const LATIN1: &[u8] = b"caf\xe9";
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_round_trip_exactly() {
+        let test = TestRepo::new("worktree-latin1");
+        let repo = &test.repo;
+        let work_tree = repo.require_work_tree().unwrap().to_path_buf();
+        let dir_path = [b"d\xe9r/".as_slice(), LATIN1].concat();
+
+        fs::create_dir(work_tree.join(path_from_bytes(b"d\xe9r"))).unwrap();
+        fs::write(work_tree.join(path_from_bytes(&dir_path)), "x\n").unwrap();
+        std::os::unix::fs::symlink(path_from_bytes(LATIN1), work_tree.join("link")).unwrap();
+
+        let index = Index::default();
+        let found = untracked_files(repo, &index, None).unwrap();
+        assert_eq!(found, [dir_path.clone(), b"link".to_vec()]);
+        assert_eq!(
+            index_path_for(&work_tree, &work_tree.join(path_from_bytes(&dir_path))),
+            Some(dir_path.clone())
+        );
+
+        let link = work_tree.join("link");
+        let metadata = fs::symlink_metadata(&link).unwrap();
+        assert_eq!(read_worktree_file(&link, &metadata).unwrap(), LATIN1);
+
+        fs::remove_dir_all(work_tree.join(path_from_bytes(b"d\xe9r"))).unwrap();
+        checkout_file(repo, &dir_path, MODE_BLOB, &test.blob("y\n")).unwrap();
+        let target = repo.write_object(&ObjectType::Blob, LATIN1).unwrap();
+        checkout_file(repo, b"link2", MODE_SYMLINK, &target).unwrap();
+
+        let written = fs::read_dir(work_tree.join(path_from_bytes(b"d\xe9r")))
+            .unwrap()
+            .map(|entry| path_to_bytes(entry.unwrap().file_name()))
+            .collect::<Vec<_>>();
+        assert_eq!(written, [LATIN1]);
+        assert_eq!(
+            fs::read(work_tree.join(path_from_bytes(&dir_path))).unwrap(),
+            b"y\n"
+        );
+        assert_eq!(
+            path_to_bytes(fs::read_link(work_tree.join("link2")).unwrap()),
+            LATIN1
+        );
+    }
+}